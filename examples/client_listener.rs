@@ -24,7 +24,7 @@ async fn main() {
             frame.sender(),
             frame.kind(),
             frame.topic(),
-            std::str::from_utf8(frame.payload()).unwrap_or("something unreadable")
+            frame.as_str().unwrap_or("something unreadable")
         );
     }
 }
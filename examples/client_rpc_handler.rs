@@ -64,7 +64,7 @@ impl RpcHandlers for MyHandlers {
             frame.sender(),
             frame.kind(),
             frame.topic(),
-            std::str::from_utf8(frame.payload()).unwrap_or("something unreadable")
+            frame.as_str().unwrap_or("something unreadable")
         );
     }
 }
@@ -50,10 +50,6 @@ async fn main() {
         }
     });
     while let Ok(frame) = rx.recv().await {
-        println!(
-            "{}: {}",
-            frame.sender(),
-            std::str::from_utf8(frame.payload()).unwrap()
-        );
+        println!("{}: {}", frame.sender(), frame.as_str().unwrap());
     }
 }
@@ -0,0 +1,336 @@
+use clap::{ArgEnum, Parser};
+use elbus::client::AsyncClient;
+use elbus::ipc::{Client, Config};
+use elbus::rpc::{DummyHandlers, Rpc, RpcClient};
+use elbus::{Error, QoS};
+use num_format::{Locale, ToFormattedString};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(all(unix, not(feature = "std-alloc")))]
+#[global_allocator]
+static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
+
+#[macro_use]
+extern crate prettytable;
+
+/// Max latency samples, collected by a single connection
+const LATENCY_SAMPLES_MAX: usize = 1_000_000;
+
+/// Payloads start with the send timestamp (nanoseconds), consumers use it to calculate the
+/// delivery latency
+const TIMESTAMP_LEN: usize = 8;
+
+#[derive(ArgEnum, Clone, Copy, Eq, PartialEq)]
+enum Mode {
+    Producer,
+    Consumer,
+    Rpc,
+}
+
+impl Mode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Mode::Producer => "producer",
+            Mode::Consumer => "consumer",
+            Mode::Rpc => "rpc",
+        }
+    }
+}
+
+#[derive(Parser)]
+#[clap(version = elbus::VERSION, author = elbus::AUTHOR)]
+struct Opts {
+    #[clap(name = "socket path or host:port")]
+    path: String,
+    #[clap(arg_enum, short = 'm', long = "mode", default_value = "rpc")]
+    mode: Mode,
+    #[clap(
+        short = 't',
+        long = "target",
+        default_value = ".broker",
+        help = "Target (producer/rpc), a mask for broadcasts"
+    )]
+    target: String,
+    #[clap(long = "method", default_value = "echo", help = "RPC method")]
+    method: String,
+    #[clap(
+        short = 'n',
+        long = "name",
+        default_value = "bench",
+        help = "Client name prefix, connections are named NAME.1 ... NAME.N"
+    )]
+    name: String,
+    #[clap(long = "payload-size", default_value = "100")]
+    payload_size: usize,
+    #[clap(short = 'q', long = "qos", default_value = "1", help = "QoS (0-3)")]
+    qos: u8,
+    #[clap(short = 'c', long = "connections", default_value = "1")]
+    connections: u32,
+    #[clap(short = 'd', long = "duration", default_value = "10", help = "seconds")]
+    duration: f64,
+    #[clap(long = "buf-size", default_value = "8192")]
+    buf_size: usize,
+    #[clap(long = "queue-size", default_value = "8192")]
+    queue_size: usize,
+    #[clap(long = "timeout", default_value = "5")]
+    timeout: f32,
+}
+
+#[derive(Default)]
+struct WorkerStats {
+    frames: u64,
+    bytes: u64,
+    latencies: Vec<u64>,
+    // consumers measure the time between the first and the last frames
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl WorkerStats {
+    #[inline]
+    fn add(&mut self, size: usize, latency: Option<Duration>) {
+        self.frames += 1;
+        self.bytes += size as u64;
+        if let Some(l) = latency {
+            if self.latencies.len() < LATENCY_SAMPLES_MAX {
+                self.latencies
+                    .push(u64::try_from(l.as_micros()).unwrap_or(u64::MAX));
+            }
+        }
+    }
+}
+
+macro_rules! fnum {
+    ($n: expr) => {
+        $n.to_formatted_string(&Locale::en).replace(',', "_")
+    };
+}
+
+fn ctable(titles: Vec<&str>) -> prettytable::Table {
+    let mut table = prettytable::Table::new();
+    let format = prettytable::format::FormatBuilder::new()
+        .column_separator(' ')
+        .borders(' ')
+        .separators(
+            &[prettytable::format::LinePosition::Title],
+            prettytable::format::LineSeparator::new('-', '-', '-', '-'),
+        )
+        .padding(0, 1)
+        .build();
+    table.set_format(format);
+    let mut titlevec: Vec<prettytable::Cell> = Vec::new();
+    for t in titles {
+        titlevec.push(prettytable::Cell::new(t).style_spec("Fb"));
+    }
+    table.set_titles(prettytable::Row::new(titlevec));
+    table
+}
+
+#[allow(clippy::cast_possible_truncation)]
+#[inline]
+fn now_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+#[inline]
+fn delivery_latency(payload: &[u8]) -> Option<Duration> {
+    let ts = u64::from_le_bytes(payload.get(..TIMESTAMP_LEN)?.try_into().ok()?);
+    Some(Duration::from_nanos(now_ns().saturating_sub(ts)))
+}
+
+#[inline]
+fn prepare_payload(size: usize) -> Vec<u8> {
+    let mut payload = vec![0xee; size];
+    if size >= TIMESTAMP_LEN {
+        payload[..TIMESTAMP_LEN].copy_from_slice(&now_ns().to_le_bytes());
+    }
+    payload
+}
+
+async fn create_client(opts: &Opts, name: &str) -> Result<Client, Error> {
+    let config = Config::new(&opts.path, name)
+        .buf_size(opts.buf_size)
+        .queue_size(opts.queue_size)
+        .timeout(Duration::from_secs_f32(opts.timeout));
+    Client::connect(&config).await
+}
+
+async fn producer(
+    mut client: Client,
+    target: String,
+    size: usize,
+    qos: QoS,
+    deadline: Instant,
+) -> Result<WorkerStats, Error> {
+    let broadcast = target.contains(&['*', '?'][..]);
+    let mut stats = WorkerStats::default();
+    while Instant::now() < deadline {
+        let payload = prepare_payload(size);
+        let op_start = Instant::now();
+        let opc = if broadcast {
+            client.send_broadcast(&target, payload.into(), qos).await?
+        } else {
+            client.send(&target, payload.into(), qos).await?
+        };
+        if let Some(c) = opc {
+            c.await.map_err(Error::io)??;
+        }
+        stats.add(size, Some(op_start.elapsed()));
+    }
+    Ok(stats)
+}
+
+async fn consumer(mut client: Client, deadline: Instant) -> Result<WorkerStats, Error> {
+    let rx = client
+        .take_event_channel()
+        .ok_or_else(|| Error::not_supported("no event channel"))?;
+    let mut stats = WorkerStats::default();
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        if let Ok(frame) = tokio::time::timeout(deadline - now, rx.recv()).await {
+            let frame = frame.map_err(Error::io)?;
+            let now = Instant::now();
+            stats.first.get_or_insert(now);
+            stats.last.replace(now);
+            stats.add(frame.payload().len(), delivery_latency(frame.payload()));
+        } else {
+            break;
+        }
+    }
+    Ok(stats)
+}
+
+async fn rpc_caller(
+    client: Client,
+    target: String,
+    method: String,
+    size: usize,
+    qos: QoS,
+    deadline: Instant,
+) -> Result<WorkerStats, Error> {
+    let rpc = RpcClient::new(client, DummyHandlers {});
+    let payload = Arc::new(vec![0xee; size]);
+    let mut stats = WorkerStats::default();
+    while Instant::now() < deadline {
+        let op_start = Instant::now();
+        rpc.call(&target, &method, payload.clone().into(), qos)
+            .await
+            .map_err(|e| Error::io(format!("RPC error {}", e.code())))?;
+        stats.add(size, Some(op_start.elapsed()));
+    }
+    Ok(stats)
+}
+
+#[inline]
+fn percentile(sorted: &[u64], p: usize) -> u64 {
+    if sorted.is_empty() {
+        0
+    } else {
+        sorted[(sorted.len() * p / 100).min(sorted.len() - 1)]
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn report(opts: &Opts, qos: QoS, stats: Vec<WorkerStats>, elapsed: Duration) {
+    let mut frames = 0;
+    let mut bytes = 0;
+    let mut latencies = Vec::new();
+    let mut first: Option<Instant> = None;
+    let mut last: Option<Instant> = None;
+    for s in stats {
+        frames += s.frames;
+        bytes += s.bytes;
+        latencies.extend(s.latencies);
+        if let Some(f) = s.first {
+            first = Some(first.map_or(f, |v| v.min(f)));
+        }
+        if let Some(l) = s.last {
+            last = Some(last.map_or(l, |v| v.max(l)));
+        }
+    }
+    let elapsed = if opts.mode == Mode::Consumer {
+        first
+            .zip(last)
+            .map_or(Duration::default(), |(f, l)| l.duration_since(f))
+    } else {
+        elapsed
+    };
+    latencies.sort_unstable();
+    let secs = elapsed.as_secs_f64();
+    let (fps, bps) = if secs > 0.0 {
+        (frames as f64 / secs, bytes as f64 / secs)
+    } else {
+        (0.0, 0.0)
+    };
+    let mut table = ctable(vec!["field", "value"]);
+    table.add_row(row!["mode", opts.mode.as_str()]);
+    table.add_row(row!["connections", opts.connections]);
+    table.add_row(row!["payload size", fnum!(opts.payload_size)]);
+    table.add_row(row!["qos", format!("{:?}", qos)]);
+    table.add_row(row!["frames", fnum!(frames)]);
+    table.add_row(row!["elapsed", format!("{:.3}", secs)]);
+    table.add_row(row!["frames/sec", fnum!(fps as u64)]);
+    table.add_row(row!["MB/sec", format!("{:.3}", bps / 1_000_000.0)]);
+    if !latencies.is_empty() {
+        table.add_row(row!["latency p50, us", fnum!(percentile(&latencies, 50))]);
+        table.add_row(row!["latency p90, us", fnum!(percentile(&latencies, 90))]);
+        table.add_row(row!["latency p99, us", fnum!(percentile(&latencies, 99))]);
+        table.add_row(row![
+            "latency max, us",
+            fnum!(latencies.last().copied().unwrap_or_default())
+        ]);
+    }
+    table.printstd();
+}
+
+#[tokio::main]
+async fn main() {
+    let opts = Opts::parse();
+    let qos: QoS = opts.qos.try_into().expect("Invalid QoS");
+    let duration = Duration::from_secs_f64(opts.duration);
+    let mut clients = Vec::new();
+    for c in 1..=opts.connections {
+        clients.push(
+            create_client(&opts, &format!("{}.{}", opts.name, c))
+                .await
+                .expect("Unable to connect to the elbus broker"),
+        );
+    }
+    let started = Instant::now();
+    let deadline = started + duration;
+    let mut futs = Vec::new();
+    for client in clients {
+        let target = opts.target.clone();
+        let size = opts.payload_size;
+        futs.push(match opts.mode {
+            Mode::Producer => tokio::spawn(producer(client, target, size, qos, deadline)),
+            Mode::Consumer => tokio::spawn(consumer(client, deadline)),
+            Mode::Rpc => tokio::spawn(rpc_caller(
+                client,
+                target,
+                opts.method.clone(),
+                size,
+                qos,
+                deadline,
+            )),
+        });
+    }
+    let mut stats = Vec::new();
+    for fut in futs {
+        match fut.await.expect("benchmark worker crashed") {
+            Ok(s) => stats.push(s),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    report(&opts, qos, stats, started.elapsed());
+}
@@ -21,6 +21,16 @@ pub enum Cow<'a> {
     Referenced(Arc<Vec<u8>>),
 }
 
+impl<'a> Clone for Cow<'a> {
+    fn clone(&self) -> Cow<'a> {
+        match self {
+            Cow::Borrowed(v) => Cow::Borrowed(v),
+            Cow::Owned(v) => Cow::Owned(v.clone()),
+            Cow::Referenced(v) => Cow::Referenced(v.clone()),
+        }
+    }
+}
+
 impl<'a> From<Vec<u8>> for Cow<'a> {
     fn from(src: Vec<u8>) -> Cow<'a> {
         Cow::Owned(src)
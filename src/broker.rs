@@ -1,59 +1,184 @@
 use crate::borrow::Cow;
+#[cfg(feature = "testing")]
+use crate::chaos::{Chaos, ChaosRule};
 use crate::client::AsyncClient;
-use crate::comm::{Flush, TtlBufWriter};
+#[cfg(windows)]
+use crate::comm::NAMED_PIPE_PREFIX;
+#[cfg(feature = "vsock")]
+use crate::comm::{parse_vsock_addr, VSOCK_PREFIX};
+use crate::comm::{Flush, TcpOptions, TtlBufWriter};
 #[cfg(feature = "rpc")]
-use crate::common::now_ns;
-use crate::common::{BrokerInfo, BrokerStats};
+use crate::common::NAMESPACE_SUBSCRIBE_METHOD;
+use crate::common::{check_trace_id, health_payload, now_ns, ClientHealth, ClientWill};
 #[cfg(feature = "rpc")]
-use crate::common::{ClientInfo, ClientList};
+use crate::common::{
+    BrokerBenchmark, ClientDrain, ClientInfo, ClientList, HelloExtensions, HelloExtensionsReply,
+    ListenerList, TopicList,
+};
+use crate::common::{BrokerCapabilities, BrokerInfo, BrokerLimits, BrokerStats, ListenerInfo};
+#[cfg(all(unix, feature = "rpc"))]
+use crate::common::{ChannelOffer, ChannelToken};
+use crate::common::{ConnectionProtocolErrors, ProtocolErrors};
+use crate::common::{MaskStats, SubscriptionBranch, SubscriptionStats};
+#[cfg(feature = "rpc")]
+use crate::common::{NamespaceEvent, NamespaceInfo, NamespaceList, NamespaceSubscriptions};
+#[cfg(feature = "rpc")]
+use crate::common::{RpcBalance, ServiceInfo, ServiceList, RPC_TARGET_PREFIX};
+use crate::common::{WIRETAP_BROADCAST, WIRETAP_CLIENT, WIRETAP_MESSAGE, WIRETAP_PUBLISH};
+use crate::metrics::{Metric, MetricKind, Metrics};
+use crate::persistence::{MemoryPersistence, Persistence};
+#[cfg(feature = "serial")]
+use crate::serial::SerialBridge;
+#[cfg(all(target_os = "linux", feature = "uring"))]
+use crate::uring::{UringReader, UringWriter};
+#[cfg(feature = "rpc")]
+use crate::HELLO_EXTENSIONS_VERSION;
 use crate::SECONDARY_SEP;
+use crate::{ClientAcl, ClientIdentity, ClientKind};
+use crate::{DeliveryMode, Frame, FrameData, FrameKind, FrameOp, IntoElbusResult, QoS};
 use crate::{Error, ErrorKind, GREETINGS, PROTOCOL_VERSION};
-use crate::{EventChannel, OpConfirm};
-use crate::{Frame, FrameData, FrameKind, FrameOp, QoS};
-use crate::{ERR_ACCESS, ERR_DATA, ERR_NOT_SUPPORTED};
-use crate::{OP_ACK, RESPONSE_OK};
+use crate::{EventChannel, OpConfirm, DEFAULT_TIMEOUT};
+use crate::{BATCH_MAX_DELAY, BATCH_MAX_SIZE, OP_ACK, OP_ACK_PRESSURE, OP_BATCH, RESPONSE_OK};
+use crate::{ERR_ACCESS, ERR_BUSY, ERR_DATA, ERR_LIMIT, ERR_NOT_SUPPORTED, ERR_UNROUTED};
+use crate::{ERR_NOT_DELIVERED, ERR_TIMEOUT};
+use crate::{FRAME_FLAG_DELIVERY, FRAME_FLAG_REALTIME, FRAME_FLAG_TIMESTAMP};
+use crate::{HEALTH_LOAD_MAX, HEALTH_STATUS_MAX_LEN};
+use crate::{HELLO_EXTENSIONS_FLAG, HELLO_EXTENSIONS_MAX_SIZE};
+use crate::{HELLO_TOKEN_FLAG, HELLO_TOKEN_MAX_LEN};
+use crate::{TRANSACTION_ABORT, TRANSACTION_BEGIN, TRANSACTION_COMMIT};
 use async_trait::async_trait;
 use ipnetwork::IpNetwork;
 use log::{debug, error, trace, warn};
+use parking_lot::RwLock;
 #[cfg(feature = "rpc")]
 use serde::{Deserialize, Serialize};
-use std::collections::{hash_map, HashMap, HashSet};
+#[cfg(all(unix, feature = "rpc"))]
+use std::collections::hash_map::RandomState;
+use std::collections::{hash_map, BTreeMap, HashMap, HashSet};
 use std::fmt;
+#[cfg(all(unix, feature = "rpc"))]
+use std::hash::BuildHasher;
 use std::hash::{Hash, Hasher};
 use std::marker::Unpin;
 use std::net::IpAddr;
 use std::net::SocketAddr;
+#[cfg(all(unix, feature = "rpc"))]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(all(target_os = "linux", feature = "uring"))]
+use std::rc::Rc;
+use std::str::FromStr;
 use std::sync::atomic;
 use std::sync::Arc;
-use std::sync::RwLock;
 use std::time::Duration;
 use std::time::Instant;
 use submap::{AclMap, BroadcastMap, SubMap};
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+#[cfg(windows)]
+use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
 #[cfg(feature = "rpc")]
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
 use tokio::time;
 
+#[cfg(all(unix, feature = "rpc"))]
+use crate::rpc::DummyHandlers;
+#[cfg(feature = "rpc")]
+use crate::rpc::{
+    ContentType, Rpc, RpcClient, RpcError, RpcEvent, RpcHandlers, RpcResult, RPC_NOTIFICATION,
+};
 #[cfg(feature = "rpc")]
-use crate::rpc::{Rpc, RpcClient, RpcError, RpcEvent, RpcHandlers, RpcResult};
+use crate::rpc::{RPC_ERROR, RPC_ERROR_CODE_UNAVAILABLE, RPC_FLAG_HEADERS};
+#[cfg(feature = "rpc")]
+use crate::rpc::{RPC_HEADER_SESSION, RPC_REPLY, RPC_REQUEST};
 #[cfg(feature = "rpc")]
 use serde_value::Value;
 
 pub const DEFAULT_QUEUE_SIZE: usize = 8192;
+/// Deliveries, routed by a client before its task yields to other clients
+pub const DEFAULT_FANOUT_BUDGET: usize = 256;
+/// Sessions, pinned to providers of a load-balanced RPC target. When the limit is reached, idle
+/// sessions are expired and, if there are none, the least recently used one is unpinned
+pub const RPC_TARGET_SESSIONS_MAX: usize = 100_000;
+/// Sessions, not used for the period, are unpinned and routed by the balance policy again
+pub const RPC_TARGET_SESSION_IDLE: Duration = Duration::from_secs(600);
 
+/// System topics, clients can subscribe to them, but only the broker can publish
+pub const BROKER_TOPIC_PREFIX: &str = ".broker/";
 pub const BROKER_INFO_TOPIC: &str = ".broker/info";
 pub const BROKER_WARN_TOPIC: &str = ".broker/warn";
+pub const BROKER_SUBSCRIPTION_TOPIC: &str = ".broker/subscription";
+/// Demand events of topic masks are published to ".broker/subscriptions/MASK"
+pub const BROKER_DEMAND_TOPIC_PREFIX: &str = ".broker/subscriptions/";
+pub const BROKER_LISTENER_TOPIC: &str = ".broker/listener";
+pub const BROKER_OVERLOAD_TOPIC: &str = ".broker/overload";
+/// Routing events of traced frames are published to ".broker/trace/ID"
+pub const BROKER_TRACE_TOPIC_PREFIX: &str = ".broker/trace/";
 pub const BROKER_NAME: &str = ".broker";
 
+/// Memory pressure is cleared when the queued bytes go below the percentage of the limit
+const MEMORY_PRESSURE_CLEAR_PERCENT: u64 = 90;
+
+const LISTENER_RESTART_DELAY_MIN: Duration = Duration::from_millis(100);
+const LISTENER_RESTART_DELAY_MAX: Duration = Duration::from_secs(30);
+const LISTENER_ACCEPT_ERRORS_MAX: u32 = 100;
+const LISTENER_ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(10);
+
+#[cfg(all(target_os = "linux", feature = "uring"))]
+const URING_PREFIX: &str = "uring://";
+/// Stopped io_uring listeners check connected clients with the interval
+#[cfg(all(target_os = "linux", feature = "uring"))]
+const URING_STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Max number of recent message targets, cached by a client
+const SEND_CACHE_SIZE: usize = 16;
+
+#[cfg(feature = "tls")]
+const TLS_PREFIX: &str = "tls://";
+
+#[cfg(feature = "quic")]
+const QUIC_PREFIX: &str = "quic://";
+
+#[cfg(feature = "websocket")]
+const WS_PREFIX: &str = "ws://";
+
+#[cfg(all(feature = "websocket", feature = "rpc"))]
+const WS_GATEWAY_PREFIX: &str = "wsgw://";
+
+const UDP_PREFIX: &str = "udp://";
+/// Max UDP datagram payload
+const UDP_MAX_DATAGRAM: usize = 65507;
+
+/// Limits of client transactions, frames, which exceed them, fail the transaction
+pub const TRANSACTION_MAX_FRAMES: usize = 1_000;
+pub const TRANSACTION_MAX_SIZE: usize = 16 * 1024 * 1024;
+
+/// Messages, sent with QoS::Delivered or QoS::Consumed and not resolved in time, are failed with
+/// ERR_TIMEOUT. Expired deliveries are checked when new ones are registered
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(60);
+const DELIVERY_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Bounds of the ping interval, a client can request in the hello extensions
+#[cfg(feature = "rpc")]
+const KEEPALIVE_MIN: Duration = Duration::from_millis(100);
+#[cfg(feature = "rpc")]
+const KEEPALIVE_MAX: Duration = Duration::from_secs(3600);
+
 #[allow(dead_code)]
 const BROKER_RPC_NOT_INIT_ERR: &str = "broker core RPC client not initialized";
+/// Max time to wait for replies to RPC calls, sent via the fifo with responses
+#[cfg(all(unix, feature = "rpc"))]
+const FIFO_CALL_TIMEOUT: Duration = Duration::from_secs(30);
 
 macro_rules! pretty_error {
     ($name: expr, $err:expr) => {
         if $err.kind() != ErrorKind::Eof {
-            error!("client {} error: {}", $name, $err);
+            error!(listener:% = $name, event = "error"; "client {} error: {}", $name, $err);
         }
     };
 }
@@ -74,8 +199,32 @@ macro_rules! make_confirm_channel {
 
 macro_rules! safe_send_frame {
     ($db: expr, $tgt: expr, $frame: expr, $timeout: expr) => {
-        if $tgt.tx.is_full() {
-            if $tgt.kind == ElbusClientKind::Internal {
+        if $tgt.draining.load(atomic::Ordering::SeqCst) {
+            Err(Error::not_delivered())
+        } else if $tgt.kind != ClientKind::Internal && $db.memory_exceeded() {
+            // the client with the largest queue is disconnected, which is not necessarily the
+            // target of the frame
+            if $db.memory_overflow_policy() == MemoryOverflowPolicy::Disconnect {
+                if let Some(victim) = $db.largest_queue_client() {
+                    warn!(
+                        "memory limit exceeded, force unregistering client {} ({} frames queued)",
+                        victim.name,
+                        victim.tx.len()
+                    );
+                    $db.report(
+                        BROKER_OVERLOAD_TOPIC,
+                        "memory_limit",
+                        None,
+                        Some(&victim.name),
+                    );
+                    $db.unregister_client(&victim, DisconnectReason::MemoryLimit)
+                        .await;
+                    victim.tx.close();
+                }
+            }
+            Err(Error::not_delivered())
+        } else if $tgt.tx.is_full() {
+            if $tgt.kind == ClientKind::Internal {
                 if let Some(timeout) = $timeout {
                     warn!(
                         "internal client {} queue is full, blocking for {:?}",
@@ -90,50 +239,194 @@ macro_rules! safe_send_frame {
                 }
             } else {
                 warn!("client {} queue is full, force unregistering", $tgt.name);
-                $db.unregister_client(&$tgt).await;
+                $db.report(
+                    BROKER_OVERLOAD_TOPIC,
+                    "queue_overflow",
+                    None,
+                    Some(&$tgt.name),
+                );
+                $db.unregister_client(&$tgt, DisconnectReason::QueueOverflow)
+                    .await;
                 $tgt.tx.close();
                 Err(Error::not_delivered())
             }
         } else {
-            $tgt.tx.send($frame).await.map_err(Into::into)
+            let frame = $frame;
+            if $tgt.tapped.load(atomic::Ordering::SeqCst) {
+                $tgt.tap(&frame, &tap_in_path(&frame));
+            }
+            $tgt.tx.send(frame).await.map_err(Into::into)
         }
     };
 }
 
+/// Checks if the client is allowed to route the frame to the target. Group sends are checked
+/// with p2p and broadcast ACLs, system topics are published by the broker only
+fn route_allowed(aaa: Option<&ClientAaa>, op: FrameOp, target: &str) -> bool {
+    match op {
+        FrameOp::PublishTopic | FrameOp::PublishTopicMandatory
+            if target.starts_with(BROKER_TOPIC_PREFIX) =>
+        {
+            false
+        }
+        _ => aaa.is_none_or(|aaa| match op {
+            FrameOp::Message | FrameOp::GroupMessage => aaa.p2p_allowed(target),
+            FrameOp::Broadcast | FrameOp::BroadcastMandatory | FrameOp::GroupBroadcast => {
+                aaa.broadcast_allowed(target)
+            }
+            FrameOp::PublishTopic | FrameOp::PublishTopicMandatory => aaa.publish_allowed(target),
+            _ => false,
+        }),
+    }
+}
+
+/// Client tap path of a frame, received by the client
+fn tap_in_path(frame: &Frame) -> String {
+    match frame.kind {
+        FrameKind::Message => "in/m".to_owned(),
+        FrameKind::Broadcast => "in/b".to_owned(),
+        FrameKind::Publish => format!("in/p/{}", frame.topic().unwrap_or_default()),
+        _ => "in".to_owned(),
+    }
+}
+
 macro_rules! send {
     ($db:expr, $client:expr, $target:expr, $header: expr,
-     $buf:expr, $payload_pos:expr, $len: expr, $realtime: expr, $timeout: expr) => {{
+     $buf:ident, $payload_pos:expr, $len: expr, $realtime: expr, $timeout: expr) => {
+        send!(
+            $db,
+            $client,
+            $target,
+            $header,
+            $buf,
+            $payload_pos,
+            $len,
+            $realtime,
+            $timeout,
+            None
+        )
+    };
+    // the target may be borrowed from the buffer, so the buffer must be a variable, which is
+    // moved only after the target is no longer needed
+    ($db:expr, $client:expr, $target:expr, $header: expr,
+     $buf:ident, $payload_pos:expr, $len: expr, $realtime: expr, $timeout: expr,
+     $delivery: expr) => {{
         $client.r_frames.fetch_add(1, atomic::Ordering::SeqCst);
         $client.r_bytes.fetch_add($len, atomic::Ordering::SeqCst);
         $db.r_frames.fetch_add(1, atomic::Ordering::SeqCst);
         $db.r_bytes.fetch_add($len, atomic::Ordering::SeqCst);
         trace!("elbus message from {} to {}", $client, $target);
-        let client = {
-            $db.clients.read().unwrap().get($target).map(|c| {
+        let mut frame_trace = $client.take_trace();
+        let header: Option<Vec<u8>> = $header;
+        // the RPC envelope is in the header if set
+        let rpc_body: &[u8] = header
+            .as_deref()
+            .unwrap_or_else(|| $buf.get($payload_pos..).unwrap_or_default());
+        #[cfg(feature = "rpc")]
+        let rpc_head = rpc_head(rpc_body);
+        #[cfg(not(feature = "rpc"))]
+        let rpc_head = None;
+        let client = $db
+            .message_target(&$client, $target, rpc_head, rpc_body)
+            .map(|c| {
                 c.w_frames.fetch_add(1, atomic::Ordering::SeqCst);
                 c.w_bytes.fetch_add($len, atomic::Ordering::SeqCst);
                 $db.w_frames.fetch_add(1, atomic::Ordering::SeqCst);
                 $db.w_bytes.fetch_add($len, atomic::Ordering::SeqCst);
-                c.clone()
-            })
-        };
-        if let Some(client) = client {
-            let frame = Arc::new(FrameData {
-                kind: FrameKind::Message,
-                sender: Some($client.name.clone()),
-                topic: None,
-                header: $header,
-                buf: $buf,
-                payload_pos: $payload_pos,
-                realtime: $realtime,
+                c
             });
-            safe_send_frame!($db, client, frame, $timeout)
+        if let Some(ref mut t) = frame_trace {
+            t.matched(usize::from(client.is_some()));
+            if client.is_none() {
+                t.result($target, &Err(Error::not_registered()));
+            }
+        }
+        // the target is copied only if there are wiretap subscribers
+        let tap_target = $db.wiretap_target($target);
+        let client_tap = $client
+            .tapped
+            .load(atomic::Ordering::SeqCst)
+            .then(|| format!("out/m/{}", $target));
+        let balanced = $target.starts_with(crate::common::RPC_TARGET_PREFIX);
+        let frame = Arc::new(FrameData {
+            kind: FrameKind::Message,
+            sender: Some($client.name.clone()),
+            topic: None,
+            header,
+            buf: $buf,
+            payload_pos: $payload_pos,
+            realtime: $realtime,
+            identity: Some($client.identity.clone()),
+            timestamp: $db.frame_timestamp(),
+            delivery: $delivery,
+            memory: Some($db.frame_memory($len)),
+        });
+        if let Some(tap_target) = tap_target {
+            $db.wiretap(&$client, WIRETAP_MESSAGE, &tap_target, &frame);
+        }
+        if let Some(path) = client_tap {
+            $client.tap(&frame, &path);
+        }
+        let result = if let Some(client) = client {
+            let result = safe_send_frame!($db, client, frame, $timeout);
+            if let Some(ref mut t) = frame_trace {
+                t.result(&client.name, &result);
+            }
+            $db.charge_fanout(&$client).await;
+            result
         } else {
             Err(Error::not_registered())
+        };
+        if result.is_err() {
+            $db.message_failed(&$client, balanced, rpc_head);
+        }
+        if let Some(t) = frame_trace {
+            $db.publish_trace(t);
         }
+        result
     }};
 }
 
+/// Returns the RPC event kind (without flags) and the call id of a message
+#[cfg(feature = "rpc")]
+#[inline]
+fn rpc_head(body: &[u8]) -> Option<(u8, u32)> {
+    let head = body.get(..5)?;
+    Some((
+        head[0] & !RPC_FLAG_HEADERS,
+        u32::from_le_bytes(head[1..5].try_into().unwrap()),
+    ))
+}
+
+/// Returns the session key header of an RPC request or notification
+#[cfg(feature = "rpc")]
+fn rpc_session(body: &[u8]) -> Option<&str> {
+    let kind = *body.first()?;
+    if kind & RPC_FLAG_HEADERS == 0 {
+        return None;
+    }
+    let pos = match kind & !RPC_FLAG_HEADERS {
+        RPC_NOTIFICATION => 1,
+        RPC_REQUEST => 5,
+        _ => return None,
+    };
+    let len = usize::from(u16::from_le_bytes(
+        body.get(pos..pos + 2)?.try_into().unwrap(),
+    ));
+    let block = body.get(pos + 2..pos + 2 + len)?;
+    let mut sp = block.split(|c| *c == 0);
+    while let Some(k) = sp.next() {
+        if k.is_empty() {
+            break;
+        }
+        let v = sp.next()?;
+        if k == RPC_HEADER_SESSION.as_bytes() {
+            return std::str::from_utf8(v).ok().filter(|v| !v.is_empty());
+        }
+    }
+    None
+}
+
 macro_rules! send_broadcast {
     ($db:expr, $client:expr, $target:expr, $header: expr,
      $buf:expr, $payload_pos:expr, $len: expr, $realtime: expr, $timeout: expr) => {{
@@ -143,17 +436,38 @@ macro_rules! send_broadcast {
         $db.r_bytes.fetch_add($len, atomic::Ordering::SeqCst);
         trace!("elbus broadcast message from {} to {}", $client, $target);
         #[allow(clippy::mutable_key_type)]
-        let subs = { $db.broadcasts.read().unwrap().get_clients_by_mask($target) };
+        let subs = { $db.broadcasts.read().get_clients_by_mask($target) };
+        let mut frame_trace = $client.take_trace();
+        // the target is copied only if there are wiretap subscribers
+        let tap_target = $db.wiretap_target($target);
+        let client_tap = $client
+            .tapped
+            .load(atomic::Ordering::SeqCst)
+            .then(|| format!("out/b/{}", $target));
+        let frame = Arc::new(FrameData {
+            kind: FrameKind::Broadcast,
+            sender: Some($client.name.clone()),
+            topic: None,
+            header: $header,
+            buf: $buf,
+            payload_pos: $payload_pos,
+            realtime: $realtime,
+            identity: Some($client.identity.clone()),
+            timestamp: $db.frame_timestamp(),
+            delivery: None,
+            memory: Some($db.frame_memory($len)),
+        });
+        if let Some(tap_target) = tap_target {
+            $db.wiretap(&$client, WIRETAP_BROADCAST, &tap_target, &frame);
+        }
+        if let Some(path) = client_tap {
+            $client.tap(&frame, &path);
+        }
+        let routed = subs.len();
+        if let Some(ref mut t) = frame_trace {
+            t.matched(routed);
+        }
         if !subs.is_empty() {
-            let frame = Arc::new(FrameData {
-                kind: FrameKind::Broadcast,
-                sender: Some($client.name.clone()),
-                topic: None,
-                header: $header,
-                buf: $buf,
-                payload_pos: $payload_pos,
-                realtime: $realtime,
-            });
             $db.w_frames
                 .fetch_add(subs.len() as u64, atomic::Ordering::SeqCst);
             $db.w_bytes
@@ -161,9 +475,105 @@ macro_rules! send_broadcast {
             for sub in subs {
                 sub.w_frames.fetch_add(1, atomic::Ordering::SeqCst);
                 sub.w_bytes.fetch_add($len, atomic::Ordering::SeqCst);
-                let _r = safe_send_frame!($db, sub, frame.clone(), $timeout);
+                let result = safe_send_frame!($db, sub, frame.clone(), $timeout);
+                if let Some(ref mut t) = frame_trace {
+                    t.result(&sub.name, &result);
+                }
+                $db.charge_fanout(&$client).await;
+            }
+        }
+        if let Some(t) = frame_trace {
+            $db.publish_trace(t);
+        }
+        routed
+    }};
+}
+
+macro_rules! send_group {
+    ($db:expr, $client:expr, $group:expr, $all: expr, $header: expr,
+     $buf:expr, $payload_pos:expr, $len: expr, $realtime: expr, $timeout: expr) => {{
+        $client.r_frames.fetch_add(1, atomic::Ordering::SeqCst);
+        $client.r_bytes.fetch_add($len, atomic::Ordering::SeqCst);
+        $db.r_frames.fetch_add(1, atomic::Ordering::SeqCst);
+        $db.r_bytes.fetch_add($len, atomic::Ordering::SeqCst);
+        trace!("elbus group message from {} to {}", $client, $group);
+        let members = $db.group_members($group, $all);
+        let mut frame_trace = $client.take_trace();
+        // the target is copied only if there are wiretap subscribers
+        let tap_target = $db.wiretap_target($group);
+        let client_tap = $client
+            .tapped
+            .load(atomic::Ordering::SeqCst)
+            .then(|| format!("out/{}/{}", if $all { "b" } else { "m" }, $group));
+        let frame = Arc::new(FrameData {
+            kind: if $all {
+                FrameKind::Broadcast
+            } else {
+                FrameKind::Message
+            },
+            sender: Some($client.name.clone()),
+            topic: None,
+            header: $header,
+            buf: $buf,
+            payload_pos: $payload_pos,
+            realtime: $realtime,
+            identity: Some($client.identity.clone()),
+            timestamp: $db.frame_timestamp(),
+            delivery: None,
+            memory: Some($db.frame_memory($len)),
+        });
+        if let Some(tap_target) = tap_target {
+            $db.wiretap(
+                &$client,
+                if $all {
+                    WIRETAP_BROADCAST
+                } else {
+                    WIRETAP_MESSAGE
+                },
+                &tap_target,
+                &frame,
+            );
+        }
+        if let Some(path) = client_tap {
+            $client.tap(&frame, &path);
+        }
+        if let Some(ref mut t) = frame_trace {
+            t.matched(members.len());
+        }
+        let result = if members.is_empty() {
+            if $all {
+                Ok(())
+            } else {
+                Err(Error::not_registered())
+            }
+        } else {
+            $db.w_frames
+                .fetch_add(members.len() as u64, atomic::Ordering::SeqCst);
+            $db.w_bytes
+                .fetch_add($len * members.len() as u64, atomic::Ordering::SeqCst);
+            let mut result = Ok(());
+            for member in members {
+                member.w_frames.fetch_add(1, atomic::Ordering::SeqCst);
+                member.w_bytes.fetch_add($len, atomic::Ordering::SeqCst);
+                let member_result = safe_send_frame!($db, member, frame.clone(), $timeout);
+                if let Some(ref mut t) = frame_trace {
+                    t.result(&member.name, &member_result);
+                }
+                if let Err(e) = member_result {
+                    result = Err(e);
+                }
+                $db.charge_fanout(&$client).await;
+            }
+            if $all {
+                Ok(())
+            } else {
+                result
             }
+        };
+        if let Some(t) = frame_trace {
+            $db.publish_trace(t);
         }
+        result
     }};
 }
 
@@ -176,27 +586,76 @@ macro_rules! publish {
         $db.r_bytes.fetch_add($len, atomic::Ordering::SeqCst);
         trace!("elbus topic publish from {} to {}", $client, $topic);
         #[allow(clippy::mutable_key_type)]
-        let subs = { $db.subscriptions.read().unwrap().get_subscribers($topic) };
-        if !subs.is_empty() {
-            let frame = Arc::new(FrameData {
-                kind: FrameKind::Publish,
-                sender: Some($client.name.clone()),
-                topic: Some($topic.to_owned()),
-                header: $header,
-                buf: $buf,
-                payload_pos: $payload_pos,
-                realtime: $realtime,
-            });
-            $db.w_frames
-                .fetch_add(subs.len() as u64, atomic::Ordering::SeqCst);
-            $db.w_bytes
-                .fetch_add($len * subs.len() as u64, atomic::Ordering::SeqCst);
-            for sub in subs {
-                sub.w_frames.fetch_add(1, atomic::Ordering::SeqCst);
-                sub.w_bytes.fetch_add($len, atomic::Ordering::SeqCst);
-                let _r = safe_send_frame!($db, sub, frame.clone(), $timeout);
+        let subs = { $db.subscriptions.read().get_subscribers($topic) };
+        let mut frame_trace = $client.take_trace();
+        // the target is copied only if there are wiretap subscribers
+        let tap_target = $db.wiretap_target($topic);
+        let client_tap = $client
+            .tapped
+            .load(atomic::Ordering::SeqCst)
+            .then(|| format!("out/p/{}", $topic));
+        let frame = Arc::new(FrameData {
+            kind: FrameKind::Publish,
+            sender: Some($client.name.clone()),
+            topic: Some($topic.to_owned()),
+            header: $header,
+            buf: $buf,
+            payload_pos: $payload_pos,
+            realtime: $realtime,
+            identity: Some($client.identity.clone()),
+            timestamp: $db.frame_timestamp(),
+            delivery: None,
+            memory: Some($db.frame_memory($len)),
+        });
+        if let Some(tap_target) = tap_target {
+            $db.wiretap(&$client, WIRETAP_PUBLISH, &tap_target, &frame);
+        }
+        if let Some(path) = client_tap {
+            $client.tap(&frame, &path);
+        }
+        // throttled subscribers are counted as well
+        let routed = subs.len();
+        if let Some(ref mut t) = frame_trace {
+            t.matched(routed);
+        }
+        let mut pressure = 0;
+        for sub in subs {
+            let throttle = if sub.throttled.load(atomic::Ordering::SeqCst) {
+                // the topic is taken from the frame, as the buffer is moved
+                sub.throttle(frame.topic().unwrap_or_default(), &frame)
+            } else {
+                ThrottleResult::Deliver
+            };
+            if let Some(ref mut t) = frame_trace {
+                match throttle {
+                    ThrottleResult::Deliver => {}
+                    ThrottleResult::Deferred => t.push("deferred", None, Some(&sub.name)),
+                    ThrottleResult::Skipped => {
+                        t.push("dropped", Some("throttled".to_owned()), Some(&sub.name));
+                    }
+                }
             }
+            if throttle == ThrottleResult::Skipped {
+                continue;
+            }
+            $db.w_frames.fetch_add(1, atomic::Ordering::SeqCst);
+            $db.w_bytes.fetch_add($len, atomic::Ordering::SeqCst);
+            sub.w_frames.fetch_add(1, atomic::Ordering::SeqCst);
+            sub.w_bytes.fetch_add($len, atomic::Ordering::SeqCst);
+            if throttle == ThrottleResult::Deliver {
+                let result = safe_send_frame!($db, sub, frame.clone(), $timeout);
+                if let Some(ref mut t) = frame_trace {
+                    t.result(&sub.name, &result);
+                }
+            }
+            pressure = pressure.max(sub.queue_fill());
+            $db.charge_fanout(&$client).await;
         }
+        $client.pressure.store(pressure, atomic::Ordering::SeqCst);
+        if let Some(t) = frame_trace {
+            $db.publish_trace(t);
+        }
+        routed
     }};
 }
 
@@ -210,59 +669,53 @@ pub struct Client {
 
 #[async_trait]
 impl AsyncClient for Client {
-    /// # Panics
-    ///
-    /// Will panic if the mutex is poisoned
+    #[inline]
     async fn subscribe(&mut self, topic: &str, qos: QoS) -> Result<OpConfirm, Error> {
-        if self
-            .db
-            .subscriptions
-            .write()
-            .unwrap()
-            .subscribe(topic, &self.client)
-        {
-            make_confirm_channel!(qos)
-        } else {
-            Err(Error::not_registered())
-        }
+        self.subscribe_bulk(&[topic], qos).await
     }
-    /// # Panics
-    ///
-    /// Will panic if the mutex is poisoned
     async fn subscribe_bulk(&mut self, topics: &[&str], qos: QoS) -> Result<OpConfirm, Error> {
-        let mut db = self.db.subscriptions.write().unwrap();
-        for topic in topics {
-            if !db.subscribe(topic, &self.client) {
-                return Err(Error::not_registered());
+        #[allow(unused_variables)]
+        let demand = {
+            let mut db = self.db.subscriptions.write();
+            #[cfg(feature = "rpc")]
+            let demand = BrokerDb::demand_idle(&db, topics);
+            #[cfg(not(feature = "rpc"))]
+            let demand: Vec<String> = Vec::new();
+            for topic in topics {
+                if !db.subscribe(topic, &self.client) {
+                    return Err(Error::not_registered());
+                }
             }
-        }
+            demand
+        };
+        #[cfg(feature = "rpc")]
+        self.db.announce_demand("active", &demand).await;
         make_confirm_channel!(qos)
     }
-    /// # Panics
-    ///
-    /// Will panic if the mutex is poisoned
+    #[inline]
     async fn unsubscribe(&mut self, topic: &str, qos: QoS) -> Result<OpConfirm, Error> {
-        if self
-            .db
-            .subscriptions
-            .write()
-            .unwrap()
-            .unsubscribe(topic, &self.client)
-        {
-            make_confirm_channel!(qos)
-        } else {
-            Err(Error::not_registered())
-        }
+        self.unsubscribe_bulk(&[topic], qos).await
     }
-    /// # Panics
-    ///
-    /// Will panic if the mutex is poisoned
     async fn unsubscribe_bulk(&mut self, topics: &[&str], qos: QoS) -> Result<OpConfirm, Error> {
-        let mut db = self.db.subscriptions.write().unwrap();
-        for topic in topics {
-            if !db.unsubscribe(topic, &self.client) {
-                return Err(Error::not_registered());
+        #[allow(unused_variables)]
+        let demand = {
+            let mut db = self.db.subscriptions.write();
+            #[cfg(feature = "rpc")]
+            let demand = BrokerDb::demand_subscribed(&db, &self.client, topics);
+            #[cfg(not(feature = "rpc"))]
+            let demand: Vec<String> = Vec::new();
+            for topic in topics {
+                if !self.db.unsubscribe_topic(&mut db, topic, &self.client) {
+                    return Err(Error::not_registered());
+                }
+                self.client.remove_throttle(topic);
             }
+            demand
+        };
+        #[cfg(feature = "rpc")]
+        {
+            let demand = BrokerDb::demand_left(&self.db.subscriptions.read(), demand);
+            self.db.announce_demand("idle", &demand).await;
         }
         make_confirm_channel!(qos)
     }
@@ -273,19 +726,30 @@ impl AsyncClient for Client {
         payload: Cow<'async_trait>,
         qos: QoS,
     ) -> Result<OpConfirm, Error> {
+        if self.buffer_transaction(FrameOp::Message, target, None, payload.as_slice(), qos)? {
+            return make_confirm_channel!(qos);
+        }
         let len = payload.len() as u64;
+        let (delivery, confirm) = self.register_delivery(target, qos)?;
+        let buf = payload.to_vec();
         send!(
             self.db,
             self.client,
             target,
             None,
-            payload.to_vec(),
+            buf,
             0,
             len,
             qos.is_realtime(),
-            self.get_timeout()
-        )?;
-        make_confirm_channel!(qos)
+            self.get_timeout(),
+            delivery
+        )
+        .map_err(|e| self.cancel_delivery(delivery, e))?;
+        if confirm.is_some() {
+            Ok(confirm)
+        } else {
+            make_confirm_channel!(qos)
+        }
     }
     #[inline]
     async fn zc_send(
@@ -295,19 +759,36 @@ impl AsyncClient for Client {
         payload: Cow<'async_trait>,
         qos: QoS,
     ) -> Result<OpConfirm, Error> {
+        if self.buffer_transaction(
+            FrameOp::Message,
+            target,
+            Some(header.as_slice()),
+            payload.as_slice(),
+            qos,
+        )? {
+            return make_confirm_channel!(qos);
+        }
         let len = (payload.len() + header.len()) as u64;
+        let (delivery, confirm) = self.register_delivery(target, qos)?;
+        let buf = payload.to_vec();
         send!(
             self.db,
             self.client,
             target,
             Some(header.to_vec()),
-            payload.to_vec(),
+            buf,
             0,
             len,
             qos.is_realtime(),
-            self.get_timeout()
-        )?;
-        make_confirm_channel!(qos)
+            self.get_timeout(),
+            delivery
+        )
+        .map_err(|e| self.cancel_delivery(delivery, e))?;
+        if confirm.is_some() {
+            Ok(confirm)
+        } else {
+            make_confirm_channel!(qos)
+        }
     }
     #[inline]
     async fn send_broadcast(
@@ -316,6 +797,9 @@ impl AsyncClient for Client {
         payload: Cow<'async_trait>,
         qos: QoS,
     ) -> Result<OpConfirm, Error> {
+        if self.buffer_transaction(FrameOp::Broadcast, target, None, payload.as_slice(), qos)? {
+            return make_confirm_channel!(qos);
+        }
         let len = payload.len() as u64;
         send_broadcast!(
             self.db,
@@ -330,6 +814,92 @@ impl AsyncClient for Client {
         );
         make_confirm_channel!(qos)
     }
+    async fn send_broadcast_mandatory(
+        &mut self,
+        target: &str,
+        payload: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error> {
+        if self.buffer_transaction(
+            FrameOp::BroadcastMandatory,
+            target,
+            None,
+            payload.as_slice(),
+            qos,
+        )? {
+            return make_confirm_channel!(qos);
+        }
+        let len = payload.len() as u64;
+        let routed = send_broadcast!(
+            self.db,
+            self.client,
+            target,
+            None,
+            payload.to_vec(),
+            0,
+            len,
+            qos.is_realtime(),
+            self.get_timeout()
+        );
+        if routed == 0 && qos.needs_ack() {
+            return Err(Error::unrouted());
+        }
+        make_confirm_channel!(qos)
+    }
+    async fn send_group(
+        &mut self,
+        group: &str,
+        payload: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error> {
+        if self.buffer_transaction(FrameOp::GroupMessage, group, None, payload.as_slice(), qos)? {
+            return make_confirm_channel!(qos);
+        }
+        let len = payload.len() as u64;
+        send_group!(
+            self.db,
+            self.client,
+            group,
+            false,
+            None,
+            payload.to_vec(),
+            0,
+            len,
+            qos.is_realtime(),
+            self.get_timeout()
+        )?;
+        make_confirm_channel!(qos)
+    }
+    async fn send_group_broadcast(
+        &mut self,
+        group: &str,
+        payload: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error> {
+        if self.buffer_transaction(
+            FrameOp::GroupBroadcast,
+            group,
+            None,
+            payload.as_slice(),
+            qos,
+        )? {
+            return make_confirm_channel!(qos);
+        }
+        let len = payload.len() as u64;
+        send_group!(
+            self.db,
+            self.client,
+            group,
+            true,
+            None,
+            payload.to_vec(),
+            0,
+            len,
+            qos.is_realtime(),
+            self.get_timeout()
+        )?;
+        make_confirm_channel!(qos)
+    }
     #[inline]
     async fn publish(
         &mut self,
@@ -337,6 +907,9 @@ impl AsyncClient for Client {
         payload: Cow<'async_trait>,
         qos: QoS,
     ) -> Result<OpConfirm, Error> {
+        if self.buffer_transaction(FrameOp::PublishTopic, topic, None, payload.as_slice(), qos)? {
+            return make_confirm_channel!(qos);
+        }
         let len = payload.len() as u64;
         publish!(
             self.db,
@@ -351,33 +924,175 @@ impl AsyncClient for Client {
         );
         make_confirm_channel!(qos)
     }
-    #[inline]
-    fn take_event_channel(&mut self) -> Option<EventChannel> {
-        self.rx.take()
-    }
-    #[inline]
-    async fn ping(&mut self) -> Result<(), Error> {
-        Ok(())
-    }
-    #[inline]
-    fn is_connected(&self) -> bool {
-        true
-    }
-    #[inline]
-    fn get_timeout(&self) -> Option<Duration> {
-        None
-    }
-    #[inline]
-    fn get_connected_beacon(&self) -> Option<Arc<atomic::AtomicBool>> {
-        None
-    }
-    #[inline]
+    async fn publish_mandatory(
+        &mut self,
+        topic: &str,
+        payload: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error> {
+        if self.buffer_transaction(
+            FrameOp::PublishTopicMandatory,
+            topic,
+            None,
+            payload.as_slice(),
+            qos,
+        )? {
+            return make_confirm_channel!(qos);
+        }
+        let len = payload.len() as u64;
+        let routed = publish!(
+            self.db,
+            self.client,
+            topic,
+            None,
+            payload.to_vec(),
+            0,
+            len,
+            qos.is_realtime(),
+            self.get_timeout()
+        );
+        if routed == 0 && qos.needs_ack() {
+            return Err(Error::unrouted());
+        }
+        make_confirm_channel!(qos)
+    }
+    #[inline]
+    fn take_event_channel(&mut self) -> Option<EventChannel> {
+        self.rx.take()
+    }
+    #[inline]
+    async fn ping(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+    #[inline]
+    fn is_connected(&self) -> bool {
+        true
+    }
+    #[inline]
+    fn get_timeout(&self) -> Option<Duration> {
+        None
+    }
+    #[inline]
+    fn get_connected_beacon(&self) -> Option<Arc<atomic::AtomicBool>> {
+        None
+    }
+    #[inline]
     fn get_name(&self) -> &str {
         self.name.as_str()
     }
+    async fn confirm(&mut self, frame: &Frame) -> Result<(), Error> {
+        self.resolve_delivery(frame, RESPONSE_OK)
+    }
+    async fn reject(&mut self, frame: &Frame, kind: ErrorKind) -> Result<(), Error> {
+        self.resolve_delivery(frame, kind as u8)
+    }
+    async fn trace(&mut self, id: &str) -> Result<(), Error> {
+        check_trace_id(id)?;
+        self.client.trace.lock().replace(id.to_owned());
+        Ok(())
+    }
+    async fn set_health(&mut self, status: &str, load: u8) -> Result<(), Error> {
+        health_payload(status, load)?;
+        self.client.health.lock().replace(ClientHealth {
+            status: status.to_owned(),
+            load,
+            t: now_ns(),
+        });
+        Ok(())
+    }
+    async fn begin_transaction(&mut self, qos: QoS) -> Result<OpConfirm, Error> {
+        {
+            let mut transaction = self.client.transaction.lock();
+            if transaction.is_some() {
+                return Err(Error::busy("the transaction is already open"));
+            }
+            transaction.replace(Transaction::default());
+        }
+        make_confirm_channel!(qos)
+    }
+    async fn commit_transaction(&mut self, qos: QoS) -> Result<OpConfirm, Error> {
+        let transaction = self
+            .client
+            .transaction
+            .lock()
+            .take()
+            .ok_or_else(|| Error::data("no transaction open"))?;
+        self.db
+            .commit_transaction(&self.client, transaction, self.get_timeout())
+            .await?;
+        make_confirm_channel!(qos)
+    }
+    async fn abort_transaction(&mut self, qos: QoS) -> Result<OpConfirm, Error> {
+        if self.client.transaction.lock().take().is_none() {
+            return Err(Error::data("no transaction open"));
+        }
+        make_confirm_channel!(qos)
+    }
 }
 
 impl Client {
+    /// Buffers the frame if the client has an open transaction, returns false if it has not
+    fn buffer_transaction(
+        &self,
+        op: FrameOp,
+        target: &str,
+        header: Option<&[u8]>,
+        payload: &[u8],
+        qos: QoS,
+    ) -> Result<bool, Error> {
+        let mut transaction = self.client.transaction.lock();
+        let Some(ref mut transaction) = *transaction else {
+            return Ok(false);
+        };
+        if op == FrameOp::Message && qos.delivery_mode() != DeliveryMode::Queued {
+            return Err(transaction.fail(Error::not_supported(
+                "delivery modes are not supported in transactions",
+            )));
+        }
+        transaction.push(TransactionFrame {
+            op,
+            target: target.to_owned(),
+            header: header.map(<[u8]>::to_vec),
+            buf: payload.to_vec(),
+            payload_pos: 0,
+            realtime: qos.is_realtime(),
+        })?;
+        Ok(true)
+    }
+    /// Registers a pending delivery for messages, sent with QoS::Delivered or QoS::Consumed
+    fn register_delivery(
+        &self,
+        target: &str,
+        qos: QoS,
+    ) -> Result<(Option<(u32, DeliveryMode)>, OpConfirm), Error> {
+        let mode = qos.delivery_mode();
+        if self.db.delivery_tracked(target, mode)? {
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            let id = self
+                .db
+                .delivery_register(DeliverySender::Internal(tx), target);
+            Ok((Some((id, mode)), Some(rx)))
+        } else {
+            Ok((None, None))
+        }
+    }
+    #[inline]
+    fn cancel_delivery(&self, delivery: Option<(u32, DeliveryMode)>, e: Error) -> Error {
+        if let Some((id, _)) = delivery {
+            self.db.delivery_cancel(id);
+        }
+        e
+    }
+    fn resolve_delivery(&self, frame: &Frame, code: u8) -> Result<(), Error> {
+        let id = frame
+            .delivery_id()
+            .ok_or_else(|| Error::data("the frame has no delivery id"))?;
+        if self.db.delivery_resolve(id, Some(&self.client.name), code) {
+            Ok(())
+        } else {
+            Err(Error::data("the delivery is not found or has been expired"))
+        }
+    }
     /// When an internal client is dropped, it is automatically dropped from the broker db, but no
     /// announce is sent. It is better to manually call "unregister" method before.
     #[inline]
@@ -385,7 +1100,24 @@ impl Client {
         self.client
             .registered
             .store(false, atomic::Ordering::SeqCst);
-        self.db.unregister_client(&self.client).await;
+        self.db
+            .unregister_client(&self.client, DisconnectReason::Unregistered)
+            .await;
+    }
+    /// The max queue fill (percents) of subscribers, reached by the last publication. Can be
+    /// used by producers to adapt the publishing rate
+    #[inline]
+    pub fn pressure(&self) -> u8 {
+        self.client.pressure.load(atomic::Ordering::SeqCst)
+    }
+    /// Sets the delivery throttle for the subscribed topic mask (None - deliver all frames)
+    #[inline]
+    pub fn set_throttle(
+        &self,
+        mask: &str,
+        throttle: Option<DeliveryThrottle>,
+    ) -> Result<(), Error> {
+        self.db.set_throttle(&self.client, mask, throttle)
     }
 }
 
@@ -397,30 +1129,12 @@ impl Drop for Client {
     }
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-enum ElbusClientKind {
-    Internal,
-    LocalIpc,
-    Tcp,
-}
-
-impl ElbusClientKind {
-    #[allow(dead_code)]
-    fn as_str(&self) -> &str {
-        match self {
-            ElbusClientKind::Internal => "internal",
-            ElbusClientKind::LocalIpc => "local_ipc",
-            ElbusClientKind::Tcp => "tcp",
-        }
-    }
-}
-
 #[allow(dead_code)]
 #[derive(Debug)]
 struct ElbusClient {
     name: String,
     primary_name: String,
-    kind: ElbusClientKind,
+    kind: ClientKind,
     source: Option<String>,
     port: Option<String>,
     disconnect_trig: triggered::Trigger,
@@ -430,8 +1144,58 @@ struct ElbusClient {
     r_bytes: atomic::AtomicU64,
     w_frames: atomic::AtomicU64,
     w_bytes: atomic::AtomicU64,
+    // frames, dropped by the writer as they have been queued past the deadline
+    w_expired: atomic::AtomicU64,
+    // frames, taken by the writer from the queue, checked by the watchdog
+    w_taken: atomic::AtomicU64,
     primary: bool,
-    secondaries: std::sync::Mutex<HashSet<String>>,
+    secondaries: parking_lot::Mutex<HashSet<String>>,
+    groups: Vec<String>,
+    identity: Arc<ClientIdentity>,
+    throttles: parking_lot::Mutex<Vec<SubscriptionThrottle>>,
+    throttled: atomic::AtomicBool,
+    // batching of outgoing frames, enabled by the client (0 - disabled)
+    batch_size: atomic::AtomicU32,
+    batch_delay: atomic::AtomicU32,
+    // broker receive timestamps of frames, requested by the client
+    timestamps: atomic::AtomicBool,
+    // no new frames are queued, the client is disconnected after its queue is flushed
+    draining: atomic::AtomicBool,
+    // admins, which receive copies of the client frames
+    taps: parking_lot::Mutex<Vec<ClientTap>>,
+    tapped: atomic::AtomicBool,
+    // the max queue fill (percents) of subscribers, reached by the last publication
+    pressure: atomic::AtomicU8,
+    // the pressure is reported in publication acks, requested by the client
+    pressure_reports: atomic::AtomicBool,
+    // set by the client in the hello extensions
+    metadata: BTreeMap<String, String>,
+    will: Option<ClientWill>,
+    // frames, rejected by the reader
+    protocol_errors: ProtocolErrorCounters,
+    // delivery modes of messages, enabled by the client
+    delivery: atomic::AtomicBool,
+    // the trace id of the next routed frame, set by the client
+    trace: parking_lot::Mutex<Option<String>>,
+    // the latest health, sent by the client with heartbeats
+    health: parking_lot::Mutex<Option<ClientHealth>>,
+    // overrides the broker subscription limits, set by the authenticator
+    subscription_limits: Option<SubscriptionLimits>,
+    // routed frames, buffered until the transaction is committed
+    transaction: parking_lot::Mutex<Option<Transaction>>,
+    // recent message targets, the most recent first
+    send_cache: parking_lot::Mutex<Vec<(String, std::sync::Weak<ElbusClient>)>>,
+    // deliveries, routed by the client since its task has yielded last time
+    fanout_spent: atomic::AtomicUsize,
+    // calls to load-balanced targets, routed to the client and not replied yet
+    #[cfg(feature = "rpc")]
+    rpc_pending: atomic::AtomicUsize,
+}
+
+#[derive(Debug)]
+struct ClientTap {
+    admin: BrokerClient,
+    expires: Instant,
 }
 
 impl fmt::Display for ElbusClient {
@@ -441,16 +1205,26 @@ impl fmt::Display for ElbusClient {
 }
 
 impl ElbusClient {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         name: &str,
         primary_name: &str,
         queue_size: usize,
-        kind: ElbusClientKind,
+        kind: ClientKind,
         source: Option<String>,
         port: Option<String>,
+        acl: Option<Arc<dyn ClientAcl>>,
+        groups: Vec<String>,
     ) -> (Self, EventChannel, triggered::Listener) {
         let (tx, rx) = async_channel::bounded(queue_size);
         let primary = name == primary_name;
+        let identity = Arc::new(ClientIdentity::new(
+            name,
+            primary_name,
+            kind,
+            source.clone(),
+            acl,
+        ));
         let (disconnect_trig, disconnect_listener) = triggered::trigger();
         (
             Self {
@@ -466,13 +1240,197 @@ impl ElbusClient {
                 r_bytes: atomic::AtomicU64::new(0),
                 w_frames: atomic::AtomicU64::new(0),
                 w_bytes: atomic::AtomicU64::new(0),
+                w_expired: atomic::AtomicU64::new(0),
+                w_taken: atomic::AtomicU64::new(0),
                 primary,
                 secondaries: <_>::default(),
+                groups,
+                identity,
+                throttles: <_>::default(),
+                throttled: atomic::AtomicBool::new(false),
+                batch_size: atomic::AtomicU32::new(0),
+                batch_delay: atomic::AtomicU32::new(0),
+                timestamps: atomic::AtomicBool::new(false),
+                draining: atomic::AtomicBool::new(false),
+                taps: <_>::default(),
+                tapped: atomic::AtomicBool::new(false),
+                pressure: atomic::AtomicU8::new(0),
+                pressure_reports: atomic::AtomicBool::new(false),
+                metadata: BTreeMap::new(),
+                will: None,
+                protocol_errors: <_>::default(),
+                delivery: atomic::AtomicBool::new(false),
+                trace: <_>::default(),
+                health: <_>::default(),
+                subscription_limits: None,
+                transaction: <_>::default(),
+                fanout_spent: atomic::AtomicUsize::new(0),
+                send_cache: <_>::default(),
+                #[cfg(feature = "rpc")]
+                rpc_pending: atomic::AtomicUsize::new(0),
             },
             rx,
             disconnect_listener,
         )
     }
+    #[inline]
+    fn take_trace(&self) -> Option<FrameTrace> {
+        self.trace.lock().take().map(FrameTrace::new)
+    }
+    /// Internal clients and clients, connected via Unix sockets, run on the broker host
+    #[cfg(all(unix, feature = "rpc"))]
+    #[inline]
+    fn is_local(&self) -> bool {
+        self.kind == ClientKind::Internal
+            || (self.kind == ClientKind::LocalIpc && self.source.is_none())
+    }
+    /// Returns the cached message target, unregistered targets are evicted
+    fn cached_target(&self, target: &str) -> Option<BrokerClient> {
+        let mut cache = self.send_cache.lock();
+        let pos = cache.iter().position(|(name, _)| name == target)?;
+        let entry = cache.remove(pos);
+        let client = entry
+            .1
+            .upgrade()
+            .filter(|c| c.registered.load(atomic::Ordering::SeqCst))?;
+        cache.insert(0, entry);
+        Some(client)
+    }
+    fn cache_target(&self, target: &str, client: &BrokerClient) {
+        let mut cache = self.send_cache.lock();
+        cache.insert(0, (target.to_owned(), Arc::downgrade(client)));
+        cache.truncate(SEND_CACHE_SIZE);
+    }
+    /// The queue fill, percents
+    fn queue_fill(&self) -> u8 {
+        self.tx.capacity().map_or(0, |capacity| {
+            #[allow(clippy::cast_possible_truncation)]
+            let fill = (self.tx.len() * 100 / capacity.max(1)).min(100) as u8;
+            fill
+        })
+    }
+    fn set_throttle(&self, mask: &str, throttle: DeliveryThrottle, mut acl: AclMap) {
+        let mut throttles = self.throttles.lock();
+        throttles.retain(|t| t.mask != mask);
+        acl.insert(mask);
+        throttles.push(SubscriptionThrottle {
+            mask: mask.to_owned(),
+            acl,
+            throttle,
+            topics: <_>::default(),
+        });
+        self.throttled.store(true, atomic::Ordering::SeqCst);
+    }
+    /// Mirrors the client frames to the admin until the tap expires (zero duration - stops
+    /// mirroring)
+    #[cfg(feature = "rpc")]
+    fn set_tap(&self, admin: BrokerClient, duration: Duration) {
+        let mut taps = self.taps.lock();
+        taps.retain(|t| t.admin.name != admin.name);
+        if !duration.is_zero() {
+            taps.push(ClientTap {
+                admin,
+                expires: Instant::now() + duration,
+            });
+        }
+        self.tapped
+            .store(!taps.is_empty(), atomic::Ordering::SeqCst);
+    }
+    /// Sends copies of the frame to the tapping admins, expired taps and taps of disconnected
+    /// admins are removed. Copies are never blocking
+    fn tap(&self, frame: &Frame, path: &str) {
+        let now = Instant::now();
+        let mut taps = self.taps.lock();
+        taps.retain(|t| t.expires > now && !t.admin.tx.is_closed());
+        if taps.is_empty() {
+            self.tapped.store(false, atomic::Ordering::SeqCst);
+            return;
+        }
+        let header = frame.header().unwrap_or_default();
+        let mut buf = Vec::with_capacity(header.len() + frame.payload().len());
+        buf.extend_from_slice(header);
+        buf.extend_from_slice(frame.payload());
+        let copy = Arc::new(FrameData {
+            kind: FrameKind::Publish,
+            sender: frame.sender.clone(),
+            topic: Some(format!("{}{}/{}", WIRETAP_CLIENT, self.name, path)),
+            header: None,
+            buf,
+            payload_pos: 0,
+            realtime: false,
+            identity: frame.identity.clone(),
+            timestamp: frame.timestamp,
+            delivery: None,
+            memory: None,
+        });
+        for t in taps.iter() {
+            if t.admin.tx.try_send(copy.clone()).is_err() {
+                trace!("client tap frame dropped for {}", t.admin.name);
+            }
+        }
+    }
+    fn remove_throttle(&self, mask: &str) {
+        if self.throttled.load(atomic::Ordering::SeqCst) {
+            let mut throttles = self.throttles.lock();
+            throttles.retain(|t| t.mask != mask);
+            self.throttled
+                .store(!throttles.is_empty(), atomic::Ordering::SeqCst);
+        }
+    }
+    /// Decides if the publication is delivered now, later (the latest one, when the throttle
+    /// interval is over) or skipped
+    fn throttle(&self, topic: &str, frame: &Frame) -> ThrottleResult {
+        let mut throttles = self.throttles.lock();
+        let Some(t) = throttles.iter_mut().find(|t| t.acl.matches(topic)) else {
+            return ThrottleResult::Deliver;
+        };
+        let state = t.topics.entry(topic.to_owned()).or_default().clone();
+        let mut st = state.lock();
+        match t.throttle {
+            DeliveryThrottle::Sample(n) => {
+                let deliver = st.count % n == 0;
+                st.count = st.count.wrapping_add(1);
+                if deliver {
+                    ThrottleResult::Deliver
+                } else {
+                    ThrottleResult::Skipped
+                }
+            }
+            DeliveryThrottle::Interval(interval) => {
+                if st.pending.is_some() {
+                    // the pending frame is replaced, the latest wins
+                    st.pending.replace(frame.clone());
+                    return ThrottleResult::Skipped;
+                }
+                let now = Instant::now();
+                match st.last_sent {
+                    Some(last_sent) if now.duration_since(last_sent) < interval => {
+                        st.pending.replace(frame.clone());
+                        let deliver_at = time::Instant::from_std(last_sent + interval);
+                        let tx = self.tx.clone();
+                        let state = state.clone();
+                        tokio::spawn(async move {
+                            time::sleep_until(deliver_at).await;
+                            let frame = {
+                                let mut st = state.lock();
+                                st.last_sent.replace(Instant::now());
+                                st.pending.take()
+                            };
+                            if let Some(frame) = frame {
+                                // deferred frames are never blocking
+                                let _r = tx.try_send(frame);
+                            }
+                        });
+                        ThrottleResult::Deferred
+                    }
+                    _ => {
+                        st.last_sent.replace(now);
+                        ThrottleResult::Deliver
+                    }
+                }
+            }
+        }
+    }
 }
 
 impl PartialEq for ElbusClient {
@@ -489,6 +1447,99 @@ impl Hash for ElbusClient {
     }
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ProtocolError {
+    Malformed,
+    Oversized,
+    UnknownOp,
+    Utf8,
+}
+
+#[derive(Debug, Default)]
+struct ProtocolErrorCounters {
+    malformed: atomic::AtomicU64,
+    oversized: atomic::AtomicU64,
+    unknown_ops: atomic::AtomicU64,
+    utf8: atomic::AtomicU64,
+}
+
+impl ProtocolErrorCounters {
+    fn count(&self, error: ProtocolError) {
+        match error {
+            ProtocolError::Malformed => &self.malformed,
+            ProtocolError::Oversized => &self.oversized,
+            ProtocolError::UnknownOp => &self.unknown_ops,
+            ProtocolError::Utf8 => &self.utf8,
+        }
+        .fetch_add(1, atomic::Ordering::SeqCst);
+    }
+    fn get(&self) -> ProtocolErrors {
+        ProtocolErrors {
+            malformed: self.malformed.load(atomic::Ordering::SeqCst),
+            oversized: self.oversized.load(atomic::Ordering::SeqCst),
+            unknown_ops: self.unknown_ops.load(atomic::Ordering::SeqCst),
+            utf8: self.utf8.load(atomic::Ordering::SeqCst),
+        }
+    }
+}
+
+/// Why a client has been unregistered, reported in "unreg" events
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DisconnectReason {
+    /// the peer has closed the connection (read EOF)
+    Closed,
+    /// the connection can not be written, e.g. the peer has stopped reading
+    WriteClosed,
+    Timeout,
+    /// protocol or I/O error
+    Error,
+    QueueOverflow,
+    MemoryLimit,
+    /// force disconnected or drained by the broker
+    Kicked,
+    /// the client writer has made no progress, recycled by the watchdog
+    Stalled,
+    /// an internal client has been unregistered
+    Unregistered,
+}
+
+impl DisconnectReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DisconnectReason::Closed => "closed",
+            DisconnectReason::WriteClosed => "write_closed",
+            DisconnectReason::Timeout => "timeout",
+            DisconnectReason::Error => "error",
+            DisconnectReason::QueueOverflow => "queue_overflow",
+            DisconnectReason::MemoryLimit => "memory_limit",
+            DisconnectReason::Kicked => "kicked",
+            DisconnectReason::Stalled => "stalled",
+            DisconnectReason::Unregistered => "unregistered",
+        }
+    }
+    fn from_read_result(result: &Result<(), Error>) -> Self {
+        match result {
+            Ok(()) => DisconnectReason::Closed,
+            Err(e) if e.kind() == ErrorKind::Eof => DisconnectReason::Closed,
+            Err(e) if e.kind() == ErrorKind::Timeout => DisconnectReason::Timeout,
+            Err(_) => DisconnectReason::Error,
+        }
+    }
+    fn from_write_result(result: &Result<(), Error>) -> Self {
+        match result {
+            Ok(()) => DisconnectReason::Kicked,
+            Err(e) if e.kind() == ErrorKind::Timeout => DisconnectReason::Timeout,
+            Err(_) => DisconnectReason::WriteClosed,
+        }
+    }
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
 #[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
 #[derive(Eq, PartialEq, Clone)]
 #[allow(clippy::module_name_repetitions)]
@@ -496,6 +1547,18 @@ pub struct BrokerEvent<'a> {
     s: &'a str,
     #[cfg_attr(feature = "rpc", serde(skip_serializing_if = "Option::is_none"))]
     d: Option<&'a str>,
+    // the client, the event is related to
+    #[cfg_attr(
+        feature = "rpc",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    c: Option<&'a str>,
+    // the disconnect reason of "unreg" events
+    #[cfg_attr(
+        feature = "rpc",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    r: Option<&'a str>,
     t: u64,
     #[cfg_attr(feature = "rpc", serde(skip))]
     topic: &'a str,
@@ -503,125 +1566,1755 @@ pub struct BrokerEvent<'a> {
 
 impl<'a> BrokerEvent<'a> {
     pub fn new(s: &'a str, d: Option<&'a str>, topic: &'a str) -> Self {
-        Self { s, d, t: 0, topic }
-    }
-    pub fn shutdown() -> Self {
         Self {
-            s: "shutdown",
-            d: None,
+            s,
+            d,
+            c: None,
+            r: None,
             t: 0,
-            topic: BROKER_WARN_TOPIC,
+            topic,
         }
     }
+    pub fn shutdown() -> Self {
+        Self::new("shutdown", None, BROKER_WARN_TOPIC)
+    }
     pub fn reg(name: &'a str) -> Self {
+        Self::new("reg", Some(name), BROKER_INFO_TOPIC)
+    }
+    pub fn unreg(name: &'a str) -> Self {
+        Self::new("unreg", Some(name), BROKER_INFO_TOPIC)
+    }
+    pub fn unreg_with_reason(name: &'a str, reason: DisconnectReason) -> Self {
         Self {
-            s: "reg",
-            d: Some(name),
-            t: 0,
-            topic: BROKER_INFO_TOPIC,
+            r: Some(reason.as_str()),
+            ..Self::unreg(name)
         }
     }
-    pub fn unreg(name: &'a str) -> Self {
+    /// Subscription change of a client, the subject is "subscribe" or "unsubscribe", the data is
+    /// the topic mask
+    pub fn subscription(subject: &'a str, client: &'a str, mask: &'a str) -> Self {
         Self {
-            s: "unreg",
-            d: Some(name),
-            t: 0,
-            topic: BROKER_INFO_TOPIC,
+            c: Some(client),
+            ..Self::new(subject, Some(mask), BROKER_SUBSCRIPTION_TOPIC)
         }
     }
+    /// Demand change of a topic mask, the subject is "active" (the first subscriber has appeared)
+    /// or "idle" (the last subscriber has left), the data is the topic mask
+    pub fn demand(subject: &'a str, mask: &'a str, topic: &'a str) -> Self {
+        Self::new(subject, Some(mask), topic)
+    }
     pub fn subject(&self) -> &str {
         self.s
     }
     pub fn data(&self) -> Option<&str> {
         self.d
     }
+    /// The client, the event is related to
+    pub fn client(&self) -> Option<&str> {
+        self.c
+    }
+    /// The disconnect reason of "unreg" events
+    pub fn reason(&self) -> Option<&str> {
+        self.r
+    }
     pub fn time(&self) -> u64 {
         self.t
     }
 }
 
-struct BrokerDb {
-    clients: RwLock<HashMap<String, BrokerClient>>,
-    broadcasts: RwLock<BroadcastMap<BrokerClient>>,
-    subscriptions: RwLock<SubMap<BrokerClient>>,
-    #[cfg(feature = "rpc")]
-    rpc_client: Arc<Mutex<Option<RpcClient>>>,
-    r_frames: atomic::AtomicU64,
-    r_bytes: atomic::AtomicU64,
-    w_frames: atomic::AtomicU64,
-    w_bytes: atomic::AtomicU64,
-    startup_time: Instant,
+/// Routing events of a traced frame, published at once when the frame is routed
+struct FrameTrace {
+    id: String,
+    // subject, data, client
+    events: Vec<(&'static str, Option<String>, Option<String>)>,
 }
 
-impl Default for BrokerDb {
-    fn default() -> Self {
+impl FrameTrace {
+    fn new(id: String) -> Self {
         Self {
-            clients: <_>::default(),
-            broadcasts: RwLock::new(
-                BroadcastMap::new()
-                    .separator('.')
-                    .match_any("?")
-                    .wildcard("*"),
-            ),
-            subscriptions: RwLock::new(SubMap::new().separator('/').match_any("+").wildcard("#")),
-            #[cfg(feature = "rpc")]
-            rpc_client: <_>::default(),
-            r_frames: atomic::AtomicU64::new(0),
-            r_bytes: atomic::AtomicU64::new(0),
-            w_frames: atomic::AtomicU64::new(0),
-            w_bytes: atomic::AtomicU64::new(0),
-            startup_time: Instant::now(),
+            id,
+            events: Vec::new(),
+        }
+    }
+    #[inline]
+    fn push(&mut self, subject: &'static str, data: Option<String>, client: Option<&str>) {
+        self.events
+            .push((subject, data, client.map(ToOwned::to_owned)));
+    }
+    /// The number of clients, matched the target
+    #[inline]
+    fn matched(&mut self, clients: usize) {
+        self.push("matched", Some(clients.to_string()), None);
+    }
+    #[inline]
+    fn result(&mut self, client: &str, result: &Result<(), Error>) {
+        match result {
+            Ok(()) => self.push("queued", None, Some(client)),
+            Err(e) => self.push("dropped", Some(e.to_string()), Some(client)),
         }
     }
 }
 
-impl BrokerDb {
-    fn stats(&self) -> BrokerStats {
-        BrokerStats {
-            uptime: self.startup_time.elapsed().as_secs(),
-            r_frames: self.r_frames.load(atomic::Ordering::SeqCst),
-            r_bytes: self.r_bytes.load(atomic::Ordering::SeqCst),
-            w_frames: self.w_frames.load(atomic::Ordering::SeqCst),
-            w_bytes: self.w_bytes.load(atomic::Ordering::SeqCst),
+/// Routed frames of a client transaction, buffered until the transaction is committed
+#[derive(Debug, Default)]
+struct Transaction {
+    frames: Vec<TransactionFrame>,
+    size: usize,
+    // set when a frame has not been accepted, the commit fails with the error kind
+    failed: Option<ErrorKind>,
+}
+
+#[derive(Debug)]
+struct TransactionFrame {
+    op: FrameOp,
+    target: String,
+    header: Option<Vec<u8>>,
+    buf: Vec<u8>,
+    payload_pos: usize,
+    realtime: bool,
+}
+
+impl Transaction {
+    fn push(&mut self, frame: TransactionFrame) -> Result<(), Error> {
+        let size = frame.buf.len() + frame.header.as_ref().map_or(0, Vec::len);
+        if self.frames.len() >= TRANSACTION_MAX_FRAMES || self.size + size > TRANSACTION_MAX_SIZE {
+            return Err(self.fail(Error::limit("transaction limits exceeded")));
         }
+        self.size += size;
+        self.frames.push(frame);
+        Ok(())
     }
+    /// Marks the transaction failed, so it can not be committed
+    fn fail(&mut self, e: Error) -> Error {
+        self.failed.get_or_insert(e.kind());
+        e
+    }
+}
+
+/// Wiretap subscription of a monitoring client
+struct Wiretap {
+    client: BrokerClient,
+    clients: Option<AclMap>,
+    topics: Option<AclMap>,
+}
+
+impl Wiretap {
     #[cfg(feature = "rpc")]
-    #[inline]
-    async fn announce(&self, mut event: BrokerEvent<'_>) -> Result<(), Error> {
-        if let Some(rpc_client) = self.rpc_client.lock().await.as_ref() {
-            event.t = now_ns();
-            rpc_client
-                .client()
-                .lock()
-                .await
-                .publish(
-                    event.topic,
-                    rmp_serde::to_vec_named(&event).map_err(Error::data)?.into(),
-                    QoS::No,
-                )
-                .await?;
+    fn new(
+        client: BrokerClient,
+        client_mask: Option<(&str, &MaskFormat)>,
+        topic_mask: Option<(&str, &MaskFormat)>,
+    ) -> Self {
+        Self {
+            client,
+            clients: client_mask.map(|(mask, format)| {
+                let mut acl = format.acl_map();
+                acl.insert(mask);
+                acl
+            }),
+            topics: topic_mask.map(|(mask, format)| {
+                let mut acl = format.acl_map();
+                acl.insert(mask);
+                acl
+            }),
         }
-        Ok(())
     }
-    #[inline]
-    async fn register_client(&self, client: Arc<ElbusClient>) -> Result<(), Error> {
-        #[cfg(feature = "rpc")]
-        // copy name for the announce
-        let name = client.name.clone();
-        let primary = client.primary;
-        self.insert_client(client)?;
-        #[cfg(feature = "rpc")]
-        if primary {
-            if let Err(e) = self.announce(BrokerEvent::reg(&name)).await {
-                error!("{}", e);
+    /// The client mask matches either the sender or the target, the topic mask matches
+    /// publications only
+    fn matches(&self, sender: &str, kind: &str, target: &str) -> bool {
+        if let Some(ref clients) = self.clients {
+            if !clients.matches(sender) && (kind == WIRETAP_PUBLISH || !clients.matches(target)) {
+                return false;
             }
         }
-        Ok(())
+        if let Some(ref topics) = self.topics {
+            if kind != WIRETAP_PUBLISH || !topics.matches(target) {
+                return false;
+            }
+        }
+        true
     }
-    fn insert_client(&self, client: Arc<ElbusClient>) -> Result<(), Error> {
-        let mut clients = self.clients.write().unwrap();
-        let primary_client = if client.primary {
-            None
+}
+
+/// What to do with frames for external clients when the broker memory limit is exceeded
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum MemoryOverflowPolicy {
+    /// frames are dropped (not delivered)
+    #[default]
+    Drop,
+    /// frames are dropped, the external client with the largest queue is force unregistered, the
+    /// same as on client queue overflow
+    Disconnect,
+}
+
+impl FromStr for MemoryOverflowPolicy {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(MemoryOverflowPolicy::Drop),
+            "disconnect" => Ok(MemoryOverflowPolicy::Disconnect),
+            _ => Err(Error::data(format!(
+                "invalid memory overflow policy: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for MemoryOverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                MemoryOverflowPolicy::Drop => "drop",
+                MemoryOverflowPolicy::Disconnect => "disconnect",
+            }
+        )
+    }
+}
+
+/// Decimated delivery of publications for dashboards and other consumers, which do not need
+/// every frame of high-rate topics. Applied per subscribed mask and per topic
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DeliveryThrottle {
+    /// deliver 1-in-N frames
+    Sample(u64),
+    /// deliver at most one frame per interval, the latest one wins
+    Interval(Duration),
+}
+
+#[derive(Debug)]
+struct SubscriptionThrottle {
+    mask: String,
+    acl: AclMap,
+    throttle: DeliveryThrottle,
+    topics: HashMap<String, Arc<parking_lot::Mutex<TopicThrottle>>>,
+}
+
+#[derive(Debug, Default)]
+struct TopicThrottle {
+    count: u64,
+    last_sent: Option<Instant>,
+    pending: Option<Frame>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ThrottleResult {
+    Deliver,
+    Deferred,
+    Skipped,
+}
+
+/// Per-client subscription caps, checked when external clients subscribe (0 - unlimited). The
+/// default subscription to .broker/warn is not counted
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct SubscriptionLimits {
+    /// max topics a client can be subscribed to
+    pub max_subscriptions: usize,
+    /// max subscriptions with wildcard ("+" or "#" by default) segments
+    pub max_wildcard_subscriptions: usize,
+    /// max number of topic segments
+    pub max_topic_depth: usize,
+    /// max topic length in bytes
+    pub max_topic_length: usize,
+}
+
+impl SubscriptionLimits {
+    #[inline]
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Client name check, performed when external clients connect. Secondary clients are checked by
+/// their primary names. Closures Fn(&str) -> Result<(), Error> can be used as validators as well
+pub trait ClientNameValidator: Send + Sync {
+    fn validate(&self, name: &str) -> Result<(), Error>;
+}
+
+impl<F> ClientNameValidator for F
+where
+    F: Fn(&str) -> Result<(), Error> + Send + Sync,
+{
+    #[inline]
+    fn validate(&self, name: &str) -> Result<(), Error> {
+        self(name)
+    }
+}
+
+/// Token authentication of external clients, the token is presented by the client after its name
+/// in the hello (ipc::Config::token). Secondary clients are checked by their primary names.
+/// Closures Fn(&str, &str) -> bool (the client name and the token) can be used as verifiers as
+/// well
+pub trait TokenVerifier: Send + Sync {
+    fn verify(&self, client: &str, token: &str) -> bool;
+}
+
+impl<F> TokenVerifier for F
+where
+    F: Fn(&str, &str) -> bool + Send + Sync,
+{
+    #[inline]
+    fn verify(&self, client: &str, token: &str) -> bool {
+        self(client, token)
+    }
+}
+
+/// A list of tokens, valid for any client. Tokens are compared in constant time
+#[derive(Default, Clone)]
+pub struct TokenList {
+    tokens: Vec<String>,
+}
+
+impl fmt::Debug for TokenList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenList")
+            .field("tokens", &self.tokens.len())
+            .finish()
+    }
+}
+
+impl TokenList {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    #[inline]
+    pub fn token(mut self, token: &str) -> Self {
+        self.tokens.push(token.to_owned());
+        self
+    }
+    /// Loads tokens from a file, one per line, empty lines and lines starting with "#" are
+    /// skipped
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let data =
+            std::fs::read_to_string(path).map_err(|e| Error::io(format!("{}: {}", path, e)))?;
+        let tokens: Vec<String> = data
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(ToOwned::to_owned)
+            .collect();
+        if let Some(t) = tokens.iter().find(|t| t.len() > HELLO_TOKEN_MAX_LEN) {
+            return Err(Error::data(format!(
+                "{}: token is longer than {} bytes ({}...)",
+                path,
+                HELLO_TOKEN_MAX_LEN,
+                t.chars().take(4).collect::<String>()
+            )));
+        }
+        Ok(Self { tokens })
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+}
+
+impl TokenVerifier for TokenList {
+    fn verify(&self, _client: &str, token: &str) -> bool {
+        // all tokens are checked, not to leak the position of the matching one
+        self.tokens.iter().fold(false, |found, t| {
+            constant_time_eq(t.as_bytes(), token.as_bytes()) | found
+        })
+    }
+}
+
+/// Connection of an external client, checked by the authenticator
+#[derive(Debug, Clone)]
+pub struct AuthRequest<'a> {
+    /// the primary client name (secondary clients are checked by their primary names)
+    pub name: &'a str,
+    pub kind: ClientKind,
+    /// the source address, None for local clients
+    pub source: Option<&'a str>,
+    /// the token, presented by the client in the hello (ipc::Config::token)
+    pub token: Option<&'a str>,
+}
+
+/// Per-client limits, set by the authenticator. Unset limits are taken from the listener and
+/// broker settings
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct ClientLimits {
+    pub queue_size: Option<usize>,
+    /// max size of frames, sent by the client
+    pub max_frame_size: Option<u32>,
+    pub subscriptions: Option<SubscriptionLimits>,
+}
+
+/// Authentication of external clients, performed when they connect, after the name is checked.
+/// If an error is returned, its code is sent to the client and the client is disconnected.
+/// Internal clients are not checked. Closures Fn(&AuthRequest) -> Result<ClientLimits, Error>
+/// can be used as authenticators as well
+pub trait Authenticator: Send + Sync {
+    fn authenticate(&self, request: &AuthRequest) -> Result<ClientLimits, Error>;
+}
+
+impl<F> Authenticator for F
+where
+    F: Fn(&AuthRequest) -> Result<ClientLimits, Error> + Send + Sync,
+{
+    #[inline]
+    fn authenticate(&self, request: &AuthRequest) -> Result<ClientLimits, Error> {
+        self(request)
+    }
+}
+
+/// Requires a token, accepted by the verifier
+struct TokenAuthenticator(Arc<dyn TokenVerifier>);
+
+impl Authenticator for TokenAuthenticator {
+    fn authenticate(&self, request: &AuthRequest) -> Result<ClientLimits, Error> {
+        let Some(token) = request.token else {
+            return Err(Error::access("no token"));
+        };
+        if self.0.verify(request.name, token) {
+            Ok(ClientLimits::default())
+        } else {
+            Err(Error::access("invalid token"))
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Naming convention rules for client names, e.g. "domain.service.instance" names are enforced
+/// with ClientNamePolicy::new().charset("-_").segments(3, 3)
+#[derive(Debug, Clone)]
+pub struct ClientNamePolicy {
+    // 0 - unlimited
+    max_length: usize,
+    // characters allowed besides ASCII letters, digits and the separator, None - any
+    charset: Option<String>,
+    separator: char,
+    // 0 - unlimited
+    min_segments: usize,
+    max_segments: usize,
+}
+
+impl Default for ClientNamePolicy {
+    fn default() -> Self {
+        Self {
+            max_length: 0,
+            charset: None,
+            separator: '.',
+            min_segments: 0,
+            max_segments: 0,
+        }
+    }
+}
+
+impl ClientNamePolicy {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Max name length in bytes
+    #[inline]
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+    /// Restricts names to ASCII letters, digits, the separator and the specified characters
+    #[inline]
+    pub fn charset(mut self, chars: &str) -> Self {
+        self.charset.replace(chars.to_owned());
+        self
+    }
+    /// Name segment separator (default: ".")
+    #[inline]
+    pub fn separator(mut self, separator: char) -> Self {
+        self.separator = separator;
+        self
+    }
+    /// Min and max number of name segments (0 - unlimited), empty segments are not allowed
+    #[inline]
+    pub fn segments(mut self, min: usize, max: usize) -> Self {
+        self.min_segments = min;
+        self.max_segments = max;
+        self
+    }
+}
+
+impl ClientNameValidator for ClientNamePolicy {
+    fn validate(&self, name: &str) -> Result<(), Error> {
+        if self.max_length > 0 && name.len() > self.max_length {
+            return Err(Error::data(format!(
+                "the name is longer than {} bytes",
+                self.max_length
+            )));
+        }
+        if let Some(ref charset) = self.charset {
+            if let Some(ch) = name.chars().find(|ch| {
+                !ch.is_ascii_alphanumeric() && *ch != self.separator && !charset.contains(*ch)
+            }) {
+                return Err(Error::data(format!("invalid character: {:?}", ch)));
+            }
+        }
+        if self.min_segments > 0 || self.max_segments > 0 {
+            let segments: Vec<&str> = name.split(self.separator).collect();
+            if segments.iter().any(|s| s.is_empty()) {
+                return Err(Error::data("empty name segment"));
+            }
+            if segments.len() < self.min_segments
+                || (self.max_segments > 0 && segments.len() > self.max_segments)
+            {
+                return Err(Error::data(format!(
+                    "invalid number of name segments: {}",
+                    segments.len()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Separator and wildcards of broadcast masks or topics, set on the broker construction. The
+/// defaults are "." / "?" / "*" for broadcasts and "/" / "+" / "#" for topics (MQTT-like). E.g.
+/// NATS-style topics are "." / "*" / ">"
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MaskFormat {
+    /// segment separator
+    pub separator: char,
+    /// matches any single segment
+    pub match_any: String,
+    /// matches all the remaining segments
+    pub wildcard: String,
+}
+
+impl MaskFormat {
+    #[inline]
+    pub fn new(separator: char, match_any: &str, wildcard: &str) -> Self {
+        Self {
+            separator,
+            match_any: match_any.to_owned(),
+            wildcard: wildcard.to_owned(),
+        }
+    }
+    /// The default format of broadcast masks
+    #[inline]
+    pub fn broadcast() -> Self {
+        Self::new('.', "?", "*")
+    }
+    /// The default format of topics
+    #[inline]
+    pub fn topic() -> Self {
+        Self::new('/', "+", "#")
+    }
+    fn validate(&self) -> Result<(), Error> {
+        if self.match_any.is_empty() || self.wildcard.is_empty() {
+            return Err(Error::data("wildcards can not be empty"));
+        }
+        if self.match_any == self.wildcard {
+            return Err(Error::data("match-any and wildcard must differ"));
+        }
+        if self.match_any.contains(self.separator) || self.wildcard.contains(self.separator) {
+            return Err(Error::data("wildcards can not contain the separator"));
+        }
+        Ok(())
+    }
+    #[inline]
+    pub(crate) fn acl_map(&self) -> AclMap {
+        AclMap::new()
+            .separator(self.separator)
+            .match_any(&self.match_any)
+            .wildcard(&self.wildcard)
+    }
+    #[inline]
+    fn is_wildcard_segment(&self, segment: &str) -> bool {
+        segment == self.wildcard || segment == self.match_any
+    }
+    #[inline]
+    fn is_wildcard_mask(&self, mask: &str) -> bool {
+        mask.split(self.separator)
+            .any(|segment| self.is_wildcard_segment(segment))
+    }
+}
+
+/// Parses "SEPARATOR,MATCH_ANY,WILDCARD", e.g. "/,+,#"
+impl FromStr for MaskFormat {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sp = s.split(',');
+        let (Some(separator), Some(match_any), Some(wildcard), None) =
+            (sp.next(), sp.next(), sp.next(), sp.next())
+        else {
+            return Err(Error::data(format!(
+                "invalid mask format: {}, SEPARATOR,MATCH_ANY,WILDCARD expected",
+                s
+            )));
+        };
+        let mut chars = separator.chars();
+        let (Some(separator), None) = (chars.next(), chars.next()) else {
+            return Err(Error::data(format!(
+                "the separator must be a single char: {}",
+                separator
+            )));
+        };
+        let format = Self::new(separator, match_any, wildcard);
+        format.validate()?;
+        Ok(format)
+    }
+}
+
+/// Frame memory accounting, the queued bytes counter is decremented when the frame is dropped
+#[derive(Debug)]
+pub(crate) struct FrameMemory {
+    counter: Arc<atomic::AtomicU64>,
+    size: u64,
+    // routed frames are accounted right before they are queued
+    queued: Instant,
+}
+
+impl Drop for FrameMemory {
+    #[inline]
+    fn drop(&mut self) {
+        self.counter.fetch_sub(self.size, atomic::Ordering::SeqCst);
+    }
+}
+
+struct BrokerDb {
+    broadcast_format: MaskFormat,
+    topic_format: MaskFormat,
+    clients: RwLock<HashMap<String, BrokerClient>>,
+    broadcasts: RwLock<BroadcastMap<BrokerClient>>,
+    subscriptions: RwLock<SubMap<BrokerClient>>,
+    groups: RwLock<HashMap<String, ClientGroup>>,
+    #[cfg(feature = "rpc")]
+    rpc_client: Arc<Mutex<Option<RpcClient>>>,
+    wiretaps: RwLock<HashMap<String, Wiretap>>,
+    wiretaps_active: atomic::AtomicBool,
+    listeners: RwLock<HashMap<String, ListenerState>>,
+    queued_bytes: Arc<atomic::AtomicU64>,
+    // 0 - unlimited
+    memory_limit: atomic::AtomicU64,
+    memory_overflow_policy: RwLock<MemoryOverflowPolicy>,
+    memory_pressure: atomic::AtomicBool,
+    subscription_limits: RwLock<SubscriptionLimits>,
+    frame_timestamps: atomic::AtomicBool,
+    // the queue size of new clients, reported in capabilities
+    queue_size: atomic::AtomicUsize,
+    // 0 - unlimited
+    fanout_budget: atomic::AtomicUsize,
+    client_name_validator: RwLock<Option<Arc<dyn ClientNameValidator>>>,
+    authenticator: RwLock<Option<Arc<dyn Authenticator>>>,
+    #[cfg(feature = "testing")]
+    chaos: Arc<Chaos>,
+    persistence: RwLock<Arc<dyn Persistence>>,
+    // client ACLs of spawned servers, by listener path
+    aaa_maps: RwLock<HashMap<String, AaaMap>>,
+    r_frames: atomic::AtomicU64,
+    r_bytes: atomic::AtomicU64,
+    w_frames: atomic::AtomicU64,
+    w_bytes: atomic::AtomicU64,
+    // frames, dropped by writers as they have been queued past the deadline
+    expired_frames: atomic::AtomicU64,
+    protocol_errors: ProtocolErrorCounters,
+    deliveries: parking_lot::Mutex<Deliveries>,
+    // commits of client transactions are serialized
+    transaction_commit: tokio::sync::Mutex<()>,
+    // topic namespace prefix - the owner client name
+    #[cfg(feature = "rpc")]
+    namespaces: RwLock<BTreeMap<String, String>>,
+    // registered services, by provider client and service names
+    #[cfg(feature = "rpc")]
+    services: RwLock<BTreeMap<(String, String), ServiceInfo>>,
+    // load-balanced RPC targets, by service names
+    #[cfg(feature = "rpc")]
+    rpc_targets: RwLock<HashMap<String, RpcTarget>>,
+    // pending calls to load-balanced targets, by caller names and call ids
+    #[cfg(feature = "rpc")]
+    rpc_calls: parking_lot::Mutex<HashMap<(String, u32), BrokerClient>>,
+    // the number of pending calls, replies are not inspected if there are none
+    #[cfg(feature = "rpc")]
+    rpc_calls_pending: atomic::AtomicUsize,
+    startup_time: Instant,
+}
+
+impl Default for BrokerDb {
+    fn default() -> Self {
+        Self::new(MaskFormat::broadcast(), MaskFormat::topic())
+    }
+}
+
+/// Members of a client group, in the order of joining
+#[derive(Default)]
+struct ClientGroup {
+    members: Vec<BrokerClient>,
+    // round-robin position
+    next: atomic::AtomicUsize,
+}
+
+/// Providers of a load-balanced RPC target, in the order of registering
+#[cfg(feature = "rpc")]
+struct RpcTarget {
+    balance: RpcBalance,
+    providers: Vec<BrokerClient>,
+    // round-robin position, the start of the least-pending search as well
+    next: atomic::AtomicUsize,
+    // session keys - providers, sessions are pinned to, and last use times
+    sessions: parking_lot::Mutex<HashMap<String, (BrokerClient, Instant)>>,
+}
+
+#[cfg(feature = "rpc")]
+impl RpcTarget {
+    /// Returns the next provider by the balance policy, draining providers are skipped
+    fn next_provider(&self) -> Option<BrokerClient> {
+        let len = self.providers.len();
+        let pos = self.next.fetch_add(1, atomic::Ordering::SeqCst) % len.max(1);
+        let mut available = (0..len)
+            .map(|i| &self.providers[(pos + i) % len])
+            .filter(|c| !c.draining.load(atomic::Ordering::SeqCst));
+        let provider = match self.balance {
+            RpcBalance::RoundRobin => available.next(),
+            // the rotating start spreads calls among equally loaded providers
+            RpcBalance::LeastPending => {
+                available.min_by_key(|c| c.rpc_pending.load(atomic::Ordering::SeqCst))
+            }
+        };
+        provider.cloned()
+    }
+}
+
+/// The party, acknowledged when a pending delivery is resolved
+enum DeliverySender {
+    Client {
+        client: BrokerClient,
+        op_id: [u8; 4],
+        realtime: bool,
+    },
+    Internal(tokio::sync::oneshot::Sender<Result<(), Error>>),
+}
+
+/// A message, sent with QoS::Delivered or QoS::Consumed. The sender is acknowledged when the
+/// frame is written to the recipient connection or confirmed by the recipient
+struct PendingDelivery {
+    sender: DeliverySender,
+    recipient: String,
+    expires: Instant,
+}
+
+#[derive(Default)]
+struct Deliveries {
+    pending: HashMap<u32, PendingDelivery>,
+    next_id: u32,
+    next_sweep: Option<Instant>,
+}
+
+#[derive(Default)]
+struct ListenerState {
+    active: bool,
+    restarts: u64,
+    last_error: Option<String>,
+}
+
+impl BrokerDb {
+    fn new(broadcast_format: MaskFormat, topic_format: MaskFormat) -> Self {
+        Self {
+            clients: <_>::default(),
+            broadcasts: RwLock::new(
+                BroadcastMap::new()
+                    .separator(broadcast_format.separator)
+                    .match_any(&broadcast_format.match_any)
+                    .wildcard(&broadcast_format.wildcard),
+            ),
+            subscriptions: RwLock::new(
+                SubMap::new()
+                    .separator(topic_format.separator)
+                    .match_any(&topic_format.match_any)
+                    .wildcard(&topic_format.wildcard),
+            ),
+            broadcast_format,
+            topic_format,
+            groups: <_>::default(),
+            #[cfg(feature = "rpc")]
+            rpc_client: <_>::default(),
+            wiretaps: <_>::default(),
+            wiretaps_active: atomic::AtomicBool::new(false),
+            listeners: <_>::default(),
+            queued_bytes: <_>::default(),
+            memory_limit: <_>::default(),
+            memory_overflow_policy: <_>::default(),
+            memory_pressure: <_>::default(),
+            subscription_limits: <_>::default(),
+            frame_timestamps: atomic::AtomicBool::new(false),
+            queue_size: atomic::AtomicUsize::new(DEFAULT_QUEUE_SIZE),
+            fanout_budget: atomic::AtomicUsize::new(DEFAULT_FANOUT_BUDGET),
+            client_name_validator: <_>::default(),
+            authenticator: <_>::default(),
+            #[cfg(feature = "testing")]
+            chaos: <_>::default(),
+            persistence: RwLock::new(Arc::new(MemoryPersistence::new())),
+            aaa_maps: <_>::default(),
+            r_frames: atomic::AtomicU64::new(0),
+            r_bytes: atomic::AtomicU64::new(0),
+            w_frames: atomic::AtomicU64::new(0),
+            w_bytes: atomic::AtomicU64::new(0),
+            expired_frames: atomic::AtomicU64::new(0),
+            protocol_errors: <_>::default(),
+            deliveries: <_>::default(),
+            transaction_commit: <_>::default(),
+            #[cfg(feature = "rpc")]
+            namespaces: <_>::default(),
+            #[cfg(feature = "rpc")]
+            services: <_>::default(),
+            #[cfg(feature = "rpc")]
+            rpc_targets: <_>::default(),
+            #[cfg(feature = "rpc")]
+            rpc_calls: <_>::default(),
+            #[cfg(feature = "rpc")]
+            rpc_calls_pending: atomic::AtomicUsize::new(0),
+            startup_time: Instant::now(),
+        }
+    }
+    /// Charges the client for a routed delivery. When the fan-out budget is spent, the client
+    /// task yields, the budget is counted across frames, so clients, which route many frames,
+    /// are scheduled in the same chunks as ones with large fan-outs
+    async fn charge_fanout(&self, client: &ElbusClient) {
+        let budget = self.fanout_budget.load(atomic::Ordering::SeqCst);
+        if budget > 0 && client.fanout_spent.fetch_add(1, atomic::Ordering::SeqCst) + 1 >= budget {
+            client.fanout_spent.store(0, atomic::Ordering::SeqCst);
+            tokio::task::yield_now().await;
+        }
+    }
+    /// Resolves recipients of transaction frames and checks they can accept them. Calls to
+    /// load-balanced targets are started on resolution and put into calls, so they can be
+    /// finished if the transaction is not routed
+    #[allow(clippy::type_complexity)]
+    fn transaction_routes(
+        &self,
+        client: &BrokerClient,
+        frames: Vec<TransactionFrame>,
+        calls: &mut Vec<(u8, u32)>,
+    ) -> Result<Vec<(TransactionFrame, Vec<BrokerClient>, Option<(u8, u32)>)>, Error> {
+        let mut routes = Vec::with_capacity(frames.len());
+        for frame in frames {
+            let mut call = None;
+            let recipients: Vec<BrokerClient> = match frame.op {
+                FrameOp::Message => {
+                    let rpc_body: &[u8] = frame
+                        .header
+                        .as_deref()
+                        .unwrap_or_else(|| frame.buf.get(frame.payload_pos..).unwrap_or_default());
+                    #[cfg(feature = "rpc")]
+                    let rpc_head = rpc_head(rpc_body);
+                    #[cfg(not(feature = "rpc"))]
+                    let rpc_head = None;
+                    let target = self.message_target(client, &frame.target, rpc_head, rpc_body);
+                    if frame.target.starts_with(crate::common::RPC_TARGET_PREFIX) {
+                        call = rpc_head;
+                        if target.is_some() {
+                            calls.extend(rpc_head);
+                        }
+                    }
+                    vec![target.ok_or_else(Error::not_registered)?]
+                }
+                FrameOp::Broadcast | FrameOp::BroadcastMandatory => self
+                    .broadcasts
+                    .read()
+                    .get_clients_by_mask(&frame.target)
+                    .into_iter()
+                    .collect(),
+                FrameOp::GroupMessage | FrameOp::GroupBroadcast => {
+                    let all = frame.op == FrameOp::GroupBroadcast;
+                    let members = self.group_members(&frame.target, all);
+                    if members.is_empty() && !all {
+                        return Err(Error::not_registered());
+                    }
+                    members
+                }
+                FrameOp::PublishTopic | FrameOp::PublishTopicMandatory => self
+                    .subscriptions
+                    .read()
+                    .get_subscribers(&frame.target)
+                    .into_iter()
+                    .collect(),
+                _ => return Err(Error::not_supported(format!("{:?}", frame.op))),
+            };
+            if recipients.is_empty()
+                && matches!(
+                    frame.op,
+                    FrameOp::BroadcastMandatory | FrameOp::PublishTopicMandatory
+                )
+            {
+                return Err(Error::unrouted());
+            }
+            routes.push((frame, recipients, call));
+        }
+        // the number of frames for each recipient
+        let mut queued: HashMap<&str, (&BrokerClient, usize)> = HashMap::new();
+        for (_, recipients, _) in &routes {
+            for r in recipients {
+                queued.entry(&r.name).or_insert((r, 0)).1 += 1;
+            }
+        }
+        for (tgt, count) in queued.values() {
+            if tgt.draining.load(atomic::Ordering::SeqCst) {
+                return Err(Error::busy(format!("client {} is draining", tgt.name)));
+            }
+            if tgt.kind != ClientKind::Internal && self.memory_exceeded() {
+                return Err(Error::busy("memory limit exceeded"));
+            }
+            if tgt
+                .tx
+                .capacity()
+                .is_some_and(|capacity| capacity.saturating_sub(tgt.tx.len()) < *count)
+            {
+                return Err(Error::busy(format!("client {} queue is full", tgt.name)));
+            }
+        }
+        Ok(routes)
+    }
+    /// Routes frames of a committed transaction. All recipients are resolved and checked before
+    /// routing, if any of them can not accept its frames, nothing is routed. Commits are
+    /// serialized, so frames of concurrent transactions are not interleaved. A queue can still
+    /// be filled by other clients after the checks, in this case the rest frames are routed and
+    /// the first send error is returned
+    async fn commit_transaction(
+        &self,
+        client: &BrokerClient,
+        transaction: Transaction,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        if let Some(kind) = transaction.failed {
+            return Err(Error::new(kind, Some("the transaction has failed")));
+        }
+        let _commit = self.transaction_commit.lock().await;
+        let mut calls = Vec::new();
+        let routes = match self.transaction_routes(client, transaction.frames, &mut calls) {
+            Ok(v) => v,
+            Err(e) => {
+                for call in calls {
+                    self.message_failed(client, true, Some(call));
+                }
+                return Err(e);
+            }
+        };
+        trace!(
+            "elbus client {} transaction commit: {} frames",
+            client,
+            routes.len()
+        );
+        let mut result = Ok(());
+        for (frame, recipients, call) in routes {
+            let len = (frame.buf.len() + frame.header.as_ref().map_or(0, Vec::len)) as u64;
+            client.r_frames.fetch_add(1, atomic::Ordering::SeqCst);
+            client.r_bytes.fetch_add(len, atomic::Ordering::SeqCst);
+            self.r_frames.fetch_add(1, atomic::Ordering::SeqCst);
+            self.r_bytes.fetch_add(len, atomic::Ordering::SeqCst);
+            let (kind, wiretap_kind, path) = match frame.op {
+                FrameOp::Message | FrameOp::GroupMessage => {
+                    (FrameKind::Message, WIRETAP_MESSAGE, "out/m/")
+                }
+                FrameOp::PublishTopic | FrameOp::PublishTopicMandatory => {
+                    (FrameKind::Publish, WIRETAP_PUBLISH, "out/p/")
+                }
+                _ => (FrameKind::Broadcast, WIRETAP_BROADCAST, "out/b/"),
+            };
+            let tap_target = self.wiretap_target(&frame.target);
+            let client_tap = client
+                .tapped
+                .load(atomic::Ordering::SeqCst)
+                .then(|| format!("{}{}", path, frame.target));
+            let data = Arc::new(FrameData {
+                kind,
+                sender: Some(client.name.clone()),
+                topic: (kind == FrameKind::Publish).then(|| frame.target.clone()),
+                header: frame.header,
+                buf: frame.buf,
+                payload_pos: frame.payload_pos,
+                realtime: frame.realtime,
+                identity: Some(client.identity.clone()),
+                timestamp: self.frame_timestamp(),
+                delivery: None,
+                memory: Some(self.frame_memory(len)),
+            });
+            if let Some(tap_target) = tap_target {
+                self.wiretap(client, wiretap_kind, &tap_target, &data);
+            }
+            if let Some(path) = client_tap {
+                client.tap(&data, &path);
+            }
+            self.w_frames
+                .fetch_add(recipients.len() as u64, atomic::Ordering::SeqCst);
+            self.w_bytes
+                .fetch_add(len * recipients.len() as u64, atomic::Ordering::SeqCst);
+            // subscription throttles are not applied, the queues have been checked, so a full
+            // one is handled as an overflow
+            for tgt in recipients {
+                tgt.w_frames.fetch_add(1, atomic::Ordering::SeqCst);
+                tgt.w_bytes.fetch_add(len, atomic::Ordering::SeqCst);
+                if let Err(e) = safe_send_frame!(self, tgt, data.clone(), timeout) {
+                    if call.is_some() {
+                        self.message_failed(client, true, call);
+                    }
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        result
+    }
+    /// Marks the listener active (err is None) or failed, state changes are reported to
+    /// BROKER_LISTENER_TOPIC
+    fn listener_state(&self, path: &str, err: Option<String>) {
+        let active = {
+            let mut listeners = self.listeners.write();
+            let state = listeners.entry(path.to_owned()).or_default();
+            let was_active = state.active;
+            if err.is_some() {
+                state.active = false;
+                state.last_error = err;
+            } else {
+                if !state.active && state.last_error.is_some() {
+                    state.restarts += 1;
+                }
+                state.active = true;
+            }
+            (state.active != was_active).then_some(state.active)
+        };
+        if let Some(active) = active {
+            self.report(
+                BROKER_LISTENER_TOPIC,
+                if active { "up" } else { "down" },
+                Some(path),
+                None,
+            );
+        }
+    }
+    fn listener_list(&self) -> Vec<ListenerInfo> {
+        let mut result: Vec<ListenerInfo> = self
+            .listeners
+            .read()
+            .iter()
+            .map(|(path, state)| ListenerInfo {
+                path: path.clone(),
+                active: state.active,
+                restarts: state.restarts,
+                last_error: state.last_error.clone(),
+            })
+            .collect();
+        result.sort_by(|a, b| a.path.cmp(&b.path));
+        result
+    }
+    fn subscription_stats(&self, limit: usize) -> SubscriptionStats {
+        let mut subscribers: HashMap<String, usize> = HashMap::new();
+        let mut clients = 0;
+        {
+            let sdb = self.subscriptions.read();
+            for client in sdb.list_clients() {
+                let mut subscribed = false;
+                // the default subscription of all clients is not counted
+                for mask in sdb.list_topics(&client) {
+                    if mask != BROKER_WARN_TOPIC {
+                        *subscribers.entry(mask.to_owned()).or_default() += 1;
+                        subscribed = true;
+                    }
+                }
+                if subscribed {
+                    clients += 1;
+                }
+            }
+        }
+        let mut nodes: HashSet<&str> = HashSet::new();
+        let mut branches: HashMap<&str, SubscriptionBranch> = HashMap::new();
+        let mut masks: Vec<MaskStats> = Vec::with_capacity(subscribers.len());
+        for (mask, count) in &subscribers {
+            let mut depth = 0;
+            let mut wildcard_at = None;
+            let mut branch_nodes = 0;
+            let sep = self.topic_format.separator;
+            for (pos, segment) in mask.split(sep).enumerate() {
+                depth += 1;
+                if wildcard_at.is_none() && self.topic_format.is_wildcard_segment(segment) {
+                    wildcard_at = Some(pos);
+                }
+                let end = mask
+                    .match_indices(sep)
+                    .nth(pos)
+                    .map_or(mask.len(), |(i, _)| i);
+                if nodes.insert(&mask[..end]) {
+                    branch_nodes += 1;
+                }
+            }
+            let first = mask.split(sep).next().unwrap_or_default();
+            let branch = branches.entry(first).or_insert_with(|| SubscriptionBranch {
+                branch: first.to_owned(),
+                nodes: 0,
+                masks: 0,
+                subscriptions: 0,
+            });
+            branch.nodes += branch_nodes;
+            branch.masks += 1;
+            branch.subscriptions += count;
+            masks.push(MaskStats {
+                mask: mask.clone(),
+                subscribers: *count,
+                depth,
+                wildcard_at,
+            });
+        }
+        let mut branches: Vec<SubscriptionBranch> = branches.into_values().collect();
+        branches.sort_by(|a, b| {
+            b.subscriptions
+                .cmp(&a.subscriptions)
+                .then_with(|| a.branch.cmp(&b.branch))
+        });
+        masks.sort_by(|a, b| {
+            b.subscribers
+                .cmp(&a.subscribers)
+                .then_with(|| a.mask.cmp(&b.mask))
+        });
+        SubscriptionStats {
+            clients,
+            subscriptions: subscribers.values().sum(),
+            masks: subscribers.len(),
+            wildcard_masks: masks.iter().filter(|m| m.wildcard_at.is_some()).count(),
+            nodes: nodes.len(),
+            max_depth: masks.iter().map(|m| m.depth).max().unwrap_or_default(),
+            branches,
+            top_masks: masks.into_iter().take(limit).collect(),
+        }
+    }
+    fn stats(&self) -> BrokerStats {
+        BrokerStats {
+            uptime: self.startup_time.elapsed().as_secs(),
+            r_frames: self.r_frames.load(atomic::Ordering::SeqCst),
+            r_bytes: self.r_bytes.load(atomic::Ordering::SeqCst),
+            w_frames: self.w_frames.load(atomic::Ordering::SeqCst),
+            w_bytes: self.w_bytes.load(atomic::Ordering::SeqCst),
+            queued_bytes: self.queued_bytes.load(atomic::Ordering::SeqCst),
+            expired_frames: self.expired_frames.load(atomic::Ordering::SeqCst),
+            protocol_errors: self.protocol_errors.get(),
+            connections: {
+                let mut connections: Vec<ConnectionProtocolErrors> = self
+                    .clients
+                    .read()
+                    .values()
+                    .filter_map(|c| {
+                        let errors = c.protocol_errors.get();
+                        (!errors.is_empty()).then(|| ConnectionProtocolErrors {
+                            client: c.name.clone(),
+                            errors,
+                        })
+                    })
+                    .collect();
+                connections.sort_by(|a, b| a.client.cmp(&b.client));
+                connections
+            },
+            health: {
+                let mut health: BTreeMap<String, usize> = BTreeMap::new();
+                for c in self.clients.read().values() {
+                    if let Some(ref h) = *c.health.lock() {
+                        *health.entry(h.status.clone()).or_default() += 1;
+                    }
+                }
+                health
+            },
+        }
+    }
+    fn protocol_error(&self, client: &ElbusClient, error: ProtocolError) {
+        debug!("elbus client {} protocol error: {:?}", client, error);
+        client.protocol_errors.count(error);
+        self.protocol_errors.count(error);
+    }
+    fn metrics(&self) -> Vec<Metric> {
+        let counter = |name, client: Option<&str>, value: &atomic::AtomicU64| Metric {
+            name,
+            kind: MetricKind::Counter,
+            client: client.map(ToOwned::to_owned),
+            value: value.load(atomic::Ordering::SeqCst),
+        };
+        let gauge = |name, client: Option<&str>, value: u64| Metric {
+            name,
+            kind: MetricKind::Gauge,
+            client: client.map(ToOwned::to_owned),
+            value,
+        };
+        let mut metrics = vec![
+            counter("r_frames", None, &self.r_frames),
+            counter("r_bytes", None, &self.r_bytes),
+            counter("w_frames", None, &self.w_frames),
+            counter("w_bytes", None, &self.w_bytes),
+            counter("expired_frames", None, &self.expired_frames),
+            gauge(
+                "queued_bytes",
+                None,
+                self.queued_bytes.load(atomic::Ordering::SeqCst),
+            ),
+        ];
+        let clients = self.clients.read();
+        metrics.push(gauge("clients", None, clients.len() as u64));
+        for client in clients.values().filter(|c| c.primary) {
+            let name = Some(client.name.as_str());
+            metrics.push(counter("client_r_frames", name, &client.r_frames));
+            metrics.push(counter("client_r_bytes", name, &client.r_bytes));
+            metrics.push(counter("client_w_frames", name, &client.w_frames));
+            metrics.push(counter("client_w_bytes", name, &client.w_bytes));
+            metrics.push(counter("client_w_expired", name, &client.w_expired));
+            metrics.push(gauge("client_queue", name, client.tx.len() as u64));
+        }
+        metrics
+    }
+    fn capabilities(&self) -> BrokerCapabilities {
+        let limits = *self.subscription_limits.read();
+        BrokerCapabilities {
+            version: crate::VERSION.to_owned(),
+            protocol_version: crate::PROTOCOL_VERSION,
+            features: compiled_features()
+                .into_iter()
+                .map(ToOwned::to_owned)
+                .collect(),
+            listeners: self.listener_list(),
+            timestamps: self.frame_timestamps.load(atomic::Ordering::SeqCst),
+            limits: BrokerLimits {
+                queue_size: self.queue_size.load(atomic::Ordering::SeqCst),
+                memory_limit: self.memory_limit.load(atomic::Ordering::SeqCst),
+                memory_overflow: self.memory_overflow_policy().to_string(),
+                max_subscriptions: limits.max_subscriptions,
+                max_wildcard_subscriptions: limits.max_wildcard_subscriptions,
+                max_topic_depth: limits.max_topic_depth,
+                max_topic_length: limits.max_topic_length,
+                batch_max_size: BATCH_MAX_SIZE,
+                batch_max_delay: BATCH_MAX_DELAY,
+            },
+        }
+    }
+    #[inline]
+    fn frame_memory(&self, size: u64) -> FrameMemory {
+        self.queued_bytes.fetch_add(size, atomic::Ordering::SeqCst);
+        FrameMemory {
+            counter: self.queued_bytes.clone(),
+            size,
+            queued: Instant::now(),
+        }
+    }
+    /// Unsubscribes the client from the topic, returns false if the client is not registered.
+    /// SubMap::unsubscribe splits topics by "/" only, so for other separators the client is
+    /// re-registered with the rest of its subscriptions
+    fn unsubscribe_topic(
+        &self,
+        sdb: &mut SubMap<BrokerClient>,
+        topic: &str,
+        client: &BrokerClient,
+    ) -> bool {
+        if self.topic_format.separator == '/' {
+            return sdb.unsubscribe(topic, client);
+        }
+        let topics: Vec<String> = sdb
+            .list_topics(client)
+            .into_iter()
+            .filter(|t| *t != topic)
+            .map(ToOwned::to_owned)
+            .collect();
+        if !sdb.unregister_client(client) {
+            return false;
+        }
+        sdb.register_client(client);
+        for t in &topics {
+            sdb.subscribe(t, client);
+        }
+        true
+    }
+    /// Checks if the client can subscribe to the topics without exceeding the limits
+    fn check_subscription_limits(
+        &self,
+        sdb: &SubMap<BrokerClient>,
+        client: &BrokerClient,
+        topics: &[&str],
+    ) -> Result<(), Error> {
+        let limits = client
+            .subscription_limits
+            .unwrap_or_else(|| *self.subscription_limits.read());
+        if limits.is_empty() {
+            return Ok(());
+        }
+        for topic in topics {
+            if limits.max_topic_length > 0 && topic.len() > limits.max_topic_length {
+                return Err(Error::limit(format!("topic too long: {}", topic)));
+            }
+            if limits.max_topic_depth > 0
+                && topic.split(self.topic_format.separator).count() > limits.max_topic_depth
+            {
+                return Err(Error::limit(format!("topic too deep: {}", topic)));
+            }
+        }
+        if limits.max_subscriptions == 0 && limits.max_wildcard_subscriptions == 0 {
+            return Ok(());
+        }
+        let mut subscribed: HashSet<&str> = sdb
+            .list_topics(client)
+            .into_iter()
+            .filter(|t| *t != BROKER_WARN_TOPIC)
+            .collect();
+        for topic in topics {
+            subscribed.insert(topic);
+        }
+        if limits.max_subscriptions > 0 && subscribed.len() > limits.max_subscriptions {
+            return Err(Error::limit(format!(
+                "max subscriptions ({}) exceeded",
+                limits.max_subscriptions
+            )));
+        }
+        if limits.max_wildcard_subscriptions > 0
+            && subscribed
+                .iter()
+                .filter(|t| self.topic_format.is_wildcard_mask(t))
+                .count()
+                > limits.max_wildcard_subscriptions
+        {
+            return Err(Error::limit(format!(
+                "max wildcard subscriptions ({}) exceeded",
+                limits.max_wildcard_subscriptions
+            )));
+        }
+        Ok(())
+    }
+    fn set_throttle(
+        &self,
+        client: &BrokerClient,
+        mask: &str,
+        throttle: Option<DeliveryThrottle>,
+    ) -> Result<(), Error> {
+        if let Some(throttle) = throttle {
+            match throttle {
+                DeliveryThrottle::Sample(0) => {
+                    return Err(Error::data("the sample rate can not be zero"));
+                }
+                DeliveryThrottle::Interval(interval) if interval.is_zero() => {
+                    return Err(Error::data("the throttle interval can not be zero"));
+                }
+                _ => {}
+            }
+            if !self
+                .subscriptions
+                .read()
+                .list_topics(client)
+                .contains(&mask)
+            {
+                return Err(Error::data(format!("not subscribed to {}", mask)));
+            }
+            client.set_throttle(mask, throttle, self.topic_format.acl_map());
+        } else {
+            client.remove_throttle(mask);
+        }
+        Ok(())
+    }
+    /// Returns all members of the group or the next one (round-robin)
+    fn group_members(&self, group: &str, all: bool) -> Vec<BrokerClient> {
+        let groups = self.groups.read();
+        let Some(g) = groups.get(group) else {
+            return Vec::new();
+        };
+        if all {
+            g.members.clone()
+        } else if g.members.is_empty() {
+            Vec::new()
+        } else {
+            // draining members are skipped, the next one takes their turn
+            let len = g.members.len();
+            let pos = g.next.fetch_add(1, atomic::Ordering::SeqCst) % len;
+            (0..len)
+                .map(|i| &g.members[(pos + i) % len])
+                .find(|c| !c.draining.load(atomic::Ordering::SeqCst))
+                .into_iter()
+                .cloned()
+                .collect()
+        }
+    }
+    #[inline]
+    fn frame_timestamp(&self) -> Option<u64> {
+        if self.frame_timestamps.load(atomic::Ordering::SeqCst) {
+            Some(now_ns())
+        } else {
+            None
+        }
+    }
+    /// Returns the external client with the largest non-empty queue
+    fn largest_queue_client(&self) -> Option<BrokerClient> {
+        self.clients
+            .read()
+            .values()
+            .filter(|c| c.kind != ClientKind::Internal && !c.tx.is_empty())
+            .max_by_key(|c| c.tx.len())
+            .cloned()
+    }
+    #[inline]
+    fn memory_overflow_policy(&self) -> MemoryOverflowPolicy {
+        *self.memory_overflow_policy.read()
+    }
+    /// Checks the memory limit, sets/clears the memory pressure state and reports it
+    fn memory_exceeded(&self) -> bool {
+        let limit = self.memory_limit.load(atomic::Ordering::SeqCst);
+        if limit == 0 {
+            return false;
+        }
+        let queued = self.queued_bytes.load(atomic::Ordering::SeqCst);
+        if queued >= limit {
+            if !self.memory_pressure.swap(true, atomic::Ordering::SeqCst) {
+                warn!("memory pressure: {} bytes queued, limit: {}", queued, limit);
+                self.report_memory_pressure(true);
+            }
+            true
+        } else {
+            if queued < limit / 100 * MEMORY_PRESSURE_CLEAR_PERCENT
+                && self.memory_pressure.swap(false, atomic::Ordering::SeqCst)
+            {
+                warn!("memory pressure cleared: {} bytes queued", queued);
+                self.report_memory_pressure(false);
+            }
+            false
+        }
+    }
+    #[inline]
+    fn report_memory_pressure(&self, active: bool) {
+        self.report(
+            BROKER_WARN_TOPIC,
+            "memory_pressure",
+            Some(if active { "on" } else { "off" }),
+            None,
+        );
+    }
+    /// Publishes a system event in the background. Events, except warnings (which all clients are
+    /// subscribed to), are published only if the topic has subscribers
+    #[allow(unused_variables)]
+    fn report(
+        &self,
+        topic: &'static str,
+        subject: &'static str,
+        data: Option<&str>,
+        client: Option<&str>,
+    ) {
+        #[cfg(feature = "rpc")]
+        {
+            if topic != BROKER_WARN_TOPIC && !self.has_subscribers(topic) {
+                return;
+            }
+            let data = data.map(ToOwned::to_owned);
+            let client = client.map(ToOwned::to_owned);
+            let rpc_client = self.rpc_client.clone();
+            tokio::spawn(async move {
+                if let Some(rpc_client) = rpc_client.lock().await.as_ref() {
+                    let event = BrokerEvent {
+                        s: subject,
+                        d: data.as_deref(),
+                        c: client.as_deref(),
+                        r: None,
+                        t: now_ns(),
+                        topic,
+                    };
+                    let payload = match rmp_serde::to_vec_named(&event) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!("{}", e);
+                            return;
+                        }
+                    };
+                    if let Err(e) = rpc_client
+                        .client()
+                        .lock()
+                        .await
+                        .publish(event.topic, payload.into(), QoS::No)
+                        .await
+                    {
+                        error!("{}", e);
+                    }
+                }
+            });
+        }
+    }
+    /// Publishes routing events of a traced frame in the background, if the trace topic has
+    /// subscribers
+    fn publish_trace(&self, trace: FrameTrace) {
+        trace!(
+            "elbus frame trace {}: {} events",
+            trace.id,
+            trace.events.len()
+        );
+        #[cfg(feature = "rpc")]
+        {
+            let topic = format!("{}{}", BROKER_TRACE_TOPIC_PREFIX, trace.id);
+            if !self.has_subscribers(&topic) {
+                return;
+            }
+            let rpc_client = self.rpc_client.clone();
+            tokio::spawn(async move {
+                if let Some(rpc_client) = rpc_client.lock().await.as_ref() {
+                    let client = rpc_client.client();
+                    let mut client = client.lock().await;
+                    for (subject, data, target) in &trace.events {
+                        let event = BrokerEvent {
+                            s: subject,
+                            d: data.as_deref(),
+                            c: target.as_deref(),
+                            r: None,
+                            t: now_ns(),
+                            topic: &topic,
+                        };
+                        let payload = match rmp_serde::to_vec_named(&event) {
+                            Ok(v) => v,
+                            Err(e) => {
+                                error!("{}", e);
+                                return;
+                            }
+                        };
+                        if let Err(e) = client.publish(&topic, payload.into(), QoS::No).await {
+                            error!("{}", e);
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+    }
+    #[cfg(feature = "rpc")]
+    #[inline]
+    fn has_subscribers(&self, topic: &str) -> bool {
+        !self.subscriptions.read().get_subscribers(topic).is_empty()
+    }
+    /// Announces subscription changes of a client, if there are subscribers
+    #[cfg(feature = "rpc")]
+    async fn announce_subscriptions(&self, subject: &str, client: &str, masks: &[&str]) {
+        if !self.has_subscribers(BROKER_SUBSCRIPTION_TOPIC) {
+            return;
+        }
+        for mask in masks {
+            if let Err(e) = self
+                .announce(BrokerEvent::subscription(subject, client, mask))
+                .await
+            {
+                error!("{}", e);
+            }
+        }
+    }
+    /// Returns masks, which have demand event subscribers and no subscribers (called before
+    /// subscribing)
+    #[cfg(feature = "rpc")]
+    fn demand_idle(sdb: &SubMap<BrokerClient>, masks: &[&str]) -> Vec<String> {
+        masks
+            .iter()
+            .filter(|mask| Self::demand_watched(sdb, mask) && !Self::mask_subscribed(sdb, mask))
+            .map(|mask| (*mask).to_owned())
+            .collect()
+    }
+    /// Returns masks, which have demand event subscribers and are subscribed by the client
+    /// (called before unsubscribing, the result is checked with demand_left)
+    #[cfg(feature = "rpc")]
+    fn demand_subscribed(
+        sdb: &SubMap<BrokerClient>,
+        client: &BrokerClient,
+        masks: &[&str],
+    ) -> Vec<String> {
+        let subscribed = sdb.list_topics(client);
+        masks
+            .iter()
+            .filter(|mask| subscribed.contains(*mask) && Self::demand_watched(sdb, mask))
+            .map(|mask| (*mask).to_owned())
+            .collect()
+    }
+    /// Returns watched masks of the client and its secondaries (called before dropping the
+    /// client, the result is checked with demand_left)
+    #[cfg(feature = "rpc")]
+    fn demand_dropped(&self, client: &BrokerClient) -> Vec<String> {
+        let mut clients = vec![client.clone()];
+        if client.primary {
+            let secondaries: Vec<String> = client.secondaries.lock().iter().cloned().collect();
+            let db = self.clients.read();
+            clients.extend(secondaries.iter().filter_map(|name| db.get(name).cloned()));
+        }
+        let sdb = self.subscriptions.read();
+        let mut demand = Vec::new();
+        for c in &clients {
+            let topics = sdb.list_topics(c);
+            demand.extend(Self::demand_subscribed(&sdb, c, &topics));
+        }
+        demand.sort();
+        demand.dedup();
+        demand
+    }
+    /// Keeps masks, which have no subscribers left
+    #[cfg(feature = "rpc")]
+    fn demand_left(sdb: &SubMap<BrokerClient>, mut masks: Vec<String>) -> Vec<String> {
+        masks.retain(|mask| !Self::mask_subscribed(sdb, mask));
+        masks
+    }
+    #[cfg(feature = "rpc")]
+    #[inline]
+    fn demand_watched(sdb: &SubMap<BrokerClient>, mask: &str) -> bool {
+        !mask.starts_with(BROKER_TOPIC_PREFIX)
+            && !sdb
+                .get_subscribers(&format!("{}{}", BROKER_DEMAND_TOPIC_PREFIX, mask))
+                .is_empty()
+    }
+    /// Returns true if any client is subscribed to the exact topic mask
+    #[cfg(feature = "rpc")]
+    fn mask_subscribed(sdb: &SubMap<BrokerClient>, mask: &str) -> bool {
+        sdb.list_clients()
+            .iter()
+            .any(|c| sdb.list_topics(c).contains(&mask))
+    }
+    /// Announces demand changes of topic masks
+    #[cfg(feature = "rpc")]
+    async fn announce_demand(&self, subject: &str, masks: &[String]) {
+        for mask in masks {
+            let topic = format!("{}{}", BROKER_DEMAND_TOPIC_PREFIX, mask);
+            if let Err(e) = self
+                .announce(BrokerEvent::demand(subject, mask, &topic))
+                .await
+            {
+                error!("{}", e);
+            }
+        }
+    }
+    #[cfg(feature = "rpc")]
+    #[inline]
+    async fn announce(&self, mut event: BrokerEvent<'_>) -> Result<(), Error> {
+        if let Some(rpc_client) = self.rpc_client.lock().await.as_ref() {
+            event.t = now_ns();
+            rpc_client
+                .client()
+                .lock()
+                .await
+                .publish(
+                    event.topic,
+                    rmp_serde::to_vec_named(&event).map_err(Error::data)?.into(),
+                    QoS::No,
+                )
+                .await?;
+        }
+        Ok(())
+    }
+    /// Returns true if the topic mask falls under the namespace prefix. Wildcard masks, which may
+    /// match topics under the namespace (e.g. "#"), fall under it as well
+    #[cfg(feature = "rpc")]
+    fn in_namespace(&self, prefix: &str, mask: &str) -> bool {
+        if mask.starts_with(prefix) {
+            return true;
+        }
+        let mut literal_len = 0;
+        for segment in mask.split(self.topic_format.separator) {
+            if self.topic_format.is_wildcard_segment(segment) {
+                return prefix.starts_with(&mask[..literal_len]);
+            }
+            literal_len += segment.len() + 1;
+        }
+        false
+    }
+    /// Returns owners of namespaces, the topic mask falls under, except the client itself
+    #[cfg(feature = "rpc")]
+    fn namespace_owners(&self, client: &str, mask: &str) -> Vec<String> {
+        self.namespaces
+            .read()
+            .iter()
+            .filter(|(prefix, owner)| *owner != client && self.in_namespace(prefix, mask))
+            .map(|(_, owner)| owner.clone())
+            .collect()
+    }
+    /// Asks namespace owners to approve the subscription. Subscriptions of owners to their own
+    /// namespaces and to topics out of claimed namespaces are always approved
+    #[cfg(feature = "rpc")]
+    async fn namespace_approve(&self, client: &str, topic: &str, timeout: Duration) -> bool {
+        let owners = self.namespace_owners(client, topic);
+        if owners.is_empty() {
+            return true;
+        }
+        let event = NamespaceEvent {
+            subject: "subscribe".to_owned(),
+            client: client.to_owned(),
+            topic: topic.to_owned(),
+        };
+        let result = async {
+            let params = rmp_serde::to_vec_named(&event).map_err(Error::data)?;
+            let rpc_client = self.rpc_client.lock().await;
+            let rpc = rpc_client
+                .as_ref()
+                .ok_or_else(|| Error::not_supported(BROKER_RPC_NOT_INIT_ERR))?;
+            for owner in owners {
+                time::timeout(
+                    timeout,
+                    rpc.call(
+                        &owner,
+                        NAMESPACE_SUBSCRIBE_METHOD,
+                        params.as_slice().into(),
+                        QoS::Processed,
+                    ),
+                )
+                .await?
+                .map_err(|e| Error::access(format!("rejected by {}: {}", owner, e)))?;
+            }
+            Ok::<(), Error>(())
+        }
+        .await;
+        if let Err(e) = result {
+            debug!(
+                "elbus client {} subscription to {} not approved: {}",
+                client, topic, e
+            );
+            false
+        } else {
+            true
+        }
+    }
+    /// Notifies namespace owners about subscription changes of a client
+    #[cfg(feature = "rpc")]
+    async fn namespace_notify(&self, subject: &str, client: &str, masks: &[&str]) {
+        if self.namespaces.read().is_empty() {
+            return;
+        }
+        for mask in masks {
+            let owners = self.namespace_owners(client, mask);
+            if owners.is_empty() {
+                continue;
+            }
+            let event = NamespaceEvent {
+                subject: subject.to_owned(),
+                client: client.to_owned(),
+                topic: (*mask).to_owned(),
+            };
+            let payload = match rmp_serde::to_vec_named(&event) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("{}", e);
+                    continue;
+                }
+            };
+            if let Some(rpc) = self.rpc_client.lock().await.as_ref() {
+                for owner in owners {
+                    if let Err(e) = rpc.notify(&owner, payload.as_slice().into(), QoS::No).await {
+                        warn!("unable to notify namespace owner {}: {}", owner, e);
+                    }
+                }
+            }
+        }
+    }
+    #[inline]
+    async fn register_client(&self, client: Arc<ElbusClient>) -> Result<(), Error> {
+        #[cfg(feature = "rpc")]
+        // copy name for the announce
+        let name = client.name.clone();
+        #[cfg(feature = "rpc")]
+        let primary = client.primary;
+        self.insert_client(client)?;
+        #[cfg(feature = "rpc")]
+        if primary {
+            if let Err(e) = self.announce(BrokerEvent::reg(&name)).await {
+                error!("{}", e);
+            }
+        }
+        Ok(())
+    }
+    fn insert_client(&self, client: Arc<ElbusClient>) -> Result<(), Error> {
+        let mut clients = self.clients.write();
+        let primary_client = if client.primary {
+            None
         } else {
             Some(
                 clients
@@ -632,17 +3325,27 @@ impl BrokerDb {
         };
         if let hash_map::Entry::Vacant(x) = clients.entry(client.name.clone()) {
             if let Some(pc) = primary_client {
-                pc.secondaries.lock().unwrap().insert(client.name.clone());
+                pc.secondaries.lock().insert(client.name.clone());
             }
             {
-                let mut bdb = self.broadcasts.write().unwrap();
+                let mut bdb = self.broadcasts.write();
                 bdb.register_client(&client.name, &client);
             }
             {
-                let mut sdb = self.subscriptions.write().unwrap();
+                let mut sdb = self.subscriptions.write();
                 sdb.register_client(&client);
                 sdb.subscribe(BROKER_WARN_TOPIC, &client);
             }
+            if !client.groups.is_empty() {
+                let mut groups = self.groups.write();
+                for group in &client.groups {
+                    groups
+                        .entry(group.clone())
+                        .or_default()
+                        .members
+                        .push(client.clone());
+                }
+            }
             client.registered.store(true, atomic::Ordering::SeqCst);
             x.insert(client);
         } else {
@@ -654,8 +3357,8 @@ impl BrokerDb {
         Ok(())
     }
     fn trigger_disconnect(&self, name: &str) -> Result<(), Error> {
-        if let Some(client) = self.clients.read().unwrap().get(name) {
-            if client.kind == ElbusClientKind::Internal {
+        if let Some(client) = self.clients.read().get(name) {
+            if client.kind == ClientKind::Internal {
                 Err(Error::not_supported("the client is internal"))
             } else {
                 client.disconnect_trig.trigger();
@@ -665,45 +3368,581 @@ impl BrokerDb {
             Err(Error::not_registered())
         }
     }
+    /// Stops queueing new frames for the client, waits until its queue is flushed (or the timeout
+    /// is expired) and disconnects it. Returns the number of frames left in the queue
+    async fn drain_client(&self, name: &str, timeout: Duration) -> Result<usize, Error> {
+        let client = self
+            .clients
+            .read()
+            .get(name)
+            .cloned()
+            .ok_or_else(Error::not_registered)?;
+        if client.kind == ClientKind::Internal {
+            return Err(Error::not_supported("the client is internal"));
+        }
+        if client.draining.swap(true, atomic::Ordering::SeqCst) {
+            return Err(Error::busy("the client is already draining"));
+        }
+        debug!("draining elbus client {}", client);
+        let started = Instant::now();
+        while !client.tx.is_empty() && started.elapsed() < timeout {
+            time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+        let pending = client.tx.len();
+        if pending == 0 {
+            // let the writer send the last frame and the pending batch
+            time::sleep(
+                DRAIN_POLL_INTERVAL
+                    + Duration::from_micros(u64::from(
+                        client.batch_delay.load(atomic::Ordering::SeqCst),
+                    )),
+            )
+            .await;
+        } else {
+            warn!(
+                "elbus client {} drain timeout, {} frames dropped",
+                client, pending
+            );
+        }
+        client.disconnect_trig.trigger();
+        Ok(pending)
+    }
+    /// Returns the message target client. Recent targets are cached by the sender, so hot
+    /// point-to-point paths do not lock the client map
+    fn target_client(&self, sender: &ElbusClient, target: &str) -> Option<BrokerClient> {
+        if let Some(client) = sender.cached_target(target) {
+            return Some(client);
+        }
+        let client = self.clients.read().get(target).cloned()?;
+        sender.cache_target(target, &client);
+        Some(client)
+    }
+    /// Returns the message target client. Calls to load-balanced targets ("@SERVICE") are routed
+    /// to one of the service providers, replies of providers finish pending calls
+    #[allow(unused_variables)]
+    fn message_target(
+        &self,
+        sender: &ElbusClient,
+        target: &str,
+        rpc_head: Option<(u8, u32)>,
+        rpc_body: &[u8],
+    ) -> Option<BrokerClient> {
+        #[cfg(feature = "rpc")]
+        {
+            if let Some(service) = target.strip_prefix(RPC_TARGET_PREFIX) {
+                if let Some(provider) = self.rpc_provider(service, rpc_session(rpc_body)) {
+                    if let (Some(p), Some((RPC_REQUEST, id))) = (&provider, rpc_head) {
+                        if id != 0 {
+                            self.rpc_call_started(sender, id, p);
+                        }
+                    }
+                    return provider;
+                }
+            } else if self.rpc_calls_pending.load(atomic::Ordering::SeqCst) > 0 {
+                if let Some((RPC_REPLY | RPC_ERROR, id)) = rpc_head {
+                    self.rpc_call_finished(target, id, Some(&sender.name));
+                }
+            }
+        }
+        self.target_client(sender, target)
+    }
+    /// Called if a message has not been routed, a pending call is dropped as the provider has
+    /// not got it
+    #[allow(unused_variables)]
+    fn message_failed(&self, sender: &ElbusClient, balanced: bool, rpc_head: Option<(u8, u32)>) {
+        #[cfg(feature = "rpc")]
+        if let Some((RPC_REQUEST, id)) = rpc_head {
+            if id != 0 && balanced {
+                self.rpc_call_finished(&sender.name, id, None);
+            }
+        }
+    }
+    /// Returns None if the load-balanced target does not exist, Some(None) if all its providers
+    /// are draining. Calls with a session key are routed to the provider, the session is pinned
+    /// to, a session of a draining provider or an idle one is pinned to another one
+    #[cfg(feature = "rpc")]
+    #[allow(clippy::option_option)]
+    fn rpc_provider(&self, service: &str, session: Option<&str>) -> Option<Option<BrokerClient>> {
+        let targets = self.rpc_targets.read();
+        let t = targets.get(service)?;
+        let Some(session) = session else {
+            return Some(t.next_provider());
+        };
+        let now = Instant::now();
+        let mut sessions = t.sessions.lock();
+        if let Some((provider, used)) = sessions.get_mut(session) {
+            if !provider.draining.load(atomic::Ordering::SeqCst)
+                && now.duration_since(*used) < RPC_TARGET_SESSION_IDLE
+            {
+                *used = now;
+                return Some(Some(provider.clone()));
+            }
+        }
+        let provider = t.next_provider();
+        if let Some(ref p) = provider {
+            if sessions.len() >= RPC_TARGET_SESSIONS_MAX && !sessions.contains_key(session) {
+                sessions.retain(|_, (_, used)| now.duration_since(*used) < RPC_TARGET_SESSION_IDLE);
+                if sessions.len() >= RPC_TARGET_SESSIONS_MAX {
+                    if let Some(lru) = sessions
+                        .iter()
+                        .min_by_key(|(_, (_, used))| *used)
+                        .map(|(key, _)| key.clone())
+                    {
+                        sessions.remove(&lru);
+                    }
+                }
+            }
+            sessions.insert(session.to_owned(), (p.clone(), now));
+        }
+        Some(provider)
+    }
+    #[cfg(feature = "rpc")]
+    fn rpc_call_started(&self, caller: &ElbusClient, id: u32, provider: &BrokerClient) {
+        provider.rpc_pending.fetch_add(1, atomic::Ordering::SeqCst);
+        // a call with the same id may be left by a caller, which has not waited for the reply
+        if let Some(prev) = self
+            .rpc_calls
+            .lock()
+            .insert((caller.name.clone(), id), provider.clone())
+        {
+            prev.rpc_pending.fetch_sub(1, atomic::Ordering::SeqCst);
+        } else {
+            self.rpc_calls_pending
+                .fetch_add(1, atomic::Ordering::SeqCst);
+        }
+    }
+    /// If the provider is specified, the call is finished only if it has been routed to it
+    #[cfg(feature = "rpc")]
+    fn rpc_call_finished(&self, caller: &str, id: u32, provider: Option<&str>) {
+        let mut calls = self.rpc_calls.lock();
+        let key = (caller.to_owned(), id);
+        if let hash_map::Entry::Occupied(entry) = calls.entry(key) {
+            if provider.is_none_or(|p| entry.get().name == p) {
+                let provider = entry.remove();
+                provider.rpc_pending.fetch_sub(1, atomic::Ordering::SeqCst);
+                self.rpc_calls_pending
+                    .fetch_sub(1, atomic::Ordering::SeqCst);
+            }
+        }
+    }
+    /// Adds the provider to the load-balanced target of the service. The target policy is set by
+    /// the first provider, others must use the same or omit it
+    #[cfg(feature = "rpc")]
+    fn rpc_target_add(
+        &self,
+        service: &str,
+        provider: &BrokerClient,
+        balance: Option<RpcBalance>,
+    ) -> Result<RpcBalance, Error> {
+        let mut targets = self.rpc_targets.write();
+        let t = targets
+            .entry(service.to_owned())
+            .or_insert_with(|| RpcTarget {
+                balance: balance.unwrap_or_default(),
+                providers: Vec::new(),
+                next: atomic::AtomicUsize::new(0),
+                sessions: <_>::default(),
+            });
+        // the policy can be changed by the only provider
+        if t.providers.iter().all(|c| Arc::ptr_eq(c, provider)) {
+            if let Some(balance) = balance {
+                t.balance = balance;
+            }
+        } else if balance.is_some_and(|b| b != t.balance) {
+            return Err(Error::data(format!(
+                "service {} is registered with a different balance policy",
+                service
+            )));
+        }
+        if !t.providers.iter().any(|c| Arc::ptr_eq(c, provider)) {
+            t.providers.push(provider.clone());
+        }
+        Ok(t.balance)
+    }
+    /// Removes the provider from load-balanced targets (from all if no service is specified),
+    /// targets without providers are removed. Sessions of the provider are unpinned
+    #[cfg(feature = "rpc")]
+    fn rpc_target_remove(&self, service: Option<&str>, provider: &ElbusClient) {
+        let mut targets = self.rpc_targets.write();
+        targets.retain(|name, t| {
+            if service.is_none_or(|s| s == name) {
+                t.providers.retain(|c| !std::ptr::eq(c.as_ref(), provider));
+                t.sessions
+                    .get_mut()
+                    .retain(|_, (c, _)| !std::ptr::eq(c.as_ref(), provider));
+            }
+            !t.providers.is_empty()
+        });
+    }
+    /// Fails pending calls, routed to the disconnected provider, with "unavailable" error
+    /// replies, so callers can retry them with the same target, which has been failed over to
+    /// the rest of providers. Pending calls of the disconnected caller are dropped
+    #[cfg(feature = "rpc")]
+    async fn rpc_calls_failover(&self, client: &ElbusClient) {
+        if self.rpc_calls_pending.load(atomic::Ordering::SeqCst) == 0 {
+            return;
+        }
+        let mut failed = Vec::new();
+        self.rpc_calls.lock().retain(|(caller, id), provider| {
+            let is_provider = std::ptr::eq(provider.as_ref(), client);
+            if is_provider || *caller == client.name {
+                provider.rpc_pending.fetch_sub(1, atomic::Ordering::SeqCst);
+                self.rpc_calls_pending
+                    .fetch_sub(1, atomic::Ordering::SeqCst);
+                if is_provider && *caller != client.name {
+                    failed.push((caller.clone(), *id));
+                }
+                false
+            } else {
+                true
+            }
+        });
+        if failed.is_empty() {
+            return;
+        }
+        let rpc_client = self.rpc_client.lock().await;
+        let Some(rpc) = rpc_client.as_ref() else {
+            return;
+        };
+        let rpc_client = rpc.client();
+        let mut c = rpc_client.lock().await;
+        let message = format!("RPC provider {} is disconnected", client.name);
+        for (caller, id) in failed {
+            let mut payload = Vec::with_capacity(message.len() + 7);
+            payload.push(RPC_ERROR);
+            payload.extend(id.to_le_bytes());
+            payload.extend(RPC_ERROR_CODE_UNAVAILABLE.to_le_bytes());
+            payload.extend(message.as_bytes());
+            if let Err(e) = c.send(&caller, payload.into(), QoS::No).await {
+                warn!("unable to fail over the call {} of {}: {}", id, caller, e);
+            }
+        }
+    }
     #[inline]
-    async fn unregister_client(&self, client: &Arc<ElbusClient>) {
+    /// The client is announced as unregistered only once, e.g. if it is disconnected because of
+    /// overload, the peer handler does not announce it again
+    #[allow(unused_variables)]
+    async fn unregister_client(&self, client: &Arc<ElbusClient>, reason: DisconnectReason) {
+        let registered = self
+            .clients
+            .read()
+            .get(&client.name)
+            .is_some_and(|c| Arc::ptr_eq(c, client));
+        #[cfg(feature = "rpc")]
+        let namespace_topics: Vec<String> = if registered && !self.namespaces.read().is_empty() {
+            self.namespaces
+                .write()
+                .retain(|_, owner| *owner != client.name);
+            self.subscriptions
+                .read()
+                .list_topics(client)
+                .into_iter()
+                .map(ToOwned::to_owned)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        #[cfg(feature = "rpc")]
+        if registered {
+            self.services
+                .write()
+                .retain(|(provider, _), _| *provider != client.name);
+            self.rpc_target_remove(None, client);
+        }
+        #[cfg(feature = "rpc")]
+        self.rpc_calls_failover(client).await;
+        #[cfg(feature = "rpc")]
+        let demand = if registered {
+            self.demand_dropped(client)
+        } else {
+            Vec::new()
+        };
         self.drop_client(client);
         #[cfg(feature = "rpc")]
-        if client.primary {
-            if let Err(e) = self.announce(BrokerEvent::unreg(&client.name)).await {
+        if !demand.is_empty() {
+            let demand = Self::demand_left(&self.subscriptions.read(), demand);
+            self.announce_demand("idle", &demand).await;
+        }
+        #[cfg(feature = "rpc")]
+        if !namespace_topics.is_empty() {
+            let topics: Vec<&str> = namespace_topics.iter().map(String::as_str).collect();
+            self.namespace_notify("unsubscribe", &client.name, &topics)
+                .await;
+        }
+        #[cfg(feature = "rpc")]
+        if client.primary && registered {
+            if let Err(e) = self
+                .announce(BrokerEvent::unreg_with_reason(&client.name, reason))
+                .await
+            {
                 error!("{}", e);
             }
         }
     }
+    #[cfg(feature = "rpc")]
+    fn wiretap_start(
+        &self,
+        client: BrokerClient,
+        client_mask: Option<&str>,
+        topic_mask: Option<&str>,
+    ) {
+        let mut wiretaps = self.wiretaps.write();
+        wiretaps.insert(
+            client.name.clone(),
+            Wiretap::new(
+                client,
+                client_mask.map(|mask| (mask, &self.broadcast_format)),
+                topic_mask.map(|mask| (mask, &self.topic_format)),
+            ),
+        );
+        self.wiretaps_active.store(true, atomic::Ordering::SeqCst);
+    }
+    fn wiretap_stop(&self, name: &str) {
+        let mut wiretaps = self.wiretaps.write();
+        if wiretaps.remove(name).is_some() {
+            self.wiretaps_active
+                .store(!wiretaps.is_empty(), atomic::Ordering::SeqCst);
+        }
+    }
+    /// Sends copies of the frame to wiretap subscribers. Copies are never blocking, if a
+    /// subscriber queue is full, the copy is dropped
+    #[inline]
+    fn wiretap_target(&self, target: &str) -> Option<String> {
+        if self.wiretaps_active.load(atomic::Ordering::SeqCst) {
+            Some(target.to_owned())
+        } else {
+            None
+        }
+    }
+    fn wiretap(&self, sender: &ElbusClient, kind: &str, target: &str, frame: &Frame) {
+        let wiretaps = self.wiretaps.read();
+        let mut tap_frame: Option<Frame> = None;
+        for tap in wiretaps.values() {
+            if tap.client.name == sender.name
+                || tap.client.name == target
+                || !tap.matches(&sender.name, kind, target)
+            {
+                continue;
+            }
+            let f = tap_frame.get_or_insert_with(|| {
+                let header = frame.header().unwrap_or_default();
+                let mut buf = Vec::with_capacity(header.len() + frame.payload().len());
+                buf.extend_from_slice(header);
+                buf.extend_from_slice(frame.payload());
+                Arc::new(FrameData {
+                    kind: FrameKind::Publish,
+                    sender: Some(sender.name.clone()),
+                    topic: Some(format!("{}{}", kind, target)),
+                    header: None,
+                    buf,
+                    payload_pos: 0,
+                    realtime: false,
+                    identity: Some(sender.identity.clone()),
+                    timestamp: frame.timestamp,
+                    delivery: None,
+                    memory: None,
+                })
+            });
+            if tap.client.tx.try_send(f.clone()).is_err() {
+                trace!("wiretap frame dropped for {}", tap.client.name);
+            }
+        }
+    }
+    /// Checks if a message to the target can be sent with the delivery mode. Returns false if the
+    /// message is acknowledged as soon as it is queued: internal clients get frames directly from
+    /// their queues
+    fn delivery_tracked(&self, target: &str, mode: DeliveryMode) -> Result<bool, Error> {
+        if mode == DeliveryMode::Queued {
+            return Ok(false);
+        }
+        #[cfg(feature = "rpc")]
+        if target
+            .strip_prefix(RPC_TARGET_PREFIX)
+            .is_some_and(|service| self.rpc_targets.read().contains_key(service))
+        {
+            return Err(Error::not_supported(
+                "delivery confirmations of load-balanced targets",
+            ));
+        }
+        let clients = self.clients.read();
+        let recipient = clients.get(target).ok_or_else(Error::not_registered)?;
+        if mode == DeliveryMode::Delivered {
+            Ok(recipient.kind != ClientKind::Internal)
+        } else if recipient.kind == ClientKind::Internal
+            || recipient.delivery.load(atomic::Ordering::SeqCst)
+        {
+            Ok(true)
+        } else {
+            Err(Error::not_supported(
+                "the recipient has not enabled delivery confirmations",
+            ))
+        }
+    }
+    /// Registers a pending delivery, returns its id. Expired deliveries are failed
+    fn delivery_register(&self, sender: DeliverySender, recipient: &str) -> u32 {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        let id = {
+            let mut deliveries = self.deliveries.lock();
+            if deliveries.next_sweep.is_none_or(|t| now >= t) {
+                let ids: Vec<u32> = deliveries
+                    .pending
+                    .iter()
+                    .filter(|(_, d)| d.expires <= now)
+                    .map(|(id, _)| *id)
+                    .collect();
+                for id in ids {
+                    if let Some(d) = deliveries.pending.remove(&id) {
+                        expired.push(d.sender);
+                    }
+                }
+                deliveries.next_sweep = Some(now + DELIVERY_SWEEP_INTERVAL);
+            }
+            loop {
+                deliveries.next_id = deliveries.next_id.wrapping_add(1);
+                if !deliveries.pending.contains_key(&deliveries.next_id) {
+                    break;
+                }
+            }
+            let id = deliveries.next_id;
+            deliveries.pending.insert(
+                id,
+                PendingDelivery {
+                    sender,
+                    recipient: recipient.to_owned(),
+                    expires: now + DELIVERY_TIMEOUT,
+                },
+            );
+            id
+        };
+        for sender in expired {
+            self.delivery_ack(sender, ERR_TIMEOUT);
+        }
+        id
+    }
+    /// Acknowledges the sender of a pending delivery. If the recipient is specified, the
+    /// delivery must belong to it. Returns false if the delivery is not found
+    fn delivery_resolve(&self, id: u32, recipient: Option<&str>, code: u8) -> bool {
+        let pending = {
+            let mut deliveries = self.deliveries.lock();
+            match deliveries.pending.entry(id) {
+                hash_map::Entry::Occupied(entry)
+                    if recipient.is_none_or(|r| entry.get().recipient == r) =>
+                {
+                    entry.remove()
+                }
+                _ => return false,
+            }
+        };
+        self.delivery_ack(pending.sender, code);
+        true
+    }
+    /// Removes a pending delivery without acknowledging, returns false if the delivery has been
+    /// already resolved
+    fn delivery_cancel(&self, id: u32) -> bool {
+        self.deliveries.lock().pending.remove(&id).is_some()
+    }
+    /// Fails pending deliveries of a disconnected client
+    fn deliveries_fail(&self, recipient: &str) {
+        let failed: Vec<DeliverySender> = {
+            let mut deliveries = self.deliveries.lock();
+            let ids: Vec<u32> = deliveries
+                .pending
+                .iter()
+                .filter(|(_, d)| d.recipient == recipient)
+                .map(|(id, _)| *id)
+                .collect();
+            ids.into_iter()
+                .filter_map(|id| deliveries.pending.remove(&id))
+                .map(|d| d.sender)
+                .collect()
+        };
+        for sender in failed {
+            self.delivery_ack(sender, ERR_NOT_DELIVERED);
+        }
+    }
+    /// Acknowledgements are never blocking, if the sender queue is full, it is going to be
+    /// disconnected anyway
+    fn delivery_ack(&self, sender: DeliverySender, code: u8) {
+        match sender {
+            DeliverySender::Client {
+                client,
+                op_id,
+                realtime,
+            } => {
+                let mut buf = Vec::with_capacity(6);
+                buf.push(OP_ACK);
+                buf.extend_from_slice(&op_id);
+                buf.push(code);
+                client.w_frames.fetch_add(1, atomic::Ordering::SeqCst);
+                client
+                    .w_bytes
+                    .fetch_add(buf.len() as u64, atomic::Ordering::SeqCst);
+                self.w_frames.fetch_add(1, atomic::Ordering::SeqCst);
+                self.w_bytes
+                    .fetch_add(buf.len() as u64, atomic::Ordering::SeqCst);
+                let frame = Arc::new(FrameData {
+                    kind: FrameKind::Prepared,
+                    sender: None,
+                    topic: None,
+                    header: None,
+                    buf,
+                    payload_pos: 0,
+                    realtime,
+                    identity: None,
+                    timestamp: None,
+                    delivery: None,
+                    memory: None,
+                });
+                if client.tx.try_send(frame).is_err() {
+                    warn!("unable to acknowledge the delivery to {}", client);
+                }
+            }
+            DeliverySender::Internal(tx) => {
+                let _r = tx.send(code.to_elbus_result());
+            }
+        }
+    }
     fn drop_client(&self, client: &Arc<ElbusClient>) {
-        self.subscriptions
-            .write()
-            .unwrap()
-            .unregister_client(client);
+        // invalidates the client in send caches of other clients
+        client.registered.store(false, atomic::Ordering::SeqCst);
+        self.wiretap_stop(&client.name);
+        self.deliveries_fail(&client.name);
+        if !client.groups.is_empty() {
+            let mut groups = self.groups.write();
+            for group in &client.groups {
+                if let hash_map::Entry::Occupied(mut entry) = groups.entry(group.clone()) {
+                    entry.get_mut().members.retain(|c| !Arc::ptr_eq(c, client));
+                    if entry.get().members.is_empty() {
+                        entry.remove();
+                    }
+                }
+            }
+        }
+        self.subscriptions.write().unregister_client(client);
         self.broadcasts
             .write()
-            .unwrap()
             .unregister_client(&client.name, client);
-        self.clients.write().unwrap().remove(&client.name);
+        self.clients.write().remove(&client.name);
         if client.primary {
-            let mut secondaries = client.secondaries.lock().unwrap();
+            let mut secondaries = client.secondaries.lock();
             for secondary in secondaries.iter() {
-                let sec = self.clients.read().unwrap().get(secondary).cloned();
+                let sec = self.clients.read().get(secondary).cloned();
                 if let Some(sec) = sec {
-                    if sec.kind != ElbusClientKind::Internal {
+                    if sec.kind != ClientKind::Internal {
                         sec.disconnect_trig.trigger();
                     }
                     self.drop_client(&sec);
                 }
             }
             secondaries.clear();
-        } else if let Some(primary) = self.clients.read().unwrap().get(&client.primary_name) {
-            primary.secondaries.lock().unwrap().remove(&client.name);
+        } else if let Some(primary) = self.clients.read().get(&client.primary_name) {
+            primary.secondaries.lock().remove(&client.name);
         }
     }
 }
 
-pub type AaaMap = Arc<std::sync::Mutex<HashMap<String, ClientAaa>>>;
+pub type AaaMap = Arc<parking_lot::Mutex<HashMap<String, ClientAaa>>>;
 
 #[derive(Debug, Clone)]
 pub struct ServerConfig {
@@ -711,6 +3950,13 @@ pub struct ServerConfig {
     buf_ttl: Duration,
     timeout: Duration,
     aaa_map: Option<AaaMap>,
+    tcp_options: TcpOptions,
+    runtime: Option<tokio::runtime::Handle>,
+    frame_deadline: Option<Duration>,
+    max_frame_size: Option<u32>,
+    // set by spawn_tls_server
+    #[cfg(feature = "tls")]
+    tls: Option<TlsAcceptor>,
 }
 
 impl Default for ServerConfig {
@@ -720,10 +3966,60 @@ impl Default for ServerConfig {
             buf_ttl: crate::DEFAULT_BUF_TTL,
             timeout: crate::DEFAULT_TIMEOUT,
             aaa_map: None,
+            tcp_options: TcpOptions::default(),
+            runtime: None,
+            frame_deadline: None,
+            max_frame_size: None,
+            #[cfg(feature = "tls")]
+            tls: None,
         }
     }
 }
 
+#[cfg(feature = "tls")]
+#[derive(Clone)]
+struct TlsAcceptor(tokio_rustls::TlsAcceptor);
+
+#[cfg(feature = "tls")]
+impl fmt::Debug for TlsAcceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TlsAcceptor")
+    }
+}
+
+#[cfg(feature = "tls")]
+fn tls_acceptor(cert_path: &str, key_path: &str) -> Result<TlsAcceptor, Error> {
+    Ok(TlsAcceptor(tokio_rustls::TlsAcceptor::from(Arc::new(
+        tls_server_config(cert_path, key_path)?,
+    ))))
+}
+
+/// Loads the certificate chain and the private key (RSA, PKCS8 or EC) from PEM files
+#[cfg(feature = "tls")]
+fn tls_server_config(
+    cert_path: &str,
+    key_path: &str,
+) -> Result<tokio_rustls::rustls::ServerConfig, Error> {
+    use rustls_pemfile::Item;
+    use tokio_rustls::rustls;
+    let certs = crate::comm::load_certs(cert_path)?;
+    let mut reader = std::io::BufReader::new(std::fs::File::open(key_path)?);
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key)) => {
+                break rustls::PrivateKey(key);
+            }
+            Some(_) => {}
+            None => return Err(Error::data(format!("no private key found in {}", key_path))),
+        }
+    };
+    rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(Error::data)
+}
+
 impl ServerConfig {
     #[inline]
     pub fn new() -> Self {
@@ -749,6 +4045,34 @@ impl ServerConfig {
         self.aaa_map.replace(aaa_map);
         self
     }
+    /// TCP socket options of accepted connections, ignored for UNIX sockets
+    #[inline]
+    pub fn tcp_options(mut self, tcp_options: TcpOptions) -> Self {
+        self.tcp_options = tcp_options;
+        self
+    }
+    /// Serve the listener (accept loop and client tasks) on a dedicated runtime, e.g. with
+    /// worker threads pinned to specific CPUs
+    #[inline]
+    pub fn runtime(mut self, runtime: tokio::runtime::Handle) -> Self {
+        self.runtime.replace(runtime);
+        self
+    }
+    /// Max time a message, broadcast or publication can wait in the client queue. Frames, which
+    /// are not written in time, are dropped and counted as expired, so a consumer, which
+    /// unblocks after a stall, does not get stale data
+    #[inline]
+    pub fn frame_deadline(mut self, deadline: Duration) -> Self {
+        self.frame_deadline.replace(deadline);
+        self
+    }
+    /// Max size of incoming frames (unlimited by default). Larger frames are skipped and
+    /// acknowledged with the limit error, the connection is kept
+    #[inline]
+    pub fn max_frame_size(mut self, size: u32) -> Self {
+        self.max_frame_size.replace(size);
+        self
+    }
 }
 
 #[allow(clippy::struct_excessive_bools)]
@@ -763,6 +4087,10 @@ pub struct ClientAaa {
     allow_subscribe_any: bool,
     allow_broadcast_to: AclMap,
     allow_broadcast_any: bool,
+    allow_wiretap: bool,
+    allow_admin: bool,
+    // None - all operations are allowed
+    allow_ops: Option<HashSet<FrameOp>>,
 }
 
 impl Default for ClientAaa {
@@ -779,6 +4107,9 @@ impl Default for ClientAaa {
             allow_subscribe_any: true,
             allow_broadcast_to: AclMap::new().separator('.').wildcard("*").match_any("?"),
             allow_broadcast_any: true,
+            allow_wiretap: false,
+            allow_admin: false,
+            allow_ops: None,
         }
     }
 }
@@ -880,12 +4211,36 @@ impl ClientAaa {
         self
     }
     #[inline]
-    pub fn deny_broadcast(mut self) -> Self {
-        self.allow_broadcast_any = false;
-        self.allow_broadcast_to = AclMap::new();
+    pub fn deny_broadcast(mut self) -> Self {
+        self.allow_broadcast_any = false;
+        self.allow_broadcast_to = AclMap::new();
+        self
+    }
+    /// Allow the client to monitor the broker traffic (wiretap)
+    #[inline]
+    pub fn allow_wiretap(mut self) -> Self {
+        self.allow_wiretap = true;
+        self
+    }
+    /// Allow the client to call administrative broker RPC methods (client.drain, benchmark)
+    #[inline]
+    pub fn allow_admin(mut self) -> Self {
+        self.allow_admin = true;
+        self
+    }
+    /// Restricts the client to the frame operations, e.g. subscribe-only monitors or
+    /// publish-only sensors. Other operations are acknowledged with ERR_ACCESS. Pings are always
+    /// allowed
+    #[inline]
+    pub fn allow_ops(mut self, ops: &[FrameOp]) -> Self {
+        self.allow_ops = Some(ops.iter().copied().collect());
         self
     }
     #[inline]
+    fn op_allowed(&self, op: FrameOp) -> bool {
+        self.allow_ops.as_ref().is_none_or(|ops| ops.contains(&op))
+    }
+    #[inline]
     fn connect_allowed(&self, addr: IpAddr) -> bool {
         for h in &self.hosts_allow {
             if h.contains(addr) {
@@ -896,10 +4251,190 @@ impl ClientAaa {
     }
 }
 
+/// Broker state snapshot files start with the magic bytes and the format version (u16 LE),
+/// followed by records: KIND (u8) LEN (u32 LE) FIELDS, each field is prefixed with its length
+/// (u32 LE)
+const SNAPSHOT_MAGIC: [u8; 2] = [0xEB, 0x5A];
+const SNAPSHOT_VERSION: u16 = 1;
+// persistence store pair: TREE KEY VALUE
+const SNAPSHOT_RECORD_PERSISTENCE: u8 = 0x01;
+// client ACL: LISTENER CLIENT FLAGS HOSTS P2P PUBLISH SUBSCRIBE BROADCAST [OPS], lists are
+// newline-separated, OPS are op codes (u8), the field is optional for older snapshots
+const SNAPSHOT_RECORD_ACL: u8 = 0x02;
+
+const ACL_FLAG_P2P_ANY: u8 = 0b0000_0001;
+const ACL_FLAG_PUBLISH_ANY: u8 = 0b0000_0010;
+const ACL_FLAG_SUBSCRIBE_ANY: u8 = 0b0000_0100;
+const ACL_FLAG_BROADCAST_ANY: u8 = 0b0000_1000;
+const ACL_FLAG_WIRETAP: u8 = 0b0001_0000;
+const ACL_FLAG_OPS: u8 = 0b0010_0000;
+const ACL_FLAG_ADMIN: u8 = 0b0100_0000;
+
+fn snapshot_record(buf: &mut Vec<u8>, kind: u8, fields: &[&[u8]]) -> Result<(), Error> {
+    let len = u32::try_from(fields.iter().map(|f| f.len() + 4).sum::<usize>())
+        .map_err(|_| Error::data("snapshot record too large"))?;
+    buf.push(kind);
+    buf.extend_from_slice(&len.to_le_bytes());
+    for field in fields {
+        #[allow(clippy::cast_possible_truncation)]
+        buf.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        buf.extend_from_slice(field);
+    }
+    Ok(())
+}
+
+fn snapshot_fields(mut data: &[u8]) -> Result<Vec<&[u8]>, Error> {
+    let mut fields = Vec::new();
+    while !data.is_empty() {
+        let len = data
+            .get(..4)
+            .map(|l| u32::from_le_bytes(l.try_into().unwrap()) as usize)
+            .ok_or_else(|| Error::data("invalid snapshot record"))?;
+        fields.push(
+            data.get(4..4 + len)
+                .ok_or_else(|| Error::data("invalid snapshot record"))?,
+        );
+        data = &data[4 + len..];
+    }
+    Ok(fields)
+}
+
+impl ClientAaa {
+    fn snapshot_flags(&self) -> u8 {
+        let mut flags = 0;
+        for (set, flag) in [
+            (self.allow_p2p_any, ACL_FLAG_P2P_ANY),
+            (self.allow_publish_any, ACL_FLAG_PUBLISH_ANY),
+            (self.allow_subscribe_any, ACL_FLAG_SUBSCRIBE_ANY),
+            (self.allow_broadcast_any, ACL_FLAG_BROADCAST_ANY),
+            (self.allow_wiretap, ACL_FLAG_WIRETAP),
+            (self.allow_ops.is_some(), ACL_FLAG_OPS),
+            (self.allow_admin, ACL_FLAG_ADMIN),
+        ] {
+            if set {
+                flags |= flag;
+            }
+        }
+        flags
+    }
+    /// Rebuilds the ACL maps if the broker uses custom mask formats. The "any" flags, set by
+    /// builders for the default wildcards, are re-evaluated for the custom ones
+    fn with_formats(mut self, broadcast: &MaskFormat, topic: &MaskFormat) -> Self {
+        for (acl, any, format) in [
+            (&mut self.allow_p2p_to, &mut self.allow_p2p_any, broadcast),
+            (
+                &mut self.allow_broadcast_to,
+                &mut self.allow_broadcast_any,
+                broadcast,
+            ),
+            (
+                &mut self.allow_publish_to,
+                &mut self.allow_publish_any,
+                topic,
+            ),
+            (
+                &mut self.allow_subscribe_to,
+                &mut self.allow_subscribe_any,
+                topic,
+            ),
+        ] {
+            let masks = acl.list();
+            let mut rebuilt = format.acl_map();
+            for mask in &masks {
+                rebuilt.insert(mask);
+            }
+            if !masks.is_empty() {
+                *any = masks.contains(&format.wildcard.as_str());
+            }
+            *acl = rebuilt;
+        }
+        self
+    }
+    fn from_snapshot(flags: u8, lists: [&str; 5], ops: &[u8]) -> Result<Self, Error> {
+        let [hosts, p2p, publish, subscribe, broadcast] = lists.map(|l| {
+            l.split('\n')
+                .filter(|v| !v.is_empty())
+                .collect::<Vec<&str>>()
+        });
+        let mut aaa = ClientAaa::new().hosts_allow(
+            hosts
+                .iter()
+                .map(|h| h.parse().map_err(Error::data))
+                .collect::<Result<Vec<IpNetwork>, Error>>()?,
+        );
+        // the "any" flag is set by builders if a wildcard mask is in the list
+        if !p2p.is_empty() {
+            aaa = aaa.allow_p2p_to(&p2p);
+        } else if flags & ACL_FLAG_P2P_ANY == 0 {
+            aaa = aaa.deny_p2p();
+        }
+        if !publish.is_empty() {
+            aaa = aaa.allow_publish_to(&publish);
+        } else if flags & ACL_FLAG_PUBLISH_ANY == 0 {
+            aaa = aaa.deny_publish();
+        }
+        if !subscribe.is_empty() {
+            aaa = aaa.allow_subscribe_to(&subscribe);
+        } else if flags & ACL_FLAG_SUBSCRIBE_ANY == 0 {
+            aaa = aaa.deny_subscribe();
+        }
+        if !broadcast.is_empty() {
+            aaa = aaa.allow_broadcast_to(&broadcast);
+        } else if flags & ACL_FLAG_BROADCAST_ANY == 0 {
+            aaa = aaa.deny_broadcast();
+        }
+        if flags & ACL_FLAG_WIRETAP != 0 {
+            aaa = aaa.allow_wiretap();
+        }
+        if flags & ACL_FLAG_ADMIN != 0 {
+            aaa = aaa.allow_admin();
+        }
+        if flags & ACL_FLAG_OPS != 0 {
+            aaa = aaa.allow_ops(
+                &ops.iter()
+                    .map(|op| FrameOp::try_from(*op))
+                    .collect::<Result<Vec<FrameOp>, Error>>()?,
+            );
+        }
+        Ok(aaa)
+    }
+}
+
+impl ClientAcl for ClientAaa {
+    #[inline]
+    fn p2p_allowed(&self, target: &str) -> bool {
+        self.allow_p2p_any || self.allow_p2p_to.matches(target)
+    }
+    #[inline]
+    fn publish_allowed(&self, topic: &str) -> bool {
+        self.allow_publish_any || self.allow_publish_to.matches(topic)
+    }
+    #[inline]
+    fn subscribe_allowed(&self, topic: &str) -> bool {
+        self.allow_subscribe_any || self.allow_subscribe_to.matches(topic)
+    }
+    #[inline]
+    fn broadcast_allowed(&self, target: &str) -> bool {
+        self.allow_broadcast_any || self.allow_broadcast_to.matches(target)
+    }
+    #[inline]
+    fn wiretap_allowed(&self) -> bool {
+        self.allow_wiretap
+    }
+    #[inline]
+    fn admin_allowed(&self) -> bool {
+        self.allow_admin
+    }
+}
+
 pub struct Broker {
     db: Arc<BrokerDb>,
     services: Vec<JoinHandle<()>>,
     queue_size: usize,
+    #[cfg(all(unix, feature = "rpc"))]
+    fifo_group: Option<u32>,
+    #[cfg(all(unix, feature = "rpc"))]
+    fifo_payload_dir: Option<std::path::PathBuf>,
 }
 
 #[cfg(feature = "rpc")]
@@ -910,47 +4445,431 @@ struct BrokerRpcHandlers {
 #[cfg(feature = "rpc")]
 const RPC_OK: [u8; 5] = [129, 162, 111, 107, 195];
 
+#[cfg(feature = "rpc")]
+#[derive(Serialize)]
+struct OkReply {
+    ok: bool,
+}
+
+#[cfg(feature = "rpc")]
+const BENCHMARK_MAX_FRAMES: u32 = 100_000;
+#[cfg(feature = "rpc")]
+const DEFAULT_SUBSCRIPTION_STATS_LIMIT: usize = 20;
+#[cfg(feature = "rpc")]
+const DEFAULT_CLIENT_TAP_DURATION: Duration = Duration::from_secs(60);
+#[cfg(feature = "rpc")]
+const BENCHMARK_MAX_FRAME_SIZE: usize = 100_000;
+#[cfg(feature = "rpc")]
+const BENCHMARK_MAX_BYTES: u64 = 100_000_000;
+
+#[cfg(feature = "rpc")]
+fn get_param<T>(params: &HashMap<String, Value>, name: &str) -> Result<T, RpcError>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    params
+        .get(name)
+        .ok_or_else(|| RpcError::params(Some(format!("{} not specified", name).into_bytes())))?
+        .clone()
+        .deserialize_into()
+        .map_err(|e| RpcError::params(Some(RpcError::convert_data(e))))
+}
+
+#[cfg(feature = "rpc")]
+fn get_opt_param<T>(params: &HashMap<String, Value>, name: &str) -> Result<Option<T>, RpcError>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    params
+        .get(name)
+        .map(|v| v.clone().deserialize_into())
+        .transpose()
+        .map_err(|e| RpcError::params(Some(RpcError::convert_data(e))))
+}
+
+/// FIFO command token
+#[cfg(all(unix, feature = "rpc"))]
+struct FifoToken {
+    value: String,
+    // quoted tokens are always processed as literals
+    quoted: bool,
+}
+
+/// Splits a FIFO command line into tokens. Tokens, which start with a single or a double quote,
+/// are quoted till the closing one, backslash escapes are processed in double quotes only
+#[cfg(all(unix, feature = "rpc"))]
+fn fifo_tokens(line: &str) -> Result<Vec<FifoToken>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = line.trim().chars();
+    let mut current: Option<String> = None;
+    while let Some(ch) = chars.next() {
+        match ch {
+            ' ' | '\t' => {
+                if let Some(value) = current.take() {
+                    tokens.push(FifoToken {
+                        value,
+                        quoted: false,
+                    });
+                }
+            }
+            '"' | '\'' if current.is_none() => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some(c) if c == ch => break,
+                        Some('\\') if ch == '"' => {
+                            value.push(chars.next().ok_or_else(|| Error::data("unclosed quote"))?);
+                        }
+                        Some(c) => value.push(c),
+                        None => return Err(Error::data("unclosed quote")),
+                    }
+                }
+                if !matches!(chars.next(), None | Some(' ' | '\t')) {
+                    return Err(Error::data("a quoted token must be followed by a space"));
+                }
+                tokens.push(FifoToken {
+                    value,
+                    quoted: true,
+                });
+            }
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(value) = current {
+        tokens.push(FifoToken {
+            value,
+            quoted: false,
+        });
+    }
+    Ok(tokens)
+}
+
+#[cfg(all(unix, feature = "rpc"))]
+fn fifo_no_more_tokens<'a>(mut tokens: impl Iterator<Item = &'a FifoToken>) -> Result<(), Error> {
+    if tokens.next().is_some() {
+        Err(Error::data(
+            "too many payload tokens, multi-word payloads must be quoted",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Reads a FIFO payload file, which must be in the payload directory
+#[cfg(all(unix, feature = "rpc"))]
+async fn fifo_file(dir: Option<&std::path::Path>, path: &str) -> Result<Vec<u8>, Error> {
+    let dir = dir.ok_or_else(|| Error::access("file payloads are not allowed"))?;
+    let path = tokio::fs::canonicalize(dir.join(path)).await?;
+    if !path.starts_with(dir) {
+        return Err(Error::access(format!(
+            "{} is outside of the payload directory",
+            path.display()
+        )));
+    }
+    tokio::fs::read(path).await.map_err(Into::into)
+}
+
+/// Decodes a FIFO payload: @file, 0xHEX or a literal
+#[cfg(all(unix, feature = "rpc"))]
+async fn fifo_payload(
+    token: &FifoToken,
+    value: &str,
+    dir: Option<&std::path::Path>,
+) -> Result<Vec<u8>, Error> {
+    if token.quoted {
+        Ok(value.as_bytes().to_vec())
+    } else if let Some(path) = value.strip_prefix('@') {
+        fifo_file(dir, path).await
+    } else if let Some(hex) = value.strip_prefix("0x") {
+        decode_hex(hex)
+    } else {
+        Ok(value.as_bytes().to_vec())
+    }
+}
+
+#[cfg(all(unix, feature = "rpc"))]
+fn decode_hex(s: &str) -> Result<Vec<u8>, Error> {
+    if !s.len().is_multiple_of(2) {
+        return Err(Error::data("invalid hex payload length"));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(|| Error::data("invalid hex payload"))
+        })
+        .collect()
+}
+
+#[cfg(all(unix, feature = "rpc"))]
+fn fifo_json_params(s: &str) -> Result<Vec<u8>, Error> {
+    let params: serde_json::Value = serde_json::from_str(s).map_err(Error::data)?;
+    rmp_serde::to_vec_named(&params).map_err(Error::data)
+}
+
+#[cfg(all(unix, feature = "rpc"))]
+type FifoCallResult = Result<RpcEvent, RpcError>;
+
+/// Prepares a JSON line for the FIFO response channel
+#[cfg(all(unix, feature = "rpc"))]
+fn fifo_response(line: &str, result: Result<Option<FifoCallResult>, Error>) -> serde_json::Value {
+    let mut response = serde_json::Map::new();
+    response.insert("cmd".to_owned(), line.trim().into());
+    match result {
+        Ok(None) => {
+            response.insert("ok".to_owned(), true.into());
+        }
+        Ok(Some(Ok(event))) => {
+            let result = if event.payload().is_empty() {
+                serde_json::Value::Null
+            } else {
+                event
+                    .unpack()
+                    .unwrap_or_else(|_| String::from_utf8_lossy(event.payload()).into())
+            };
+            response.insert("ok".to_owned(), true.into());
+            response.insert("result".to_owned(), result);
+        }
+        Ok(Some(Err(e))) => {
+            response.insert("ok".to_owned(), false.into());
+            response.insert("code".to_owned(), e.code().into());
+            if let Some(data) = e.data().filter(|d| !d.is_empty()) {
+                response.insert("error".to_owned(), String::from_utf8_lossy(data).into());
+            }
+        }
+        Err(e) => {
+            response.insert("ok".to_owned(), false.into());
+            response.insert("error".to_owned(), e.to_string().into());
+        }
+    }
+    response.into()
+}
+
+#[cfg(feature = "rpc")]
+impl BrokerRpcHandlers {
+    /// Sends n notifications of the specified size to the caller
+    async fn benchmark(&self, target: &str, n: u32, size: usize) -> Result<BrokerBenchmark, Error> {
+        let client = self
+            .db
+            .rpc_client
+            .lock()
+            .await
+            .as_ref()
+            .map(Rpc::client)
+            .ok_or_else(|| Error::not_supported("core RPC client is not set"))?;
+        let payload = Arc::new(vec![0xee; size]);
+        let started = Instant::now();
+        // the client is locked for each frame only, so other core RPC calls and fifo commands
+        // are not blocked for the run
+        for _ in 0..n {
+            let opc = client
+                .lock()
+                .await
+                .zc_send(
+                    target,
+                    (&[RPC_NOTIFICATION][..]).into(),
+                    payload.clone().into(),
+                    QoS::Processed,
+                )
+                .await?;
+            if let Some(c) = opc {
+                c.await??;
+            }
+        }
+        Ok(BrokerBenchmark {
+            n,
+            size,
+            elapsed: started.elapsed().as_secs_f64(),
+        })
+    }
+}
+
 #[cfg(feature = "rpc")]
 #[async_trait]
 impl RpcHandlers for BrokerRpcHandlers {
     async fn handle_call(&self, event: RpcEvent) -> RpcResult {
         let method = event.parse_method()?;
-        if method == "benchmark.test" {
+        if method == "echo" || method == "benchmark.test" {
             return Ok(Some(event.payload().to_vec()));
         }
         let payload = event.payload();
         let params: HashMap<String, Value> = if payload.is_empty() {
             HashMap::new()
         } else {
-            rmp_serde::from_slice(event.payload())?
+            event.unpack()?
         };
         match event.parse_method()? {
             "test" => {
                 if !params.is_empty() {
                     return Err(RpcError::params(None));
                 }
-                Ok(Some(RPC_OK.to_vec()))
+                if event.content_type()? == ContentType::MsgPack {
+                    Ok(Some(RPC_OK.to_vec()))
+                } else {
+                    event.pack(&OkReply { ok: true })
+                }
             }
             "info" => {
                 if !params.is_empty() {
                     return Err(RpcError::params(None));
                 }
-                Ok(Some(rmp_serde::to_vec_named(&Broker::info())?))
+                event.pack(&Broker::info())
+            }
+            "capabilities" => {
+                if !params.is_empty() {
+                    return Err(RpcError::params(None));
+                }
+                event.pack(&self.db.capabilities())
             }
             "stats" => {
                 if !params.is_empty() {
                     return Err(RpcError::params(None));
                 }
-                Ok(Some(rmp_serde::to_vec_named(&self.db.stats())?))
+                event.pack(&self.db.stats())
+            }
+            "listener.list" => {
+                if !params.is_empty() {
+                    return Err(RpcError::params(None));
+                }
+                event.pack(&ListenerList {
+                    listeners: self.db.listener_list(),
+                })
+            }
+            "topic.list" => {
+                if !params.is_empty() {
+                    return Err(RpcError::params(None));
+                }
+                let sdb = self.db.subscriptions.read();
+                let mut topics: Vec<String> = sdb
+                    .list_clients()
+                    .iter()
+                    .flat_map(|c| sdb.list_topics(c))
+                    .map(ToOwned::to_owned)
+                    .collect();
+                topics.sort();
+                topics.dedup();
+                event.pack(&TopicList { topics })
+            }
+            "subscription.stats" => {
+                let limit: Option<usize> = get_opt_param(&params, "limit")?;
+                event.pack(
+                    &self
+                        .db
+                        .subscription_stats(limit.unwrap_or(DEFAULT_SUBSCRIPTION_STATS_LIMIT)),
+                )
+            }
+            "benchmark" => {
+                if !event
+                    .sender_identity()
+                    .is_some_and(ClientIdentity::admin_allowed)
+                {
+                    return Err(Error::access("benchmark is not allowed").into());
+                }
+                let n: u32 = get_param(&params, "n")?;
+                let size: usize = get_param(&params, "size")?;
+                if n > BENCHMARK_MAX_FRAMES
+                    || size > BENCHMARK_MAX_FRAME_SIZE
+                    || u64::from(n) * size as u64 > BENCHMARK_MAX_BYTES
+                {
+                    return Err(RpcError::params(Some(
+                        "benchmark params are out of range".as_bytes().to_vec(),
+                    )));
+                }
+                event.pack(&self.benchmark(event.sender(), n, size).await?)
+            }
+            "wiretap.start" => {
+                if !event
+                    .sender_identity()
+                    .is_some_and(ClientIdentity::wiretap_allowed)
+                {
+                    return Err(Error::access("wiretap is not allowed").into());
+                }
+                let client_mask: Option<String> = get_opt_param(&params, "client")?;
+                let topic_mask: Option<String> = get_opt_param(&params, "topic")?;
+                let client = self
+                    .db
+                    .clients
+                    .read()
+                    .get(event.sender())
+                    .cloned()
+                    .ok_or_else(Error::not_registered)?;
+                self.db
+                    .wiretap_start(client, client_mask.as_deref(), topic_mask.as_deref());
+                Ok(None)
+            }
+            "subscription.throttle" => {
+                let mask: String = get_param(&params, "mask")?;
+                let every: Option<u64> = get_opt_param(&params, "every")?;
+                let interval: Option<f64> = get_opt_param(&params, "interval")?;
+                let throttle = match (every, interval) {
+                    (Some(_), Some(_)) => {
+                        return Err(RpcError::params(Some(
+                            "every and interval can not be set together"
+                                .as_bytes()
+                                .to_vec(),
+                        )));
+                    }
+                    (Some(n), None) => Some(DeliveryThrottle::Sample(n)),
+                    (None, Some(interval)) => Some(DeliveryThrottle::Interval(
+                        Duration::try_from_secs_f64(interval).map_err(Error::data)?,
+                    )),
+                    (None, None) => None,
+                };
+                let client = self
+                    .db
+                    .clients
+                    .read()
+                    .get(event.sender())
+                    .cloned()
+                    .ok_or_else(Error::not_registered)?;
+                self.db.set_throttle(&client, &mask, throttle)?;
+                Ok(None)
+            }
+            "client.tap" => {
+                // clients without ACLs are denied
+                if !event
+                    .sender_identity()
+                    .is_some_and(ClientIdentity::wiretap_allowed)
+                {
+                    return Err(Error::access("wiretap is not allowed").into());
+                }
+                let name: String = get_param(&params, "name")?;
+                let duration: Option<f64> = get_opt_param(&params, "duration")?;
+                let duration = if let Some(d) = duration {
+                    Duration::try_from_secs_f64(d).map_err(Error::data)?
+                } else {
+                    DEFAULT_CLIENT_TAP_DURATION
+                };
+                if name == event.sender() {
+                    return Err(Error::data("the client can not tap itself").into());
+                }
+                let (client, admin) = {
+                    let clients = self.db.clients.read();
+                    (
+                        clients.get(&name).cloned(),
+                        clients.get(event.sender()).cloned(),
+                    )
+                };
+                let client = client.ok_or_else(Error::not_registered)?;
+                let admin = admin.ok_or_else(Error::not_registered)?;
+                client.set_tap(admin, duration);
+                Ok(None)
+            }
+            "wiretap.stop" => {
+                if !params.is_empty() {
+                    return Err(RpcError::params(None));
+                }
+                self.db.wiretap_stop(event.sender());
+                Ok(None)
             }
             "client.list" => {
                 if !params.is_empty() {
                     return Err(RpcError::params(None));
                 }
-                let db = self.db.clients.read().unwrap();
+                let db = self.db.clients.read();
                 let mut clients: Vec<ClientInfo> = db
                     .values()
-                    .into_iter()
                     .filter(|c| c.primary)
                     .map(|v| ClientInfo {
                         name: &v.name,
@@ -961,96 +4880,728 @@ impl RpcHandlers for BrokerRpcHandlers {
                         r_bytes: v.r_bytes.load(atomic::Ordering::SeqCst),
                         w_frames: v.w_frames.load(atomic::Ordering::SeqCst),
                         w_bytes: v.w_bytes.load(atomic::Ordering::SeqCst),
+                        w_expired: v.w_expired.load(atomic::Ordering::SeqCst),
                         queue: v.tx.len(),
-                        instances: v.secondaries.lock().unwrap().len() + 1,
+                        instances: v.secondaries.lock().len() + 1,
+                        groups: v.groups.iter().map(String::as_str).collect(),
+                        metadata: v
+                            .metadata
+                            .iter()
+                            .map(|(k, v)| (k.as_str(), v.as_str()))
+                            .collect(),
+                        health: v.health.lock().clone(),
                     })
                     .collect();
                 clients.sort();
-                Ok(Some(rmp_serde::to_vec_named(&ClientList { clients })?))
+                event.pack(&ClientList { clients })
+            }
+            #[cfg(unix)]
+            "channel.open" => {
+                let target: String = get_param(&params, "target")?;
+                let path: String = get_param(&params, "path")?;
+                if !event
+                    .sender_identity()
+                    .is_some_and(|i| i.p2p_allowed(&target))
+                {
+                    return Err(
+                        Error::access(format!("channels to {} are not allowed", target)).into(),
+                    );
+                }
+                let (client, target_client) = {
+                    let clients = self.db.clients.read();
+                    (
+                        clients.get(event.sender()).cloned(),
+                        clients.get(&target).cloned(),
+                    )
+                };
+                let client = client.ok_or_else(Error::not_registered)?;
+                let target_client = target_client.ok_or_else(Error::not_registered)?;
+                if !client.is_local() || !target_client.is_local() {
+                    return Err(Error::not_supported(
+                        "direct channels are available for clients on the broker host only",
+                    )
+                    .into());
+                }
+                // the socket must be bound by the caller before the channel is offered
+                if !tokio::fs::metadata(&path)
+                    .await
+                    .is_ok_and(|m| m.file_type().is_socket())
+                {
+                    return Err(Error::data(format!("{} is not a socket", path)).into());
+                }
+                let token = channel_token();
+                let offer = ChannelOffer {
+                    client: event.sender().to_owned(),
+                    path,
+                    token: token.clone(),
+                };
+                let payload = rmp_serde::to_vec_named(&offer).map_err(Error::data)?;
+                {
+                    let rpc_client = self.db.rpc_client.lock().await;
+                    let rpc = rpc_client
+                        .as_ref()
+                        .ok_or_else(|| Error::not_supported(BROKER_RPC_NOT_INIT_ERR))?;
+                    rpc.notify(&target, payload.into(), QoS::Processed).await?;
+                }
+                trace!("elbus channel {} -> {} offered", offer.client, target);
+                event.pack(&ChannelToken { token })
+            }
+            "namespace.claim" => {
+                let prefix: String = get_param(&params, "prefix")?;
+                if prefix.is_empty() || self.db.topic_format.is_wildcard_mask(&prefix) {
+                    return Err(Error::data(format!("invalid namespace prefix: {}", prefix)).into());
+                }
+                if !event
+                    .sender_identity()
+                    .is_some_and(|i| i.publish_allowed(&prefix))
+                {
+                    return Err(
+                        Error::access(format!("namespace {} can not be claimed", prefix)).into(),
+                    );
+                }
+                let owner = event.sender();
+                {
+                    let mut namespaces = self.db.namespaces.write();
+                    if let Some((p, o)) = namespaces.iter().find(|(p, o)| {
+                        *o != owner && (p.starts_with(&prefix) || prefix.starts_with(p.as_str()))
+                    }) {
+                        return Err(Error::busy(format!(
+                            "namespace {} is owned by {} ({})",
+                            prefix, o, p
+                        ))
+                        .into());
+                    }
+                    namespaces.insert(prefix.clone(), owner.to_owned());
+                }
+                debug!("elbus namespace {} claimed by {}", prefix, owner);
+                let mut subscriptions = Vec::new();
+                {
+                    let sdb = self.db.subscriptions.read();
+                    for client in sdb.list_clients() {
+                        if client.name == owner {
+                            continue;
+                        }
+                        for mask in sdb.list_topics(&client) {
+                            if self.db.in_namespace(&prefix, mask) {
+                                subscriptions.push(NamespaceEvent {
+                                    subject: "subscribe".to_owned(),
+                                    client: client.name.clone(),
+                                    topic: mask.to_owned(),
+                                });
+                            }
+                        }
+                    }
+                }
+                event.pack(&NamespaceSubscriptions { subscriptions })
+            }
+            "namespace.release" => {
+                let prefix: String = get_param(&params, "prefix")?;
+                let mut namespaces = self.db.namespaces.write();
+                match namespaces.get(&prefix) {
+                    Some(owner) if owner == event.sender() => {
+                        namespaces.remove(&prefix);
+                        debug!("elbus namespace {} released", prefix);
+                        Ok(None)
+                    }
+                    Some(_) => Err(Error::access(format!(
+                        "namespace {} is owned by another client",
+                        prefix
+                    ))
+                    .into()),
+                    None => Err(Error::data(format!("namespace {} is not claimed", prefix)).into()),
+                }
+            }
+            "namespace.list" => {
+                if !params.is_empty() {
+                    return Err(RpcError::params(None));
+                }
+                let namespaces = self
+                    .db
+                    .namespaces
+                    .read()
+                    .iter()
+                    .map(|(prefix, owner)| NamespaceInfo {
+                        prefix: prefix.clone(),
+                        owner: owner.clone(),
+                    })
+                    .collect();
+                event.pack(&NamespaceList { namespaces })
+            }
+            "service.register" => {
+                let name: String = get_param(&params, "name")?;
+                let methods: Vec<String> = get_opt_param(&params, "methods")?.unwrap_or_default();
+                let tags: Vec<String> = get_opt_param(&params, "tags")?.unwrap_or_default();
+                let balance: Option<RpcBalance> = get_opt_param(&params, "balance")?;
+                if name.is_empty()
+                    || methods.iter().any(String::is_empty)
+                    || tags.iter().any(String::is_empty)
+                {
+                    return Err(
+                        Error::data("service name, methods and tags can not be empty").into(),
+                    );
+                }
+                let provider = event.sender().to_owned();
+                let client = self
+                    .db
+                    .clients
+                    .read()
+                    .get(&provider)
+                    .cloned()
+                    .ok_or_else(Error::not_registered)?;
+                let balance = self.db.rpc_target_add(&name, &client, balance)?;
+                debug!("elbus service {} registered by {}", name, provider);
+                self.db.services.write().insert(
+                    (provider.clone(), name.clone()),
+                    ServiceInfo {
+                        name,
+                        provider,
+                        methods,
+                        tags,
+                        balance,
+                    },
+                );
+                Ok(None)
+            }
+            "service.unregister" => {
+                let name: String = get_param(&params, "name")?;
+                if self
+                    .db
+                    .services
+                    .write()
+                    .remove(&(event.sender().to_owned(), name.clone()))
+                    .is_some()
+                {
+                    if let Some(client) = self.db.clients.read().get(event.sender()) {
+                        self.db.rpc_target_remove(Some(&name), client);
+                    }
+                    debug!("elbus service {} unregistered by {}", name, event.sender());
+                    Ok(None)
+                } else {
+                    Err(Error::data(format!("service {} is not registered", name)).into())
+                }
+            }
+            "service.discover" => {
+                let tag: Option<String> = get_opt_param(&params, "tag")?;
+                let name: Option<String> = get_opt_param(&params, "name")?;
+                let method: Option<String> = get_opt_param(&params, "method")?;
+                let mut services: Vec<ServiceInfo> = self
+                    .db
+                    .services
+                    .read()
+                    .values()
+                    .filter(|s| {
+                        tag.as_ref().is_none_or(|t| s.tags.contains(t))
+                            && name.as_ref().is_none_or(|n| s.name == *n)
+                            && method.as_ref().is_none_or(|m| s.methods.contains(m))
+                    })
+                    .cloned()
+                    .collect();
+                services.sort_by(|a, b| a.name.cmp(&b.name).then(a.provider.cmp(&b.provider)));
+                event.pack(&ServiceList { services })
+            }
+            "client.drain" => {
+                let name: String = get_param(&params, "name")?;
+                let timeout: Option<f64> = get_opt_param(&params, "timeout")?;
+                if !event
+                    .sender_identity()
+                    .is_some_and(ClientIdentity::admin_allowed)
+                {
+                    return Err(Error::access(format!("{} can not be drained", name)).into());
+                }
+                let timeout = if let Some(t) = timeout {
+                    Duration::try_from_secs_f64(t).map_err(Error::data)?
+                } else {
+                    DEFAULT_DRAIN_TIMEOUT
+                };
+                let started = Instant::now();
+                // the client is disconnected even if the call is abandoned
+                let db = self.db.clone();
+                let pending = tokio::spawn(async move { db.drain_client(&name, timeout).await })
+                    .await
+                    .map_err(Error::io)??;
+                event.pack(&ClientDrain {
+                    pending,
+                    elapsed: started.elapsed().as_secs_f64(),
+                })
             }
             _ => Err(RpcError::method(None)),
         }
     }
-    async fn handle_notification(&self, _event: RpcEvent) {}
-    async fn handle_frame(&self, _frame: Frame) {}
+    async fn handle_notification(&self, _event: RpcEvent) {}
+    async fn handle_frame(&self, _frame: Frame) {}
+}
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_wraps)]
+#[inline]
+fn prepare_unix_stream(_stream: &UnixStream, _config: &ServerConfig) -> Result<(), Error> {
+    Ok(())
+}
+
+#[inline]
+fn prepare_tcp_stream(stream: &TcpStream, config: &ServerConfig) -> Result<(), Error> {
+    config.tcp_options.apply(stream)
+}
+
+#[allow(clippy::unnecessary_wraps)]
+fn prepare_tcp_source(addr: &SocketAddr) -> Option<String> {
+    Some(addr.to_string())
+}
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_wraps)]
+fn prepare_unix_source(_addr: &tokio::net::unix::SocketAddr) -> Option<String> {
+    None
+}
+
+#[cfg(unix)]
+async fn bind_unix(path: &str) -> Result<UnixListener, Error> {
+    let _r = tokio::fs::remove_file(path).await;
+    UnixListener::bind(path).map_err(Into::into)
+}
+
+/// Generates a direct channel token (128 bits, hex-encoded), hasher keys are random
+#[cfg(all(unix, feature = "rpc"))]
+fn channel_token() -> String {
+    let mut token = String::with_capacity(crate::channel::CHANNEL_TOKEN_LEN);
+    for _ in 0..2 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(now_ns());
+        token.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    token
+}
+
+/// Parses a UDP datagram, returns the operation, QoS and the target length
+fn parse_datagram(datagram: &[u8]) -> Result<(FrameOp, QoS, usize), ProtocolError> {
+    if datagram.len() < 9 {
+        return Err(ProtocolError::Malformed);
+    }
+    let flags = datagram[4];
+    let qos = QoS::try_from(flags >> 6).map_err(|_| ProtocolError::Malformed)?;
+    if qos.needs_ack() {
+        return Err(ProtocolError::Malformed);
+    }
+    let op = match FrameOp::try_from(flags & 0b0011_1111) {
+        Ok(op @ (FrameOp::Message | FrameOp::Broadcast | FrameOp::PublishTopic)) => op,
+        _ => return Err(ProtocolError::UnknownOp),
+    };
+    let len = u32::from_le_bytes(datagram[5..9].try_into().unwrap());
+    let buf = &datagram[9..];
+    if len as usize != buf.len() {
+        return Err(ProtocolError::Malformed);
+    }
+    let tgt_len = buf
+        .iter()
+        .position(|c| *c == 0)
+        .ok_or(ProtocolError::Malformed)?;
+    if std::str::from_utf8(&buf[..tgt_len]).is_err() {
+        return Err(ProtocolError::Utf8);
+    }
+    Ok((op, qos, tgt_len))
+}
+
+#[inline]
+async fn bind_tcp(path: &str) -> Result<TcpListener, Error> {
+    TcpListener::bind(path).await.map_err(Into::into)
+}
+
+#[cfg(feature = "tls")]
+#[inline]
+async fn bind_tls(path: &str) -> Result<TcpListener, Error> {
+    bind_tcp(path.strip_prefix(TLS_PREFIX).unwrap_or(path)).await
+}
+
+#[cfg(unix)]
+#[allow(clippy::unused_async)]
+async fn split_unix_stream(
+    stream: UnixStream,
+    _config: &ServerConfig,
+) -> Result<
+    (
+        tokio::net::unix::OwnedReadHalf,
+        tokio::net::unix::OwnedWriteHalf,
+    ),
+    Error,
+> {
+    Ok(stream.into_split())
+}
+
+#[allow(clippy::unused_async)]
+async fn split_tcp_stream(
+    stream: TcpStream,
+    _config: &ServerConfig,
+) -> Result<
+    (
+        tokio::net::tcp::OwnedReadHalf,
+        tokio::net::tcp::OwnedWriteHalf,
+    ),
+    Error,
+> {
+    Ok(stream.into_split())
+}
+
+#[cfg(feature = "websocket")]
+#[inline]
+async fn bind_ws(path: &str) -> Result<TcpListener, Error> {
+    bind_tcp(path.strip_prefix(WS_PREFIX).unwrap_or(path)).await
+}
+
+#[cfg(feature = "websocket")]
+type WsStream = tokio_tungstenite::WebSocketStream<TcpStream>;
+
+#[cfg(feature = "websocket")]
+async fn split_ws_stream(
+    stream: TcpStream,
+    config: &ServerConfig,
+) -> Result<
+    (
+        crate::comm::WsReader<futures_util::stream::SplitStream<WsStream>>,
+        crate::comm::WsWriter<
+            futures_util::stream::SplitSink<WsStream, tokio_tungstenite::tungstenite::Message>,
+        >,
+    ),
+    Error,
+> {
+    use futures_util::StreamExt;
+    let stream = time::timeout(config.timeout, tokio_tungstenite::accept_async(stream))
+        .await?
+        .map_err(Error::io)?;
+    let (sink, stream) = stream.split();
+    Ok((
+        crate::comm::WsReader::new(stream),
+        crate::comm::WsWriter::new(sink),
+    ))
+}
+
+/// The listener is accepted by a single loop, the lock is required as tokio-vsock accepts
+/// connections with a mutable reference
+#[cfg(feature = "vsock")]
+struct VsockListener(tokio::sync::Mutex<tokio_vsock::VsockListener>);
+
+#[cfg(feature = "vsock")]
+impl VsockListener {
+    async fn accept(&self) -> std::io::Result<(tokio_vsock::VsockStream, tokio_vsock::VsockAddr)> {
+        self.0.lock().await.accept().await
+    }
 }
 
+#[cfg(feature = "vsock")]
+#[allow(clippy::unused_async)]
+async fn bind_vsock(path: &str) -> Result<VsockListener, Error> {
+    let (cid, port) = parse_vsock_addr(path)?;
+    let listener = tokio_vsock::VsockListener::bind(cid, port)?;
+    Ok(VsockListener(tokio::sync::Mutex::new(listener)))
+}
+
+#[cfg(feature = "vsock")]
 #[allow(clippy::unnecessary_wraps)]
-#[inline]
-fn prepare_unix_stream(_stream: &UnixStream) -> Result<(), Error> {
+fn prepare_vsock_stream(
+    _stream: &tokio_vsock::VsockStream,
+    _config: &ServerConfig,
+) -> Result<(), Error> {
     Ok(())
 }
 
-#[inline]
-fn prepare_tcp_stream(stream: &TcpStream) -> Result<(), Error> {
-    stream.set_nodelay(true).map_err(Into::into)
+#[cfg(feature = "vsock")]
+#[allow(clippy::unnecessary_wraps)]
+fn prepare_vsock_source(addr: &tokio_vsock::VsockAddr) -> Option<String> {
+    Some(format!("{}{}:{}", VSOCK_PREFIX, addr.cid(), addr.port()))
+}
+
+#[cfg(feature = "vsock")]
+#[allow(clippy::unused_async)]
+async fn split_vsock_stream(
+    stream: tokio_vsock::VsockStream,
+    _config: &ServerConfig,
+) -> Result<
+    (
+        tokio::io::ReadHalf<tokio_vsock::VsockStream>,
+        tokio::io::WriteHalf<tokio_vsock::VsockStream>,
+    ),
+    Error,
+> {
+    Ok(tokio::io::split(stream))
+}
+
+/// Named pipe clients have no addresses
+#[cfg(windows)]
+#[derive(Debug)]
+struct NamedPipeAddr;
+
+/// A named pipe instance serves a single client, the next instance is created as soon as the
+/// current one is connected, so there is always an instance new clients can open
+#[cfg(windows)]
+struct NamedPipeListener {
+    path: String,
+    server: tokio::sync::Mutex<NamedPipeServer>,
+}
+
+#[cfg(windows)]
+impl NamedPipeListener {
+    async fn accept(&self) -> std::io::Result<(NamedPipeServer, NamedPipeAddr)> {
+        let mut server = self.server.lock().await;
+        server.connect().await?;
+        let next = ServerOptions::new().create(&self.path)?;
+        Ok((std::mem::replace(&mut *server, next), NamedPipeAddr))
+    }
+}
+
+#[cfg(windows)]
+#[allow(clippy::unused_async)]
+async fn bind_named_pipe(path: &str) -> Result<NamedPipeListener, Error> {
+    // the first instance fails if the pipe is already served by another process
+    let server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(path)?;
+    Ok(NamedPipeListener {
+        path: path.to_owned(),
+        server: tokio::sync::Mutex::new(server),
+    })
 }
 
+#[cfg(windows)]
 #[allow(clippy::unnecessary_wraps)]
-fn prepare_tcp_source(addr: &SocketAddr) -> Option<String> {
-    Some(addr.to_string())
+fn prepare_named_pipe_stream(
+    _stream: &NamedPipeServer,
+    _config: &ServerConfig,
+) -> Result<(), Error> {
+    Ok(())
 }
 
+#[cfg(windows)]
 #[allow(clippy::unnecessary_wraps)]
-fn prepare_unix_source(_addr: &tokio::net::unix::SocketAddr) -> Option<String> {
+fn prepare_named_pipe_source(_addr: &NamedPipeAddr) -> Option<String> {
     None
 }
 
+#[cfg(windows)]
+#[allow(clippy::unused_async)]
+async fn split_named_pipe_stream(
+    stream: NamedPipeServer,
+    _config: &ServerConfig,
+) -> Result<
+    (
+        tokio::io::ReadHalf<NamedPipeServer>,
+        tokio::io::WriteHalf<NamedPipeServer>,
+    ),
+    Error,
+> {
+    Ok(tokio::io::split(stream))
+}
+
+#[cfg(feature = "tls")]
+type TlsStream = tokio_rustls::server::TlsStream<TcpStream>;
+
+#[cfg(feature = "tls")]
+async fn split_tls_stream(
+    stream: TcpStream,
+    config: &ServerConfig,
+) -> Result<
+    (
+        tokio::io::ReadHalf<TlsStream>,
+        tokio::io::WriteHalf<TlsStream>,
+    ),
+    Error,
+> {
+    let acceptor = config
+        .tls
+        .as_ref()
+        .ok_or_else(|| Error::not_supported("TLS is not configured"))?;
+    let stream = time::timeout(config.timeout, acceptor.0.accept(stream)).await??;
+    Ok(tokio::io::split(stream))
+}
+
+/// Aborts the task when dropped, used to stop accept loops together with their supervisors
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Listener accept loops are supervised: if a loop crashes or gets too many accept errors in a
+/// row, the listener is re-bound and the loop is restarted with backoff
 macro_rules! spawn_server {
     ($self: expr, $path: expr, $listener: expr, $config: expr,
-     $kind: expr, $prepare: ident, $prepare_source: ident) => {{
+     $kind: expr, $bind: ident, $prepare: ident, $prepare_source: ident, $split: ident) => {{
         let socket_path = $path.to_owned();
         let db = $self.db.clone();
         let queue_size = $self.queue_size;
+        db.listener_state(&socket_path, None);
+        if let Some(ref aaa_map) = $config.aaa_map {
+            db.aaa_maps
+                .write()
+                .insert(socket_path.clone(), aaa_map.clone());
+        }
         let service = tokio::spawn(async move {
+            let mut listener = Some($listener);
+            let mut backoff = LISTENER_RESTART_DELAY_MIN;
             loop {
-                match $listener.accept().await {
-                    Ok((stream, addr)) => {
-                        trace!("elbus client connected from {:?} to {}", addr, socket_path);
-                        if let Err(e) = $prepare(&stream) {
-                            error!("{}", e);
+                let listener = if let Some(l) = listener.take() {
+                    l
+                } else {
+                    match $bind(&socket_path).await {
+                        Ok(l) => {
+                            warn!("listener {} restarted", socket_path);
+                            l
+                        }
+                        Err(e) => {
+                            error!("unable to restart listener {}: {}", socket_path, e);
+                            db.listener_state(&socket_path, Some(e.to_string()));
+                            tokio::time::sleep(backoff).await;
+                            backoff = (backoff * 2).min(LISTENER_RESTART_DELAY_MAX);
                             continue;
                         }
-                        let (reader, writer) = stream.into_split();
-                        let reader = BufReader::with_capacity($config.buf_size, reader);
-                        let writer = TtlBufWriter::new(
-                            writer,
-                            $config.buf_size,
-                            $config.buf_ttl,
-                            $config.timeout,
-                        );
-                        let cdb = db.clone();
-                        let name = socket_path.clone();
-                        let client_source = $prepare_source(&addr);
-                        let client_path = socket_path.clone();
-                        let aaa_map = $config.aaa_map.clone();
-                        tokio::spawn(async move {
-                            if let Err(e) = Self::handle_peer(PeerHandlerParams {
-                                db: cdb,
-                                reader,
-                                writer,
-                                timeout: $config.timeout,
-                                aaa_map,
-                                ip: addr.into(),
-                                queue_size,
-                                kind: $kind,
-                                source: client_source,
-                                source_port: Some(client_path),
-                            })
-                            .await
-                            {
-                                pretty_error!(name, e);
+                    }
+                };
+                db.listener_state(&socket_path, None);
+                let started = Instant::now();
+                let config = $config.clone();
+                let a_db = db.clone();
+                let a_path = socket_path.clone();
+                let runtime = config.runtime.clone();
+                let accept_fut = async move {
+                    let mut errors = 0;
+                    loop {
+                        match listener.accept().await {
+                            Ok((stream, addr)) => {
+                                errors = 0;
+                                trace!("elbus client connected from {:?} to {}", addr, a_path);
+                                if let Err(e) = $prepare(&stream, &config) {
+                                    error!("{}", e);
+                                    continue;
+                                }
+                                let cdb = a_db.clone();
+                                let name = a_path.clone();
+                                let client_source = $prepare_source(&addr);
+                                let client_path = a_path.clone();
+                                let config = config.clone();
+                                tokio::spawn(async move {
+                                    // streams are split (and TLS handshakes are performed) by
+                                    // client tasks, not to block the accept loop
+                                    let (reader, writer) = match $split(stream, &config).await {
+                                        Ok(v) => v,
+                                        Err(e) => {
+                                            pretty_error!(name, e);
+                                            return;
+                                        }
+                                    };
+                                    let reader = BufReader::with_capacity(config.buf_size, reader);
+                                    let writer = TtlBufWriter::new(
+                                        writer,
+                                        config.buf_size,
+                                        config.buf_ttl,
+                                        config.timeout,
+                                    );
+                                    if let Err(e) = Self::handle_peer(PeerHandlerParams {
+                                        db: cdb,
+                                        reader,
+                                        writer,
+                                        timeout: config.timeout,
+                                        aaa_map: config.aaa_map,
+                                        ip: addr.into(),
+                                        queue_size,
+                                        kind: $kind,
+                                        source: client_source,
+                                        source_port: Some(client_path),
+                                        frame_deadline: config.frame_deadline,
+                                        max_frame_size: config.max_frame_size,
+                                    })
+                                    .await
+                                    {
+                                        pretty_error!(name, e);
+                                    }
+                                });
                             }
-                        });
+                            Err(e) => {
+                                error!("{}: {}", a_path, e);
+                                errors += 1;
+                                if errors >= LISTENER_ACCEPT_ERRORS_MAX {
+                                    return Error::from(e);
+                                }
+                                // e.g. out of file descriptors, give the system some time
+                                tokio::time::sleep(LISTENER_ACCEPT_ERROR_DELAY).await;
+                            }
+                        }
                     }
-                    Err(e) => error!("{}", e),
+                };
+                // client tasks are spawned by the accept loop, so they run on the same runtime
+                let mut accept_loop = AbortOnDrop(if let Some(rt) = runtime {
+                    rt.spawn(accept_fut)
+                } else {
+                    tokio::spawn(accept_fut)
+                });
+                let err = match (&mut accept_loop.0).await {
+                    Ok(e) => e.to_string(),
+                    Err(e) => format!("listener task crashed: {}", e),
+                };
+                error!("listener {} failed: {}", socket_path, err);
+                db.listener_state(&socket_path, Some(err));
+                if started.elapsed() > LISTENER_RESTART_DELAY_MAX {
+                    backoff = LISTENER_RESTART_DELAY_MIN;
                 }
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(LISTENER_RESTART_DELAY_MAX);
             }
         });
         $self.services.push(service);
     }};
 }
 
+/// The client hello: the name with groups to join (separated with zero bytes), the token and the
+/// extension block
+struct Hello {
+    name: Vec<u8>,
+    token: Option<Vec<u8>>,
+    extensions: Option<Vec<u8>>,
+}
+
+/// Reads the client hello, invalid token and extension block lengths are returned as data errors
+async fn read_hello<R>(reader: &mut R, timeout: Duration) -> Result<Hello, Error>
+where
+    R: AsyncReadExt + Unpin,
+{
+    let mut buf = [0; 2];
+    time::timeout(timeout, reader.read_exact(&mut buf)).await??;
+    let len = u16::from_le_bytes(buf);
+    let mut name = vec![0; (len & !(HELLO_EXTENSIONS_FLAG | HELLO_TOKEN_FLAG)) as usize];
+    time::timeout(timeout, reader.read_exact(&mut name)).await??;
+    // the token is sent after the name, before the extension block
+    let token = if len & HELLO_TOKEN_FLAG == 0 {
+        None
+    } else {
+        let mut token_len = [0; 2];
+        time::timeout(timeout, reader.read_exact(&mut token_len)).await??;
+        let token_len = usize::from(u16::from_le_bytes(token_len));
+        if token_len == 0 || token_len > HELLO_TOKEN_MAX_LEN {
+            return Err(Error::data("invalid token length"));
+        }
+        let mut token = vec![0; token_len];
+        time::timeout(timeout, reader.read_exact(&mut token)).await??;
+        Some(token)
+    };
+    let extensions = if len & HELLO_EXTENSIONS_FLAG == 0 {
+        None
+    } else {
+        let mut ext_len = [0; 4];
+        time::timeout(timeout, reader.read_exact(&mut ext_len)).await??;
+        let ext_len = u32::from_le_bytes(ext_len);
+        if ext_len > HELLO_EXTENSIONS_MAX_SIZE {
+            return Err(Error::data("hello extension block is too large"));
+        }
+        let mut ext = vec![0; ext_len as usize];
+        time::timeout(timeout, reader.read_exact(&mut ext)).await??;
+        Some(ext)
+    };
+    Ok(Hello {
+        name,
+        token,
+        extensions,
+    })
+}
+
 struct PeerHandlerParams<R, W>
 where
     R: AsyncReadExt + Unpin,
@@ -1063,9 +5614,11 @@ where
     aaa_map: Option<AaaMap>,
     ip: ClientIp,
     queue_size: usize,
-    kind: ElbusClientKind,
+    kind: ClientKind,
     source: Option<String>,
     source_port: Option<String>,
+    frame_deadline: Option<Duration>,
+    max_frame_size: Option<u32>,
 }
 
 enum ClientIp {
@@ -1073,6 +5626,7 @@ enum ClientIp {
     Addr(IpAddr),
 }
 
+#[cfg(unix)]
 impl From<tokio::net::unix::SocketAddr> for ClientIp {
     fn from(_addr: tokio::net::unix::SocketAddr) -> Self {
         Self::No
@@ -1085,12 +5639,30 @@ impl From<std::net::SocketAddr> for ClientIp {
     }
 }
 
+#[cfg(feature = "vsock")]
+impl From<tokio_vsock::VsockAddr> for ClientIp {
+    fn from(_addr: tokio_vsock::VsockAddr) -> Self {
+        Self::No
+    }
+}
+
+#[cfg(windows)]
+impl From<NamedPipeAddr> for ClientIp {
+    fn from(_addr: NamedPipeAddr) -> Self {
+        Self::No
+    }
+}
+
 impl Default for Broker {
     fn default() -> Self {
         Self {
             db: <_>::default(),
             services: <_>::default(),
             queue_size: DEFAULT_QUEUE_SIZE,
+            #[cfg(all(unix, feature = "rpc"))]
+            fifo_group: None,
+            #[cfg(all(unix, feature = "rpc"))]
+            fifo_payload_dir: None,
         }
     }
 }
@@ -1099,10 +5671,43 @@ impl Broker {
     pub fn new() -> Self {
         Self::default()
     }
+    /// Creates a broker with custom separators and wildcards of broadcast masks and topics
+    pub fn with_mask_formats(broadcast: MaskFormat, topic: MaskFormat) -> Result<Self, Error> {
+        broadcast.validate()?;
+        topic.validate()?;
+        Ok(Self {
+            db: Arc::new(BrokerDb::new(broadcast, topic)),
+            services: <_>::default(),
+            queue_size: DEFAULT_QUEUE_SIZE,
+            #[cfg(all(unix, feature = "rpc"))]
+            fifo_group: None,
+            #[cfg(all(unix, feature = "rpc"))]
+            fifo_payload_dir: None,
+        })
+    }
     #[inline]
     pub fn stats(&self) -> BrokerStats {
         self.db.stats()
     }
+    /// The number of connected clients, including internal and secondary ones
+    #[inline]
+    pub fn client_count(&self) -> usize {
+        self.db.clients.read().len()
+    }
+    /// Stops all spawned servers (listeners and fifo channels), connected clients are kept
+    pub fn drain(&mut self) {
+        for service in self.services.drain(..) {
+            service.abort();
+        }
+        for state in self.db.listeners.write().values_mut() {
+            state.active = false;
+        }
+    }
+    /// Listener health: active or not, number of restarts, the last error
+    #[inline]
+    pub fn listeners(&self) -> Vec<ListenerInfo> {
+        self.db.listener_list()
+    }
     #[inline]
     pub fn info<'a>() -> BrokerInfo<'a> {
         BrokerInfo {
@@ -1110,6 +5715,11 @@ impl Broker {
             version: crate::VERSION,
         }
     }
+    /// Versions, compiled-in features, listeners and limits of the broker
+    #[inline]
+    pub fn capabilities(&self) -> BrokerCapabilities {
+        self.db.capabilities()
+    }
     #[cfg(feature = "rpc")]
     pub async fn init_default_core_rpc(&self) -> Result<(), Error> {
         let client = self.register_client(BROKER_NAME).await?;
@@ -1121,8 +5731,328 @@ impl Broker {
         Ok(())
     }
     #[inline]
+    /// Fifo channels, spawned after the call, are writable (response fifos - readable) by the
+    /// group (by the owner only if not set)
+    #[cfg(all(unix, feature = "rpc"))]
+    pub fn set_fifo_group(&mut self, gid: Option<u32>) {
+        self.fifo_group = gid;
+    }
+    /// Allows fifo commands of channels, spawned after the call, to load payloads from files
+    /// (@FILE) in the directory. Relative paths are resolved in the directory, files outside of
+    /// it are denied. If not set, file payloads are denied
+    #[cfg(all(unix, feature = "rpc"))]
+    pub fn set_fifo_payload_dir(&mut self, dir: Option<&str>) -> Result<(), Error> {
+        self.fifo_payload_dir = dir.map(std::fs::canonicalize).transpose()?;
+        Ok(())
+    }
     pub fn set_queue_size(&mut self, queue_size: usize) {
         self.queue_size = queue_size;
+        self.db
+            .queue_size
+            .store(queue_size, atomic::Ordering::SeqCst);
+    }
+    /// Sets the number of deliveries a client may route before its task yields (0 - unlimited).
+    /// Large fan-outs are split into cooperative chunks, so publications to topics with many
+    /// subscribers do not delay frames of other clients
+    pub fn set_fanout_budget(&mut self, budget: usize) {
+        self.db
+            .fanout_budget
+            .store(budget, atomic::Ordering::SeqCst);
+    }
+    /// Sets the global cap for bytes, buffered in client queues (0 - unlimited). When exceeded,
+    /// frames for external clients are processed according to the policy and the memory
+    /// pressure warning is published to .broker/warn (requires rpc feature)
+    pub fn set_memory_limit(&mut self, limit: u64, policy: MemoryOverflowPolicy) {
+        self.db.memory_limit.store(limit, atomic::Ordering::SeqCst);
+        *self.db.memory_overflow_policy.write() = policy;
+    }
+    /// Sets per-client subscription caps, protecting the subscription tree from abusive
+    /// clients. A subscribe request, which exceeds the limits, is rejected as a whole with
+    /// ERR_LIMIT
+    pub fn set_subscription_limits(&mut self, limits: SubscriptionLimits) {
+        *self.db.subscription_limits.write() = limits;
+    }
+    /// Stamps routed frames (messages, broadcasts, publications) with the receive time, so
+    /// consumers can measure end-to-end latency and order events without trusting producer
+    /// clocks. The timestamp is available with Frame::timestamp
+    pub fn set_frame_timestamps(&mut self, enabled: bool) {
+        self.db
+            .frame_timestamps
+            .store(enabled, atomic::Ordering::SeqCst);
+    }
+    /// Injects latency, reordering and drops into the delivery path (requires "testing"
+    /// feature). The rules are checked when frames leave client queues
+    #[cfg(feature = "testing")]
+    pub fn add_chaos_rule(&mut self, rule: ChaosRule) -> Result<(), Error> {
+        self.db
+            .chaos
+            .add(rule, &self.db.broadcast_format, &self.db.topic_format)
+    }
+    #[cfg(feature = "testing")]
+    pub fn clear_chaos_rules(&mut self) {
+        self.db.chaos.clear();
+    }
+    /// Sets the validator, which checks names of external clients when they connect, in addition
+    /// to the built-in rules (the name must be non-empty and must not start with a dot)
+    pub fn set_client_name_validator(&mut self, validator: Arc<dyn ClientNameValidator>) {
+        self.db.client_name_validator.write().replace(validator);
+    }
+    /// Sets the authenticator of external clients, replaces the token verifier if set.
+    /// Internal clients are not checked.
+    ///
+    /// WebSocket gateways and UDP listeners can not authenticate clients and refuse to start if
+    /// the authenticator is set, so it must be set before the listeners are spawned
+    pub fn set_authenticator(&mut self, authenticator: Arc<dyn Authenticator>) {
+        let unauthenticated = |path: &str| {
+            #[cfg(all(feature = "websocket", feature = "rpc"))]
+            if path.starts_with(WS_GATEWAY_PREFIX) {
+                return true;
+            }
+            path.starts_with(UDP_PREFIX)
+        };
+        if self.db.listeners.read().keys().any(|p| unauthenticated(p)) {
+            warn!(
+                "gateway/UDP listeners, spawned before the authenticator is set, are not checked"
+            );
+        }
+        self.db.authenticator.write().replace(authenticator);
+    }
+    /// Enables token authentication: external clients must present a token, accepted by the
+    /// verifier, otherwise they get ERR_ACCESS and are disconnected. Replaces the authenticator
+    /// if set
+    pub fn set_token_verifier(&mut self, verifier: Arc<dyn TokenVerifier>) {
+        self.set_authenticator(Arc::new(TokenAuthenticator(verifier)));
+    }
+    /// Enables token authentication with the list of valid tokens
+    pub fn set_tokens(&mut self, tokens: &[&str]) {
+        let list = tokens
+            .iter()
+            .fold(TokenList::new(), |list, token| list.token(token));
+        self.set_token_verifier(Arc::new(list));
+    }
+    /// Reports broker and client counters to the metrics backend with the interval. By default,
+    /// metrics are not collected, the values can be obtained with stats and client.list only
+    pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics>, interval: Duration) {
+        let db = self.db.clone();
+        let service = tokio::spawn(async move {
+            let mut int = time::interval(interval);
+            int.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+            loop {
+                int.tick().await;
+                metrics.report(&db.metrics());
+            }
+        });
+        self.services.push(service);
+    }
+    /// Spawns the watchdog, which checks client writers with the interval. A client is recycled
+    /// (force disconnected with the "stalled" reason) if its queue has not been empty and the
+    /// writer has not taken a frame from it for the stall timeout. Diagnostics are logged and
+    /// the "stalled" event is published to .broker/overload. Internal clients are not checked
+    pub fn set_watchdog(&mut self, interval: Duration, stall_timeout: Duration) {
+        let db = self.db.clone();
+        let service = tokio::spawn(async move {
+            let mut int = time::interval(interval);
+            int.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+            // clients with non-empty queues: the writer counter and the time it has been seen
+            let mut suspects: HashMap<String, (u64, Instant)> = HashMap::new();
+            loop {
+                int.tick().await;
+                let clients: Vec<BrokerClient> = db
+                    .clients
+                    .read()
+                    .values()
+                    .filter(|c| c.kind != ClientKind::Internal)
+                    .cloned()
+                    .collect();
+                let mut checked = HashMap::with_capacity(suspects.len());
+                let mut stalled = Vec::new();
+                for client in clients {
+                    let queued = client.tx.len();
+                    if queued == 0 {
+                        continue;
+                    }
+                    let taken = client.w_taken.load(atomic::Ordering::SeqCst);
+                    let since = match suspects.remove(&client.name) {
+                        Some((t, since)) if t == taken => since,
+                        _ => Instant::now(),
+                    };
+                    if since.elapsed() >= stall_timeout {
+                        stalled.push((client, queued, since.elapsed()));
+                    } else {
+                        checked.insert(client.name.clone(), (taken, since));
+                    }
+                }
+                suspects = checked;
+                for (client, queued, elapsed) in stalled {
+                    warn!(
+                        "watchdog: client {} writer is stuck for {:?}, recycling \
+                        (kind: {}, source: {}, queued: {}, r_frames: {}, w_frames: {})",
+                        client,
+                        elapsed,
+                        client.kind,
+                        client.source.as_deref().unwrap_or_default(),
+                        queued,
+                        client.r_frames.load(atomic::Ordering::SeqCst),
+                        client.w_frames.load(atomic::Ordering::SeqCst)
+                    );
+                    db.report(BROKER_OVERLOAD_TOPIC, "stalled", None, Some(&client.name));
+                    db.unregister_client(&client, DisconnectReason::Stalled)
+                        .await;
+                    client.tx.close();
+                    client.disconnect_trig.trigger();
+                }
+            }
+        });
+        self.services.push(service);
+    }
+    /// Sets the storage for durability features (default: in-memory)
+    pub fn set_persistence(&mut self, persistence: Arc<dyn Persistence>) {
+        *self.db.persistence.write() = persistence;
+    }
+    #[inline]
+    pub fn persistence(&self) -> Arc<dyn Persistence> {
+        self.db.persistence.read().clone()
+    }
+    /// Subscription tree diagnostics, helps to find wildcard subscriptions which slow down
+    /// matching. The limit is the max number of the most subscribed masks returned
+    pub fn subscription_stats(&self, limit: usize) -> SubscriptionStats {
+        self.db.subscription_stats(limit)
+    }
+    /// Saves the broker state to the file: all trees of the persistence store and client ACLs of
+    /// the spawned servers. The file is replaced atomically
+    pub fn snapshot(&self, path: &str) -> Result<(), Error> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&SNAPSHOT_MAGIC);
+        buf.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        let persistence = self.persistence();
+        for tree in persistence.trees()? {
+            for (key, value) in persistence.list(&tree, &[])? {
+                snapshot_record(
+                    &mut buf,
+                    SNAPSHOT_RECORD_PERSISTENCE,
+                    &[tree.as_bytes(), &key, &value],
+                )?;
+            }
+        }
+        for (listener, aaa_map) in self.db.aaa_maps.read().iter() {
+            for (name, aaa) in aaa_map.lock().iter() {
+                let hosts = aaa
+                    .hosts_allow
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                snapshot_record(
+                    &mut buf,
+                    SNAPSHOT_RECORD_ACL,
+                    &[
+                        listener.as_bytes(),
+                        name.as_bytes(),
+                        &[aaa.snapshot_flags()],
+                        hosts.as_bytes(),
+                        aaa.allow_p2p_to.list().join("\n").as_bytes(),
+                        aaa.allow_publish_to.list().join("\n").as_bytes(),
+                        aaa.allow_subscribe_to.list().join("\n").as_bytes(),
+                        aaa.allow_broadcast_to.list().join("\n").as_bytes(),
+                        &aaa.allow_ops
+                            .as_ref()
+                            .map(|ops| ops.iter().map(|op| *op as u8).collect::<Vec<u8>>())
+                            .unwrap_or_default(),
+                    ],
+                )?;
+            }
+        }
+        let tmp_path = format!("{}.tmp", path);
+        std::fs::write(&tmp_path, buf).map_err(Error::io)?;
+        std::fs::rename(&tmp_path, path).map_err(Error::io)
+    }
+    /// Loads the broker state, saved with snapshot. Persistence store trees, which are present in
+    /// the snapshot, are replaced. Client ACLs replace ones of the spawned servers with the same
+    /// listener paths, so the method must be called after the servers are spawned. Nothing is
+    /// changed if the file is invalid
+    pub fn restore(&self, path: &str) -> Result<(), Error> {
+        let data = std::fs::read(path).map_err(Error::io)?;
+        if data.get(..2) != Some(&SNAPSHOT_MAGIC[..]) || data.len() < 4 {
+            return Err(Error::data("not an elbus snapshot file"));
+        }
+        let version = u16::from_le_bytes([data[2], data[3]]);
+        if version != SNAPSHOT_VERSION {
+            return Err(Error::not_supported(format!(
+                "unsupported snapshot version: {}",
+                version
+            )));
+        }
+        let mut pairs = Vec::new();
+        let mut acls: HashMap<&str, HashMap<String, ClientAaa>> = HashMap::new();
+        let mut pos = 4;
+        while pos < data.len() {
+            let (kind, len) = data
+                .get(pos..pos + 5)
+                .map(|h| {
+                    (
+                        h[0],
+                        u32::from_le_bytes(h[1..].try_into().unwrap()) as usize,
+                    )
+                })
+                .ok_or_else(|| Error::data("invalid snapshot record"))?;
+            let body = data
+                .get(pos + 5..pos + 5 + len)
+                .ok_or_else(|| Error::data("invalid snapshot record"))?;
+            pos += 5 + len;
+            let fields = snapshot_fields(body)?;
+            match (kind, fields.as_slice()) {
+                (SNAPSHOT_RECORD_PERSISTENCE, [tree, key, value]) => {
+                    pairs.push((
+                        std::str::from_utf8(tree).map_err(Error::data)?,
+                        *key,
+                        *value,
+                    ));
+                }
+                (SNAPSHOT_RECORD_ACL, [listener, name, flags, lists @ ..])
+                    if flags.len() == 1 && (lists.len() == 5 || lists.len() == 6) =>
+                {
+                    let mut l = [""; 5];
+                    for (s, field) in l.iter_mut().zip(&lists[..5]) {
+                        *s = std::str::from_utf8(field).map_err(Error::data)?;
+                    }
+                    acls.entry(std::str::from_utf8(listener).map_err(Error::data)?)
+                        .or_default()
+                        .insert(
+                            std::str::from_utf8(name).map_err(Error::data)?.to_owned(),
+                            ClientAaa::from_snapshot(
+                                flags[0],
+                                l,
+                                lists.get(5).copied().unwrap_or_default(),
+                            )?,
+                        );
+                }
+                (SNAPSHOT_RECORD_PERSISTENCE | SNAPSHOT_RECORD_ACL, _) => {
+                    return Err(Error::data("invalid snapshot record"));
+                }
+                _ => debug!("unknown snapshot record {:x} skipped", kind),
+            }
+        }
+        let persistence = self.persistence();
+        for tree in pairs
+            .iter()
+            .map(|(tree, _, _)| *tree)
+            .collect::<HashSet<&str>>()
+        {
+            persistence.clear(tree)?;
+        }
+        for (tree, key, value) in pairs {
+            persistence.insert(tree, key, value)?;
+        }
+        persistence.flush()?;
+        let aaa_maps = self.db.aaa_maps.read();
+        for (listener, map) in acls {
+            if let Some(aaa_map) = aaa_maps.get(listener) {
+                *aaa_map.lock() = map;
+            } else {
+                warn!("snapshot ACLs for {} skipped: no such listener", listener);
+            }
+        }
+        Ok(())
     }
     #[cfg(feature = "rpc")]
     #[inline]
@@ -1139,45 +6069,80 @@ impl Broker {
     pub async fn announce(&self, event: BrokerEvent<'_>) -> Result<(), Error> {
         self.db.announce(event).await
     }
+    #[inline]
     pub async fn register_client(&self, name: &str) -> Result<Client, Error> {
+        self.register_client_with_groups(name, &[]).await
+    }
+    /// Registers an internal client, which joins the groups
+    pub async fn register_client_with_groups(
+        &self,
+        name: &str,
+        groups: &[&str],
+    ) -> Result<Client, Error> {
+        Self::register_internal_client(
+            &self.db,
+            self.queue_size,
+            name,
+            groups,
+            ClientKind::Internal,
+            None,
+            None,
+        )
+        .await
+    }
+    async fn register_internal_client(
+        db: &Arc<BrokerDb>,
+        queue_size: usize,
+        name: &str,
+        groups: &[&str],
+        kind: ClientKind,
+        source: Option<String>,
+        port: Option<String>,
+    ) -> Result<Client, Error> {
+        if groups.iter().any(|g| g.is_empty()) {
+            return Err(Error::data("empty group name"));
+        }
         let client_primary_name = name
             .find(SECONDARY_SEP)
             .map_or_else(|| name, |pos| &name[..pos]);
         let (c, rx, _) = ElbusClient::new(
             name,
             client_primary_name,
-            self.queue_size,
-            ElbusClientKind::Internal,
-            None,
+            queue_size,
+            kind,
+            source,
+            port,
             None,
+            groups.iter().map(|&g| g.to_owned()).collect(),
         );
         let client = Arc::new(c);
-        self.db.register_client(client.clone()).await?;
+        db.register_client(client.clone()).await?;
         Ok(Client {
             name: name.to_owned(),
             client,
-            db: self.db.clone(),
+            db: db.clone(),
             rx: Some(rx),
             secondary_counter: atomic::AtomicUsize::new(0),
         })
     }
-    /// # Panics
-    ///
-    /// Will panic if the client's secondaries mutex is poisoned
     pub async fn register_secondary_for(&self, client: &Client) -> Result<Client, Error> {
         if client.client.primary {
             let secondary_id = client
                 .secondary_counter
                 .fetch_add(1, atomic::Ordering::SeqCst);
             let secondary_name = format!("{}{}{}", client.client.name, SECONDARY_SEP, secondary_id);
-            self.register_client(&secondary_name).await
+            let groups: Vec<&str> = client.client.groups.iter().map(String::as_str).collect();
+            self.register_client_with_groups(&secondary_name, &groups)
+                .await
         } else {
             Err(Error::not_supported("not a primary client"))
         }
     }
     #[inline]
     pub async fn unregister_client(&self, client: &Client) {
-        self.db.unregister_client(&client.client).await;
+        self.db
+            .unregister_client(&client.client, DisconnectReason::Unregistered)
+            .await;
     }
     #[inline]
     /// Force disconnect a client
@@ -1189,69 +6154,780 @@ impl Broker {
     pub fn force_disconnect(&self, name: &str) -> Result<(), Error> {
         self.db.trigger_disconnect(name)
     }
-    pub async fn spawn_unix_server(
+    /// Gracefully disconnects the client: new frames are no longer queued for it (group
+    /// round-robin skips it), the queue is flushed, then the client is disconnected. Returns the
+    /// number of frames left in the queue if the timeout has been expired
+    pub async fn drain_client(&self, name: &str, timeout: Duration) -> Result<usize, Error> {
+        self.db.drain_client(name, timeout).await
+    }
+    #[cfg(unix)]
+    pub async fn spawn_unix_server(
+        &mut self,
+        path: &str,
+        config: ServerConfig,
+    ) -> Result<(), Error> {
+        let listener = bind_unix(path).await?;
+        spawn_server!(
+            self,
+            path,
+            listener,
+            config,
+            ClientKind::LocalIpc,
+            bind_unix,
+            prepare_unix_stream,
+            prepare_unix_source,
+            split_unix_stream
+        );
+        Ok(())
+    }
+    /// Spawns a Windows named pipe server, the path must start with \\.\pipe\. Clients are
+    /// served as local IPC ones
+    #[cfg(windows)]
+    pub async fn spawn_named_pipe_server(
+        &mut self,
+        path: &str,
+        config: ServerConfig,
+    ) -> Result<(), Error> {
+        if !path.starts_with(NAMED_PIPE_PREFIX) {
+            return Err(Error::data(format!(
+                "invalid named pipe path: {}, {}NAME expected",
+                path, NAMED_PIPE_PREFIX
+            )));
+        }
+        let listener = bind_named_pipe(path).await?;
+        spawn_server!(
+            self,
+            path,
+            listener,
+            config,
+            ClientKind::LocalIpc,
+            bind_named_pipe,
+            prepare_named_pipe_stream,
+            prepare_named_pipe_source,
+            split_named_pipe_stream
+        );
+        Ok(())
+    }
+    pub async fn spawn_tcp_server(
+        &mut self,
+        path: &str,
+        config: ServerConfig,
+    ) -> Result<(), Error> {
+        let listener = bind_tcp(path).await?;
+        spawn_server!(
+            self,
+            path,
+            listener,
+            config,
+            ClientKind::Tcp,
+            bind_tcp,
+            prepare_tcp_stream,
+            prepare_tcp_source,
+            split_tcp_stream
+        );
+        Ok(())
+    }
+    /// Spawns a TLS server (requires "tls" feature), the certificate chain and the private key
+    /// are loaded from PEM files. Clients are served as TCP ones, the listener is reported as
+    /// "tls://ADDR"
+    #[cfg(feature = "tls")]
+    pub async fn spawn_tls_server(
+        &mut self,
+        path: &str,
+        cert_path: &str,
+        key_path: &str,
+        mut config: ServerConfig,
+    ) -> Result<(), Error> {
+        config.tls.replace(tls_acceptor(cert_path, key_path)?);
+        let path = format!(
+            "{}{}",
+            TLS_PREFIX,
+            path.strip_prefix(TLS_PREFIX).unwrap_or(path)
+        );
+        let listener = bind_tls(&path).await?;
+        spawn_server!(
+            self,
+            path,
+            listener,
+            config,
+            ClientKind::Tcp,
+            bind_tls,
+            prepare_tcp_stream,
+            prepare_tcp_source,
+            split_tls_stream
+        );
+        Ok(())
+    }
+    /// Spawns a QUIC server (requires "quic" feature), the certificate chain and the private key
+    /// are loaded from PEM files. Each bidirectional stream of a connection is served as a
+    /// separate client, so secondary clients can share the connection of the primary one. A
+    /// client must send the greeting byte first, as QUIC streams are not visible to the peer
+    /// until data is sent. Clients are served as TCP ones, the listener is reported as
+    /// "quic://ADDR"
+    #[cfg(feature = "quic")]
+    pub async fn spawn_quic_server(
+        &mut self,
+        path: &str,
+        cert_path: &str,
+        key_path: &str,
+        config: ServerConfig,
+    ) -> Result<(), Error> {
+        let addr = path.strip_prefix(QUIC_PREFIX).unwrap_or(path);
+        let path = format!("{}{}", QUIC_PREFIX, addr);
+        let addr = tokio::net::lookup_host(addr)
+            .await?
+            .next()
+            .ok_or_else(|| Error::data(format!("unable to resolve {}", addr)))?;
+        let mut crypto = tls_server_config(cert_path, key_path)?;
+        crypto.alpn_protocols = vec![crate::comm::QUIC_ALPN.to_vec()];
+        let endpoint =
+            quinn::Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(crypto)), addr)?;
+        let db = self.db.clone();
+        let queue_size = self.queue_size;
+        db.listener_state(&path, None);
+        if let Some(ref aaa_map) = config.aaa_map {
+            db.aaa_maps.write().insert(path.clone(), aaa_map.clone());
+        }
+        let service = tokio::spawn(async move {
+            while let Some(connecting) = endpoint.accept().await {
+                let db = db.clone();
+                let path = path.clone();
+                let config = config.clone();
+                tokio::spawn(async move {
+                    let conn = match time::timeout(config.timeout, connecting).await {
+                        Ok(Ok(conn)) => conn,
+                        Ok(Err(e)) => {
+                            error!("{}: {}", path, e);
+                            return;
+                        }
+                        Err(e) => {
+                            error!("{}: {}", path, e);
+                            return;
+                        }
+                    };
+                    let addr = conn.remote_address();
+                    trace!("elbus QUIC connection from {:?} to {}", addr, path);
+                    loop {
+                        let (writer, mut reader) = match conn.accept_bi().await {
+                            Ok(v) => v,
+                            Err(e) => {
+                                trace!("{}: QUIC connection from {} closed: {}", path, addr, e);
+                                break;
+                            }
+                        };
+                        let db = db.clone();
+                        let path = path.clone();
+                        let config = config.clone();
+                        tokio::spawn(async move {
+                            let mut buf = [0_u8; 1];
+                            let result = time::timeout(config.timeout, reader.read_exact(&mut buf))
+                                .await
+                                .map_err(Into::into)
+                                .and_then(|r| r.map_err(Error::io));
+                            let result = match result {
+                                Ok(()) if buf == GREETINGS => {
+                                    Self::handle_peer(PeerHandlerParams {
+                                        db,
+                                        reader: BufReader::with_capacity(config.buf_size, reader),
+                                        writer: TtlBufWriter::new(
+                                            writer,
+                                            config.buf_size,
+                                            config.buf_ttl,
+                                            config.timeout,
+                                        ),
+                                        timeout: config.timeout,
+                                        aaa_map: config.aaa_map,
+                                        ip: addr.into(),
+                                        queue_size,
+                                        kind: ClientKind::Tcp,
+                                        source: prepare_tcp_source(&addr),
+                                        source_port: Some(path.clone()),
+                                        frame_deadline: config.frame_deadline,
+                                        max_frame_size: config.max_frame_size,
+                                    })
+                                    .await
+                                }
+                                Ok(()) => Err(Error::not_supported("Invalid greetings")),
+                                Err(e) => Err(e),
+                            };
+                            if let Err(e) = result {
+                                pretty_error!(path, e);
+                            }
+                        });
+                    }
+                });
+            }
+        });
+        self.services.push(service);
+        Ok(())
+    }
+    /// Spawns a WebSocket server (requires "websocket" feature), the protocol byte stream is
+    /// carried in binary messages, message boundaries are not significant. Clients are served as
+    /// TCP ones, the listener is reported as "ws://ADDR"
+    #[cfg(feature = "websocket")]
+    pub async fn spawn_websocket_server(
         &mut self,
         path: &str,
         config: ServerConfig,
     ) -> Result<(), Error> {
-        let _r = tokio::fs::remove_file(path).await;
-        let listener = UnixListener::bind(path)?;
+        let path = format!(
+            "{}{}",
+            WS_PREFIX,
+            path.strip_prefix(WS_PREFIX).unwrap_or(path)
+        );
+        let listener = bind_ws(&path).await?;
         spawn_server!(
             self,
             path,
             listener,
             config,
-            ElbusClientKind::LocalIpc,
-            prepare_unix_stream,
-            prepare_unix_source
+            ClientKind::Tcp,
+            bind_ws,
+            prepare_tcp_stream,
+            prepare_tcp_source,
+            split_ws_stream
         );
         Ok(())
     }
-    pub async fn spawn_tcp_server(
+    /// Spawns an AF_VSOCK server (requires "vsock" feature, Linux only), so virtual machine
+    /// guests can connect to a broker on the hypervisor host without networking. The path is
+    /// CID:PORT (the CID can be "any"), the listener is reported as "vsock://CID:PORT".
+    /// Clients are served as local IPC ones, the client source is "vsock://GUEST_CID:PORT"
+    #[cfg(feature = "vsock")]
+    pub async fn spawn_vsock_server(
         &mut self,
         path: &str,
         config: ServerConfig,
     ) -> Result<(), Error> {
-        let listener = TcpListener::bind(path).await?;
+        let path = format!(
+            "{}{}",
+            VSOCK_PREFIX,
+            path.strip_prefix(VSOCK_PREFIX).unwrap_or(path)
+        );
+        let listener = bind_vsock(&path).await?;
         spawn_server!(
             self,
             path,
             listener,
             config,
-            ElbusClientKind::Tcp,
-            prepare_tcp_stream,
-            prepare_tcp_source
+            ClientKind::LocalIpc,
+            bind_vsock,
+            prepare_vsock_stream,
+            prepare_vsock_source,
+            split_vsock_stream
         );
         Ok(())
     }
-    #[allow(clippy::items_after_statements)]
+    /// Spawns a TCP server, which uses io_uring for peer I/O (requires "uring" feature, Linux
+    /// only). The listener and its clients are served by a dedicated thread with a
+    /// single-threaded io_uring runtime, the listener is reported as "uring://ADDR". Reads are
+    /// submitted to the ring directly, writes are passed to a local task of the runtime. TLS is
+    /// not supported, the runtime of the server config is ignored
+    ///
+    /// When the listener is stopped, connected clients are kept until they disconnect
+    #[cfg(all(target_os = "linux", feature = "uring"))]
+    pub async fn spawn_uring_server(
+        &mut self,
+        path: &str,
+        config: ServerConfig,
+    ) -> Result<(), Error> {
+        let addr = path.strip_prefix(URING_PREFIX).unwrap_or(path);
+        let addr = std::net::ToSocketAddrs::to_socket_addrs(addr)?
+            .next()
+            .ok_or_else(|| Error::data(format!("unable to resolve {}", addr)))?;
+        let path = format!("{}{}", URING_PREFIX, addr);
+        let db = self.db.clone();
+        let queue_size = self.queue_size;
+        if let Some(ref aaa_map) = config.aaa_map {
+            db.aaa_maps.write().insert(path.clone(), aaa_map.clone());
+        }
+        let (bind_tx, bind_rx) = tokio::sync::oneshot::channel();
+        // the service keeps the listener running, the thread stops accepting clients when the
+        // service is aborted
+        let (stop_tx, mut stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let socket_path = path.clone();
+        std::thread::Builder::new()
+            .name(format!("elbus-uring-{}", addr.port()))
+            .spawn(move || {
+                tokio_uring::start(async move {
+                    let listener = match tokio_uring::net::TcpListener::bind(addr) {
+                        Ok(v) => {
+                            let _r = bind_tx.send(Ok(()));
+                            v
+                        }
+                        Err(e) => {
+                            let _r = bind_tx.send(Err(Error::from(e)));
+                            return;
+                        }
+                    };
+                    db.listener_state(&socket_path, None);
+                    let peers = Rc::new(());
+                    let mut errors = 0;
+                    loop {
+                        let (stream, peer_addr) = tokio::select! {
+                            result = listener.accept() => match result {
+                                Ok(v) => {
+                                    errors = 0;
+                                    v
+                                }
+                                Err(e) => {
+                                    error!("{}: {}", socket_path, e);
+                                    errors += 1;
+                                    if errors >= LISTENER_ACCEPT_ERRORS_MAX {
+                                        db.listener_state(&socket_path, Some(e.to_string()));
+                                        break;
+                                    }
+                                    tokio::time::sleep(LISTENER_ACCEPT_ERROR_DELAY).await;
+                                    continue;
+                                }
+                            },
+                            _ = &mut stop_rx => break,
+                        };
+                        trace!(
+                            "elbus client connected from {:?} to {}",
+                            peer_addr,
+                            socket_path
+                        );
+                        if let Err(e) = config.tcp_options.apply(&stream) {
+                            error!("{}", e);
+                            continue;
+                        }
+                        let stream = Rc::new(stream);
+                        let reader = UringReader::new(stream.clone(), config.buf_size);
+                        let (writer, write_fut) = UringWriter::new(stream, config.buf_size);
+                        tokio_uring::spawn(write_fut);
+                        let writer = TtlBufWriter::new(
+                            writer,
+                            config.buf_size,
+                            config.buf_ttl,
+                            config.timeout,
+                        );
+                        let params = PeerHandlerParams {
+                            db: db.clone(),
+                            reader,
+                            writer,
+                            timeout: config.timeout,
+                            aaa_map: config.aaa_map.clone(),
+                            ip: peer_addr.into(),
+                            queue_size,
+                            kind: ClientKind::Tcp,
+                            source: prepare_tcp_source(&peer_addr),
+                            source_port: Some(socket_path.clone()),
+                            frame_deadline: config.frame_deadline,
+                            max_frame_size: config.max_frame_size,
+                        };
+                        let name = socket_path.clone();
+                        let peer = peers.clone();
+                        tokio_uring::spawn(async move {
+                            if let Err(e) = Self::handle_peer(params).await {
+                                pretty_error!(name, e);
+                            }
+                            drop(peer);
+                        });
+                    }
+                    drop(listener);
+                    // clients are served until disconnected
+                    while Rc::strong_count(&peers) > 1 {
+                        tokio::time::sleep(URING_STOP_CHECK_INTERVAL).await;
+                    }
+                });
+            })?;
+        bind_rx
+            .await
+            .map_err(|_| Error::io("io_uring listener thread crashed"))??;
+        let service = tokio::spawn(async move {
+            let _stop = stop_tx;
+            std::future::pending::<()>().await;
+        });
+        self.services.push(service);
+        Ok(())
+    }
+    /// Spawns a browser-facing JSON gateway (requires "websocket" and "rpc" features), see
+    /// [`WsGateway`](crate::gateway::WsGateway) for the message format. Each connection is
+    /// served by a dedicated client ".wsgw.N", the listener is reported as "wsgw://ADDR"
+    ///
+    /// AAA maps are not supported, access is limited with the gateway topic and target masks.
+    /// Gateway clients can not be authenticated, the method fails if the authenticator is set
+    #[cfg(all(feature = "websocket", feature = "rpc"))]
+    pub async fn spawn_websocket_gateway(
+        &mut self,
+        path: &str,
+        gateway: crate::gateway::WsGateway,
+        config: ServerConfig,
+    ) -> Result<(), Error> {
+        if config.aaa_map.is_some() {
+            return Err(Error::not_supported(
+                "AAA maps are not supported by WebSocket gateways",
+            ));
+        }
+        if self.db.authenticator.read().is_some() {
+            return Err(Error::not_supported(
+                "WebSocket gateway clients can not be authenticated",
+            ));
+        }
+        let path = format!(
+            "{}{}",
+            WS_GATEWAY_PREFIX,
+            path.strip_prefix(WS_GATEWAY_PREFIX).unwrap_or(path)
+        );
+        let listener = bind_tcp(path.strip_prefix(WS_GATEWAY_PREFIX).unwrap_or(&path)).await?;
+        let acl = Arc::new(gateway.acl(&self.db.broadcast_format, &self.db.topic_format));
+        let db = self.db.clone();
+        let queue_size = self.queue_size;
+        db.listener_state(&path, None);
+        let service = tokio::spawn(async move {
+            loop {
+                let (stream, addr) = match listener.accept().await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!("{}: {}", path, e);
+                        tokio::time::sleep(LISTENER_ACCEPT_ERROR_DELAY).await;
+                        continue;
+                    }
+                };
+                trace!("gateway client connected from {:?} to {}", addr, path);
+                if let Err(e) = prepare_tcp_stream(&stream, &config) {
+                    error!("{}", e);
+                    continue;
+                }
+                let db = db.clone();
+                let acl = acl.clone();
+                let source = prepare_tcp_source(&addr);
+                let path = path.clone();
+                let timeout = config.timeout;
+                tokio::spawn(async move {
+                    let result = async {
+                        let ws = time::timeout(timeout, tokio_tungstenite::accept_async(stream))
+                            .await?
+                            .map_err(Error::io)?;
+                        let client = Self::register_internal_client(
+                            &db,
+                            queue_size,
+                            &crate::gateway::client_name(),
+                            &[],
+                            ClientKind::Tcp,
+                            source,
+                            Some(path.clone()),
+                        )
+                        .await?;
+                        crate::gateway::serve(ws, client, acl).await
+                    }
+                    .await;
+                    if let Err(e) = result {
+                        pretty_error!(path, e);
+                    }
+                });
+            }
+        });
+        self.services.push(service);
+        Ok(())
+    }
+    /// Spawns a UDP listener for fire-and-forget frames, useful for sensors and shell scripts,
+    /// which can not hold a connection. Each datagram must contain a single publish (0x01),
+    /// message (0x12) or broadcast (0x13) frame with no acknowledgement requested, as sent by
+    /// clients: OP-ID FLAGS LEN TARGET 00 PAYLOAD
+    ///
+    /// Frames are routed on behalf of the listener client (.udp.N). Malformed datagrams
+    /// are counted as protocol errors of the client and dropped silently. UDP sources can not be
+    /// authenticated, the listener should be bound to trusted networks only, the method fails if
+    /// the authenticator is set
+    pub async fn spawn_udp_server(&mut self, path: &str) -> Result<(), Error> {
+        if self.db.authenticator.read().is_some() {
+            return Err(Error::not_supported("UDP sources can not be authenticated"));
+        }
+        let path = format!(
+            "{}{}",
+            UDP_PREFIX,
+            path.strip_prefix(UDP_PREFIX).unwrap_or(path)
+        );
+        let socket =
+            tokio::net::UdpSocket::bind(path.strip_prefix(UDP_PREFIX).unwrap_or(&path)).await?;
+        let mut client = Self::register_internal_client(
+            &self.db,
+            self.queue_size,
+            &format!(".udp.{}", self.services.len() + 1),
+            &[],
+            ClientKind::Internal,
+            None,
+            Some(path.clone()),
+        )
+        .await?;
+        let rx = client.take_event_channel().unwrap();
+        let db = self.db.clone();
+        db.listener_state(&path, None);
+        let service = tokio::spawn(async move {
+            let mut buf = vec![0; UDP_MAX_DATAGRAM];
+            loop {
+                tokio::select! {
+                    // frames, sent to the listener client, are discarded
+                    frame = rx.recv() => {
+                        if frame.is_err() {
+                            break;
+                        }
+                    }
+                    result = socket.recv_from(&mut buf) => {
+                        let (len, addr) = match result {
+                            Ok(v) => v,
+                            Err(e) => {
+                                error!("{}: {}", path, e);
+                                tokio::time::sleep(LISTENER_ACCEPT_ERROR_DELAY).await;
+                                continue;
+                            }
+                        };
+                        if let Err(e) = Self::route_datagram(&db, &client.client, &buf[..len]).await {
+                            trace!("{}: datagram from {} not routed: {}", path, addr, e);
+                        }
+                    }
+                }
+            }
+        });
+        self.services.push(service);
+        Ok(())
+    }
+    async fn route_datagram(
+        db: &BrokerDb,
+        client: &BrokerClient,
+        datagram: &[u8],
+    ) -> Result<(), Error> {
+        let (op, qos, tgt_len) = match parse_datagram(datagram) {
+            Ok(v) => v,
+            Err(e) => {
+                db.protocol_error(client, e);
+                return Err(Error::data(format!("{:?}", e)));
+            }
+        };
+        let buf = datagram[9..].to_vec();
+        let target = std::str::from_utf8(&buf[..tgt_len]).unwrap();
+        let payload_pos = tgt_len + 1;
+        let len = buf.len() as u64;
+        let realtime = qos.is_realtime();
+        let timeout = Some(DEFAULT_TIMEOUT);
+        match op {
+            FrameOp::Message => {
+                send!(
+                    db,
+                    client,
+                    target,
+                    None,
+                    buf,
+                    payload_pos,
+                    len,
+                    realtime,
+                    timeout
+                )?;
+            }
+            FrameOp::Broadcast => {
+                send_broadcast!(
+                    db,
+                    client,
+                    target,
+                    None,
+                    buf,
+                    payload_pos,
+                    len,
+                    realtime,
+                    timeout
+                );
+            }
+            _ => {
+                // system topics are published by the broker only
+                if target.starts_with(BROKER_TOPIC_PREFIX) {
+                    return Err(Error::access(format!("topic {} is not allowed", target)));
+                }
+                publish!(
+                    db,
+                    client,
+                    target,
+                    None,
+                    buf,
+                    payload_pos,
+                    len,
+                    realtime,
+                    timeout
+                );
+            }
+        }
+        Ok(())
+    }
+    /// Connects out to the remote endpoint (e.g. a relay in a cloud) and serves the elbus
+    /// protocol over the connection, as it was accepted by a TCP server. Useful for NAT-ed edge
+    /// sites, where inbound connections are impossible. A single connection is kept, if it is
+    /// failed or closed, the broker reconnects with backoff. The connection state is reported
+    /// in the listener list as "dial:ADDR"
+    pub fn spawn_tcp_dialout(&mut self, addr: &str, config: ServerConfig) {
+        let path = format!("dial:{}", addr);
+        let addr = addr.to_owned();
+        let db = self.db.clone();
+        let queue_size = self.queue_size;
+        if let Some(ref aaa_map) = config.aaa_map {
+            db.aaa_maps.write().insert(path.clone(), aaa_map.clone());
+        }
+        let runtime = config.runtime.clone();
+        let fut = async move {
+            let mut backoff = LISTENER_RESTART_DELAY_MIN;
+            loop {
+                let started = Instant::now();
+                let result = match time::timeout(config.timeout, TcpStream::connect(&addr)).await {
+                    Ok(Ok(stream)) => {
+                        db.listener_state(&path, None);
+                        debug!("connected to {}", addr);
+                        let peer = stream.peer_addr();
+                        match (prepare_tcp_stream(&stream, &config), peer) {
+                            (Ok(()), Ok(peer)) => {
+                                let (reader, writer) = stream.into_split();
+                                Self::handle_peer(PeerHandlerParams {
+                                    db: db.clone(),
+                                    reader: BufReader::with_capacity(config.buf_size, reader),
+                                    writer: TtlBufWriter::new(
+                                        writer,
+                                        config.buf_size,
+                                        config.buf_ttl,
+                                        config.timeout,
+                                    ),
+                                    timeout: config.timeout,
+                                    aaa_map: config.aaa_map.clone(),
+                                    ip: peer.into(),
+                                    queue_size,
+                                    kind: ClientKind::Tcp,
+                                    source: prepare_tcp_source(&peer),
+                                    source_port: Some(path.clone()),
+                                    frame_deadline: config.frame_deadline,
+                                    max_frame_size: config.max_frame_size,
+                                })
+                                .await
+                            }
+                            (Err(e), _) => Err(e),
+                            (_, Err(e)) => Err(e.into()),
+                        }
+                    }
+                    Ok(Err(e)) => Err(e.into()),
+                    Err(e) => Err(e.into()),
+                };
+                let err = match result {
+                    Err(e) if e.kind() != ErrorKind::Eof => {
+                        error!("connection to {} failed: {}", addr, e);
+                        e.to_string()
+                    }
+                    _ => {
+                        warn!("connection to {} closed", addr);
+                        "connection closed".to_owned()
+                    }
+                };
+                db.listener_state(&path, Some(err));
+                if started.elapsed() > LISTENER_RESTART_DELAY_MAX {
+                    backoff = LISTENER_RESTART_DELAY_MIN;
+                }
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(LISTENER_RESTART_DELAY_MAX);
+            }
+        };
+        let service = if let Some(rt) = runtime {
+            rt.spawn(fut)
+        } else {
+            tokio::spawn(fut)
+        };
+        self.services.push(service);
+    }
     /// Broker fifo channel is useful for shell scripts and allows to send:
     ///
     /// echo TARGET MESSAGE > /path/to/fifo # a one-to-one or broadcast message
     /// echo '=TOPIC' MESSAGE # publish to a topic
     /// echo TARGET .MESSAGE # RPC notification
     /// echo TARGET :method param=value param=value # RPC call, the payload will be sent as msgpack
+    /// echo 'TARGET :method {"param":"value"}' # RPC call with JSON params
+    /// echo TARGET :method @params.json # RPC call with JSON params from a file
+    ///
+    /// Multi-word messages and params must be quoted ("TARGET \"multi-word message\"",
+    /// "TARGET '.multi-word notification'"). Unquoted messages, which start with "@", are loaded
+    /// from files (@path/to/file) in the payload directory (see set_fifo_payload_dir, denied if
+    /// not set), which start with "0x" - decoded from hex (0xDEADBEEF)
+    ///
+    /// The fifo is writable by the owner only or by the owner and the fifo group (see
+    /// set_fifo_group)
     ///
     /// Requires rpc feature + broker core rpc client to be set
-    #[cfg(feature = "rpc")]
+    #[cfg(all(unix, feature = "rpc"))]
+    #[inline]
     pub async fn spawn_fifo(&mut self, path: &str, buf_size: usize) -> Result<(), Error> {
+        self.spawn_fifo_with_responses(path, None, buf_size).await
+    }
+    /// Same as spawn_fifo, but if the response path is specified, the results of commands are
+    /// written to the response fifo as JSON lines:
+    ///
+    /// {"cmd":"TARGET :method","ok":true,"result":...}
+    /// {"cmd":"TARGET :method","ok":false,"code":-32601,"error":"..."}
+    ///
+    /// RPC calls wait for replies (up to 30 seconds), which are decoded according to the content
+    /// type. Responses are buffered by the response fifo, if it becomes full (no one reads it),
+    /// responses are dropped. The response fifo is readable by the owner only or by the owner
+    /// and the fifo group
+    ///
+    /// Requires rpc feature + broker core rpc client to be set
+    #[cfg(all(unix, feature = "rpc"))]
+    #[allow(clippy::items_after_statements)]
+    pub async fn spawn_fifo_with_responses(
+        &mut self,
+        path: &str,
+        response_path: Option<&str>,
+        buf_size: usize,
+    ) -> Result<(), Error> {
         let rpc_client = self.db.rpc_client.clone();
         if rpc_client.lock().await.is_none() {
             return Err(Error::not_supported(BROKER_RPC_NOT_INIT_ERR));
         }
-        let _r = tokio::fs::remove_file(path).await;
-        unix_named_pipe::create(path, Some(0o622))?;
+        use std::io::Write as _;
         use std::os::unix::fs::PermissionsExt;
         use tokio::io::AsyncBufReadExt;
-        // chown fifo as it's usually created with 644
-        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o622)).await?;
+        let fifo_gid = self.fifo_group.map(nix::unistd::Gid::from_raw);
+        let responder = if let Some(resp_path) = response_path {
+            let _r = tokio::fs::remove_file(resp_path).await;
+            // command results may contain sensitive data, so the response fifo is readable by
+            // the owner and the fifo group only
+            let mode = if fifo_gid.is_some() { 0o640 } else { 0o600 };
+            unix_named_pipe::create(resp_path, Some(mode))?;
+            if fifo_gid.is_some() {
+                nix::unistd::chown(resp_path, None, fifo_gid).map_err(Error::io)?;
+            }
+            tokio::fs::set_permissions(resp_path, std::fs::Permissions::from_mode(mode)).await?;
+            // opened for reading as well, so writes never fail if there is no reader
+            use std::os::unix::fs::OpenOptionsExt;
+            let fd = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(nix::libc::O_NONBLOCK)
+                .open(resp_path)?;
+            // replies are collected by a dedicated client to keep the core one unlocked
+            let client = self
+                .register_client(&format!(
+                    "{}{}fifo.{}",
+                    BROKER_NAME,
+                    SECONDARY_SEP,
+                    self.services.len() + 1
+                ))
+                .await?;
+            Some((fd, RpcClient::new(client, DummyHandlers {})))
+        } else {
+            None
+        };
+        let _r = tokio::fs::remove_file(path).await;
+        // commands are executed with the broker permissions, so the fifo is writable by the
+        // owner and the fifo group only
+        let mode = if fifo_gid.is_some() { 0o620 } else { 0o600 };
+        unix_named_pipe::create(path, Some(mode))?;
+        if fifo_gid.is_some() {
+            nix::unistd::chown(path, None, fifo_gid).map_err(Error::io)?;
+        }
+        // set the mode explicitly as it is masked by umask on creation
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await?;
         let fd = unix_named_pipe::open_read(path)?;
         let socket_path = path.to_owned();
+        let payload_dir = self.fifo_payload_dir.clone();
         let service = tokio::spawn(async move {
             let f = tokio::fs::File::from_std(fd);
             let reader = BufReader::with_capacity(buf_size, f);
             let mut lines = reader.lines();
             let sleep_step = Duration::from_millis(100);
+            let mut responder = responder;
             loop {
                 while let Some(line) = match lines.next_line().await {
                     Ok(v) => v,
@@ -1260,8 +6936,25 @@ impl Broker {
                         None
                     }
                 } {
-                    if let Err(e) = Self::send_fifo_cmd(&rpc_client, line).await {
-                        error!("{}: {}", socket_path, e);
+                    if let Some((ref mut resp_fd, ref rpc)) = responder {
+                        let result =
+                            Self::send_fifo_cmd(rpc, &line, payload_dir.as_deref(), true).await;
+                        if let Err(ref e) = result {
+                            error!("{}: {}", socket_path, e);
+                        }
+                        let mut response = fifo_response(&line, result).to_string();
+                        response.push('\n');
+                        if let Err(e) = resp_fd.write_all(response.as_bytes()) {
+                            warn!("{}: unable to write response: {}", socket_path, e);
+                        }
+                    } else if let Some(rpc) = rpc_client.lock().await.as_ref() {
+                        if let Err(e) =
+                            Self::send_fifo_cmd(rpc, &line, payload_dir.as_deref(), false).await
+                        {
+                            error!("{}: {}", socket_path, e);
+                        }
+                    } else {
+                        error!("{}: {}", socket_path, BROKER_RPC_NOT_INIT_ERR);
                     }
                 }
                 tokio::time::sleep(sleep_step).await;
@@ -1270,77 +6963,102 @@ impl Broker {
         self.services.push(service);
         Ok(())
     }
-    #[cfg(feature = "rpc")]
+    /// Serial port bridge (requires "serial" feature): the device is exposed as an internal client
+    /// with the name, frames, arriving on the port, are published to the bridge topic, payloads
+    /// of messages, sent to the client, are written to the port. The port is reopened if the
+    /// device is gone
+    #[cfg(feature = "serial")]
+    pub async fn spawn_serial(&mut self, name: &str, bridge: SerialBridge) -> Result<(), Error> {
+        let port = bridge.open()?;
+        let mut client = self.register_client(name).await?;
+        // warnings are not written to the port
+        client.unsubscribe(BROKER_WARN_TOPIC, QoS::No).await?;
+        let rx = client
+            .take_event_channel()
+            .ok_or_else(|| Error::not_supported("no event channel"))?;
+        self.services
+            .push(tokio::spawn(bridge.run(client, rx, port)));
+        Ok(())
+    }
+    /// Executes a FIFO command. If wait_reply is true, RPC calls wait for replies, which are
+    /// returned as Some(result)
+    #[cfg(all(unix, feature = "rpc"))]
     async fn send_fifo_cmd(
-        rpc_c: &Arc<Mutex<Option<RpcClient>>>,
-        line: String,
-    ) -> Result<(), Error> {
-        let cmd = line.trim();
-        let mut c = rpc_c.lock().await;
-        let rpc = if let Some(rpc) = c.as_mut() {
-            rpc
-        } else {
-            return Err(Error::not_supported(BROKER_RPC_NOT_INIT_ERR));
-        };
+        rpc: &RpcClient,
+        line: &str,
+        payload_dir: Option<&std::path::Path>,
+        wait_reply: bool,
+    ) -> Result<Option<FifoCallResult>, Error> {
+        let tokens = fifo_tokens(line)?;
+        if tokens.is_empty() {
+            return Ok(None);
+        }
+        let mut sp = tokens.iter();
+        let target = sp
+            .next()
+            .ok_or_else(|| Error::data("target not specified"))?;
+        let first = sp
+            .next()
+            .ok_or_else(|| Error::data("payload not specified"))?;
         // topic
-        if let Some(s) = cmd.strip_prefix('=') {
-            let mut sp = s.split(' ');
-            let topic = sp
-                .next()
-                .ok_or_else(|| Error::data("topic not specified"))?;
-            let payload = sp
-                .next()
-                .ok_or_else(|| Error::data("payload not specified"))?;
+        if let Some(topic) = target.value.strip_prefix('=') {
+            let payload = fifo_payload(first, &first.value, payload_dir).await?;
+            fifo_no_more_tokens(sp)?;
             rpc.client()
                 .lock()
                 .await
-                .publish(topic, payload.as_bytes().into(), QoS::No)
+                .publish(topic, payload.into(), QoS::No)
                 .await?;
-            Ok(())
-        } else {
-            let mut sp = line.split(' ');
-            let target = sp
-                .next()
-                .ok_or_else(|| Error::data("target not specified"))?;
-            let payload = sp
-                .next()
-                .ok_or_else(|| Error::data("payload not specified"))?;
+            Ok(None)
+        } else if let Some(s) = first.value.strip_prefix('.') {
             // rpc notification
-            if let Some(s) = payload.strip_prefix('.') {
-                rpc.notify(target, s.as_bytes().into(), QoS::No).await?;
-                Ok(())
-            } else if let Some(method) = payload.strip_prefix(':') {
-                let s = sp.collect::<Vec<&str>>();
-                let params = crate::common::str_to_params_map(&s)?;
-                rpc.call0(
-                    target,
-                    method,
-                    rmp_serde::to_vec_named(&params)
-                        .map_err(Error::data)?
-                        .into(),
-                    QoS::No,
+            let payload = fifo_payload(first, s, payload_dir).await?;
+            fifo_no_more_tokens(sp)?;
+            rpc.notify(&target.value, payload.into(), QoS::No).await?;
+            Ok(None)
+        } else if let Some(method) = first.value.strip_prefix(':') {
+            let params: Vec<&FifoToken> = sp.collect();
+            let payload = match params.as_slice() {
+                [p] if p.value.starts_with('{') => fifo_json_params(&p.value)?,
+                [p] if !p.quoted && p.value.starts_with('@') => {
+                    let data = fifo_file(payload_dir, &p.value[1..]).await?;
+                    fifo_json_params(std::str::from_utf8(&data)?)?
+                }
+                _ => {
+                    let s: Vec<&str> = params.iter().map(|p| p.value.as_str()).collect();
+                    let params = crate::common::str_to_params_map(&s)?;
+                    rmp_serde::to_vec_named(&params).map_err(Error::data)?
+                }
+            };
+            if wait_reply {
+                // a stuck handler must not block the fifo forever
+                let result = time::timeout(
+                    FIFO_CALL_TIMEOUT,
+                    rpc.call(&target.value, method, payload.into(), QoS::Processed),
                 )
-                .await?;
-                Ok(())
+                .await
+                .unwrap_or_else(|_| Err(Error::timeout().into()));
+                Ok(Some(result))
             } else {
-                // regular message
-                // broadcast
-                if target.contains(&['*', '?'][..]) {
-                    rpc.client()
-                        .lock()
-                        .await
-                        .send_broadcast(target, payload.as_bytes().into(), QoS::No)
-                        .await?;
-                    Ok(())
-                } else {
-                    rpc.client()
-                        .lock()
-                        .await
-                        .send(target, payload.as_bytes().into(), QoS::No)
-                        .await?;
-                    Ok(())
-                }
+                rpc.call0(&target.value, method, payload.into(), QoS::No)
+                    .await?;
+                Ok(None)
             }
+        } else {
+            // regular message
+            let payload = fifo_payload(first, &first.value, payload_dir).await?;
+            fifo_no_more_tokens(sp)?;
+            let client = rpc.client();
+            let mut client = client.lock().await;
+            // broadcast
+            if target.value.contains(&['*', '?'][..]) {
+                client
+                    .send_broadcast(&target.value, payload.into(), QoS::No)
+                    .await?;
+            } else {
+                client.send(&target.value, payload.into(), QoS::No).await?;
+            }
+            Ok(None)
         }
     }
     #[allow(clippy::too_many_lines)]
@@ -1373,21 +7091,89 @@ impl Broker {
             return Err(Error::not_supported("unsupported protocol version"));
         }
         write_and_flush!(&[RESPONSE_OK]);
-        let mut buf = vec![0; 2];
-        time::timeout(timeout, reader.read_exact(&mut buf)).await??;
-        let len = u16::from_le_bytes(buf.try_into().unwrap());
-        let mut buf = vec![0; len as usize];
-        time::timeout(timeout, reader.read_exact(&mut buf)).await??;
-        let client_name = std::str::from_utf8(&buf)?.to_owned();
+        let hello = match read_hello(&mut reader, timeout).await {
+            Ok(v) => v,
+            Err(e) => {
+                if e.kind() == ErrorKind::Data {
+                    write_and_flush!(&[ERR_DATA]);
+                }
+                return Err(e);
+            }
+        };
+        let (buf, token, extensions) = (hello.name, hello.token, hello.extensions);
+        // the name can be followed by groups to join, separated with zero bytes
+        let mut sp = buf.split(|c| *c == 0);
+        let client_name = std::str::from_utf8(sp.next().unwrap_or_default())?.to_owned();
         if client_name.is_empty() || client_name.starts_with('.') {
             write_and_flush!(&[ERR_DATA]);
             return Err(Error::data(format!("Invalid client name: {}", client_name)));
         }
+        let mut groups = Vec::new();
+        for g in sp {
+            let group = std::str::from_utf8(g)?;
+            if group.is_empty() {
+                write_and_flush!(&[ERR_DATA]);
+                return Err(Error::data(format!(
+                    "Empty group name, client: {}",
+                    client_name
+                )));
+            }
+            groups.push(group.to_owned());
+        }
         let client_primary_name = client_name
             .find(SECONDARY_SEP)
             .map_or_else(|| client_name.as_str(), |pos| &client_name[..pos]);
+        let validator = db.client_name_validator.read().clone();
+        if let Some(validator) = validator {
+            if let Err(e) = validator.validate(client_primary_name) {
+                write_and_flush!(&[ERR_DATA]);
+                return Err(Error::data(format!(
+                    "Invalid client name: {} ({})",
+                    client_name, e
+                )));
+            }
+        }
+        let authenticator = db.authenticator.read().clone();
+        let limits = if let Some(authenticator) = authenticator {
+            let Ok(token) = token.as_deref().map(std::str::from_utf8).transpose() else {
+                write_and_flush!(&[ERR_ACCESS]);
+                return Err(Error::access(format!(
+                    "client {} is not authenticated (invalid token)",
+                    client_name
+                )));
+            };
+            let request = AuthRequest {
+                name: client_primary_name,
+                kind: params.kind,
+                source: params.source.as_deref(),
+                token,
+            };
+            match authenticator.authenticate(&request) {
+                Ok(limits) => limits,
+                Err(e) => {
+                    write_and_flush!(&[e.kind() as u8]);
+                    return Err(Error::new(
+                        e.kind(),
+                        Some(format!(
+                            "client {} is not authenticated ({})",
+                            client_name, e
+                        )),
+                    ));
+                }
+            }
+        } else {
+            ClientLimits::default()
+        };
         let aaa = if let Some(aaa_map) = params.aaa_map {
-            let aaa = aaa_map.lock().unwrap().get(client_primary_name).cloned();
+            let aaa = aaa_map.lock().get(client_primary_name).cloned().map(|a| {
+                if db.broadcast_format == MaskFormat::broadcast()
+                    && db.topic_format == MaskFormat::topic()
+                {
+                    a
+                } else {
+                    a.with_formats(&db.broadcast_format, &db.topic_format)
+                }
+            });
             if let Some(ref a) = aaa {
                 if let ClientIp::Addr(addr) = params.ip {
                     if !a.connect_allowed(addr) {
@@ -1409,61 +7195,204 @@ impl Broker {
         } else {
             None
         };
+        #[cfg(feature = "rpc")]
+        let extensions = if let Some(ext) = extensions {
+            match rmp_serde::from_slice::<HelloExtensions>(&ext) {
+                Ok(ext) => Some(ext),
+                Err(e) => {
+                    write_and_flush!(&[ERR_DATA]);
+                    return Err(Error::data(format!(
+                        "Invalid hello extensions, client: {} ({})",
+                        client_name, e
+                    )));
+                }
+            }
+        } else {
+            None
+        };
+        #[cfg(feature = "rpc")]
+        if let Some(will) = extensions.as_ref().and_then(|ext| ext.will.as_ref()) {
+            // the will is checked as a regular publication
+            let allowed = if will.topic.is_empty() || will.topic.starts_with(BROKER_TOPIC_PREFIX) {
+                false
+            } else if let Some(ref a) = aaa {
+                a.publish_allowed(&will.topic)
+            } else {
+                true
+            };
+            if !allowed {
+                write_and_flush!(&[ERR_ACCESS]);
+                return Err(Error::access(format!(
+                    "Client {} is not allowed to publish the will to {}",
+                    client_name, will.topic
+                )));
+            }
+        }
+        #[cfg(feature = "rpc")]
+        let keepalive = extensions
+            .as_ref()
+            .and_then(|ext| ext.keepalive)
+            .map_or(timeout, |k| {
+                Duration::try_from_secs_f64(k)
+                    .unwrap_or(KEEPALIVE_MAX)
+                    .clamp(KEEPALIVE_MIN, KEEPALIVE_MAX)
+            });
+        #[cfg(not(feature = "rpc"))]
+        let keepalive = timeout;
+        // sent after the registration result, the broker without "rpc" does not decode the
+        // extensions and replies with an empty block
+        let extensions_reply: Option<Vec<u8>> = {
+            #[cfg(feature = "rpc")]
+            {
+                extensions
+                    .as_ref()
+                    .map(|ext| {
+                        rmp_serde::to_vec_named(&HelloExtensionsReply {
+                            v: ext.v.min(HELLO_EXTENSIONS_VERSION),
+                            keepalive: keepalive.as_secs_f64(),
+                            compression: None,
+                        })
+                    })
+                    .transpose()
+                    .map_err(Error::data)?
+            }
+            #[cfg(not(feature = "rpc"))]
+            {
+                extensions.map(|_| Vec::new())
+            }
+        };
         let (client, rx, disconnect_listener) = {
-            let (c, rx, disconnect_listener) = ElbusClient::new(
+            let (mut c, rx, disconnect_listener) = ElbusClient::new(
                 &client_name,
                 client_primary_name,
-                queue_size,
+                limits.queue_size.unwrap_or(queue_size),
                 params.kind,
                 params.source,
                 params.source_port,
+                aaa.clone().map(|a| Arc::new(a) as Arc<dyn ClientAcl>),
+                groups,
             );
+            #[cfg(feature = "rpc")]
+            if let Some(ext) = extensions {
+                c.metadata = ext.metadata;
+                c.will = ext.will;
+            }
+            c.subscription_limits = limits.subscriptions;
             let client = Arc::new(c);
             if let Err(e) = db.register_client(client.clone()).await {
                 write_and_flush!(&[e.kind as u8]);
                 return Err(e);
             }
-            write_and_flush!(&[RESPONSE_OK]);
+            if let Some(reply) = extensions_reply {
+                let mut buf = Vec::with_capacity(reply.len() + 5);
+                buf.push(RESPONSE_OK);
+                #[allow(clippy::cast_possible_truncation)]
+                buf.extend_from_slice(&(reply.len() as u32).to_le_bytes());
+                buf.extend_from_slice(&reply);
+                write_and_flush!(&buf);
+            } else {
+                write_and_flush!(&[RESPONSE_OK]);
+            }
             (client, rx, disconnect_listener)
         };
-        debug!("elbus client registered: {}", client_name);
-        let pinger_fut = Self::handle_pinger(&client_name, client.tx.clone(), timeout);
-        let reader_fut = Self::handle_reader(&db, client.clone(), &mut reader, timeout, aaa);
-        let writer_fut = Self::handle_writer(rx, &mut writer, timeout);
+        let source = client.source.as_deref().unwrap_or_default();
+        debug!(
+            client = client_name.as_str(), source, event = "connect";
+            "elbus client registered: {}", client_name
+        );
+        let pinger_fut = Self::handle_pinger(&client_name, client.tx.clone(), keepalive);
+        let reader_fut = Self::handle_reader(
+            &db,
+            client.clone(),
+            &mut reader,
+            timeout,
+            limits.max_frame_size.or(params.max_frame_size),
+            aaa,
+        );
+        #[cfg(feature = "testing")]
+        let rx = db.chaos.relay(&client_name, rx);
+        let write_failure = writer.failure();
+        let writer_fut = Self::handle_writer(
+            rx,
+            &mut writer,
+            timeout,
+            params.frame_deadline,
+            &db,
+            &client,
+        );
         macro_rules! finish_peer {
-            () => {
-                db.unregister_client(&client).await;
-                debug!("elbus client disconnected: {}", client_name);
+            ($reason: expr) => {
+                let reason = $reason;
+                // there is no disconnect operation, a crashed client closes the socket as well
+                if let Some(ref will) = client.will {
+                    let len = will.payload.len() as u64;
+                    publish!(
+                        db,
+                        client,
+                        &will.topic,
+                        None,
+                        will.payload.clone(),
+                        0,
+                        len,
+                        false,
+                        Some(timeout)
+                    );
+                    debug!(
+                        "elbus client {} will published to {} ({})",
+                        client_name, will.topic, reason
+                    );
+                }
+                db.unregister_client(&client, reason).await;
+                debug!(
+                    client = client_name.as_str(), source, event = "disconnect",
+                    reason:% = reason;
+                    "elbus client disconnected: {} ({})", client_name, reason
+                );
             };
         }
-        tokio::select! {
+        let result = tokio::select! {
             result = reader_fut => {
-                finish_peer!();
+                finish_peer!(DisconnectReason::from_read_result(&result));
                 result
             }
             result = writer_fut => {
-                finish_peer!();
+                finish_peer!(DisconnectReason::from_write_result(&result));
                 result
             }
+            // the peer may stop reading while the client has no frames to write
+            e = write_failure => {
+                finish_peer!(DisconnectReason::WriteClosed);
+                Err(e.into())
+            }
             result = pinger_fut => {
-                finish_peer!();
+                finish_peer!(DisconnectReason::QueueOverflow);
                 result
             }
             _ = disconnect_listener => {
                 debug!("disconnected by the broker: {}", client_name);
-                finish_peer!();
+                finish_peer!(DisconnectReason::Kicked);
                 Ok(())
             }
+        };
+        // errors of registered clients are logged here, as the listener knows no client name
+        if let Err(e) = result {
+            if e.kind() != ErrorKind::Eof {
+                error!(
+                    client = client_name.as_str(), source, event = "error";
+                    "client {} error: {}", client_name, e
+                );
+            }
         }
+        Ok(())
     }
 
     async fn handle_pinger(
         client_name: &str,
         tx: async_channel::Sender<Frame>,
-        timeout: Duration,
+        interval: Duration,
     ) -> Result<(), Error> {
         loop {
-            time::sleep(timeout).await;
+            time::sleep(interval).await;
             if tx.is_full() {
                 warn!("client {} queue is full, force unregistering", client_name);
                 return Err(Error::io("client queue overflow"));
@@ -1472,13 +7401,13 @@ impl Broker {
         }
     }
 
-    // TODO send ack only after the client received message (QoS2)
     #[allow(clippy::too_many_lines)]
     async fn handle_reader<R>(
         db: &BrokerDb,
         client: Arc<ElbusClient>,
         reader: &mut R,
         timeout: Duration,
+        max_frame_size: Option<u32>,
         aaa: Option<ClientAaa>,
     ) -> Result<(), Error>
     where
@@ -1499,17 +7428,25 @@ impl Broker {
                 continue;
             }
             let op_id = &buf[0..4];
-            let op: FrameOp = (flags & 0b0011_1111).try_into()?;
             let qos: QoS = (flags >> 6 & 0b0011_1111).try_into()?;
             let len = u32::from_le_bytes(buf[5..9].try_into().unwrap());
-            let mut buf = vec![0; len as usize];
-            time::timeout(timeout, reader.read_exact(&mut buf)).await??;
             macro_rules! send_ack {
                 ($code:expr, $realtime: expr) => {
-                    let mut buf = Vec::with_capacity(6);
-                    buf.push(OP_ACK);
+                    send_ack!($code, $realtime, None);
+                };
+                ($code:expr, $realtime: expr, $pressure: expr) => {
+                    let pressure: Option<u8> = $pressure;
+                    let mut buf = Vec::with_capacity(7);
+                    buf.push(if pressure.is_some() {
+                        OP_ACK_PRESSURE
+                    } else {
+                        OP_ACK
+                    });
                     buf.extend_from_slice(op_id);
                     buf.push($code);
+                    if let Some(pressure) = pressure {
+                        buf.push(pressure);
+                    }
                     client.w_frames.fetch_add(1, atomic::Ordering::SeqCst);
                     client
                         .w_bytes
@@ -1527,10 +7464,83 @@ impl Broker {
                             buf,
                             payload_pos: 0,
                             realtime: $realtime,
+                            identity: None,
+                            timestamp: None,
+                            delivery: None,
+                            memory: None,
                         }))
                         .await?;
                 };
             }
+            // the pending trace is reported for routed frames, which have not been routed
+            macro_rules! trace_unrouted {
+                ($subject: expr, $data: expr, $target: expr) => {
+                    if let Some(mut t) = client.take_trace() {
+                        t.push($subject, $data, $target);
+                        db.publish_trace(t);
+                    }
+                };
+            }
+            // rejected frames are skipped, the connection is kept
+            macro_rules! reject {
+                ($error: expr, $code: expr) => {
+                    db.protocol_error(&client, $error);
+                    if qos.needs_ack() {
+                        send_ack!($code, qos.is_realtime());
+                    }
+                    continue;
+                };
+            }
+            if max_frame_size.is_some_and(|max| len > max) {
+                let skipped = time::timeout(
+                    timeout,
+                    tokio::io::copy(
+                        &mut (&mut *reader).take(u64::from(len)),
+                        &mut tokio::io::sink(),
+                    ),
+                )
+                .await??;
+                if skipped < u64::from(len) {
+                    return Err(Error::io("broken frame"));
+                }
+                reject!(ProtocolError::Oversized, ERR_LIMIT);
+            }
+            let mut buf = vec![0; len as usize];
+            time::timeout(timeout, reader.read_exact(&mut buf)).await??;
+            let Ok(op) = FrameOp::try_from(flags & 0b0011_1111) else {
+                reject!(ProtocolError::UnknownOp, ERR_NOT_SUPPORTED);
+            };
+            let routed_op = matches!(
+                op,
+                FrameOp::Message
+                    | FrameOp::Broadcast
+                    | FrameOp::BroadcastMandatory
+                    | FrameOp::GroupMessage
+                    | FrameOp::GroupBroadcast
+                    | FrameOp::PublishTopic
+                    | FrameOp::PublishTopicMandatory
+            );
+            // access denials of routed frames
+            macro_rules! deny {
+                () => {
+                    trace_unrouted!("denied", None, None);
+                    if qos.needs_ack() {
+                        send_ack!(ERR_ACCESS, qos.is_realtime());
+                    }
+                };
+            }
+            if let Some(ref aaa) = aaa {
+                if !aaa.op_allowed(op) {
+                    trace!("elbus client {} operation {:?} denied", client, op);
+                    if routed_op {
+                        trace_unrouted!("denied", None, None);
+                    }
+                    if qos.needs_ack() {
+                        send_ack!(ERR_ACCESS, qos.is_realtime());
+                    }
+                    continue;
+                }
+            }
             match op {
                 FrameOp::SubscribeTopic => {
                     client.r_frames.fetch_add(1, atomic::Ordering::SeqCst);
@@ -1540,15 +7550,24 @@ impl Broker {
                     db.r_frames.fetch_add(1, atomic::Ordering::SeqCst);
                     db.r_bytes
                         .fetch_add(u64::from(len), atomic::Ordering::SeqCst);
+                    if std::str::from_utf8(&buf).is_err() {
+                        reject!(ProtocolError::Utf8, ERR_DATA);
+                    }
                     let sp = buf.split(|c| *c == 0);
                     let mut topics = Vec::new();
                     for t in sp {
                         let topic = std::str::from_utf8(t)?;
-                        let allowed = if let Some(ref aaa) = aaa {
-                            aaa.allow_subscribe_any || aaa.allow_subscribe_to.matches(topic)
+                        #[allow(unused_mut)]
+                        let mut allowed = if let Some(ref aaa) = aaa {
+                            aaa.subscribe_allowed(topic)
                         } else {
                             true
                         };
+                        // subscriptions under claimed namespaces are approved by owners
+                        #[cfg(feature = "rpc")]
+                        if allowed {
+                            allowed = db.namespace_approve(&client.name, topic, timeout).await;
+                        }
                         if allowed {
                             topics.push(topic);
                         } else if qos.needs_ack() {
@@ -1558,15 +7577,216 @@ impl Broker {
                             continue;
                         }
                     }
-                    {
-                        let mut sdb = db.subscriptions.write().unwrap();
-                        for t in topics {
-                            sdb.subscribe(t, &client);
-                            trace!("elbus client {} subscribed to topic {}", client, t);
+                    #[cfg(feature = "rpc")]
+                    let mut demand = Vec::new();
+                    let result = {
+                        let mut sdb = db.subscriptions.write();
+                        let result = db.check_subscription_limits(&sdb, &client, &topics);
+                        if result.is_ok() {
+                            #[cfg(feature = "rpc")]
+                            {
+                                demand = BrokerDb::demand_idle(&sdb, &topics);
+                            }
+                            for t in &topics {
+                                sdb.subscribe(t, &client);
+                                trace!("elbus client {} subscribed to topic {}", client, t);
+                            }
+                        }
+                        result
+                    };
+                    if let Err(e) = result {
+                        debug!("elbus client {} subscription rejected: {}", client, e);
+                        if qos.needs_ack() {
+                            send_ack!(ERR_LIMIT, qos.is_realtime());
+                        }
+                    } else {
+                        if qos.needs_ack() {
+                            send_ack!(RESPONSE_OK, qos.is_realtime());
+                        }
+                        #[cfg(feature = "rpc")]
+                        {
+                            db.announce_subscriptions("subscribe", &client.name, &topics)
+                                .await;
+                            db.namespace_notify("subscribe", &client.name, &topics)
+                                .await;
+                            db.announce_demand("active", &demand).await;
                         }
                     }
+                }
+                FrameOp::Batch => {
+                    // max batch size and delay (microseconds), u32 LE
+                    let code = if buf.len() == 8 {
+                        let size = u32::from_le_bytes(buf[..4].try_into().unwrap());
+                        let delay = u32::from_le_bytes(buf[4..].try_into().unwrap());
+                        if size > BATCH_MAX_SIZE || delay > BATCH_MAX_DELAY {
+                            ERR_DATA
+                        } else {
+                            debug!(
+                                "elbus client {} batching: size {}, delay {} us",
+                                client, size, delay
+                            );
+                            client.batch_delay.store(delay, atomic::Ordering::SeqCst);
+                            client.batch_size.store(size, atomic::Ordering::SeqCst);
+                            RESPONSE_OK
+                        }
+                    } else {
+                        reject!(ProtocolError::Malformed, ERR_DATA);
+                    };
                     if qos.needs_ack() {
-                        send_ack!(RESPONSE_OK, qos.is_realtime());
+                        send_ack!(code, qos.is_realtime());
+                    }
+                }
+                FrameOp::Timestamps => {
+                    // 1 - deliver timestamps, 0 - do not
+                    let code = match buf.as_slice() {
+                        [v @ (0 | 1)] => {
+                            client.timestamps.store(*v == 1, atomic::Ordering::SeqCst);
+                            RESPONSE_OK
+                        }
+                        _ => {
+                            reject!(ProtocolError::Malformed, ERR_DATA);
+                        }
+                    };
+                    if qos.needs_ack() {
+                        send_ack!(code, qos.is_realtime());
+                    }
+                }
+                FrameOp::Pressure => {
+                    // 1 - report queue pressure in publication acks, 0 - do not
+                    let code = match buf.as_slice() {
+                        [v @ (0 | 1)] => {
+                            client
+                                .pressure_reports
+                                .store(*v == 1, atomic::Ordering::SeqCst);
+                            RESPONSE_OK
+                        }
+                        _ => {
+                            reject!(ProtocolError::Malformed, ERR_DATA);
+                        }
+                    };
+                    if qos.needs_ack() {
+                        send_ack!(code, qos.is_realtime());
+                    }
+                }
+                FrameOp::Delivery => {
+                    // 1 - acknowledged messages carry the delivery mode byte, 0 - do not
+                    let code = match buf.as_slice() {
+                        [v @ (0 | 1)] => {
+                            client.delivery.store(*v == 1, atomic::Ordering::SeqCst);
+                            RESPONSE_OK
+                        }
+                        _ => {
+                            reject!(ProtocolError::Malformed, ERR_DATA);
+                        }
+                    };
+                    if qos.needs_ack() {
+                        send_ack!(code, qos.is_realtime());
+                    }
+                }
+                FrameOp::DeliveryAck => {
+                    // the delivery id (u32 LE) and the result code for the sender
+                    let code = match buf.as_slice() {
+                        [id @ .., code] if id.len() == 4 => {
+                            let id = u32::from_le_bytes(id.try_into().unwrap());
+                            if db.delivery_resolve(id, Some(&client.name), *code) {
+                                RESPONSE_OK
+                            } else {
+                                trace!("elbus client {} unknown delivery {}", client, id);
+                                ERR_DATA
+                            }
+                        }
+                        _ => {
+                            reject!(ProtocolError::Malformed, ERR_DATA);
+                        }
+                    };
+                    if qos.needs_ack() {
+                        send_ack!(code, qos.is_realtime());
+                    }
+                }
+                FrameOp::Trace => {
+                    // the trace id, applied to the next routed frame
+                    let code = match std::str::from_utf8(&buf) {
+                        Ok(id) if check_trace_id(id).is_ok() => {
+                            client.trace.lock().replace(id.to_owned());
+                            RESPONSE_OK
+                        }
+                        _ => {
+                            reject!(ProtocolError::Malformed, ERR_DATA);
+                        }
+                    };
+                    if qos.needs_ack() {
+                        send_ack!(code, qos.is_realtime());
+                    }
+                }
+                FrameOp::Heartbeat => {
+                    // the load (percents) and the status
+                    let code = match buf.as_slice() {
+                        [load, status @ ..]
+                            if *load <= HEALTH_LOAD_MAX
+                                && !status.is_empty()
+                                && status.len() <= HEALTH_STATUS_MAX_LEN =>
+                        {
+                            if let Ok(status) = std::str::from_utf8(status) {
+                                trace!("elbus client {} health: {} {}%", client, status, load);
+                                client.health.lock().replace(ClientHealth {
+                                    status: status.to_owned(),
+                                    load: *load,
+                                    t: now_ns(),
+                                });
+                                RESPONSE_OK
+                            } else {
+                                reject!(ProtocolError::Malformed, ERR_DATA);
+                            }
+                        }
+                        _ => {
+                            reject!(ProtocolError::Malformed, ERR_DATA);
+                        }
+                    };
+                    if qos.needs_ack() {
+                        send_ack!(code, qos.is_realtime());
+                    }
+                }
+                FrameOp::Transaction => {
+                    // 1 - begin, 2 - commit, 0 - abort
+                    let code = match buf.as_slice() {
+                        [TRANSACTION_BEGIN] => {
+                            let mut transaction = client.transaction.lock();
+                            if transaction.is_some() {
+                                ERR_BUSY
+                            } else {
+                                transaction.replace(Transaction::default());
+                                RESPONSE_OK
+                            }
+                        }
+                        [TRANSACTION_COMMIT] => {
+                            let transaction = client.transaction.lock().take();
+                            if let Some(transaction) = transaction {
+                                if let Err(e) = db
+                                    .commit_transaction(&client, transaction, Some(timeout))
+                                    .await
+                                {
+                                    debug!("elbus client {} transaction failed: {}", client, e);
+                                    e.kind as u8
+                                } else {
+                                    RESPONSE_OK
+                                }
+                            } else {
+                                ERR_DATA
+                            }
+                        }
+                        [TRANSACTION_ABORT] => {
+                            if client.transaction.lock().take().is_some() {
+                                RESPONSE_OK
+                            } else {
+                                ERR_DATA
+                            }
+                        }
+                        _ => {
+                            reject!(ProtocolError::Malformed, ERR_DATA);
+                        }
+                    };
+                    if qos.needs_ack() {
+                        send_ack!(code, qos.is_realtime());
                     }
                 }
                 FrameOp::UnsubscribeTopic => {
@@ -1577,36 +7797,122 @@ impl Broker {
                     db.r_frames.fetch_add(1, atomic::Ordering::SeqCst);
                     db.r_bytes
                         .fetch_add(u64::from(len), atomic::Ordering::SeqCst);
-                    let sp = buf.split(|c| *c == 0);
-                    {
-                        let mut sdb = db.subscriptions.write().unwrap();
-                        for t in sp {
-                            let topic = std::str::from_utf8(t)?;
-                            sdb.unsubscribe(topic, &client);
+                    if std::str::from_utf8(&buf).is_err() {
+                        reject!(ProtocolError::Utf8, ERR_DATA);
+                    }
+                    let masks = buf
+                        .split(|c| *c == 0)
+                        .map(std::str::from_utf8)
+                        .collect::<Result<Vec<&str>, _>>()?;
+                    #[allow(unused_variables)]
+                    let (topics, demand) = {
+                        let mut sdb = db.subscriptions.write();
+                        #[cfg(feature = "rpc")]
+                        let demand = BrokerDb::demand_subscribed(&sdb, &client, &masks);
+                        #[cfg(not(feature = "rpc"))]
+                        let demand: Vec<String> = Vec::new();
+                        let mut topics = Vec::new();
+                        for topic in masks {
+                            if db.unsubscribe_topic(&mut sdb, topic, &client) {
+                                topics.push(topic);
+                            }
+                            client.remove_throttle(topic);
                             trace!("elbus client {} unsubscribed from topic {}", client, topic);
                         }
-                    }
+                        #[cfg(feature = "rpc")]
+                        let demand = BrokerDb::demand_left(&sdb, demand);
+                        (topics, demand)
+                    };
                     if qos.needs_ack() {
                         send_ack!(RESPONSE_OK, qos.is_realtime());
                     }
+                    #[cfg(feature = "rpc")]
+                    {
+                        db.announce_subscriptions("unsubscribe", &client.name, &topics)
+                            .await;
+                        db.namespace_notify("unsubscribe", &client.name, &topics)
+                            .await;
+                        db.announce_demand("idle", &demand).await;
+                    }
                 }
                 _ => {
-                    let mut sp = buf.splitn(2, |c| *c == 0);
-                    let tgt = sp.next().ok_or_else(|| Error::data("broken frame"))?;
-                    let target = std::str::from_utf8(tgt)?;
-                    sp.next().ok_or_else(|| Error::data("broken frame"))?;
-                    let payload_pos = tgt.len() + 1;
-                    drop(sp);
+                    // with delivery modes enabled, acknowledged messages start with the mode byte
+                    let (mode, start) = if op == FrameOp::Message
+                        && qos.needs_ack()
+                        && client.delivery.load(atomic::Ordering::SeqCst)
+                    {
+                        let Some(Ok(mode)) = buf.first().map(|m| DeliveryMode::try_from(*m)) else {
+                            trace_unrouted!("rejected", Some("malformed".to_owned()), None);
+                            reject!(ProtocolError::Malformed, ERR_DATA);
+                        };
+                        (mode, 1)
+                    } else {
+                        (DeliveryMode::Queued, 0)
+                    };
+                    let Some(tgt_len) = buf[start..].iter().position(|c| *c == 0) else {
+                        trace_unrouted!("rejected", Some("malformed".to_owned()), None);
+                        reject!(ProtocolError::Malformed, ERR_DATA);
+                    };
+                    let Ok(target) = std::str::from_utf8(&buf[start..start + tgt_len]) else {
+                        trace_unrouted!("rejected", Some("utf8".to_owned()), None);
+                        reject!(ProtocolError::Utf8, ERR_DATA);
+                    };
+                    let payload_pos = start + tgt_len + 1;
+                    // frames of an open transaction are buffered until it is committed
+                    if client.transaction.lock().is_some() {
+                        let result = if !route_allowed(aaa.as_ref(), op, target) {
+                            Err(Error::access("the frame is not allowed"))
+                        } else if mode == DeliveryMode::Queued {
+                            Ok(TransactionFrame {
+                                op,
+                                target: target.to_owned(),
+                                header: None,
+                                buf,
+                                payload_pos,
+                                realtime: qos.is_realtime(),
+                            })
+                        } else {
+                            Err(Error::not_supported(
+                                "delivery modes are not supported in transactions",
+                            ))
+                        };
+                        let code = client.transaction.lock().as_mut().map_or(ERR_DATA, |t| {
+                            match result.and_then(|frame| t.push(frame)) {
+                                Ok(()) => RESPONSE_OK,
+                                Err(e) => t.fail(e).kind as u8,
+                            }
+                        });
+                        if qos.needs_ack() {
+                            send_ack!(code, qos.is_realtime());
+                        }
+                        continue;
+                    }
                     match op {
                         FrameOp::Message => {
                             let len = buf.len() as u64;
                             let realtime = qos.is_realtime();
-                            let allowed = if let Some(ref aaa) = aaa {
-                                aaa.allow_p2p_any || aaa.allow_p2p_to.matches(target)
-                            } else {
-                                true
-                            };
-                            if allowed {
+                            if route_allowed(aaa.as_ref(), op, target) {
+                                // delivered and consumed messages are acknowledged later
+                                let delivery = match db.delivery_tracked(target, mode) {
+                                    Ok(true) => {
+                                        let sender = DeliverySender::Client {
+                                            client: client.clone(),
+                                            op_id: op_id.try_into().unwrap(),
+                                            realtime,
+                                        };
+                                        Some((db.delivery_register(sender, target), mode))
+                                    }
+                                    Ok(false) => None,
+                                    Err(e) => {
+                                        trace_unrouted!(
+                                            "dropped",
+                                            Some(e.to_string()),
+                                            Some(target)
+                                        );
+                                        send_ack!(e.kind as u8, realtime);
+                                        continue;
+                                    }
+                                };
                                 if let Err(e) = send!(
                                     db,
                                     client,
@@ -1616,28 +7922,28 @@ impl Broker {
                                     payload_pos,
                                     len,
                                     realtime,
-                                    Some(timeout)
+                                    Some(timeout),
+                                    delivery
                                 ) {
-                                    if qos.needs_ack() {
+                                    // the delivery may be already failed if the recipient has
+                                    // been disconnected
+                                    if qos.needs_ack()
+                                        && delivery.is_none_or(|(id, _)| db.delivery_cancel(id))
+                                    {
                                         send_ack!(e.kind as u8, realtime);
                                     }
-                                } else if qos.needs_ack() {
+                                } else if qos.needs_ack() && delivery.is_none() {
                                     send_ack!(RESPONSE_OK, realtime);
                                 }
-                            } else if qos.needs_ack() {
-                                send_ack!(ERR_ACCESS, qos.is_realtime());
+                            } else {
+                                deny!();
                             }
                         }
-                        FrameOp::Broadcast => {
-                            let allowed = if let Some(ref aaa) = aaa {
-                                aaa.allow_broadcast_any || aaa.allow_broadcast_to.matches(target)
-                            } else {
-                                true
-                            };
-                            if allowed {
+                        FrameOp::Broadcast | FrameOp::BroadcastMandatory => {
+                            if route_allowed(aaa.as_ref(), op, target) {
                                 let len = buf.len() as u64;
                                 let realtime = qos.is_realtime();
-                                send_broadcast!(
+                                let routed = send_broadcast!(
                                     db,
                                     client,
                                     target,
@@ -1649,22 +7955,48 @@ impl Broker {
                                     Some(timeout)
                                 );
                                 if qos.needs_ack() {
-                                    send_ack!(RESPONSE_OK, realtime);
+                                    if routed == 0 && op == FrameOp::BroadcastMandatory {
+                                        send_ack!(ERR_UNROUTED, realtime);
+                                    } else {
+                                        send_ack!(RESPONSE_OK, realtime);
+                                    }
                                 }
-                            } else if qos.needs_ack() {
-                                send_ack!(ERR_ACCESS, qos.is_realtime());
+                            } else {
+                                deny!();
                             }
                         }
-                        FrameOp::PublishTopic => {
-                            let allowed = if let Some(ref aaa) = aaa {
-                                aaa.allow_publish_any || aaa.allow_publish_to.matches(target)
+                        FrameOp::GroupMessage | FrameOp::GroupBroadcast => {
+                            let all = op == FrameOp::GroupBroadcast;
+                            if route_allowed(aaa.as_ref(), op, target) {
+                                let len = buf.len() as u64;
+                                let realtime = qos.is_realtime();
+                                if let Err(e) = send_group!(
+                                    db,
+                                    client,
+                                    target,
+                                    all,
+                                    None,
+                                    buf,
+                                    payload_pos,
+                                    len,
+                                    realtime,
+                                    Some(timeout)
+                                ) {
+                                    if qos.needs_ack() {
+                                        send_ack!(e.kind as u8, realtime);
+                                    }
+                                } else if qos.needs_ack() {
+                                    send_ack!(RESPONSE_OK, realtime);
+                                }
                             } else {
-                                true
-                            };
-                            if allowed {
+                                deny!();
+                            }
+                        }
+                        FrameOp::PublishTopic | FrameOp::PublishTopicMandatory => {
+                            if route_allowed(aaa.as_ref(), op, target) {
                                 let len = buf.len() as u64;
                                 let realtime = qos.is_realtime();
-                                publish!(
+                                let routed = publish!(
                                     db,
                                     client,
                                     target,
@@ -1676,10 +8008,18 @@ impl Broker {
                                     Some(timeout)
                                 );
                                 if qos.needs_ack() {
-                                    send_ack!(RESPONSE_OK, realtime);
+                                    let pressure = client
+                                        .pressure_reports
+                                        .load(atomic::Ordering::SeqCst)
+                                        .then(|| client.pressure.load(atomic::Ordering::SeqCst));
+                                    if routed == 0 && op == FrameOp::PublishTopicMandatory {
+                                        send_ack!(ERR_UNROUTED, realtime, pressure);
+                                    } else {
+                                        send_ack!(RESPONSE_OK, realtime, pressure);
+                                    }
                                 }
-                            } else if qos.needs_ack() {
-                                send_ack!(ERR_ACCESS, qos.is_realtime());
+                            } else {
+                                deny!();
                             }
                         }
                         _ => {}
@@ -1693,54 +8033,244 @@ impl Broker {
         rx: EventChannel,
         writer: &mut TtlBufWriter<W>,
         timeout: Duration,
+        frame_deadline: Option<Duration>,
+        db: &BrokerDb,
+        client: &ElbusClient,
     ) -> Result<(), Error>
     where
         W: AsyncWriteExt + Unpin + Send + Sync + 'static,
     {
+        macro_rules! write_data {
+            ($data: expr, $flush: expr) => {
+                time::timeout(timeout, writer.write($data, $flush)).await??;
+            };
+        }
+        macro_rules! write_frame {
+            ($frame: expr) => {
+                if $frame.kind == FrameKind::Prepared {
+                    write_data!(&$frame.buf, $frame.realtime.into());
+                } else {
+                    write_data!(
+                        &frame_wire_header(
+                            &$frame,
+                            client.timestamps.load(atomic::Ordering::SeqCst)
+                        ),
+                        Flush::No
+                    );
+                    if let Some(header) = $frame.header() {
+                        write_data!(header, Flush::No);
+                    }
+                    write_data!($frame.payload(), $frame.realtime.into());
+                    if let Some((id, DeliveryMode::Delivered)) = $frame.delivery {
+                        db.delivery_resolve(id, None, RESPONSE_OK);
+                    }
+                }
+            };
+        }
+        // acks and other prepared frames are never expired
+        macro_rules! expired {
+            ($frame: expr) => {
+                if let (Some(deadline), Some(memory)) = (frame_deadline, $frame.memory.as_ref()) {
+                    if memory.queued.elapsed() > deadline {
+                        trace!("elbus client {} frame expired", client);
+                        client.w_expired.fetch_add(1, atomic::Ordering::SeqCst);
+                        db.expired_frames.fetch_add(1, atomic::Ordering::SeqCst);
+                        if let Some((id, _)) = $frame.delivery {
+                            db.delivery_resolve(id, None, ERR_NOT_DELIVERED);
+                        }
+                        true
+                    } else {
+                        false
+                    }
+                } else {
+                    false
+                }
+            };
+        }
+        let mut batch: Vec<u8> = Vec::new();
+        let mut batch_frames = 0;
+        // a single frame is written as-is, multiple ones are packed into a batch frame
+        macro_rules! flush_batch {
+            () => {
+                if batch_frames == 1 {
+                    write_data!(&batch, Flush::Instant);
+                } else if batch_frames > 1 {
+                    let mut buf = Vec::with_capacity(6);
+                    buf.push(OP_BATCH);
+                    #[allow(clippy::cast_possible_truncation)]
+                    buf.extend_from_slice(&(batch.len() as u32).to_le_bytes());
+                    buf.push(0);
+                    write_data!(&buf, Flush::No);
+                    write_data!(&batch, Flush::Instant);
+                }
+                batch.clear();
+                batch_frames = 0;
+            };
+        }
+        macro_rules! progress {
+            () => {
+                client.w_taken.fetch_add(1, atomic::Ordering::SeqCst);
+            };
+        }
         while let Ok(frame) = rx.recv().await {
-            macro_rules! write_data {
-                ($data: expr, $flush: expr) => {
-                    time::timeout(timeout, writer.write($data, $flush)).await??;
-                };
+            progress!();
+            if expired!(frame) {
+                continue;
             }
-            if frame.kind == FrameKind::Prepared {
-                write_data!(&frame.buf, frame.realtime.into());
-            } else {
-                let sender = frame.sender.as_ref().map(String::as_bytes);
-                let topic = frame.topic.as_ref().map(String::as_bytes);
-                #[allow(clippy::redundant_closure_for_method_calls)]
-                let mut extra_len = sender.map_or(0, |v| v.len() + 1);
-                if let Some(t) = topic.as_ref() {
-                    extra_len += t.len() + 1;
-                }
-                if let Some(header) = frame.header.as_ref() {
-                    extra_len += header.len();
-                }
-                let mut buf = Vec::with_capacity(6 + extra_len);
-                buf.push(frame.kind as u8); // byte 0
-                let frame_len = extra_len + frame.buf.len() - frame.payload_pos;
-                #[allow(clippy::cast_possible_truncation)]
-                buf.extend_from_slice(&(frame_len as u32).to_le_bytes()); // bytes 1-4
-                buf.push(if frame.realtime { 1 } else { 0 }); // byte 5 - reserved
-                if let Some(s) = sender {
-                    buf.extend_from_slice(s);
-                    buf.push(0x00);
-                }
-                if let Some(t) = topic.as_ref() {
-                    buf.extend_from_slice(t);
-                    buf.push(0x00);
-                };
-                write_data!(&buf, Flush::No);
-                if let Some(header) = frame.header() {
-                    write_data!(header, Flush::No);
+            let batch_size = client.batch_size.load(atomic::Ordering::SeqCst) as usize;
+            // tracked deliveries are resolved right after the frame is written
+            if batch_size == 0 || frame.realtime || frame.delivery.is_some() {
+                write_frame!(frame);
+                continue;
+            }
+            let deadline = time::Instant::now()
+                + Duration::from_micros(u64::from(
+                    client.batch_delay.load(atomic::Ordering::SeqCst),
+                ));
+            let timestamps = client.timestamps.load(atomic::Ordering::SeqCst);
+            let mut next = Some(frame);
+            while let Some(frame) = next.take() {
+                progress!();
+                let size = frame_wire_len(&frame, timestamps);
+                if expired!(frame) {
+                    // dropped, the next frame is taken
+                } else if frame.realtime || frame.delivery.is_some() || size + 6 > batch_size {
+                    // large, real-time and tracked frames are written as-is, real-time ones close
+                    // the batch
+                    flush_batch!();
+                    write_frame!(frame);
+                    if frame.realtime {
+                        break;
+                    }
+                } else {
+                    if batch.len() + size + 6 > batch_size {
+                        flush_batch!();
+                    }
+                    encode_frame(&frame, timestamps, &mut batch);
+                    batch_frames += 1;
                 }
-                write_data!(frame.payload(), frame.realtime.into());
+                // if the channel is closed, the batch is flushed and the outer loop is over
+                next = match rx.try_recv() {
+                    Ok(frame) => Some(frame),
+                    Err(async_channel::TryRecvError::Empty) => {
+                        time::timeout_at(deadline, rx.recv())
+                            .await
+                            .ok()
+                            .and_then(Result::ok)
+                    }
+                    Err(async_channel::TryRecvError::Closed) => None,
+                };
             }
+            flush_batch!();
         }
         Ok(())
     }
 }
 
+/// The wire frame header: kind, length, the realtime flag, the sender and the topic
+/// If timestamps are requested by the client, the receive timestamp (if the frame has one) is
+/// put before the sender and bit 1 of the reserved byte is set. Frames, which must be confirmed
+/// by the recipient, have the delivery id put after the timestamp and bit 2 set
+fn frame_wire_header(frame: &FrameData, timestamps: bool) -> Vec<u8> {
+    let sender = frame.sender.as_ref().map(String::as_bytes);
+    let topic = frame.topic.as_ref().map(String::as_bytes);
+    let timestamp = frame.timestamp.filter(|_| timestamps);
+    let delivery_id = frame.delivery_id();
+    #[allow(clippy::redundant_closure_for_method_calls)]
+    let mut extra_len = sender.map_or(0, |v| v.len() + 1);
+    if timestamp.is_some() {
+        extra_len += 8;
+    }
+    if delivery_id.is_some() {
+        extra_len += 4;
+    }
+    if let Some(t) = topic.as_ref() {
+        extra_len += t.len() + 1;
+    }
+    if let Some(header) = frame.header.as_ref() {
+        extra_len += header.len();
+    }
+    let mut buf = Vec::with_capacity(6 + extra_len);
+    buf.push(frame.kind as u8); // byte 0
+    let frame_len = extra_len + frame.buf.len() - frame.payload_pos;
+    #[allow(clippy::cast_possible_truncation)]
+    buf.extend_from_slice(&(frame_len as u32).to_le_bytes()); // bytes 1-4
+    let mut flags = 0;
+    if frame.realtime {
+        flags |= FRAME_FLAG_REALTIME;
+    }
+    if timestamp.is_some() {
+        flags |= FRAME_FLAG_TIMESTAMP;
+    }
+    if delivery_id.is_some() {
+        flags |= FRAME_FLAG_DELIVERY;
+    }
+    buf.push(flags); // byte 5 - reserved
+    if let Some(ts) = timestamp {
+        buf.extend_from_slice(&ts.to_le_bytes());
+    }
+    if let Some(id) = delivery_id {
+        buf.extend_from_slice(&id.to_le_bytes());
+    }
+    if let Some(s) = sender {
+        buf.extend_from_slice(s);
+        buf.push(0x00);
+    }
+    if let Some(t) = topic.as_ref() {
+        buf.extend_from_slice(t);
+        buf.push(0x00);
+    };
+    buf
+}
+
+/// The size of the frame body on the wire, not including the 6-byte header
+fn frame_wire_len(frame: &FrameData, timestamps: bool) -> usize {
+    if frame.kind == FrameKind::Prepared {
+        frame.buf.len().saturating_sub(6)
+    } else {
+        frame.timestamp.filter(|_| timestamps).map_or(0, |_| 8)
+            + frame.delivery_id().map_or(0, |_| 4)
+            + frame.sender.as_ref().map_or(0, |v| v.len() + 1)
+            + frame.topic.as_ref().map_or(0, |v| v.len() + 1)
+            + frame.header.as_ref().map_or(0, Vec::len)
+            + frame.payload().len()
+    }
+}
+
+fn encode_frame(frame: &FrameData, timestamps: bool, buf: &mut Vec<u8>) {
+    if frame.kind == FrameKind::Prepared {
+        buf.extend_from_slice(&frame.buf);
+    } else {
+        buf.extend(frame_wire_header(frame, timestamps));
+        if let Some(header) = frame.header() {
+            buf.extend_from_slice(header);
+        }
+        buf.extend_from_slice(frame.payload());
+    }
+}
+
+/// Crate features, the broker is built with
+fn compiled_features() -> Vec<&'static str> {
+    let mut features = vec!["broker"];
+    for (feature, enabled) in [
+        ("ipc", cfg!(feature = "ipc")),
+        ("rpc", cfg!(feature = "rpc")),
+        ("server", cfg!(feature = "server")),
+        ("sled", cfg!(feature = "sled")),
+        ("webhook", cfg!(feature = "webhook")),
+        ("sql", cfg!(feature = "sql")),
+        ("serial", cfg!(feature = "serial")),
+        ("uring", cfg!(all(target_os = "linux", feature = "uring"))),
+        ("syslog", cfg!(all(unix, feature = "syslog"))),
+        ("testing", cfg!(feature = "testing")),
+    ] {
+        if enabled {
+            features.push(feature);
+        }
+    }
+    features
+}
+
 impl Drop for Broker {
     fn drop(&mut self) {
         for service in &self.services {
@@ -1748,3 +8278,375 @@ impl Drop for Broker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // flags, hosts, p2p/publish/subscribe/broadcast lists, ops
+    type AclState = (u8, Vec<String>, Vec<Vec<String>>, Option<Vec<u8>>);
+
+    fn acl_state(aaa: &ClientAaa) -> AclState {
+        let mut hosts: Vec<String> = aaa.hosts_allow.iter().map(ToString::to_string).collect();
+        hosts.sort();
+        let lists = [
+            &aaa.allow_p2p_to,
+            &aaa.allow_publish_to,
+            &aaa.allow_subscribe_to,
+            &aaa.allow_broadcast_to,
+        ]
+        .into_iter()
+        .map(|acl| {
+            let mut list: Vec<String> = acl.list().iter().map(ToString::to_string).collect();
+            list.sort();
+            list
+        })
+        .collect();
+        let ops = aaa.allow_ops.as_ref().map(|ops| {
+            let mut ops: Vec<u8> = ops.iter().map(|op| *op as u8).collect();
+            ops.sort_unstable();
+            ops
+        });
+        (aaa.snapshot_flags(), hosts, lists, ops)
+    }
+
+    #[cfg(feature = "ipc")]
+    #[tokio::test]
+    async fn hello_round_trip() {
+        let groups = ["g1".to_owned(), "g2".to_owned()];
+        let ext: &[u8] = &[1, 2, 3];
+        for (token, extensions) in [
+            (None, None),
+            (Some("secret"), None),
+            (None, Some(ext)),
+            (Some("secret"), Some(&[][..])),
+        ] {
+            let buf = crate::ipc::hello("test", &groups, token, extensions).unwrap();
+            let len = u16::from_le_bytes([buf[0], buf[1]]);
+            assert_eq!(len & HELLO_TOKEN_FLAG != 0, token.is_some());
+            assert_eq!(len & HELLO_EXTENSIONS_FLAG != 0, extensions.is_some());
+            let mut reader = buf.as_slice();
+            let hello = read_hello(&mut reader, Duration::from_secs(1))
+                .await
+                .unwrap();
+            assert!(reader.is_empty());
+            assert_eq!(hello.name, b"test\0g1\0g2");
+            assert_eq!(hello.token.as_deref(), token.map(str::as_bytes));
+            assert_eq!(hello.extensions.as_deref(), extensions);
+        }
+        // the name length must not overlap the flags
+        assert!(crate::ipc::hello(&"a".repeat(0x4000), &[], None, None).is_err());
+        // a zero token length
+        let mut reader: &[u8] = &[0x01, 0x40, b'a', 0x00, 0x00];
+        let err = read_hello(&mut reader, Duration::from_secs(1))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::Data);
+    }
+
+    #[cfg(feature = "ipc")]
+    #[test]
+    fn batch_round_trip() {
+        let mut publish = FrameData::new(
+            FrameKind::Publish,
+            Some("sender".to_owned()),
+            Some("sensors/temp".to_owned()),
+            None,
+            b"25.5".to_vec(),
+            0,
+            false,
+        );
+        publish.timestamp = Some(1_650_000_000_123_456_789);
+        let frames = [
+            FrameData::new(
+                FrameKind::Message,
+                Some("sender".to_owned()),
+                None,
+                Some(vec![0x01, 0x02]),
+                b"hello".to_vec(),
+                0,
+                false,
+            ),
+            publish,
+            FrameData::new(
+                FrameKind::Broadcast,
+                Some("sender".to_owned()),
+                None,
+                None,
+                Vec::new(),
+                0,
+                true,
+            ),
+        ];
+        let mut batch = Vec::new();
+        for frame in &frames {
+            let pos = batch.len();
+            encode_frame(frame, true, &mut batch);
+            assert_eq!(batch.len() - pos, frame_wire_len(frame, true) + 6);
+        }
+        // acknowledgements are packed into batches as well
+        batch.extend_from_slice(&[OP_ACK, 1, 0, 0, 0, RESPONSE_OK]);
+        let split = crate::ipc::split_batch(&batch).unwrap();
+        assert_eq!(split.len(), frames.len() + 1);
+        for (frame, (kind, header, data)) in frames.iter().zip(&split) {
+            assert_eq!(*kind, frame.kind());
+            let parsed = crate::ipc::parse_frame(*kind, header[5], data.to_vec()).unwrap();
+            assert_eq!(parsed.sender(), frame.sender());
+            assert_eq!(parsed.topic(), frame.topic());
+            assert_eq!(parsed.timestamp(), frame.timestamp());
+            assert_eq!(parsed.is_realtime(), frame.is_realtime());
+            // IPC frames have no separate headers, the header is a part of the payload
+            let mut payload = frame.header().unwrap_or_default().to_vec();
+            payload.extend_from_slice(frame.payload());
+            assert_eq!(parsed.payload(), payload);
+        }
+        assert_eq!(split[frames.len()].0, FrameKind::Acknowledge);
+        assert!(crate::ipc::split_batch(&batch[..batch.len() - 1]).is_err());
+        let mut nested = vec![OP_BATCH, 0, 0, 0, 0, 0];
+        nested.extend_from_slice(&batch);
+        assert!(crate::ipc::split_batch(&nested).is_err());
+    }
+
+    #[cfg(all(unix, feature = "rpc"))]
+    #[test]
+    fn fifo_tokens_quoted() {
+        let tokens = |line: &str| -> Vec<(String, bool)> {
+            fifo_tokens(line)
+                .unwrap()
+                .into_iter()
+                .map(|t| (t.value, t.quoted))
+                .collect()
+        };
+        assert!(tokens("  ").is_empty());
+        assert_eq!(
+            tokens("target  :method\tx=1 "),
+            [
+                ("target".to_owned(), false),
+                (":method".to_owned(), false),
+                ("x=1".to_owned(), false)
+            ]
+        );
+        assert_eq!(
+            tokens(r#"target "multi-word \"message\"" '.multi-word notification'"#),
+            [
+                ("target".to_owned(), false),
+                ("multi-word \"message\"".to_owned(), true),
+                (".multi-word notification".to_owned(), true)
+            ]
+        );
+        // quoted tokens are literals, backslashes are kept in single quotes
+        assert_eq!(
+            tokens(r#"target '@/etc/passwd' 'a\b' "" "#),
+            [
+                ("target".to_owned(), false),
+                ("@/etc/passwd".to_owned(), true),
+                ("a\\b".to_owned(), true),
+                (String::new(), true)
+            ]
+        );
+        // quotes inside unquoted tokens are kept
+        assert_eq!(tokens("a\"b"), [("a\"b".to_owned(), false)]);
+        assert!(fifo_tokens(r#"target "unclosed"#).is_err());
+        assert!(fifo_tokens(r#"target "escaped\"#).is_err());
+        assert!(fifo_tokens(r#"target "quoted"tail"#).is_err());
+    }
+
+    fn test_client(name: &str, limits: Option<SubscriptionLimits>) -> BrokerClient {
+        let (mut client, _rx, _disconnect) = ElbusClient::new(
+            name,
+            name,
+            16,
+            ClientKind::Tcp,
+            None,
+            None,
+            None,
+            Vec::new(),
+        );
+        client.subscription_limits = limits;
+        Arc::new(client)
+    }
+
+    #[test]
+    fn subscription_limits() {
+        let db = BrokerDb::new(MaskFormat::broadcast(), MaskFormat::topic());
+        *db.subscription_limits.write() = SubscriptionLimits {
+            max_subscriptions: 3,
+            max_wildcard_subscriptions: 1,
+            max_topic_depth: 3,
+            max_topic_length: 16,
+        };
+        let client = test_client("c1", None);
+        let mut sdb = db.subscriptions.write();
+        sdb.register_client(&client);
+        // the default subscription is not counted
+        sdb.subscribe(BROKER_WARN_TOPIC, &client);
+        let check = |sdb: &SubMap<BrokerClient>, client: &BrokerClient, topics: &[&str]| {
+            db.check_subscription_limits(sdb, client, topics)
+                .map_err(|e| e.kind())
+        };
+        assert_eq!(check(&sdb, &client, &["a/b", "a/+", "a/b/c"]), Ok(()));
+        assert_eq!(check(&sdb, &client, &["a/b/c/d"]), Err(ErrorKind::Limit));
+        assert_eq!(
+            check(&sdb, &client, &["a/0123456789abcdef"]),
+            Err(ErrorKind::Limit)
+        );
+        assert_eq!(check(&sdb, &client, &["a/+", "#"]), Err(ErrorKind::Limit));
+        assert_eq!(
+            check(&sdb, &client, &["a", "b", "c", "d"]),
+            Err(ErrorKind::Limit)
+        );
+        sdb.subscribe("a/b", &client);
+        sdb.subscribe("a/+", &client);
+        // topics, the client is already subscribed to, are not counted twice
+        assert_eq!(check(&sdb, &client, &["a/b", "a/c"]), Ok(()));
+        assert_eq!(check(&sdb, &client, &["a/c", "a/d"]), Err(ErrorKind::Limit));
+        assert_eq!(check(&sdb, &client, &["a/#"]), Err(ErrorKind::Limit));
+        // per-client limits override the broker ones, empty limits disable checks
+        let limited = test_client(
+            "c2",
+            Some(SubscriptionLimits {
+                max_subscriptions: 1,
+                ..SubscriptionLimits::default()
+            }),
+        );
+        sdb.register_client(&limited);
+        assert_eq!(check(&sdb, &limited, &["a/b/c/d"]), Ok(()));
+        assert_eq!(check(&sdb, &limited, &["a", "b"]), Err(ErrorKind::Limit));
+        let unlimited = test_client("c3", Some(SubscriptionLimits::default()));
+        sdb.register_client(&unlimited);
+        assert_eq!(
+            check(&sdb, &unlimited, &["a", "b", "c", "d/+", "#"]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn mask_formats() {
+        assert_eq!("/,+,#".parse::<MaskFormat>().unwrap(), MaskFormat::topic());
+        assert_eq!(
+            ".,?,*".parse::<MaskFormat>().unwrap(),
+            MaskFormat::broadcast()
+        );
+        for s in [
+            "", "/,+", "/,+,#,*", "//,+,#", ",+,#", "/,,#", "/,+,+", "/,a/b,#",
+        ] {
+            assert!(s.parse::<MaskFormat>().is_err(), "{}", s);
+        }
+        // MQTT-like topics, NATS-style topics and broadcast masks
+        for (format, topic, wildcard_masks, literal_masks) in [
+            (
+                MaskFormat::topic(),
+                "sensors/room1/temp",
+                ["sensors/+/temp", "sensors/#"],
+                ["sensors/room1/temp", "sensors/*/temp"],
+            ),
+            (
+                ".,*,>".parse().unwrap(),
+                "sensors.room1.temp",
+                ["sensors.*.temp", "sensors.>"],
+                ["sensors.room1.temp", "sensors/#"],
+            ),
+            (
+                MaskFormat::broadcast(),
+                "sensors.room1.temp",
+                ["sensors.?.temp", "sensors.*"],
+                ["sensors.room1.temp", "sensors.+.temp"],
+            ),
+        ] {
+            let db = BrokerDb::new(format.clone(), format.clone());
+            let client = test_client("c1", None);
+            let mut sdb = db.subscriptions.write();
+            sdb.register_client(&client);
+            for mask in wildcard_masks {
+                assert!(format.is_wildcard_mask(mask), "{}", mask);
+                sdb.subscribe(mask, &client);
+                assert!(sdb.get_subscribers(topic).contains(&client), "{}", mask);
+                assert!(db.unsubscribe_topic(&mut sdb, mask, &client));
+            }
+            for mask in literal_masks {
+                assert!(!format.is_wildcard_mask(mask), "{}", mask);
+            }
+            assert!(sdb.get_subscribers(topic).is_empty(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn snapshot_record_round_trip() {
+        let mut buf = Vec::new();
+        snapshot_record(
+            &mut buf,
+            SNAPSHOT_RECORD_PERSISTENCE,
+            &[b"tree", b"", b"value"],
+        )
+        .unwrap();
+        assert_eq!(buf[0], SNAPSHOT_RECORD_PERSISTENCE);
+        let len = u32::from_le_bytes(buf[1..5].try_into().unwrap()) as usize;
+        assert_eq!(len, buf.len() - 5);
+        let fields = snapshot_fields(&buf[5..]).unwrap();
+        assert_eq!(fields, [&b"tree"[..], b"", b"value"]);
+        assert!(snapshot_fields(&buf[5..buf.len() - 1]).is_err());
+        assert!(snapshot_fields(&buf[5..7]).is_err());
+    }
+
+    #[tokio::test]
+    async fn snapshot_restore_round_trip() {
+        let broker = Broker::new();
+        let persistence = broker.persistence();
+        persistence.insert("t1", b"k1", b"v1").unwrap();
+        persistence.insert("t1", b"k2", b"").unwrap();
+        persistence.insert("t2", b"k1", b"v2").unwrap();
+        let acls = [
+            (
+                "c1",
+                ClientAaa::new()
+                    .hosts_allow(vec!["10.0.0.0/8".parse().unwrap()])
+                    .allow_p2p_to(&["c2", "c3/#"])
+                    .deny_publish()
+                    .allow_subscribe_to(&["#"])
+                    .allow_wiretap()
+                    .allow_admin()
+                    .allow_ops(&[FrameOp::Message, FrameOp::SubscribeTopic]),
+            ),
+            ("c2", ClientAaa::new().deny_p2p().deny_broadcast()),
+        ];
+        let expected: HashMap<&str, _> = acls
+            .iter()
+            .map(|(name, aaa)| (*name, acl_state(aaa)))
+            .collect();
+        let aaa_map: AaaMap = <_>::default();
+        aaa_map
+            .lock()
+            .extend(acls.into_iter().map(|(name, aaa)| (name.to_owned(), aaa)));
+        broker
+            .db
+            .aaa_maps
+            .write()
+            .insert("/tmp/test.sock".to_owned(), aaa_map.clone());
+        let path = std::env::temp_dir().join(format!("elbus-snapshot-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        broker.snapshot(path).unwrap();
+        persistence.clear("t1").unwrap();
+        persistence.insert("t2", b"k3", b"v3").unwrap();
+        aaa_map.lock().clear();
+        broker.restore(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            persistence.list("t1", &[]).unwrap(),
+            [
+                (b"k1".to_vec(), b"v1".to_vec()),
+                (b"k2".to_vec(), Vec::new())
+            ]
+        );
+        // trees are replaced
+        assert_eq!(
+            persistence.list("t2", &[]).unwrap(),
+            [(b"k1".to_vec(), b"v2".to_vec())]
+        );
+        let restored = aaa_map.lock();
+        assert_eq!(restored.len(), expected.len());
+        for (name, state) in expected {
+            assert_eq!(acl_state(&restored[name]), state, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use crate::{Error, ErrorKind, FrameKind};
+use std::io::{Read, Write};
+
+/// Capture files start with the magic bytes, followed by the format version (u16 LE). The
+/// format is specified in "docs/capture.rst"
+pub const CAPTURE_MAGIC: [u8; 2] = [0xEB, 0xCA];
+pub const CAPTURE_VERSION: u16 = 1;
+
+/// Max record length, protects readers from corrupted files
+pub const CAPTURE_RECORD_MAX: u32 = 512 * 1024 * 1024;
+
+/// Returns the file header
+#[inline]
+pub fn capture_header() -> [u8; 4] {
+    let version = CAPTURE_VERSION.to_le_bytes();
+    [CAPTURE_MAGIC[0], CAPTURE_MAGIC[1], version[0], version[1]]
+}
+
+/// A captured frame
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CaptureRecord {
+    /// nanoseconds since the UNIX epoch
+    pub timestamp: u64,
+    /// Message, Broadcast or Publish
+    pub kind: FrameKind,
+    pub sender: String,
+    /// the target client, the broadcast mask or the topic
+    pub target: String,
+    pub payload: Vec<u8>,
+}
+
+impl CaptureRecord {
+    /// Encodes the record, including the length prefix
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        match self.kind {
+            FrameKind::Message | FrameKind::Broadcast | FrameKind::Publish => {}
+            _ => {
+                return Err(Error::data(
+                    "only messages and publications can be captured",
+                ))
+            }
+        }
+        if self.sender.contains('\0') || self.target.contains('\0') {
+            return Err(Error::data("sender and target can not contain zero bytes"));
+        }
+        let len = u32::try_from(
+            8 + 1 + self.sender.len() + 1 + self.target.len() + 1 + self.payload.len(),
+        )
+        .ok()
+        .filter(|len| *len <= CAPTURE_RECORD_MAX)
+        .ok_or_else(|| Error::data("record too large"))?;
+        let mut buf = Vec::with_capacity(len as usize + 4);
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(&self.timestamp.to_le_bytes());
+        buf.push(self.kind as u8);
+        buf.extend_from_slice(self.sender.as_bytes());
+        buf.push(0x00);
+        buf.extend_from_slice(self.target.as_bytes());
+        buf.push(0x00);
+        buf.extend_from_slice(&self.payload);
+        Ok(buf)
+    }
+}
+
+pub struct CaptureWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Writes the file header
+    pub fn new(mut inner: W) -> Result<Self, Error> {
+        inner.write_all(&capture_header()).map_err(Error::io)?;
+        Ok(Self { inner })
+    }
+    pub fn write(&mut self, record: &CaptureRecord) -> Result<(), Error> {
+        self.inner.write_all(&record.encode()?).map_err(Error::io)
+    }
+    #[inline]
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush().map_err(Error::io)
+    }
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+pub struct CaptureReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Reads and checks the file header
+    pub fn new(mut inner: R) -> Result<Self, Error> {
+        let mut header = [0_u8; 4];
+        inner.read_exact(&mut header).map_err(Error::io)?;
+        if header[..2] != CAPTURE_MAGIC {
+            return Err(Error::data("not an elbus capture file"));
+        }
+        let version = u16::from_le_bytes([header[2], header[3]]);
+        if version != CAPTURE_VERSION {
+            return Err(Error::not_supported(format!(
+                "unsupported capture file version: {}",
+                version
+            )));
+        }
+        Ok(Self { inner })
+    }
+    /// Returns None at the end of file, truncated records (e.g. written by an interrupted
+    /// capture) are reported as Eof errors
+    pub fn read(&mut self) -> Result<Option<CaptureRecord>, Error> {
+        let mut len_buf = [0_u8; 4];
+        let mut pos = 0;
+        while pos < len_buf.len() {
+            match self.inner.read(&mut len_buf[pos..]) {
+                Ok(0) if pos == 0 => return Ok(None),
+                Ok(0) => return Err(truncated()),
+                Ok(n) => pos += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(Error::io(e)),
+            }
+        }
+        let len = u32::from_le_bytes(len_buf);
+        if len > CAPTURE_RECORD_MAX {
+            return Err(Error::data("record too large"));
+        }
+        let mut buf = vec![0_u8; len as usize];
+        self.inner.read_exact(&mut buf).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                truncated()
+            } else {
+                Error::io(e)
+            }
+        })?;
+        if buf.len() < 9 {
+            return Err(Error::data("record too short"));
+        }
+        let timestamp = u64::from_le_bytes(buf[..8].try_into().unwrap());
+        let kind = match FrameKind::try_from(buf[8])? {
+            k @ (FrameKind::Message | FrameKind::Broadcast | FrameKind::Publish) => k,
+            k => return Err(Error::data(format!("invalid record kind: {:?}", k))),
+        };
+        let mut sp = buf[9..].splitn(3, |c| *c == 0);
+        let sender = sp.next().ok_or_else(|| Error::data("sender missing"))?;
+        let target = sp.next().ok_or_else(|| Error::data("target missing"))?;
+        let payload = sp.next().ok_or_else(|| Error::data("payload missing"))?;
+        Ok(Some(CaptureRecord {
+            timestamp,
+            kind,
+            sender: std::str::from_utf8(sender).map_err(Error::data)?.to_owned(),
+            target: std::str::from_utf8(target).map_err(Error::data)?.to_owned(),
+            payload: payload.to_vec(),
+        }))
+    }
+}
+
+#[inline]
+fn truncated() -> Error {
+    Error::new(ErrorKind::Eof, Some("truncated record"))
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<CaptureRecord, Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<CaptureRecord> {
+        vec![
+            CaptureRecord {
+                timestamp: 1_650_000_000_123_456_789,
+                kind: FrameKind::Message,
+                sender: "client1".to_owned(),
+                target: "client2".to_owned(),
+                payload: b"hello".to_vec(),
+            },
+            CaptureRecord {
+                timestamp: 1_650_000_001_000_000_000,
+                kind: FrameKind::Broadcast,
+                sender: "client1".to_owned(),
+                target: "clients.*".to_owned(),
+                payload: Vec::new(),
+            },
+            CaptureRecord {
+                timestamp: 1_650_000_002_000_000_000,
+                kind: FrameKind::Publish,
+                sender: "client2".to_owned(),
+                target: "sensors/temp".to_owned(),
+                payload: vec![0x00, 0xff, 0x00],
+            },
+        ]
+    }
+
+    fn capture(records: &[CaptureRecord]) -> Vec<u8> {
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        writer.into_inner()
+    }
+
+    #[test]
+    fn capture_round_trip() {
+        let records = records();
+        let data = capture(&records);
+        let reader = CaptureReader::new(data.as_slice()).unwrap();
+        let read: Vec<CaptureRecord> = reader.map(Result::unwrap).collect();
+        assert_eq!(read, records);
+    }
+
+    #[test]
+    fn capture_truncated() {
+        let records = records();
+        let data = capture(&records[..1]);
+        let header_len = capture_header().len();
+        // cut inside the length prefix and inside the record body
+        for len in [header_len + 2, data.len() - 1] {
+            let mut reader = CaptureReader::new(&data[..len]).unwrap();
+            let err = reader.read().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Eof);
+        }
+        let mut reader = CaptureReader::new(&data[..header_len]).unwrap();
+        assert!(reader.read().unwrap().is_none());
+    }
+}
@@ -0,0 +1,99 @@
+use crate::common::{ChannelOffer, ChannelToken};
+use crate::rpc::{ContentType, Rpc, RpcError, RpcEvent};
+use crate::{Error, QoS, RESPONSE_OK};
+use serde::Serialize;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+
+/// Direct channel tokens are hex-encoded 128-bit random numbers, issued by the broker
+pub const CHANNEL_TOKEN_LEN: usize = 32;
+
+const BROKER_NAME: &str = ".broker";
+
+#[derive(Serialize)]
+struct OpenParams<'a> {
+    target: &'a str,
+    path: &'a str,
+}
+
+/// A direct channel between two clients on the broker host. The broker checks the client ACL and
+/// offers the channel to the target, bulk data is transferred over the Unix socket and never
+/// passes the broker. The socket file is removed when the listener is dropped
+pub struct ChannelListener {
+    listener: UnixListener,
+    path: String,
+    token: String,
+    timeout: Duration,
+}
+
+impl ChannelListener {
+    /// Binds the Unix socket and asks the broker to offer the channel to the target
+    pub async fn open<R: Rpc + ?Sized>(
+        rpc: &R,
+        target: &str,
+        path: &str,
+        timeout: Duration,
+    ) -> Result<Self, RpcError> {
+        let _r = tokio::fs::remove_file(path).await;
+        let mut channel = Self {
+            listener: UnixListener::bind(path).map_err(Error::from)?,
+            path: path.to_owned(),
+            token: String::new(),
+            timeout,
+        };
+        let params = ContentType::MsgPack.pack(&OpenParams { target, path })?;
+        let event = rpc
+            .call(BROKER_NAME, "channel.open", params.into(), QoS::Processed)
+            .await?;
+        let reply: ChannelToken = event.unpack()?;
+        channel.token = reply.token;
+        Ok(channel)
+    }
+    /// Accepts a connection of the target, connections with invalid tokens are rejected
+    pub async fn accept(&self) -> Result<UnixStream, Error> {
+        let (mut stream, _) = self.listener.accept().await?;
+        let mut token = [0; CHANNEL_TOKEN_LEN];
+        tokio::time::timeout(self.timeout, stream.read_exact(&mut token)).await??;
+        if token[..] != *self.token.as_bytes() {
+            return Err(Error::access("invalid direct channel token"));
+        }
+        tokio::time::timeout(self.timeout, stream.write_all(&[RESPONSE_OK])).await??;
+        Ok(stream)
+    }
+    #[inline]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Drop for ChannelListener {
+    fn drop(&mut self) {
+        let _r = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Parses a notification, returns None if it is not a direct channel offer of the broker
+pub fn parse_offer(event: &RpcEvent) -> Option<ChannelOffer> {
+    if event.sender() == BROKER_NAME {
+        event.unpack().ok()
+    } else {
+        None
+    }
+}
+
+/// Connects to the channel, offered by the broker
+pub async fn connect(offer: &ChannelOffer, timeout: Duration) -> Result<UnixStream, Error> {
+    if offer.token.len() != CHANNEL_TOKEN_LEN {
+        return Err(Error::data("invalid direct channel token"));
+    }
+    let mut stream = tokio::time::timeout(timeout, UnixStream::connect(&offer.path)).await??;
+    tokio::time::timeout(timeout, stream.write_all(offer.token.as_bytes())).await??;
+    let mut buf = [0; 1];
+    tokio::time::timeout(timeout, stream.read_exact(&mut buf)).await??;
+    if buf[0] == RESPONSE_OK {
+        Ok(stream)
+    } else {
+        Err(Error::access("the direct channel is not accepted"))
+    }
+}
@@ -0,0 +1,223 @@
+use crate::broker::MaskFormat;
+use crate::common::now_ns;
+use crate::{Error, EventChannel, Frame, FrameKind};
+use parking_lot::RwLock;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+use submap::AclMap;
+use tokio::time;
+
+/// A frame, held for reordering, is delivered right after the next one or when the hold time is
+/// expired
+const REORDER_HOLD: Duration = Duration::from_millis(100);
+
+/// Fault injection rule for soak tests (requires "testing" feature). Applied to messages,
+/// broadcasts and publications, delivered to clients, which match the client mask (the broadcast
+/// mask format), publications can be filtered with the topic mask as well. If several rules match
+/// a frame, the first one is used
+///
+/// Latency is counted from the moment a frame leaves the client queue, so the delivery throughput
+/// is kept. Pings and acknowledgements are not affected
+#[derive(Debug, Clone, Default)]
+pub struct ChaosRule {
+    client: Option<String>,
+    topic: Option<String>,
+    latency: Duration,
+    jitter: Duration,
+    drop_rate: f64,
+    reorder_rate: f64,
+}
+
+impl ChaosRule {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Target client mask (default: all clients)
+    #[inline]
+    pub fn client(mut self, mask: &str) -> Self {
+        self.client.replace(mask.to_owned());
+        self
+    }
+    /// Topic mask, if set, the rule is applied to publications only
+    #[inline]
+    pub fn topic(mut self, mask: &str) -> Self {
+        self.topic.replace(mask.to_owned());
+        self
+    }
+    #[inline]
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+    /// Random extra latency, from zero to the value
+    #[inline]
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+    /// Probability of a frame to be dropped (0.0 - 1.0)
+    #[inline]
+    pub fn drop_rate(mut self, rate: f64) -> Self {
+        self.drop_rate = rate;
+        self
+    }
+    /// Probability of a frame to be delivered after the next one (0.0 - 1.0)
+    #[inline]
+    pub fn reorder_rate(mut self, rate: f64) -> Self {
+        self.reorder_rate = rate;
+        self
+    }
+}
+
+struct ChaosMatcher {
+    rule: ChaosRule,
+    clients: Option<AclMap>,
+    topics: Option<AclMap>,
+}
+
+impl ChaosMatcher {
+    fn matches(&self, client: &str, frame: &Frame) -> bool {
+        if let Some(ref clients) = self.clients {
+            if !clients.matches(client) {
+                return false;
+            }
+        }
+        if let Some(ref topics) = self.topics {
+            frame.topic().is_some_and(|topic| topics.matches(topic))
+        } else {
+            true
+        }
+    }
+}
+
+#[derive(Default)]
+struct ChaosAction {
+    drop: bool,
+    delay: Duration,
+    reorder: bool,
+}
+
+#[derive(Default)]
+pub(crate) struct Chaos {
+    rules: RwLock<Vec<ChaosMatcher>>,
+}
+
+impl Chaos {
+    pub(crate) fn add(
+        &self,
+        rule: ChaosRule,
+        broadcast_format: &MaskFormat,
+        topic_format: &MaskFormat,
+    ) -> Result<(), Error> {
+        for rate in [rule.drop_rate, rule.reorder_rate] {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(Error::data(format!("invalid chaos rate: {}", rate)));
+            }
+        }
+        let clients = rule.client.as_deref().map(|mask| {
+            let mut acl = broadcast_format.acl_map();
+            acl.insert(mask);
+            acl
+        });
+        let topics = rule.topic.as_deref().map(|mask| {
+            let mut acl = topic_format.acl_map();
+            acl.insert(mask);
+            acl
+        });
+        self.rules.write().push(ChaosMatcher {
+            rule,
+            clients,
+            topics,
+        });
+        Ok(())
+    }
+    pub(crate) fn clear(&self) {
+        self.rules.write().clear();
+    }
+    fn action(&self, client: &str, frame: &Frame) -> Option<ChaosAction> {
+        match frame.kind() {
+            FrameKind::Message | FrameKind::Broadcast | FrameKind::Publish => {}
+            _ => return None,
+        }
+        let rules = self.rules.read();
+        let rule = &rules.iter().find(|m| m.matches(client, frame))?.rule;
+        let mut action = ChaosAction::default();
+        if rule.drop_rate > 0.0 && random() < rule.drop_rate {
+            action.drop = true;
+            return Some(action);
+        }
+        action.delay = rule.latency;
+        if !rule.jitter.is_zero() {
+            action.delay += rule.jitter.mul_f64(random());
+        }
+        action.reorder = rule.reorder_rate > 0.0 && random() < rule.reorder_rate;
+        Some(action)
+    }
+    /// Relays frames from the client queue to the writer, applying the rules. Delayed frames are
+    /// kept in a buffer of the client queue size
+    pub(crate) fn relay(self: &Arc<Self>, client: &str, rx: EventChannel) -> EventChannel {
+        let (delayed_tx, delayed_rx) =
+            async_channel::bounded::<(time::Instant, bool, Frame)>(rx.capacity().unwrap_or(1));
+        let (tx, relay_rx) = async_channel::bounded(1);
+        let chaos = self.clone();
+        let client = client.to_owned();
+        tokio::spawn(async move {
+            while let Ok(frame) = rx.recv().await {
+                let now = time::Instant::now();
+                let (due, reorder) = match chaos.action(&client, &frame) {
+                    Some(action) if action.drop => continue,
+                    Some(action) => (now + action.delay, action.reorder),
+                    None => (now, false),
+                };
+                if delayed_tx.send((due, reorder, frame)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        tokio::spawn(async move {
+            let mut held: Option<Frame> = None;
+            loop {
+                let (due, reorder, frame) = if held.is_some() {
+                    match time::timeout(REORDER_HOLD, delayed_rx.recv()).await {
+                        Ok(Ok(v)) => v,
+                        Ok(Err(_)) => break,
+                        Err(_) => {
+                            if tx.send(held.take().unwrap()).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                    }
+                } else if let Ok(v) = delayed_rx.recv().await {
+                    v
+                } else {
+                    break;
+                };
+                time::sleep_until(due).await;
+                if reorder && held.is_none() {
+                    held.replace(frame);
+                    continue;
+                }
+                if tx.send(frame).await.is_err() {
+                    break;
+                }
+                if let Some(frame) = held.take() {
+                    if tx.send(frame).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        relay_rx
+    }
+}
+
+/// Returns a random number in the range 0.0..1.0, good enough for fault injection
+fn random() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(now_ns());
+    (hasher.finish() >> 11) as f64 / (1_u64 << 53) as f64
+}
@@ -1,26 +1,40 @@
 use async_trait::async_trait;
 use atty::Stream;
-use clap::{Parser, Subcommand};
+use clap::{ArgEnum, Parser, Subcommand};
 use colored::Colorize;
+use elbus::capture::{CaptureReader, CaptureRecord, CaptureWriter};
 use elbus::client::AsyncClient;
-use elbus::common::{BrokerInfo, BrokerStats, ClientList};
+use elbus::common::SubscriptionStats;
+use elbus::common::{
+    BrokerBenchmark, BrokerCapabilities, BrokerInfo, BrokerStats, ClientDrain, ClientList,
+    TopicList,
+};
+use elbus::common::{WIRETAP_BROADCAST, WIRETAP_MESSAGE, WIRETAP_PUBLISH};
 use elbus::ipc::{Client, Config};
-use elbus::rpc::{DummyHandlers, Rpc, RpcClient, RpcError, RpcEvent, RpcHandlers, RpcResult};
-use elbus::{empty_payload, Error, Frame, QoS};
-use log::{error, info};
+use elbus::rpc::{
+    DummyHandlers, Options, Rpc, RpcClient, RpcError, RpcEvent, RpcHandlers, RpcResult,
+    RPC_ERROR_CODE_INTERNAL, RPC_ERROR_CODE_INVALID_METHOD_PARAMS, RPC_ERROR_CODE_INVALID_REQUEST,
+    RPC_ERROR_CODE_METHOD_NOT_FOUND, RPC_ERROR_CODE_PARSE,
+};
+use elbus::tools::BenchResponder;
+use elbus::{empty_payload, Error, ErrorKind, Frame, FrameKind, OpConfirm, QoS};
+use log::{error, info, warn};
 use num_format::{Locale, ToFormattedString};
+use serde::Deserialize;
 use serde_value::Value;
-use std::collections::BTreeMap;
+use std::collections::{hash_map, BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write as _};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
 use tokio::sync::Mutex;
 use tokio::time::sleep;
 
 #[macro_use]
 extern crate bma_benchmark;
 
-#[cfg(not(feature = "std-alloc"))]
+#[cfg(all(unix, not(feature = "std-alloc")))]
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
@@ -45,12 +59,56 @@ where
 enum BrokerCommand {
     #[clap(name = "client.list")]
     ClientList,
+    #[clap(
+        name = "client.drain",
+        about = "Stop queueing frames for the client, flush its queue and disconnect it"
+    )]
+    ClientDrain(BrokerClientDrainCommand),
     #[clap(name = "info")]
     Info,
+    #[clap(
+        name = "capabilities",
+        about = "Versions, compiled-in features, listeners and limits"
+    )]
+    Capabilities,
     #[clap(name = "stats")]
     Stats,
     #[clap(name = "test")]
     Test,
+    #[clap(
+        name = "subscription.stats",
+        about = "Subscription tree diagnostics, the most subscribed masks"
+    )]
+    SubscriptionStats(BrokerSubscriptionStatsCommand),
+    #[clap(
+        name = "benchmark",
+        about = "Receive notifications, generated by the broker (requires the admin permission)"
+    )]
+    Benchmark(BrokerBenchmarkCommand),
+}
+
+#[derive(Parser, Clone)]
+struct BrokerSubscriptionStatsCommand {
+    #[clap(short = 'n', long = "limit", default_value = "20")]
+    limit: usize,
+}
+
+#[derive(Parser, Clone)]
+struct BrokerClientDrainCommand {
+    name: String,
+    #[clap(
+        long = "drain-timeout",
+        help = "Max time to flush the client queue (seconds)"
+    )]
+    timeout: Option<f64>,
+}
+
+#[derive(Parser, Clone)]
+struct BrokerBenchmarkCommand {
+    #[clap(short = 'n', long = "frames", default_value = "100000")]
+    n: u32,
+    #[clap(long = "payload-size", default_value = "100")]
+    size: usize,
 }
 
 #[derive(Parser, Clone)]
@@ -59,12 +117,106 @@ struct ListenCommand {
     topics: Vec<String>,
 }
 
+#[derive(ArgEnum, Clone, Copy)]
+enum OutputFormat {
+    Json,
+    Hex,
+}
+
+#[derive(Parser, Clone)]
+struct SubscribeCommand {
+    #[clap(required = true, help = "Topic masks")]
+    masks: Vec<String>,
+    #[clap(
+        arg_enum,
+        short = 'o',
+        long = "output",
+        default_value = "json",
+        help = "Output format, one frame per line"
+    )]
+    output: OutputFormat,
+}
+
+#[derive(Parser, Clone)]
+struct MonitorCommand {
+    #[clap(long = "client", help = "Sender or target client mask")]
+    client: Option<String>,
+    #[clap(long = "topic", help = "Topic mask (publications only)")]
+    topic: Option<String>,
+    #[clap(
+        short = 'w',
+        long = "capture",
+        help = "Write captured frames to the file"
+    )]
+    capture: Option<String>,
+}
+
+#[derive(Parser, Clone)]
+struct ReplayCommand {
+    #[clap(help = "Capture file")]
+    file: String,
+    #[clap(
+        long = "speed",
+        default_value = "1",
+        parse(try_from_str = parse_speed),
+        help = "Replay speed, e.g. 2x, 0 - no delays"
+    )]
+    speed: f64,
+    #[clap(
+        long = "remap",
+        parse(try_from_str = parse_remap),
+        help = "Replace a client name, mask or topic: OLD=NEW"
+    )]
+    remap: Vec<(String, String)>,
+}
+
+fn parse_speed(s: &str) -> Result<f64, String> {
+    let speed: f64 = s
+        .strip_suffix('x')
+        .unwrap_or(s)
+        .parse()
+        .map_err(|e| format!("invalid speed: {}", e))?;
+    if speed.is_finite() && speed >= 0.0 {
+        Ok(speed)
+    } else {
+        Err("invalid speed".to_owned())
+    }
+}
+
+fn parse_remap(s: &str) -> Result<(String, String), String> {
+    let (old, new) = s
+        .split_once('=')
+        .ok_or_else(|| "OLD=NEW expected".to_owned())?;
+    if old.is_empty() || new.is_empty() {
+        return Err("OLD=NEW expected".to_owned());
+    }
+    Ok((old.to_owned(), new.to_owned()))
+}
+
 #[derive(Parser, Clone)]
 struct TargetPayload {
     #[clap()]
     target: String,
     #[clap(help = "payload string or empty for stdin")]
     payload: Option<String>,
+    #[clap(
+        long = "mandatory",
+        help = "Fail if no clients match the broadcast mask"
+    )]
+    mandatory: bool,
+}
+
+#[derive(Parser, Clone)]
+struct GroupPayload {
+    #[clap()]
+    group: String,
+    #[clap(help = "payload string or empty for stdin")]
+    payload: Option<String>,
+    #[clap(
+        long = "all",
+        help = "Send to all group members (default: to one, round-robin)"
+    )]
+    all: bool,
 }
 
 #[derive(Parser, Clone)]
@@ -75,6 +227,26 @@ struct RpcCall {
     method: String,
     #[clap(help = "payload string key=value, '-' for stdin payload")]
     params: Vec<String>,
+    #[clap(
+        long = "json",
+        conflicts_with = "params",
+        help = "params as JSON, encoded to MessagePack"
+    )]
+    json: Option<String>,
+}
+
+#[derive(Parser, Clone)]
+struct RpcNotify {
+    #[clap()]
+    target: String,
+    #[clap(help = "payload string or empty for stdin")]
+    payload: Option<String>,
+    #[clap(
+        long = "json",
+        conflicts_with = "payload",
+        help = "payload as JSON, encoded to MessagePack"
+    )]
+    json: Option<String>,
 }
 
 #[derive(Parser, Clone)]
@@ -83,16 +255,30 @@ struct PublishCommand {
     topic: String,
     #[clap(help = "payload string or empty for stdin")]
     payload: Option<String>,
+    #[clap(long = "mandatory", help = "Fail if the topic has no subscribers")]
+    mandatory: bool,
 }
 
 #[derive(Subcommand, Clone)]
 enum RpcCommand {
     Listen(RpcListenCommand),
-    Notify(TargetPayload),
+    Notify(RpcNotify),
     Call0(RpcCall),
+    #[clap(after_help = RPC_EXIT_CODES_HELP)]
     Call(RpcCall),
 }
 
+const RPC_EXIT_CODES_HELP: &str = "EXIT CODES:
+    0 - success
+    1 - application error
+    3 - parse error, invalid request or params
+    4 - method not found
+    5 - internal error
+    6 - target not registered
+    7 - timeout
+    8 - access denied
+    9 - other elbus error";
+
 #[derive(Parser, Clone)]
 struct RpcListenCommand {
     #[clap(short = 't', long = "topics", help = "Subscribe to topics")]
@@ -107,6 +293,12 @@ struct BenchmarkCommand {
     payload_size: usize,
     #[clap(short = 'i', long = "iters", default_value = "1000000")]
     iters: u32,
+    #[clap(
+        long = "in-flight",
+        default_value = "1000",
+        help = "Concurrent RPC calls for the in-flight benchmark"
+    )]
+    in_flight: u32,
 }
 
 #[derive(Clone, Subcommand)]
@@ -114,11 +306,120 @@ enum Command {
     #[clap(subcommand)]
     Broker(BrokerCommand),
     Listen(ListenCommand),
+    #[clap(about = "Subscribe to topics and stream frames to stdout")]
+    Subscribe(SubscribeCommand),
+    #[clap(about = "Monitor the broker traffic (requires wiretap permission)")]
+    Monitor(MonitorCommand),
+    #[clap(about = "Re-inject the traffic, captured by the monitor")]
+    Replay(ReplayCommand),
     r#Send(TargetPayload),
+    #[clap(about = "Send to a client group")]
+    SendGroup(GroupPayload),
     Publish(PublishCommand),
     #[clap(subcommand)]
     Rpc(RpcCommand),
     Benchmark(BenchmarkCommand),
+    #[clap(about = "Interactive shell, commands are executed with a persistent connection")]
+    Shell(ShellCommand),
+    #[cfg(feature = "dbus")]
+    #[clap(about = "Bridge elbus and D-Bus, runs until interrupted")]
+    DbusBridge(DbusBridgeCommand),
+}
+
+#[cfg(feature = "dbus")]
+#[derive(Parser, Clone)]
+struct DbusBridgeCommand {
+    #[clap(long = "system", help = "Connect to the system bus (default: session)")]
+    system: bool,
+    #[clap(long = "address", help = "D-Bus address", conflicts_with = "system")]
+    address: Option<String>,
+    #[clap(
+        long = "name",
+        default_value = elbus::dbus::DBUS_DEFAULT_NAME,
+        help = "D-Bus well-known name"
+    )]
+    name: String,
+    #[clap(
+        long = "path",
+        default_value = elbus::dbus::DBUS_DEFAULT_PATH,
+        help = "D-Bus object path"
+    )]
+    path: String,
+    #[clap(
+        short = 't',
+        long = "topic",
+        help = "elbus topic mask, emitted as D-Bus signals, can be specified multiple times"
+    )]
+    topics: Vec<String>,
+    #[clap(
+        long = "target",
+        help = "elbus RPC target mask, allowed for D-Bus calls, can be specified multiple times"
+    )]
+    targets: Vec<String>,
+    #[clap(
+        long = "publish",
+        help = "elbus topic mask, allowed for D-Bus publications, can be specified multiple times"
+    )]
+    publish: Vec<String>,
+    #[clap(
+        long = "signal",
+        help = "D-Bus match rule, signals are published to elbus, can be specified multiple times"
+    )]
+    signals: Vec<String>,
+    #[clap(
+        long = "topic-prefix",
+        default_value = elbus::dbus::DBUS_DEFAULT_TOPIC_PREFIX,
+        help = "elbus topic prefix for D-Bus signals"
+    )]
+    topic_prefix: String,
+}
+
+#[derive(Parser, Clone)]
+struct ShellCommand {
+    #[clap(long = "history", help = "History file (default: ~/.elbus_history)")]
+    history: Option<String>,
+}
+
+#[derive(Parser)]
+#[clap(
+    no_binary_name = true,
+    disable_version_flag = true,
+    override_usage = "<COMMAND> [ARGS]"
+)]
+struct ShellLine {
+    #[clap(subcommand)]
+    command: ShellLineCommand,
+}
+
+#[derive(Subcommand)]
+enum ShellLineCommand {
+    #[clap(subcommand)]
+    Broker(BrokerCommand),
+    #[clap(about = "Subscribe to topics, incoming frames are printed as they arrive")]
+    Subscribe(ShellTopics),
+    #[clap(about = "Unsubscribe from topics")]
+    Unsubscribe(ShellTopics),
+    r#Send(TargetPayload),
+    Publish(PublishCommand),
+    #[clap(subcommand)]
+    Rpc(ShellRpcCommand),
+    #[clap(about = "Print the command history")]
+    History,
+    #[clap(about = "Close the session", alias = "quit")]
+    Exit,
+}
+
+#[derive(Parser)]
+struct ShellTopics {
+    #[clap(required = true, help = "Topic masks")]
+    topics: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum ShellRpcCommand {
+    Notify(RpcNotify),
+    Call0(RpcCall),
+    Call(RpcCall),
 }
 
 #[derive(Parser)]
@@ -128,10 +429,38 @@ struct Opts {
     path: String,
     #[clap(short = 'n', long = "name")]
     name: Option<String>,
+    #[clap(short = 'g', long = "group", help = "Join the client group")]
+    groups: Vec<String>,
     #[clap(long = "buf-size", default_value = "8192")]
     buf_size: usize,
     #[clap(long = "queue-size", default_value = "8192")]
     queue_size: usize,
+    #[clap(
+        long = "proxy",
+        help = "Connect through a proxy: socks5://[user:password@]host:port or http://[user:password@]host:port"
+    )]
+    proxy: Option<String>,
+    #[clap(
+        long = "token",
+        help = "Present the token to the broker (if token authentication is enabled)"
+    )]
+    token: Option<String>,
+    #[clap(
+        long = "batch-size",
+        help = "Ask the broker to pack incoming frames into batches up to the size (bytes)"
+    )]
+    batch_size: Option<u32>,
+    #[clap(
+        long = "batch-delay",
+        default_value = "1000",
+        help = "Max batch delay (microseconds)"
+    )]
+    batch_delay: u64,
+    #[clap(
+        long = "timestamps",
+        help = "Request broker receive timestamps of incoming frames"
+    )]
+    timestamps: bool,
     #[clap(long = "timeout", default_value = "5")]
     timeout: f32,
     #[clap(short = 'v', long = "verbose")]
@@ -222,6 +551,52 @@ async fn print_payload(payload: &[u8], silent: bool) {
     }
 }
 
+/// Converts the payload for the machine-readable output: JSON and MessagePack payloads are
+/// decoded, strings are printed as-is, other payloads are hex-encoded
+fn payload_to_json(payload: &[u8]) -> serde_json::Value {
+    if let Ok(s) = std::str::from_utf8(payload) {
+        if let Ok(j) = serde_json::from_str(s) {
+            return j;
+        }
+        if !s.bytes().any(|c| c < 9) {
+            return serde_json::Value::String(s.to_owned());
+        }
+    }
+    let mut de = rmp_serde::Deserializer::new(std::io::Cursor::new(payload));
+    if let Ok(data) = Value::deserialize(&mut de) {
+        // the payload must be a single MessagePack value
+        if de.get_ref().position() == payload.len() as u64 {
+            if let Ok(j) = serde_json::to_value(&data) {
+                return j;
+            }
+        }
+    }
+    serde_json::Value::String(hex::encode(payload))
+}
+
+fn print_frame_line(frame: &Frame, output: OutputFormat) {
+    match output {
+        OutputFormat::Json => {
+            let line = serde_json::json!({
+                "kind": frame.kind().to_debug_string(),
+                "sender": frame.sender(),
+                "topic": frame.topic(),
+                "size": frame.payload().len(),
+                "payload": payload_to_json(frame.payload()),
+            });
+            println!("{}", line);
+        }
+        OutputFormat::Hex => {
+            println!(
+                "{} {} {}",
+                frame.sender(),
+                frame.topic().unwrap_or("-"),
+                hex::encode(frame.payload())
+            );
+        }
+    }
+}
+
 fn print_hex(payload: &[u8]) {
     let (p, dots) = if payload.len() > 256 {
         (&payload[..256], "...")
@@ -277,10 +652,100 @@ async fn print_frame(frame: &Frame) {
     if let Some(topic) = frame.topic() {
         println!("topic: {}", topic.magenta());
     }
+    if let Some(timestamp) = frame.timestamp() {
+        let now = u64::try_from(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos(),
+        )
+        .unwrap_or(u64::MAX);
+        #[allow(clippy::cast_precision_loss)]
+        let (time, latency) = (
+            timestamp as f64 / 1_000_000_000.0,
+            now.saturating_sub(timestamp) as f64 / 1000.0,
+        );
+        println!("broker time: {:.6} (latency: {:.3} us)", time, latency);
+    }
     print_payload(frame.payload(), false).await;
     sep();
 }
 
+const MONITOR_PREVIEW_LEN: usize = 80;
+
+struct MonitorHandlers {
+    capture: Option<std::sync::Mutex<CaptureWriter<BufWriter<File>>>>,
+}
+
+#[async_trait]
+impl RpcHandlers for MonitorHandlers {
+    async fn handle_frame(&self, frame: Frame) {
+        let topic = frame.topic().unwrap_or_default();
+        let (kind, frame_kind, target) = if let Some(t) = topic.strip_prefix(WIRETAP_MESSAGE) {
+            ("msg", FrameKind::Message, t)
+        } else if let Some(t) = topic.strip_prefix(WIRETAP_BROADCAST) {
+            ("bcast", FrameKind::Broadcast, t)
+        } else if let Some(t) = topic.strip_prefix(WIRETAP_PUBLISH) {
+            ("pub", FrameKind::Publish, t)
+        } else {
+            return;
+        };
+        if let Some(ref capture) = self.capture {
+            let record = CaptureRecord {
+                // the broker receive time, if requested
+                timestamp: frame.timestamp().unwrap_or_else(|| {
+                    u64::try_from(
+                        SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_nanos(),
+                    )
+                    .unwrap_or(u64::MAX)
+                }),
+                kind: frame_kind,
+                sender: frame.sender().to_owned(),
+                target: target.to_owned(),
+                payload: frame.payload().to_vec(),
+            };
+            let mut writer = capture.lock().unwrap();
+            // flushed after each record, as the monitor is usually stopped with Ctrl-C
+            if let Err(e) = writer.write(&record).and_then(|()| writer.flush()) {
+                error!("Unable to write the capture file: {}", e);
+                std::process::exit(1);
+            }
+        }
+        let mut preview = payload_to_json(frame.payload()).to_string();
+        if preview.len() > MONITOR_PREVIEW_LEN {
+            let mut pos = MONITOR_PREVIEW_LEN;
+            while !preview.is_char_boundary(pos) {
+                pos -= 1;
+            }
+            preview.truncate(pos);
+            preview.push_str("...");
+        }
+        println!(
+            "{:.6} {:5} {} -> {} {} {}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            kind.yellow(),
+            frame.sender().bold(),
+            if kind == "pub" {
+                target.magenta()
+            } else {
+                target.cyan()
+            },
+            fnum!(frame.payload().len()).dimmed(),
+            preview
+        );
+    }
+    async fn handle_notification(&self, _event: RpcEvent) {}
+    async fn handle_call(&self, _event: RpcEvent) -> RpcResult {
+        Err(RpcError::method(None))
+    }
+}
+
 struct Handlers {}
 
 #[async_trait]
@@ -326,6 +791,56 @@ impl RpcHandlers for Handlers {
     }
 }
 
+/// Maps the RPC error code to the process exit code
+fn rpc_exit_code(code: i16) -> i32 {
+    match code {
+        RPC_ERROR_CODE_PARSE
+        | RPC_ERROR_CODE_INVALID_REQUEST
+        | RPC_ERROR_CODE_INVALID_METHOD_PARAMS => 3,
+        RPC_ERROR_CODE_METHOD_NOT_FOUND => 4,
+        RPC_ERROR_CODE_INTERNAL => 5,
+        c if c <= -32000 && c > -32256 => match ErrorKind::from((-32000 - c) as u8) {
+            ErrorKind::NotRegistered | ErrorKind::NotDelivered => 6,
+            ErrorKind::Timeout => 7,
+            ErrorKind::Access => 8,
+            _ => 9,
+        },
+        _ => 1,
+    }
+}
+
+fn rpc_error_message(e: &RpcError) -> String {
+    let message = e
+        .data()
+        .map_or("", |data| std::str::from_utf8(data).unwrap_or(""));
+    format!("RPC Error {}: {}", e.code(), message)
+}
+
+fn exit_rpc_error(e: &RpcError) -> ! {
+    error!("{}", rpc_error_message(e));
+    std::process::exit(rpc_exit_code(e.code()));
+}
+
+fn try_json_to_msgpack(s: &str) -> Result<Vec<u8>, serde_json::Error> {
+    let value: serde_json::Value = serde_json::from_str(s)?;
+    Ok(rmp_serde::to_vec_named(&value).unwrap())
+}
+
+fn json_to_msgpack(s: &str) -> Vec<u8> {
+    try_json_to_msgpack(s).unwrap_or_else(|e| {
+        error!("Invalid JSON: {}", e);
+        std::process::exit(3);
+    })
+}
+
+/// Waits for the operation confirmation
+async fn confirm(result: Result<OpConfirm, Error>) -> Result<(), Error> {
+    if let Some(c) = result? {
+        c.await.map_err(Error::io)??;
+    }
+    Ok(())
+}
+
 async fn read_stdin() -> Vec<u8> {
     let mut stdin = tokio::io::stdin();
     let mut buf: Vec<u8> = Vec::new();
@@ -345,15 +860,243 @@ async fn get_payload(candidate: &Option<String>) -> Vec<u8> {
 }
 
 async fn create_client(opts: &Opts, name: &str) -> Client {
-    let config = Config::new(&opts.path, name)
+    let groups: Vec<&str> = opts.groups.iter().map(String::as_str).collect();
+    let mut config = Config::new(&opts.path, name)
+        .groups(&groups)
         .buf_size(opts.buf_size)
         .queue_size(opts.queue_size)
         .timeout(Duration::from_secs_f32(opts.timeout));
+    if let Some(batch_size) = opts.batch_size {
+        config = config.batch(batch_size, Duration::from_micros(opts.batch_delay));
+    }
+    if opts.timestamps {
+        config = config.timestamps();
+    }
+    if let Some(ref proxy) = opts.proxy {
+        config = config.proxy(proxy.parse().expect("Invalid proxy"));
+    }
+    if let Some(ref token) = opts.token {
+        config = config.token(token);
+    }
     Client::connect(&config)
         .await
         .expect("Unable to connect to the elbus broker")
 }
 
+async fn broker_command(
+    rpc: &RpcClient,
+    op: &BrokerCommand,
+    client_name: &str,
+    silent: bool,
+) -> Result<(), RpcError> {
+    match op {
+        BrokerCommand::ClientList => {
+            let result = rpc
+                .call(".broker", "client.list", empty_payload!(), QoS::Processed)
+                .await?;
+            let mut clients: ClientList = result.unpack()?;
+            clients.clients.sort();
+            let mut table = ctable(vec![
+                "name", "type", "source", "port", "r_frames", "r_bytes", "w_frames", "w_bytes",
+                "queue", "ins", "groups", "health",
+            ]);
+            for c in clients.clients {
+                if c.name != client_name {
+                    table.add_row(row![
+                        c.name,
+                        c.kind,
+                        c.source.unwrap_or_default(),
+                        c.port.unwrap_or_default(),
+                        fnum!(c.r_frames),
+                        fnum!(c.r_bytes),
+                        fnum!(c.w_frames),
+                        fnum!(c.w_bytes),
+                        fnum!(c.queue),
+                        fnum!(c.instances),
+                        c.groups.join(","),
+                        c.health
+                            .map(|h| format!("{} {}%", h.status, h.load))
+                            .unwrap_or_default(),
+                    ]);
+                }
+            }
+            table.printstd();
+        }
+        BrokerCommand::Stats => {
+            let result = rpc
+                .call(".broker", "stats", empty_payload!(), QoS::Processed)
+                .await?;
+            let stats: BrokerStats = result.unpack()?;
+            let mut table = ctable(vec!["field", "value"]);
+            table.add_row(row!["r_frames", stats.r_frames]);
+            table.add_row(row!["r_bytes", stats.r_bytes]);
+            table.add_row(row!["w_frames", stats.w_frames]);
+            table.add_row(row!["w_bytes", stats.w_bytes]);
+            table.add_row(row!["queued_bytes", stats.queued_bytes]);
+            table.add_row(row!["expired_frames", stats.expired_frames]);
+            table.add_row(row!["uptime", stats.uptime]);
+            table.printstd();
+            if !stats.protocol_errors.is_empty() {
+                let mut table = ctable(vec![
+                    "connection",
+                    "malformed",
+                    "oversized",
+                    "unknown_ops",
+                    "utf8",
+                ]);
+                let connections = stats
+                    .connections
+                    .iter()
+                    .map(|c| (c.client.as_str(), &c.errors))
+                    .chain(std::iter::once(("total", &stats.protocol_errors)));
+                for (client, errors) in connections {
+                    table.add_row(row![
+                        client,
+                        errors.malformed,
+                        errors.oversized,
+                        errors.unknown_ops,
+                        errors.utf8
+                    ]);
+                }
+                table.printstd();
+            }
+        }
+        BrokerCommand::SubscriptionStats(ref cmd) => {
+            let mut params = HashMap::new();
+            params.insert("limit", Value::U64(cmd.limit as u64));
+            let result = rpc
+                .call(
+                    ".broker",
+                    "subscription.stats",
+                    rmp_serde::to_vec_named(&params).unwrap().into(),
+                    QoS::Processed,
+                )
+                .await?;
+            let stats: SubscriptionStats = result.unpack()?;
+            let mut table = ctable(vec!["field", "value"]);
+            table.add_row(row!["clients", fnum!(stats.clients)]);
+            table.add_row(row!["subscriptions", fnum!(stats.subscriptions)]);
+            table.add_row(row!["masks", fnum!(stats.masks)]);
+            table.add_row(row!["wildcard_masks", fnum!(stats.wildcard_masks)]);
+            table.add_row(row!["nodes", fnum!(stats.nodes)]);
+            table.add_row(row!["max_depth", stats.max_depth]);
+            table.printstd();
+            let mut table = ctable(vec!["branch", "nodes", "masks", "subscriptions"]);
+            for b in stats.branches {
+                table.add_row(row![
+                    b.branch,
+                    fnum!(b.nodes),
+                    fnum!(b.masks),
+                    fnum!(b.subscriptions)
+                ]);
+            }
+            table.printstd();
+            let mut table = ctable(vec!["mask", "subscribers", "depth", "wildcard_at"]);
+            for m in stats.top_masks {
+                table.add_row(row![
+                    m.mask,
+                    fnum!(m.subscribers),
+                    m.depth,
+                    m.wildcard_at.map(|v| v.to_string()).unwrap_or_default()
+                ]);
+            }
+            table.printstd();
+        }
+        BrokerCommand::Info => {
+            let result = rpc
+                .call(".broker", "info", empty_payload!(), QoS::Processed)
+                .await?;
+            let info: BrokerInfo = result.unpack()?;
+            let mut table = ctable(vec!["field", "value"]);
+            table.add_row(row!["author", info.author]);
+            table.add_row(row!["version", info.version]);
+            table.printstd();
+        }
+        BrokerCommand::Capabilities => {
+            let result = rpc
+                .call(".broker", "capabilities", empty_payload!(), QoS::Processed)
+                .await?;
+            let caps: BrokerCapabilities = result.unpack()?;
+            let limits = caps.limits;
+            let mut table = ctable(vec!["field", "value"]);
+            table.add_row(row!["version", caps.version]);
+            table.add_row(row!["protocol version", caps.protocol_version]);
+            table.add_row(row!["features", caps.features.join(", ")]);
+            table.add_row(row!["timestamps", caps.timestamps]);
+            table.add_row(row!["queue size", fnum!(limits.queue_size)]);
+            table.add_row(row!["memory limit", fnum!(limits.memory_limit)]);
+            table.add_row(row!["memory overflow", limits.memory_overflow]);
+            table.add_row(row!["max subscriptions", fnum!(limits.max_subscriptions)]);
+            table.add_row(row![
+                "max wildcard subscriptions",
+                fnum!(limits.max_wildcard_subscriptions)
+            ]);
+            table.add_row(row!["max topic depth", limits.max_topic_depth]);
+            table.add_row(row!["max topic length", fnum!(limits.max_topic_length)]);
+            table.add_row(row!["batch max size", fnum!(limits.batch_max_size)]);
+            table.add_row(row!["batch max delay", fnum!(limits.batch_max_delay)]);
+            for listener in caps.listeners {
+                table.add_row(row!["listener", listener.path]);
+            }
+            table.printstd();
+        }
+        BrokerCommand::ClientDrain(ref cmd) => {
+            let mut params = HashMap::new();
+            params.insert("name", Value::String(cmd.name.clone()));
+            if let Some(timeout) = cmd.timeout {
+                params.insert("timeout", Value::F64(timeout));
+            }
+            let result = rpc
+                .call(
+                    ".broker",
+                    "client.drain",
+                    rmp_serde::to_vec_named(&params).unwrap().into(),
+                    QoS::Processed,
+                )
+                .await?;
+            let drain: ClientDrain = result.unpack()?;
+            let mut table = ctable(vec!["field", "value"]);
+            table.add_row(row!["pending", fnum!(drain.pending)]);
+            table.add_row(row!["elapsed", format!("{:.3}", drain.elapsed)]);
+            table.printstd();
+        }
+        BrokerCommand::Test => {
+            let result = rpc
+                .call(".broker", "test", empty_payload!(), QoS::Processed)
+                .await?;
+            print_payload(result.payload(), silent).await;
+        }
+        BrokerCommand::Benchmark(ref cmd) => {
+            let mut params = HashMap::new();
+            params.insert("n", Value::U32(cmd.n));
+            params.insert("size", Value::U64(cmd.size as u64));
+            let result = rpc
+                .call(
+                    ".broker",
+                    "benchmark",
+                    rmp_serde::to_vec_named(&params).unwrap().into(),
+                    QoS::Processed,
+                )
+                .await?;
+            let bm: BrokerBenchmark = result.unpack()?;
+            let mut table = ctable(vec!["field", "value"]);
+            table.add_row(row!["frames", fnum!(bm.n)]);
+            table.add_row(row!["payload size", fnum!(bm.size)]);
+            table.add_row(row!["elapsed", format!("{:.3}", bm.elapsed)]);
+            table.add_row(row![
+                "frames/sec",
+                fnum!((f64::from(bm.n) / bm.elapsed) as u64)
+            ]);
+            table.add_row(row![
+                "bytes/sec",
+                fnum!((f64::from(bm.n) * bm.size as f64 / bm.elapsed) as u64)
+            ]);
+            table.printstd();
+        }
+    }
+    Ok(())
+}
+
 macro_rules! bm_finish {
     ($iters: expr, $futs: expr) => {
         while let Some(f) = $futs.pop() {
@@ -446,27 +1189,13 @@ async fn benchmark_client(
     }
 }
 
-struct BenchmarkHandlers {}
-
-#[async_trait]
-impl RpcHandlers for BenchmarkHandlers {
-    async fn handle_frame(&self, _frame: Frame) {}
-    async fn handle_notification(&self, _event: RpcEvent) {}
-    async fn handle_call(&self, event: RpcEvent) -> RpcResult {
-        if event.parse_method()? == "benchmark.selftest" {
-            Ok(Some(event.payload().to_vec()))
-        } else {
-            Err(RpcError::method(None))
-        }
-    }
-}
-
 async fn benchmark_rpc(
     opts: &Opts,
     client_name: &str,
     iters: u32,
     workers: u32,
     payload_size: usize,
+    in_flight: u32,
 ) {
     let iters_worker = iters / workers;
     let data = Arc::new(vec![0xee; payload_size]);
@@ -477,7 +1206,7 @@ async fn benchmark_rpc(
         let cname = format!("{}-{}", client_name, w + 1);
         let cname_null = format!("{}-{}-null", client_name, w + 1);
         let client = create_client(opts, &cname).await;
-        let rpc = RpcClient::new(client, BenchmarkHandlers {});
+        let rpc = BenchResponder::new().spawn(client);
         rpcs.push(Arc::new(Mutex::new(rpc)));
         cnns.push(cname_null);
     }
@@ -517,7 +1246,7 @@ async fn benchmark_rpc(
     for w in 0..workers {
         let rpc = rpcs[w as usize].clone();
         let payload = data.clone();
-        spawn_caller!(rpc, ".broker", "benchmark.test", payload, true);
+        spawn_caller!(rpc, ".broker", "echo", payload, true);
     }
     bm_finish!(iters, futs);
     staged_benchmark_start!("rpc.call+handle");
@@ -536,6 +1265,789 @@ async fn benchmark_rpc(
         spawn_caller!(rpc, target, "test", payload, false);
     }
     bm_finish!(iters, futs);
+    // many calls in-flight, multiplexed by a single client
+    let rpc = Arc::new(
+        BenchResponder::new()
+            .spawn(create_client(opts, &format!("{}-in-flight", client_name)).await),
+    );
+    let iters_call = iters / in_flight;
+    staged_benchmark_start!("rpc.call.in-flight");
+    for _ in 0..in_flight {
+        let rpc = rpc.clone();
+        let payload = data.clone();
+        futs.push(tokio::spawn(async move {
+            for _ in 0..iters_call {
+                let result = rpc
+                    .call(
+                        ".broker",
+                        "echo",
+                        payload.clone().into(),
+                        QoS::RealtimeProcessed,
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(result.payload(), *payload);
+            }
+        }));
+    }
+    bm_finish!(iters_call * in_flight, futs);
+}
+
+/// Re-injects captured frames, a client is connected for each recorded sender
+async fn replay(opts: &Opts, cmd: &ReplayCommand) -> bool {
+    let file = File::open(&cmd.file).unwrap_or_else(|e| {
+        error!("Unable to open the capture file {}: {}", cmd.file, e);
+        std::process::exit(1);
+    });
+    let reader = CaptureReader::new(BufReader::new(file)).unwrap_or_else(|e| {
+        error!("Unable to read the capture file {}: {}", cmd.file, e);
+        std::process::exit(1);
+    });
+    let remap: HashMap<&str, &str> = cmd
+        .remap
+        .iter()
+        .map(|(old, new)| (old.as_str(), new.as_str()))
+        .collect();
+    let mut clients: HashMap<String, Client> = HashMap::new();
+    let mut started: Option<(u64, Instant)> = None;
+    let mut frames: u64 = 0;
+    let mut failed: u64 = 0;
+    for record in reader {
+        let record = record.unwrap_or_else(|e| {
+            error!("Unable to read the capture file {}: {}", cmd.file, e);
+            std::process::exit(1);
+        });
+        if cmd.speed > 0.0 {
+            let (first, start) = *started.get_or_insert((record.timestamp, Instant::now()));
+            let offset = Duration::from_nanos(record.timestamp.saturating_sub(first));
+            tokio::time::sleep_until((start + offset.div_f64(cmd.speed)).into()).await;
+        }
+        let sender = remap
+            .get(record.sender.as_str())
+            .copied()
+            .unwrap_or(&record.sender);
+        // internal broker clients can not be registered via IPC, their frames (e.g. broker
+        // events) are skipped
+        if sender.starts_with('.') {
+            continue;
+        }
+        let target = remap
+            .get(record.target.as_str())
+            .copied()
+            .unwrap_or(&record.target);
+        let client = match clients.entry(sender.to_owned()) {
+            hash_map::Entry::Occupied(v) => v.into_mut(),
+            hash_map::Entry::Vacant(v) => {
+                info!("connecting replay client {}", sender.cyan());
+                let mut client = create_client(opts, sender).await;
+                // incoming frames are not processed, the channel is drained to keep the
+                // client connected
+                let rx = client.take_event_channel().unwrap();
+                tokio::spawn(async move { while rx.recv().await.is_ok() {} });
+                v.insert(client)
+            }
+        };
+        let payload = record.payload.into();
+        let result = match record.kind {
+            FrameKind::Message => client.send(target, payload, QoS::Processed).await,
+            FrameKind::Broadcast => client.send_broadcast(target, payload, QoS::Processed).await,
+            _ => client.publish(target, payload, QoS::Processed).await,
+        };
+        if let Err(e) = confirm(result).await {
+            warn!("{} -> {}: {}", sender, target, e);
+            failed += 1;
+        } else {
+            frames += 1;
+        }
+    }
+    info!(
+        "{} frame(s) replayed, {} failed",
+        fnum!(frames),
+        fnum!(failed)
+    );
+    failed == 0
+}
+
+/// Minimal terminal line editor for the interactive shell: history (Up/Down), cursor movement
+/// (Left/Right, Home/End, Ctrl-A/Ctrl-E), Ctrl-U/Ctrl-W and Tab completion
+mod line_editor {
+    #[cfg(unix)]
+    use nix::sys::termios::{
+        self, InputFlags, LocalFlags, SetArg, SpecialCharacterIndices, Termios,
+    };
+    use std::io::{self, Read, Write};
+    #[cfg(unix)]
+    use std::os::unix::io::RawFd;
+
+    #[cfg(unix)]
+    const STDIN: RawFd = 0;
+
+    pub enum Input {
+        Line(String),
+        Interrupted,
+        Eof,
+    }
+
+    enum Key {
+        Up,
+        Down,
+        Left,
+        Right,
+        Home,
+        End,
+        Delete,
+        Unknown,
+    }
+
+    /// Switches the terminal to the raw mode, the original settings are restored on drop
+    #[cfg(unix)]
+    struct RawMode(Termios);
+
+    #[cfg(unix)]
+    impl RawMode {
+        fn enable() -> io::Result<Self> {
+            let orig = termios::tcgetattr(STDIN)?;
+            let mut raw = orig.clone();
+            raw.local_flags.remove(
+                LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG | LocalFlags::IEXTEN,
+            );
+            raw.input_flags.remove(InputFlags::IXON | InputFlags::ICRNL);
+            raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+            raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+            termios::tcsetattr(STDIN, SetArg::TCSADRAIN, &raw)?;
+            Ok(Self(orig))
+        }
+    }
+
+    #[cfg(unix)]
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            let _r = termios::tcsetattr(STDIN, SetArg::TCSADRAIN, &self.0);
+        }
+    }
+
+    /// The Windows console is left in the line mode, lines are edited by the console itself
+    #[cfg(not(unix))]
+    struct RawMode;
+
+    #[cfg(not(unix))]
+    impl RawMode {
+        #[allow(clippy::unnecessary_wraps)]
+        fn enable() -> io::Result<Self> {
+            Ok(Self)
+        }
+    }
+
+    fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+        let mut buf = [0_u8; 1];
+        Ok(if input.read(&mut buf)? == 0 {
+            None
+        } else {
+            Some(buf[0])
+        })
+    }
+
+    fn read_char(input: &mut impl Read) -> io::Result<Option<char>> {
+        let first = if let Some(b) = read_byte(input)? {
+            b
+        } else {
+            return Ok(None);
+        };
+        let len = match first {
+            0..=0x7f => return Ok(Some(char::from(first))),
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Ok(Some(char::REPLACEMENT_CHARACTER)),
+        };
+        let mut buf = vec![first];
+        for _ in 1..len {
+            if let Some(b) = read_byte(input)? {
+                buf.push(b);
+            } else {
+                return Ok(None);
+            }
+        }
+        Ok(Some(
+            std::str::from_utf8(&buf).map_or(char::REPLACEMENT_CHARACTER, |s| {
+                s.chars().next().unwrap_or(char::REPLACEMENT_CHARACTER)
+            }),
+        ))
+    }
+
+    fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+        let kind = read_byte(input)?;
+        if kind != Some(b'[') && kind != Some(b'O') {
+            return Ok(Key::Unknown);
+        }
+        let mut code = Vec::new();
+        loop {
+            match read_byte(input)? {
+                Some(b) if b.is_ascii_digit() => code.push(b),
+                Some(b) => {
+                    return Ok(match (b, code.as_slice()) {
+                        (b'A', _) => Key::Up,
+                        (b'B', _) => Key::Down,
+                        (b'C', _) => Key::Right,
+                        (b'D', _) => Key::Left,
+                        (b'H', _) | (b'~', b"1" | b"7") => Key::Home,
+                        (b'F', _) | (b'~', b"4" | b"8") => Key::End,
+                        (b'~', b"3") => Key::Delete,
+                        _ => Key::Unknown,
+                    })
+                }
+                None => return Ok(Key::Unknown),
+            }
+        }
+    }
+
+    fn redraw(out: &mut impl Write, prompt: &str, line: &[char], pos: usize) -> io::Result<()> {
+        write!(out, "\r\x1b[K{}{}", prompt, line.iter().collect::<String>())?;
+        if pos < line.len() {
+            write!(out, "\x1b[{}D", line.len() - pos)?;
+        }
+        out.flush()
+    }
+
+    fn common_prefix<'a>(words: &[&'a str]) -> &'a str {
+        let first = words[0];
+        let mut len = first.len();
+        for w in &words[1..] {
+            len = first
+                .bytes()
+                .zip(w.bytes())
+                .take(len)
+                .take_while(|(a, b)| a == b)
+                .count();
+        }
+        while !first.is_char_boundary(len) {
+            len -= 1;
+        }
+        &first[..len]
+    }
+
+    /// Completes the word under the cursor. If there are several candidates, the word is
+    /// extended to their common prefix, if it can not be extended, the candidates are listed
+    fn complete(
+        out: &mut impl Write,
+        line: &mut Vec<char>,
+        pos: &mut usize,
+        words: &[String],
+    ) -> io::Result<()> {
+        let start = line[..*pos]
+            .iter()
+            .rposition(|c| c.is_whitespace())
+            .map_or(0, |p| p + 1);
+        let prefix: String = line[start..*pos].iter().collect();
+        let candidates: Vec<&str> = words
+            .iter()
+            .map(String::as_str)
+            .filter(|w| w.starts_with(&prefix))
+            .collect();
+        if candidates.is_empty() {
+            return Ok(());
+        }
+        let mut insert: Vec<char> = common_prefix(&candidates)[prefix.len()..].chars().collect();
+        if candidates.len() == 1 {
+            insert.push(' ');
+        }
+        if insert.is_empty() {
+            write!(out, "\n{}\n", candidates.join("  "))?;
+        } else {
+            let len = insert.len();
+            line.splice(*pos..*pos, insert);
+            *pos += len;
+        }
+        Ok(())
+    }
+
+    /// Reads a line from the terminal, the words are used for the Tab completion
+    pub fn read_line(prompt: &str, history: &[String], words: &[String]) -> io::Result<Input> {
+        let _raw = RawMode::enable()?;
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        let mut out = io::stdout();
+        let mut line: Vec<char> = Vec::new();
+        let mut pos = 0;
+        let mut hist_pos = history.len();
+        // the line being edited, while the history is browsed
+        let mut edited: Vec<char> = Vec::new();
+        redraw(&mut out, prompt, &line, pos)?;
+        loop {
+            match read_char(&mut input)? {
+                None => return Ok(Input::Eof),
+                Some('\r' | '\n') => {
+                    writeln!(out)?;
+                    out.flush()?;
+                    return Ok(Input::Line(line.into_iter().collect()));
+                }
+                Some('\x03') => {
+                    writeln!(out, "^C")?;
+                    out.flush()?;
+                    return Ok(Input::Interrupted);
+                }
+                Some('\x04') => {
+                    if line.is_empty() {
+                        writeln!(out)?;
+                        out.flush()?;
+                        return Ok(Input::Eof);
+                    }
+                    if pos < line.len() {
+                        line.remove(pos);
+                    }
+                }
+                Some('\x7f' | '\x08') => {
+                    if pos > 0 {
+                        pos -= 1;
+                        line.remove(pos);
+                    }
+                }
+                Some('\x01') => pos = 0,
+                Some('\x05') => pos = line.len(),
+                Some('\x15') => {
+                    line.drain(..pos);
+                    pos = 0;
+                }
+                Some('\x17') => {
+                    let mut start = pos;
+                    while start > 0 && line[start - 1].is_whitespace() {
+                        start -= 1;
+                    }
+                    while start > 0 && !line[start - 1].is_whitespace() {
+                        start -= 1;
+                    }
+                    line.drain(start..pos);
+                    pos = start;
+                }
+                Some('\t') => complete(&mut out, &mut line, &mut pos, words)?,
+                Some('\x1b') => match read_escape(&mut input)? {
+                    Key::Up => {
+                        if hist_pos > 0 {
+                            if hist_pos == history.len() {
+                                edited = line;
+                            }
+                            hist_pos -= 1;
+                            line = history[hist_pos].chars().collect();
+                            pos = line.len();
+                        }
+                    }
+                    Key::Down => {
+                        if hist_pos < history.len() {
+                            hist_pos += 1;
+                            line = if hist_pos == history.len() {
+                                std::mem::take(&mut edited)
+                            } else {
+                                history[hist_pos].chars().collect()
+                            };
+                            pos = line.len();
+                        }
+                    }
+                    Key::Left => pos = pos.saturating_sub(1),
+                    Key::Right => pos = (pos + 1).min(line.len()),
+                    Key::Home => pos = 0,
+                    Key::End => pos = line.len(),
+                    Key::Delete => {
+                        if pos < line.len() {
+                            line.remove(pos);
+                        }
+                    }
+                    Key::Unknown => {}
+                },
+                Some(c) if !c.is_control() => {
+                    line.insert(pos, c);
+                    pos += 1;
+                }
+                Some(_) => {}
+            }
+            redraw(&mut out, prompt, &line, pos)?;
+        }
+    }
+}
+
+const SHELL_PROMPT: &str = "elbus> ";
+
+const SHELL_HISTORY_MAX: usize = 1000;
+
+const SHELL_KEYWORDS: &[&str] = &[
+    "broker",
+    "client.list",
+    "client.drain",
+    "info",
+    "stats",
+    "test",
+    "benchmark",
+    "subscribe",
+    "unsubscribe",
+    "send",
+    "publish",
+    "rpc",
+    "notify",
+    "call0",
+    "call",
+    "history",
+    "help",
+    "exit",
+    "quit",
+];
+
+/// Prints incoming frames, notifications and calls. Notifications from the broker are
+/// skipped, as the broker benchmark floods the caller with them
+struct ShellHandlers {}
+
+#[async_trait]
+impl RpcHandlers for ShellHandlers {
+    async fn handle_frame(&self, frame: Frame) {
+        Handlers {}.handle_frame(frame).await;
+    }
+    async fn handle_notification(&self, event: RpcEvent) {
+        if event.sender() != ".broker" {
+            Handlers {}.handle_notification(event).await;
+        }
+    }
+    async fn handle_call(&self, event: RpcEvent) -> RpcResult {
+        Handlers {}.handle_call(event).await
+    }
+}
+
+enum ShellStatus {
+    Ok,
+    Failed,
+    Exit,
+}
+
+/// Splits a shell command line into arguments. Single and double quotes group words, a
+/// backslash escapes the next char (inside double quotes as well)
+fn split_args(line: &str) -> Result<Vec<String>, &'static str> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some(ch) if ch == c => break,
+                        Some('\\') if c == '"' => {
+                            arg.push(chars.next().ok_or("unterminated quote")?);
+                        }
+                        Some(ch) => arg.push(ch),
+                        None => return Err("unterminated quote"),
+                    }
+                }
+            }
+            '\\' => current
+                .get_or_insert_with(String::new)
+                .push(chars.next().ok_or("trailing backslash")?),
+            c if c.is_whitespace() => {
+                if let Some(arg) = current.take() {
+                    args.push(arg);
+                }
+            }
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(arg) = current {
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+fn shell_call_payload(c: &RpcCall) -> Result<Vec<u8>, String> {
+    if let Some(ref json) = c.json {
+        try_json_to_msgpack(json).map_err(|e| format!("Invalid JSON: {}", e))
+    } else if c.params.len() == 1 && c.params[0] == "-" {
+        Err("stdin payloads are not supported in the shell".to_owned())
+    } else if c.params.is_empty() {
+        Ok(Vec::new())
+    } else {
+        let s = c.params.iter().map(String::as_str).collect::<Vec<&str>>();
+        let params = elbus::common::str_to_params_map(&s).map_err(|e| e.to_string())?;
+        Ok(rmp_serde::to_vec_named(&params).unwrap())
+    }
+}
+
+/// Collects the completion words: shell commands, clients and subscribed topics
+async fn shell_words(rpc: &RpcClient, topics: &BTreeSet<String>) -> Vec<String> {
+    let mut words: BTreeSet<String> = SHELL_KEYWORDS.iter().map(|&w| w.to_owned()).collect();
+    words.extend(topics.iter().cloned());
+    if let Ok(result) = rpc
+        .call(".broker", "client.list", empty_payload!(), QoS::Processed)
+        .await
+    {
+        if let Ok(clients) = result.unpack::<ClientList>() {
+            words.extend(clients.clients.into_iter().map(|c| c.name.to_owned()));
+        }
+    }
+    // topic.list is not supported by older brokers
+    if let Ok(result) = rpc
+        .call(".broker", "topic.list", empty_payload!(), QoS::Processed)
+        .await
+    {
+        if let Ok(list) = result.unpack::<TopicList>() {
+            words.extend(list.topics);
+        }
+    }
+    words.into_iter().collect()
+}
+
+async fn shell_exec(
+    rpc: &RpcClient,
+    line: &str,
+    client_name: &str,
+    topics: &mut BTreeSet<String>,
+    history: &[String],
+) -> ShellStatus {
+    macro_rules! shell_try {
+        ($result: expr) => {
+            match $result {
+                Ok(v) => v,
+                Err(e) => {
+                    println!("{} {}", "ERROR".red(), e);
+                    return ShellStatus::Failed;
+                }
+            }
+        };
+    }
+    let args = shell_try!(split_args(line));
+    let parsed = match ShellLine::try_parse_from(&args) {
+        Ok(v) => v,
+        Err(e) => {
+            let _r = e.print();
+            return if e.use_stderr() {
+                ShellStatus::Failed
+            } else {
+                ShellStatus::Ok
+            };
+        }
+    };
+    match parsed.command {
+        ShellLineCommand::Broker(ref op) => {
+            shell_try!(broker_command(rpc, op, client_name, false)
+                .await
+                .map_err(|e| rpc_error_message(&e)));
+        }
+        ShellLineCommand::Subscribe(cmd) => {
+            let masks = cmd.topics.iter().map(String::as_str).collect::<Vec<&str>>();
+            let result = rpc
+                .client()
+                .lock()
+                .await
+                .subscribe_bulk(&masks, QoS::Processed)
+                .await;
+            shell_try!(confirm(result).await);
+            topics.extend(cmd.topics);
+            ok!();
+        }
+        ShellLineCommand::Unsubscribe(cmd) => {
+            let masks = cmd.topics.iter().map(String::as_str).collect::<Vec<&str>>();
+            let result = rpc
+                .client()
+                .lock()
+                .await
+                .unsubscribe_bulk(&masks, QoS::Processed)
+                .await;
+            shell_try!(confirm(result).await);
+            for topic in cmd.topics {
+                topics.remove(&topic);
+            }
+            ok!();
+        }
+        ShellLineCommand::r#Send(cmd) => {
+            let payload = cmd.payload.unwrap_or_default().into_bytes();
+            let result = {
+                let client = rpc.client();
+                let mut client = client.lock().await;
+                if cmd.target.contains(&['*', '?'][..]) {
+                    if cmd.mandatory {
+                        client
+                            .send_broadcast_mandatory(&cmd.target, payload.into(), QoS::Processed)
+                            .await
+                    } else {
+                        client
+                            .send_broadcast(&cmd.target, payload.into(), QoS::Processed)
+                            .await
+                    }
+                } else {
+                    client
+                        .send(&cmd.target, payload.into(), QoS::Processed)
+                        .await
+                }
+            };
+            shell_try!(confirm(result).await);
+            ok!();
+        }
+        ShellLineCommand::Publish(cmd) => {
+            let payload = cmd.payload.unwrap_or_default().into_bytes();
+            let result = {
+                let client = rpc.client();
+                let mut client = client.lock().await;
+                if cmd.mandatory {
+                    client
+                        .publish_mandatory(&cmd.topic, payload.into(), QoS::Processed)
+                        .await
+                } else {
+                    client
+                        .publish(&cmd.topic, payload.into(), QoS::Processed)
+                        .await
+                }
+            };
+            shell_try!(confirm(result).await);
+            ok!();
+        }
+        ShellLineCommand::Rpc(ShellRpcCommand::Notify(cmd)) => {
+            let payload = if let Some(ref json) = cmd.json {
+                shell_try!(try_json_to_msgpack(json).map_err(|e| format!("Invalid JSON: {}", e)))
+            } else {
+                cmd.payload.unwrap_or_default().into_bytes()
+            };
+            shell_try!(
+                confirm(
+                    rpc.notify(&cmd.target, payload.into(), QoS::Processed)
+                        .await
+                )
+                .await
+            );
+            ok!();
+        }
+        ShellLineCommand::Rpc(ShellRpcCommand::Call0(cmd)) => {
+            let payload = shell_try!(shell_call_payload(&cmd));
+            shell_try!(
+                confirm(
+                    rpc.call0(&cmd.target, &cmd.method, payload.into(), QoS::Processed)
+                        .await
+                )
+                .await
+            );
+            ok!();
+        }
+        ShellLineCommand::Rpc(ShellRpcCommand::Call(cmd)) => {
+            let payload = shell_try!(shell_call_payload(&cmd));
+            let result = shell_try!(rpc
+                .call(&cmd.target, &cmd.method, payload.into(), QoS::Processed)
+                .await
+                .map_err(|e| rpc_error_message(&e)));
+            print_payload(result.payload(), false).await;
+        }
+        ShellLineCommand::History => {
+            for (n, line) in history.iter().enumerate() {
+                println!("{:>5}  {}", n + 1, line);
+            }
+        }
+        ShellLineCommand::Exit => return ShellStatus::Exit,
+    }
+    ShellStatus::Ok
+}
+
+fn save_history(path: &str, history: &[String]) {
+    let skip = history.len().saturating_sub(SHELL_HISTORY_MAX);
+    let mut data = history[skip..].join("\n");
+    data.push('\n');
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // the history may contain tokens and payloads, so it is readable by the owner only
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let result = options.open(path).and_then(|mut f| {
+        // the mode is applied on creation only, fix the permissions of existing files
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            f.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        }
+        f.write_all(data.as_bytes())
+    });
+    if let Err(e) = result {
+        error!("Unable to save the history file {}: {}", path, e);
+    }
+}
+
+/// Runs the interactive shell. If stdin is not a terminal, commands are read line-by-line
+/// without editing (e.g. for scripts). Returns false if any command has failed in a script
+async fn shell(opts: &Opts, client_name: &str, cmd: &ShellCommand) -> bool {
+    let client = create_client(opts, client_name).await;
+    let rpc = RpcClient::new(client, ShellHandlers {});
+    let interactive = atty::is(Stream::Stdin);
+    let history_path = cmd.history.clone().or_else(|| {
+        std::env::var("HOME")
+            .ok()
+            .map(|home| format!("{}/.elbus_history", home))
+    });
+    let mut history: Vec<String> = if interactive {
+        history_path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|s| s.lines().map(ToOwned::to_owned).collect())
+            .unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    let mut topics = BTreeSet::new();
+    let mut stdin_lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let mut success = true;
+    if interactive {
+        println!(
+            "Connected to {} as {}, type \"help\" for the list of commands",
+            opts.path.yellow(),
+            client_name.cyan().bold()
+        );
+    }
+    while rpc.is_connected() {
+        let line = if interactive {
+            let words = shell_words(&rpc, &topics).await;
+            let (input, h) = tokio::task::spawn_blocking(move || {
+                (
+                    line_editor::read_line(SHELL_PROMPT, &history, &words),
+                    history,
+                )
+            })
+            .await
+            .unwrap();
+            history = h;
+            match input {
+                Ok(line_editor::Input::Line(line)) => line,
+                Ok(line_editor::Input::Interrupted) => continue,
+                Ok(line_editor::Input::Eof) => break,
+                Err(e) => {
+                    error!("Terminal error: {}", e);
+                    break;
+                }
+            }
+        } else {
+            match stdin_lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => {
+                    error!("Unable to read stdin: {}", e);
+                    success = false;
+                    break;
+                }
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if interactive && history.last().is_none_or(|l| l != line) {
+            history.push(line.to_owned());
+        }
+        match shell_exec(&rpc, line, client_name, &mut topics, &history).await {
+            ShellStatus::Ok => {}
+            ShellStatus::Failed => success = false,
+            ShellStatus::Exit => break,
+        }
+    }
+    if interactive {
+        if let Some(ref path) = history_path {
+            save_history(path, &history);
+        }
+        true
+    } else {
+        success
+    }
 }
 
 #[allow(clippy::too_many_lines)]
@@ -572,7 +2084,9 @@ async fn main() {
     macro_rules! prepare_rpc_call {
         ($c: expr, $client: expr) => {{
             let rpc = RpcClient::new($client, DummyHandlers {});
-            let payload = if $c.params.len() == 1 && $c.params[0] == "-" {
+            let payload = if let Some(ref json) = $c.json {
+                json_to_msgpack(json)
+            } else if $c.params.len() == 1 && $c.params[0] == "-" {
                 read_stdin().await
             } else if $c.params.is_empty() {
                 Vec::new()
@@ -586,72 +2100,9 @@ async fn main() {
     match opts.command {
         Command::Broker(ref op) => {
             let client = create_client(&opts, &client_name).await;
-            match op {
-                BrokerCommand::ClientList => {
-                    let rpc = RpcClient::new(client, DummyHandlers {});
-                    let result = rpc
-                        .call(".broker", "client.list", empty_payload!(), QoS::Processed)
-                        .await
-                        .unwrap();
-                    let mut clients: ClientList = rmp_serde::from_slice(result.payload()).unwrap();
-                    clients.clients.sort();
-                    let mut table = ctable(vec![
-                        "name", "type", "source", "port", "r_frames", "r_bytes", "w_frames",
-                        "w_bytes", "queue", "ins",
-                    ]);
-                    for c in clients.clients {
-                        if c.name != client_name {
-                            table.add_row(row![
-                                c.name,
-                                c.kind,
-                                c.source.unwrap_or_default(),
-                                c.port.unwrap_or_default(),
-                                fnum!(c.r_frames),
-                                fnum!(c.r_bytes),
-                                fnum!(c.w_frames),
-                                fnum!(c.w_bytes),
-                                fnum!(c.queue),
-                                fnum!(c.instances),
-                            ]);
-                        }
-                    }
-                    table.printstd();
-                }
-                BrokerCommand::Stats => {
-                    let rpc = RpcClient::new(client, DummyHandlers {});
-                    let result = rpc
-                        .call(".broker", "stats", empty_payload!(), QoS::Processed)
-                        .await
-                        .unwrap();
-                    let stats: BrokerStats = rmp_serde::from_slice(result.payload()).unwrap();
-                    let mut table = ctable(vec!["field", "value"]);
-                    table.add_row(row!["r_frames", stats.r_frames]);
-                    table.add_row(row!["r_bytes", stats.r_bytes]);
-                    table.add_row(row!["w_frames", stats.w_frames]);
-                    table.add_row(row!["w_bytes", stats.w_bytes]);
-                    table.add_row(row!["uptime", stats.uptime]);
-                    table.printstd();
-                }
-                BrokerCommand::Info => {
-                    let rpc = RpcClient::new(client, DummyHandlers {});
-                    let result = rpc
-                        .call(".broker", "info", empty_payload!(), QoS::Processed)
-                        .await
-                        .unwrap();
-                    let info: BrokerInfo = rmp_serde::from_slice(result.payload()).unwrap();
-                    let mut table = ctable(vec!["field", "value"]);
-                    table.add_row(row!["author", info.author]);
-                    table.add_row(row!["version", info.version]);
-                    table.printstd();
-                }
-                BrokerCommand::Test => {
-                    let rpc = RpcClient::new(client, DummyHandlers {});
-                    let result = rpc
-                        .call(".broker", "test", empty_payload!(), QoS::Processed)
-                        .await
-                        .unwrap();
-                    print_payload(result.payload(), opts.silent).await;
-                }
+            let rpc = RpcClient::new(client, DummyHandlers {});
+            if let Err(e) = broker_command(&rpc, op, &client_name, opts.silent).await {
+                exit_rpc_error(&e);
             }
         }
         Command::Listen(ref cmd) => {
@@ -667,28 +2118,92 @@ async fn main() {
                 print_frame(&frame).await;
             }
         }
+        Command::Subscribe(ref cmd) => {
+            let mut client = create_client(&opts, &client_name).await;
+            subscribe_topics(&mut client, &cmd.masks).await.unwrap();
+            let rx = client.take_event_channel().unwrap();
+            while let Ok(frame) = rx.recv().await {
+                print_frame_line(&frame, cmd.output);
+            }
+        }
+        Command::Monitor(ref cmd) => {
+            let client = create_client(&opts, &client_name).await;
+            let capture = cmd.capture.as_ref().map(|path| {
+                let file = File::create(path).unwrap_or_else(|e| {
+                    error!("Unable to create the capture file {}: {}", path, e);
+                    std::process::exit(1);
+                });
+                std::sync::Mutex::new(CaptureWriter::new(BufWriter::new(file)).unwrap_or_else(
+                    |e| {
+                        error!("Unable to write the capture file {}: {}", path, e);
+                        std::process::exit(1);
+                    },
+                ))
+            });
+            let rpc = RpcClient::create(
+                client,
+                MonitorHandlers { capture },
+                Options::new().blocking_frames(),
+            );
+            let mut params = HashMap::new();
+            if let Some(ref mask) = cmd.client {
+                params.insert("client", mask.as_str());
+            }
+            if let Some(ref mask) = cmd.topic {
+                params.insert("topic", mask.as_str());
+            }
+            if let Err(e) = rpc
+                .call(
+                    ".broker",
+                    "wiretap.start",
+                    rmp_serde::to_vec_named(&params).unwrap().into(),
+                    QoS::Processed,
+                )
+                .await
+            {
+                exit_rpc_error(&e);
+            }
+            info!("Monitoring the broker traffic, Ctrl-C to stop...");
+            let sleep_step = Duration::from_millis(100);
+            while rpc.is_connected() {
+                sleep(sleep_step).await;
+            }
+        }
         Command::r#Send(ref cmd) => {
             let mut client = create_client(&opts, &client_name).await;
             let payload = get_payload(&cmd.payload).await;
             let fut = if cmd.target.contains(&['*', '?'][..]) {
-                client.send_broadcast(&cmd.target, payload.into(), QoS::Processed)
+                if cmd.mandatory {
+                    client.send_broadcast_mandatory(&cmd.target, payload.into(), QoS::Processed)
+                } else {
+                    client.send_broadcast(&cmd.target, payload.into(), QoS::Processed)
+                }
             } else {
                 client.send(&cmd.target, payload.into(), QoS::Processed)
             };
             fut.await.unwrap().unwrap().await.unwrap().unwrap();
             ok!();
         }
+        Command::SendGroup(ref cmd) => {
+            let mut client = create_client(&opts, &client_name).await;
+            let payload = get_payload(&cmd.payload).await;
+            let fut = if cmd.all {
+                client.send_group_broadcast(&cmd.group, payload.into(), QoS::Processed)
+            } else {
+                client.send_group(&cmd.group, payload.into(), QoS::Processed)
+            };
+            fut.await.unwrap().unwrap().await.unwrap().unwrap();
+            ok!();
+        }
         Command::Publish(ref cmd) => {
             let mut client = create_client(&opts, &client_name).await;
             let payload = get_payload(&cmd.payload).await;
-            client
-                .publish(&cmd.topic, payload.into(), QoS::Processed)
-                .await
-                .unwrap()
-                .unwrap()
-                .await
-                .unwrap()
-                .unwrap();
+            let fut = if cmd.mandatory {
+                client.publish_mandatory(&cmd.topic, payload.into(), QoS::Processed)
+            } else {
+                client.publish(&cmd.topic, payload.into(), QoS::Processed)
+            };
+            fut.await.unwrap().unwrap().await.unwrap().unwrap();
             ok!();
         }
         Command::Rpc(ref r) => {
@@ -709,25 +2224,31 @@ async fn main() {
                 }
                 RpcCommand::Notify(cmd) => {
                     let rpc = RpcClient::new(client, DummyHandlers {});
-                    let payload = get_payload(&cmd.payload).await;
-                    rpc.notify(&cmd.target, payload.into(), QoS::Processed)
-                        .await
-                        .unwrap()
-                        .unwrap()
-                        .await
-                        .unwrap()
-                        .unwrap();
+                    let payload = if let Some(ref json) = cmd.json {
+                        json_to_msgpack(json)
+                    } else {
+                        get_payload(&cmd.payload).await
+                    };
+                    if let Err(e) = confirm(
+                        rpc.notify(&cmd.target, payload.into(), QoS::Processed)
+                            .await,
+                    )
+                    .await
+                    {
+                        exit_rpc_error(&e.into());
+                    }
                     ok!();
                 }
                 RpcCommand::Call0(cmd) => {
                     let (rpc, payload) = prepare_rpc_call!(cmd, client);
-                    rpc.call0(&cmd.target, &cmd.method, payload.into(), QoS::Processed)
-                        .await
-                        .unwrap()
-                        .unwrap()
-                        .await
-                        .unwrap()
-                        .unwrap();
+                    if let Err(e) = confirm(
+                        rpc.call0(&cmd.target, &cmd.method, payload.into(), QoS::Processed)
+                            .await,
+                    )
+                    .await
+                    {
+                        exit_rpc_error(&e.into());
+                    }
                     ok!();
                 }
                 RpcCommand::Call(cmd) => {
@@ -737,13 +2258,7 @@ async fn main() {
                         .await
                     {
                         Ok(result) => print_payload(result.payload(), opts.silent).await,
-                        Err(e) => {
-                            let message = e
-                                .data()
-                                .map_or("", |data| std::str::from_utf8(data).unwrap_or(""));
-                            error!("RPC Error {}: {}", e.code(), message);
-                            std::process::exit(1);
-                        }
+                        Err(e) => exit_rpc_error(&e),
                     }
                 }
             }
@@ -770,9 +2285,52 @@ async fn main() {
                 cmd.iters,
                 cmd.workers,
                 cmd.payload_size,
+                cmd.in_flight,
             )
             .await;
             staged_benchmark_print!();
         }
+        Command::Replay(ref cmd) => {
+            if !replay(&opts, cmd).await {
+                std::process::exit(1);
+            }
+        }
+        Command::Shell(ref cmd) => {
+            if !shell(&opts, &client_name, cmd).await {
+                std::process::exit(1);
+            }
+        }
+        #[cfg(feature = "dbus")]
+        Command::DbusBridge(ref cmd) => {
+            let client = create_client(&opts, &client_name).await;
+            let bus = if cmd.system {
+                elbus::dbus::DbusBus::System
+            } else if let Some(ref address) = cmd.address {
+                elbus::dbus::DbusBus::Address(address.clone())
+            } else {
+                elbus::dbus::DbusBus::Session
+            };
+            let mut bridge = elbus::dbus::DbusBridge::new()
+                .bus(bus)
+                .name(&cmd.name)
+                .path(&cmd.path)
+                .topic_prefix(&cmd.topic_prefix);
+            for mask in &cmd.topics {
+                bridge = bridge.topic(mask);
+            }
+            for mask in &cmd.targets {
+                bridge = bridge.target(mask);
+            }
+            for mask in &cmd.publish {
+                bridge = bridge.publish(mask);
+            }
+            for rule in &cmd.signals {
+                bridge = bridge.signal(rule);
+            }
+            if let Err(e) = bridge.run(client).await {
+                eprintln!("{}", e.to_string().red());
+                std::process::exit(1);
+            }
+        }
     }
 }
@@ -1,5 +1,5 @@
 use crate::borrow::Cow;
-use crate::{Error, Frame, OpConfirm, QoS};
+use crate::{Error, ErrorKind, Frame, OpConfirm, QoS};
 
 use async_trait::async_trait;
 use std::sync::{atomic, Arc};
@@ -28,12 +28,42 @@ pub trait AsyncClient: Send + Sync {
         payload: Cow<'async_trait>,
         qos: QoS,
     ) -> Result<OpConfirm, Error>;
+    /// Sends the message to one member of the group (round-robin)
+    async fn send_group(
+        &mut self,
+        group: &str,
+        payload: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error>;
+    /// Sends the message to all members of the group
+    async fn send_group_broadcast(
+        &mut self,
+        group: &str,
+        payload: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error>;
+    /// Sends the broadcast, the confirmation fails with ErrorKind::Unrouted if no clients
+    /// match the mask
+    async fn send_broadcast_mandatory(
+        &mut self,
+        target: &str,
+        payload: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error>;
     async fn publish(
         &mut self,
         target: &str,
         payload: Cow<'async_trait>,
         qos: QoS,
     ) -> Result<OpConfirm, Error>;
+    /// Publishes the frame, the confirmation fails with ErrorKind::Unrouted if the topic has no
+    /// subscribers
+    async fn publish_mandatory(
+        &mut self,
+        target: &str,
+        payload: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error>;
     async fn subscribe(&mut self, topic: &str, qos: QoS) -> Result<OpConfirm, Error>;
     async fn unsubscribe(&mut self, topic: &str, qos: QoS) -> Result<OpConfirm, Error>;
     async fn subscribe_bulk(&mut self, topics: &[&str], qos: QoS) -> Result<OpConfirm, Error>;
@@ -43,6 +73,40 @@ pub trait AsyncClient: Send + Sync {
     fn get_connected_beacon(&self) -> Option<Arc<atomic::AtomicBool>>;
     fn get_timeout(&self) -> Option<Duration>;
     fn get_name(&self) -> &str;
+    /// Confirms a frame, sent with QoS::Consumed (Frame::delivery_id is set), the sender gets
+    /// the acknowledgement
+    async fn confirm(&mut self, _frame: &Frame) -> Result<(), Error> {
+        Err(Error::not_supported("delivery confirmations"))
+    }
+    /// Rejects a frame, sent with QoS::Consumed, the sender gets the error
+    async fn reject(&mut self, _frame: &Frame, _kind: ErrorKind) -> Result<(), Error> {
+        Err(Error::not_supported("delivery confirmations"))
+    }
+    /// Traces routing of the next message, broadcast or publication of the client, the broker
+    /// publishes routing events to ".broker/trace/ID"
+    async fn trace(&mut self, _id: &str) -> Result<(), Error> {
+        Err(Error::not_supported("frame tracing"))
+    }
+    /// Sets the client health (the status, e.g. "ok" or "degraded", and the load in percents),
+    /// which is sent to the broker immediately and then attached to pings. The broker keeps the
+    /// latest health of each client (client.list, stats)
+    async fn set_health(&mut self, _status: &str, _load: u8) -> Result<(), Error> {
+        Err(Error::not_supported("client health"))
+    }
+    /// Begins a transaction, messages, broadcasts and publications of the client are buffered
+    /// by the broker until the transaction is committed or aborted
+    async fn begin_transaction(&mut self, _qos: QoS) -> Result<OpConfirm, Error> {
+        Err(Error::not_supported("transactions"))
+    }
+    /// Commits the transaction, the buffered frames are routed either all or none, the
+    /// confirmation fails if the transaction has not been routed
+    async fn commit_transaction(&mut self, _qos: QoS) -> Result<OpConfirm, Error> {
+        Err(Error::not_supported("transactions"))
+    }
+    /// Aborts the transaction, the buffered frames are dropped
+    async fn abort_transaction(&mut self, _qos: QoS) -> Result<OpConfirm, Error> {
+        Err(Error::not_supported("transactions"))
+    }
 }
 
 #[macro_export]
@@ -1,10 +1,84 @@
+use crate::Error;
+use std::future::Future;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::io::{AsyncWriteExt, BufWriter};
-use tokio::sync::oneshot;
 use tokio::sync::Mutex;
+use tokio::sync::{oneshot, watch};
 use tokio::task::JoinHandle;
 
+/// Loads a certificate chain (or a CA bundle) from a PEM file
+#[cfg(feature = "tls")]
+pub(crate) fn load_certs(path: &str) -> Result<Vec<tokio_rustls::rustls::Certificate>, Error> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let certs: Vec<tokio_rustls::rustls::Certificate> = rustls_pemfile::certs(&mut reader)?
+        .into_iter()
+        .map(tokio_rustls::rustls::Certificate)
+        .collect();
+    if certs.is_empty() {
+        Err(Error::data(format!("no certificates found in {}", path)))
+    } else {
+        Ok(certs)
+    }
+}
+
+/// ALPN protocol of QUIC connections
+#[cfg(feature = "quic")]
+pub(crate) const QUIC_ALPN: &[u8] = b"elbus";
+
+#[cfg(feature = "vsock")]
+pub(crate) const VSOCK_PREFIX: &str = "vsock://";
+
+/// VMADDR_CID_ANY
+#[cfg(feature = "vsock")]
+const VSOCK_CID_ANY: u32 = u32::MAX;
+
+/// Parses vsock://CID:PORT (the prefix is optional), the CID can be "any"
+#[cfg(feature = "vsock")]
+pub(crate) fn parse_vsock_addr(path: &str) -> Result<(u32, u32), Error> {
+    let addr = path.strip_prefix(VSOCK_PREFIX).unwrap_or(path);
+    let (cid, port) = addr
+        .split_once(':')
+        .ok_or_else(|| Error::data(format!("invalid vsock address: {}", addr)))?;
+    let cid = if cid == "any" {
+        VSOCK_CID_ANY
+    } else {
+        cid.parse()
+            .map_err(|e| Error::data(format!("invalid vsock CID {}: {}", cid, e)))?
+    };
+    let port = port
+        .parse()
+        .map_err(|e| Error::data(format!("invalid vsock port {}: {}", port, e)))?;
+    Ok((cid, port))
+}
+
+/// Windows named pipe paths: \\.\pipe\NAME
+#[cfg(windows)]
+pub(crate) const NAMED_PIPE_PREFIX: &str = r"\\.\pipe\";
+
+/// ERROR_PIPE_BUSY, all the pipe instances are connected to other clients
+#[cfg(all(windows, feature = "ipc"))]
+const ERROR_PIPE_BUSY: i32 = 231;
+
+#[cfg(all(windows, feature = "ipc"))]
+const NAMED_PIPE_BUSY_DELAY: Duration = Duration::from_millis(50);
+
+/// Opens a named pipe, waiting until a pipe instance is available. The broker creates the next
+/// instance right after a client is connected, so the wait is usually short
+#[cfg(all(windows, feature = "ipc"))]
+pub(crate) async fn connect_named_pipe(
+    path: &str,
+) -> Result<tokio::net::windows::named_pipe::NamedPipeClient, Error> {
+    loop {
+        match tokio::net::windows::named_pipe::ClientOptions::new().open(path) {
+            Ok(client) => return Ok(client),
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) => {}
+            Err(e) => return Err(e.into()),
+        }
+        tokio::time::sleep(NAMED_PIPE_BUSY_DELAY).await;
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Flush {
     No,
@@ -23,11 +97,114 @@ impl From<bool> for Flush {
     }
 }
 
+/// TCP socket options, applied to TCP connections only
+#[derive(Debug, Clone)]
+pub struct TcpOptions {
+    nodelay: bool,
+    keepalive_idle: Option<Duration>,
+    keepalive_interval: Option<Duration>,
+    keepalive_count: Option<u32>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive_idle: None,
+            keepalive_interval: None,
+            keepalive_count: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        }
+    }
+}
+
+impl TcpOptions {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// TCP_NODELAY (default: true)
+    #[inline]
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+    /// Enables keepalive, probes are sent after the connection has been idle for the specified
+    /// time
+    #[inline]
+    pub fn keepalive_idle(mut self, idle: Duration) -> Self {
+        self.keepalive_idle.replace(idle);
+        self
+    }
+    /// Enables keepalive and sets the interval between probes
+    #[inline]
+    pub fn keepalive_interval(mut self, interval: Duration) -> Self {
+        self.keepalive_interval.replace(interval);
+        self
+    }
+    /// Enables keepalive and sets the number of unanswered probes before the connection is
+    /// dropped (ignored on Windows, where the number is fixed)
+    #[inline]
+    pub fn keepalive_count(mut self, count: u32) -> Self {
+        self.keepalive_count.replace(count);
+        self
+    }
+    /// SO_SNDBUF
+    #[inline]
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size.replace(size);
+        self
+    }
+    /// SO_RCVBUF
+    #[inline]
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size.replace(size);
+        self
+    }
+    /// Keepalive parameters not set are left as system defaults
+    pub fn apply<S>(&self, stream: &S) -> Result<(), Error>
+    where
+        for<'s> socket2::SockRef<'s>: From<&'s S>,
+    {
+        let sock = socket2::SockRef::from(stream);
+        sock.set_nodelay(self.nodelay)?;
+        if self.keepalive_idle.is_some()
+            || self.keepalive_interval.is_some()
+            || self.keepalive_count.is_some()
+        {
+            let mut keepalive = socket2::TcpKeepalive::new();
+            if let Some(idle) = self.keepalive_idle {
+                keepalive = keepalive.with_time(idle);
+            }
+            if let Some(interval) = self.keepalive_interval {
+                keepalive = keepalive.with_interval(interval);
+            }
+            #[cfg(unix)]
+            if let Some(count) = self.keepalive_count {
+                keepalive = keepalive.with_retries(count);
+            }
+            sock.set_tcp_keepalive(&keepalive)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            sock.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            sock.set_recv_buffer_size(size)?;
+        }
+        Ok(())
+    }
+}
+
 pub struct TtlBufWriter<W> {
     writer: Arc<Mutex<BufWriter<W>>>,
     tx: async_channel::Sender<()>,
     dtx: Option<oneshot::Sender<()>>,
     flusher: JoinHandle<()>,
+    // set when a scheduled flush fails, the connection can not be written any longer
+    failure: watch::Receiver<Option<std::io::ErrorKind>>,
 }
 
 impl<W> TtlBufWriter<W>
@@ -38,12 +215,20 @@ where
         let writer = Arc::new(Mutex::new(BufWriter::with_capacity(cap, writer)));
         let wf = writer.clone();
         let (tx, rx) = async_channel::bounded::<()>(1);
+        let (failure_tx, failure) = watch::channel(None);
         // flusher future
         let flusher = tokio::spawn(async move {
             while rx.recv().await.is_ok() {
+                #[cfg(unix)]
                 let _r = tokio_timerfd::sleep(ttl).await;
+                // timerfd is Linux-only, the tokio timer is less precise but portable
+                #[cfg(not(unix))]
+                tokio::time::sleep(ttl).await;
                 if let Ok(mut writer) = tokio::time::timeout(timeout, wf.lock()).await {
-                    let _r = tokio::time::timeout(timeout, writer.flush()).await;
+                    if let Ok(Err(e)) = tokio::time::timeout(timeout, writer.flush()).await {
+                        let _r = failure_tx.send(Some(e.kind()));
+                        break;
+                    }
                 }
             }
         });
@@ -60,10 +245,15 @@ where
             tx,
             dtx: Some(dtx),
             flusher,
+            failure,
         }
     }
     #[inline]
     pub async fn write(&mut self, buf: &[u8], flush: Flush) -> std::io::Result<()> {
+        let failure = *self.failure.borrow();
+        if let Some(kind) = failure {
+            return Err(kind.into());
+        }
         let mut writer = self.writer.lock().await;
         let result = writer.write_all(buf).await;
         if flush == Flush::Instant {
@@ -73,6 +263,22 @@ where
         }
         result
     }
+    /// Resolves when a scheduled (background) flush fails, e.g. the peer has closed the
+    /// connection, so it can be dropped without waiting for the next write
+    pub fn failure(&self) -> impl Future<Output = std::io::Error> + Send + 'static {
+        let mut failure = self.failure.clone();
+        async move {
+            loop {
+                let kind = *failure.borrow_and_update();
+                if let Some(kind) = kind {
+                    return kind.into();
+                }
+                if failure.changed().await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+            }
+        }
+    }
 }
 
 impl<W> Drop for TtlBufWriter<W> {
@@ -81,3 +287,125 @@ impl<W> Drop for TtlBufWriter<W> {
         let _ = self.dtx.take().unwrap().send(());
     }
 }
+
+#[cfg(feature = "websocket")]
+fn ws_io_error(e: tokio_tungstenite::tungstenite::Error) -> std::io::Error {
+    match e {
+        tokio_tungstenite::tungstenite::Error::Io(e) => e,
+        e => std::io::Error::other(e),
+    }
+}
+
+/// Reads the byte stream, carried in binary WebSocket messages, message boundaries are not
+/// significant. Control messages are skipped, the close message is the end of the stream
+#[cfg(feature = "websocket")]
+pub(crate) struct WsReader<S> {
+    stream: S,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+#[cfg(feature = "websocket")]
+impl<S> WsReader<S> {
+    pub(crate) fn new(stream: S) -> Self {
+        Self {
+            stream,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl<S> tokio::io::AsyncRead for WsReader<S>
+where
+    S: futures_util::Stream<
+            Item = Result<
+                tokio_tungstenite::tungstenite::Message,
+                tokio_tungstenite::tungstenite::Error,
+            >,
+        > + Unpin,
+{
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        use std::task::Poll;
+        use tokio_tungstenite::tungstenite::Message;
+        while self.pos == self.buf.len() {
+            match futures_util::ready!(std::pin::Pin::new(&mut self.stream).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => {
+                    self.buf = data;
+                    self.pos = 0;
+                }
+                Some(Ok(Message::Text(_))) => {
+                    return Poll::Ready(Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "text WebSocket messages are not supported",
+                    )));
+                }
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Poll::Ready(Err(ws_io_error(e))),
+            }
+        }
+        let len = buf.remaining().min(self.buf.len() - self.pos);
+        let pos = self.pos;
+        buf.put_slice(&self.buf[pos..pos + len]);
+        self.pos += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Writes the byte stream as binary WebSocket messages, a message per write
+#[cfg(feature = "websocket")]
+pub(crate) struct WsWriter<S> {
+    sink: S,
+}
+
+#[cfg(feature = "websocket")]
+impl<S> WsWriter<S> {
+    pub(crate) fn new(sink: S) -> Self {
+        Self { sink }
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl<S> tokio::io::AsyncWrite for WsWriter<S>
+where
+    S: futures_util::Sink<
+            tokio_tungstenite::tungstenite::Message,
+            Error = tokio_tungstenite::tungstenite::Error,
+        > + Unpin,
+{
+    fn poll_write(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        let mut sink = std::pin::Pin::new(&mut self.sink);
+        futures_util::ready!(sink.as_mut().poll_ready(cx)).map_err(ws_io_error)?;
+        sink.start_send(tokio_tungstenite::tungstenite::Message::Binary(
+            buf.to_vec(),
+        ))
+        .map_err(ws_io_error)?;
+        std::task::Poll::Ready(Ok(buf.len()))
+    }
+    fn poll_flush(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.sink)
+            .poll_flush(cx)
+            .map_err(ws_io_error)
+    }
+    fn poll_shutdown(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.sink)
+            .poll_close(cx)
+            .map_err(ws_io_error)
+    }
+}
@@ -4,8 +4,31 @@ use crate::Error;
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "rpc")]
 use serde_value::Value;
+use std::collections::BTreeMap;
 #[cfg(feature = "rpc")]
 use std::collections::HashMap;
+#[cfg(feature = "rpc")]
+use std::str::FromStr;
+
+/// Wiretap frames are delivered to monitors as publish frames with topics
+/// ".tap/m/TARGET" (messages), ".tap/b/MASK" (broadcasts) and ".tap/p/TOPIC" (publications)
+pub const WIRETAP_TOPIC_PREFIX: &str = ".tap/";
+pub const WIRETAP_MESSAGE: &str = ".tap/m/";
+pub const WIRETAP_BROADCAST: &str = ".tap/b/";
+pub const WIRETAP_PUBLISH: &str = ".tap/p/";
+/// Client tap frames have topics ".tap/c/CLIENT/in/KIND" (received by the client, the topic is
+/// appended for publications) and ".tap/c/CLIENT/out/KIND/TARGET" (sent by the client), where
+/// KIND is "m" (message), "b" (broadcast) or "p" (publication)
+pub const WIRETAP_CLIENT: &str = ".tap/c/";
+
+/// The method of namespace owners, called by the broker to approve subscriptions of other clients
+/// to topics under the namespace, the params are NamespaceEvent. If an error is returned, the
+/// subscription is rejected
+pub const NAMESPACE_SUBSCRIBE_METHOD: &str = "namespace.subscribe";
+
+/// RPC calls to "@SERVICE" targets are distributed by the broker among providers of the
+/// registered service
+pub const RPC_TARGET_PREFIX: &str = "@";
 
 #[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
 #[derive(Eq, PartialEq, Clone)]
@@ -18,8 +41,25 @@ pub struct ClientInfo<'a> {
     pub r_bytes: u64,
     pub w_frames: u64,
     pub w_bytes: u64,
+    /// frames, dropped as they have been queued past the deadline
+    #[cfg_attr(feature = "rpc", serde(default))]
+    pub w_expired: u64,
     pub queue: usize,
     pub instances: usize,
+    #[cfg_attr(feature = "rpc", serde(default, borrow))]
+    pub groups: Vec<&'a str>,
+    /// metadata, set by the client in the hello extensions
+    #[cfg_attr(
+        feature = "rpc",
+        serde(default, borrow, skip_serializing_if = "BTreeMap::is_empty")
+    )]
+    pub metadata: BTreeMap<&'a str, &'a str>,
+    /// the latest health, sent by the client with heartbeats
+    #[cfg_attr(
+        feature = "rpc",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub health: Option<ClientHealth>,
 }
 impl<'a> Ord for ClientInfo<'a> {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
@@ -31,6 +71,16 @@ impl<'a> PartialOrd for ClientInfo<'a> {
         Some(self.cmp(other))
     }
 }
+/// Client health, the status is set by the client (e.g. "ok", "degraded"), the load is in
+/// percents, the time is the receive time of the heartbeat (nanoseconds since the UNIX epoch)
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ClientHealth {
+    pub status: String,
+    pub load: u8,
+    pub t: u64,
+}
+
 #[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
 #[derive(Clone)]
 pub struct ClientList<'a> {
@@ -38,6 +88,68 @@ pub struct ClientList<'a> {
     pub clients: Vec<ClientInfo<'a>>,
 }
 
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Eq, PartialEq, Clone)]
+pub struct ListenerInfo {
+    pub path: String,
+    pub active: bool,
+    pub restarts: u64,
+    pub last_error: Option<String>,
+}
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Clone)]
+pub struct ListenerList {
+    pub listeners: Vec<ListenerInfo>,
+}
+
+/// Topic masks, subscribed by connected clients
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Clone)]
+pub struct TopicList {
+    pub topics: Vec<String>,
+}
+
+/// Subscription tree diagnostics. Masks are split into segments, which form a tree, so every
+/// mask prefix is a tree node
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Clone)]
+pub struct SubscriptionStats {
+    /// clients with at least one subscription
+    pub clients: usize,
+    pub subscriptions: usize,
+    /// distinct masks
+    pub masks: usize,
+    /// masks which contain wildcards
+    pub wildcard_masks: usize,
+    pub nodes: usize,
+    /// the max number of mask segments
+    pub max_depth: usize,
+    /// per first mask segment, sorted by subscriptions (descending)
+    pub branches: Vec<SubscriptionBranch>,
+    /// the most subscribed masks
+    pub top_masks: Vec<MaskStats>,
+}
+
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Clone)]
+pub struct SubscriptionBranch {
+    pub branch: String,
+    pub nodes: usize,
+    pub masks: usize,
+    pub subscriptions: usize,
+}
+
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Clone)]
+pub struct MaskStats {
+    pub mask: String,
+    pub subscribers: usize,
+    pub depth: usize,
+    /// the first wildcard segment position (0-based), wildcards close to the root match more
+    /// topics and make matching slower
+    pub wildcard_at: Option<usize>,
+}
+
 #[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
 #[derive(Clone)]
 pub struct BrokerStats {
@@ -46,6 +158,166 @@ pub struct BrokerStats {
     pub r_bytes: u64,
     pub w_frames: u64,
     pub w_bytes: u64,
+    /// bytes of frames, buffered in client queues
+    #[cfg_attr(feature = "rpc", serde(default))]
+    pub queued_bytes: u64,
+    /// frames, dropped as they have been queued past the deadline
+    #[cfg_attr(feature = "rpc", serde(default))]
+    pub expired_frames: u64,
+    /// protocol errors of all connections since the broker start
+    #[cfg_attr(feature = "rpc", serde(default))]
+    pub protocol_errors: ProtocolErrors,
+    /// connected clients with protocol errors
+    #[cfg_attr(feature = "rpc", serde(default))]
+    pub connections: Vec<ConnectionProtocolErrors>,
+    /// the number of clients by health statuses, reported with heartbeats
+    #[cfg_attr(
+        feature = "rpc",
+        serde(default, skip_serializing_if = "BTreeMap::is_empty")
+    )]
+    pub health: BTreeMap<String, usize>,
+}
+
+/// Frames, rejected by the broker. Such frames are skipped (acknowledged with an error code, if
+/// required), the connection is kept
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct ProtocolErrors {
+    /// broken frames (e.g. no target separator) and invalid operation payloads
+    pub malformed: u64,
+    /// frames larger than the max frame size of the listener
+    pub oversized: u64,
+    /// frames with unknown operation codes
+    pub unknown_ops: u64,
+    /// targets and topics, which are not valid UTF-8
+    pub utf8: u64,
+}
+
+impl ProtocolErrors {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub struct ConnectionProtocolErrors {
+    pub client: String,
+    pub errors: ProtocolErrors,
+}
+
+/// Broker benchmark method result, elapsed time is in seconds
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Clone)]
+pub struct BrokerBenchmark {
+    pub n: u32,
+    pub size: usize,
+    pub elapsed: f64,
+}
+
+/// Client drain method result, pending - frames left in the client queue when it was
+/// disconnected (0 - the queue has been flushed)
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Clone)]
+pub struct ClientDrain {
+    pub pending: usize,
+    pub elapsed: f64,
+}
+
+/// Direct channel offer, sent by the broker to the target client as a notification. The target
+/// connects to the Unix socket of the offering client and sends the token
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub struct ChannelOffer {
+    /// the offering client
+    pub client: String,
+    pub path: String,
+    pub token: String,
+}
+
+/// Direct channel open method result, the token is expected from the target client
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub struct ChannelToken {
+    pub token: String,
+}
+
+/// Topic namespace subscription change, sent by the broker to the namespace owner as a
+/// notification when clients subscribe to or unsubscribe from topic masks under the namespace
+/// (the subject is "subscribe" or "unsubscribe")
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub struct NamespaceEvent {
+    pub subject: String,
+    pub client: String,
+    pub topic: String,
+}
+
+/// Namespace claim result: subscriptions under the namespace, made before the claim
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub struct NamespaceSubscriptions {
+    pub subscriptions: Vec<NamespaceEvent>,
+}
+
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub struct NamespaceInfo {
+    pub prefix: String,
+    pub owner: String,
+}
+
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub struct NamespaceList {
+    pub namespaces: Vec<NamespaceInfo>,
+}
+
+/// Service capabilities, registered by the provider client with the "service.register" broker
+/// RPC method
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ServiceInfo {
+    pub name: String,
+    /// the client, which has registered the service (the RPC call target)
+    pub provider: String,
+    #[cfg_attr(feature = "rpc", serde(default))]
+    pub methods: Vec<String>,
+    #[cfg_attr(feature = "rpc", serde(default))]
+    pub tags: Vec<String>,
+    /// the distribution policy of calls to the load-balanced target of the service
+    #[cfg_attr(feature = "rpc", serde(default))]
+    pub balance: RpcBalance,
+}
+
+/// Distribution of calls among providers of a load-balanced RPC target
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "rpc", serde(rename_all = "kebab-case"))]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum RpcBalance {
+    #[default]
+    RoundRobin,
+    /// the provider with the least number of pending calls
+    LeastPending,
+}
+
+#[cfg(feature = "rpc")]
+impl FromStr for RpcBalance {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(RpcBalance::RoundRobin),
+            "least-pending" => Ok(RpcBalance::LeastPending),
+            _ => Err(Error::data(format!("invalid balance policy: {}", s))),
+        }
+    }
+}
+
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub struct ServiceList {
+    pub services: Vec<ServiceInfo>,
 }
 
 #[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
@@ -55,6 +327,131 @@ pub struct BrokerInfo<'a> {
     pub version: &'a str,
 }
 
+/// Broker capabilities for embedders and orchestration tools: versions, compiled-in features,
+/// listeners and limits
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Clone)]
+pub struct BrokerCapabilities {
+    pub version: String,
+    pub protocol_version: u16,
+    pub features: Vec<String>,
+    pub listeners: Vec<ListenerInfo>,
+    /// frames are stamped with the receive time
+    pub timestamps: bool,
+    pub limits: BrokerLimits,
+}
+
+/// Broker limits, zero values mean unlimited
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Clone)]
+pub struct BrokerLimits {
+    pub queue_size: usize,
+    pub memory_limit: u64,
+    /// "drop" or "disconnect"
+    pub memory_overflow: String,
+    pub max_subscriptions: usize,
+    pub max_wildcard_subscriptions: usize,
+    pub max_topic_depth: usize,
+    pub max_topic_length: usize,
+    /// max batch frame size (bytes), a client can request
+    pub batch_max_size: u32,
+    /// max batch delay (microseconds), a client can request
+    pub batch_max_delay: u32,
+}
+
+/// Client capabilities, sent in the MessagePack-encoded hello extension block. Unknown fields are
+/// ignored by the broker, so new client features do not require a protocol version bump
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default)]
+pub struct HelloExtensions {
+    /// the extension block version
+    pub v: u8,
+    /// published by the broker on behalf of the client if the connection is lost
+    #[cfg_attr(
+        feature = "rpc",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub will: Option<ClientWill>,
+    #[cfg_attr(
+        feature = "rpc",
+        serde(default, skip_serializing_if = "BTreeMap::is_empty")
+    )]
+    pub metadata: BTreeMap<String, String>,
+    /// the interval (seconds) the broker pings the client with
+    #[cfg_attr(
+        feature = "rpc",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub keepalive: Option<f64>,
+    /// compression algorithms, supported by the client, in order of preference
+    #[cfg_attr(feature = "rpc", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub compression: Vec<String>,
+}
+
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub struct ClientWill {
+    pub topic: String,
+    #[cfg_attr(feature = "rpc", serde(with = "bytes"))]
+    pub payload: Vec<u8>,
+}
+
+/// The broker reply to the hello extension block
+#[cfg_attr(feature = "rpc", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default)]
+pub struct HelloExtensionsReply {
+    /// the extension block version, used by the broker (not greater than the client one)
+    pub v: u8,
+    /// the ping interval (seconds)
+    #[cfg_attr(feature = "rpc", serde(default))]
+    pub keepalive: f64,
+    /// the selected compression algorithm, none if not negotiated
+    #[cfg_attr(feature = "rpc", serde(default))]
+    pub compression: Option<String>,
+}
+
+/// Binary payloads are serialized as MessagePack bin, arrays of bytes are accepted as well
+#[cfg(feature = "rpc")]
+mod bytes {
+    use serde::de::{self, SeqAccess, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(value)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct BytesVisitor;
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = Vec<u8>;
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("bytes")
+            }
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+                Ok(v.to_vec())
+            }
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Vec<u8>, E> {
+                Ok(v.as_bytes().to_vec())
+            }
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+                let mut result = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+                while let Some(b) = seq.next_element()? {
+                    result.push(b);
+                }
+                Ok(result)
+            }
+        }
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
 #[allow(clippy::ptr_arg)]
 #[cfg(feature = "rpc")]
 pub fn str_to_params_map<'a>(s: &'a [&'a str]) -> Result<HashMap<&'a str, Value>, Error> {
@@ -85,12 +482,51 @@ pub fn str_to_params_map<'a>(s: &'a [&'a str]) -> Result<HashMap<&'a str, Value>
     Ok(params)
 }
 
+#[cfg(any(feature = "broker", feature = "ipc"))]
+pub(crate) fn check_trace_id(id: &str) -> Result<(), crate::Error> {
+    if id.is_empty() || id.len() > crate::TRACE_ID_MAX_LEN {
+        Err(crate::Error::data(format!("invalid trace id: {}", id)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Encodes the heartbeat payload: the load byte and the status
+#[cfg(any(feature = "broker", feature = "ipc"))]
+pub(crate) fn health_payload(status: &str, load: u8) -> Result<Vec<u8>, crate::Error> {
+    if status.is_empty() || status.len() > crate::HEALTH_STATUS_MAX_LEN {
+        return Err(crate::Error::data(format!(
+            "invalid health status: {}",
+            status
+        )));
+    }
+    if load > crate::HEALTH_LOAD_MAX {
+        return Err(crate::Error::data(format!("invalid health load: {}", load)));
+    }
+    let mut payload = Vec::with_capacity(status.len() + 1);
+    payload.push(load);
+    payload.extend_from_slice(status.as_bytes());
+    Ok(payload)
+}
+
 #[cfg(feature = "broker")]
 #[allow(clippy::cast_sign_loss)]
 /// # Panics
 ///
 /// Will panic if system clock is not available
 pub fn now_ns() -> u64 {
-    let t = nix::time::clock_gettime(nix::time::ClockId::CLOCK_REALTIME).unwrap();
-    t.tv_sec() as u64 * 1_000_000_000 + t.tv_nsec() as u64
+    #[cfg(unix)]
+    {
+        let t = nix::time::clock_gettime(nix::time::ClockId::CLOCK_REALTIME).unwrap();
+        t.tv_sec() as u64 * 1_000_000_000 + t.tv_nsec() as u64
+    }
+    #[cfg(not(unix))]
+    {
+        #[allow(clippy::cast_possible_truncation)]
+        let t = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        t
+    }
 }
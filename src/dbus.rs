@@ -0,0 +1,376 @@
+use crate::borrow::Cow;
+use crate::client::AsyncClient;
+use crate::rpc::{Rpc, RpcClient, RpcError, RpcEvent, RpcHandlers, RpcResult};
+use crate::{Error, Frame, FrameKind, QoS};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use log::{error, info};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use submap::AclMap;
+use zbus::zvariant::{OwnedValue, Value};
+use zbus::{dbus_interface, Connection, ConnectionBuilder, MessageStream, SignalContext};
+
+pub const DBUS_DEFAULT_NAME: &str = "org.elbus.Bridge";
+pub const DBUS_DEFAULT_PATH: &str = "/org/elbus/Bridge";
+pub const DBUS_INTERFACE: &str = "org.elbus.Bridge1";
+pub const DBUS_DEFAULT_TOPIC_PREFIX: &str = "dbus";
+
+/// Max number of D-Bus signals, queued per match rule
+const SIGNAL_QUEUE_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum DbusBus {
+    Session,
+    System,
+    /// D-Bus address, e.g. "unix:path=/run/dbus/system_bus_socket"
+    Address(String),
+}
+
+/// Bridges elbus and D-Bus on the same host (requires "dbus" feature)
+///
+/// The bridge owns a well-known D-Bus name and serves the "org.elbus.Bridge1" interface:
+///
+/// * elbus frames, published to the configured topics, are emitted as D-Bus signals
+///   Frame(sender: s, topic: s, payload: ay)
+///
+/// * D-Bus method Call(target: s, method: s, params: ay) -> ay calls elbus RPC methods of the
+///   allowed targets
+///
+/// * D-Bus method Publish(topic: s, payload: ay) publishes frames to the allowed elbus topics
+///
+/// * D-Bus signals, matching the configured rules, are published to elbus topics
+///   PREFIX/INTERFACE/MEMBER, payloads are MessagePack arrays of the signal arguments
+pub struct DbusBridge {
+    bus: DbusBus,
+    name: String,
+    path: String,
+    topics: Vec<String>,
+    targets: Vec<String>,
+    publish: Vec<String>,
+    signals: Vec<String>,
+    topic_prefix: String,
+}
+
+impl Default for DbusBridge {
+    fn default() -> Self {
+        Self {
+            bus: DbusBus::Session,
+            name: DBUS_DEFAULT_NAME.to_owned(),
+            path: DBUS_DEFAULT_PATH.to_owned(),
+            topics: Vec::new(),
+            targets: Vec::new(),
+            publish: Vec::new(),
+            signals: Vec::new(),
+            topic_prefix: DBUS_DEFAULT_TOPIC_PREFIX.to_owned(),
+        }
+    }
+}
+
+impl DbusBridge {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    #[inline]
+    pub fn bus(mut self, bus: DbusBus) -> Self {
+        self.bus = bus;
+        self
+    }
+    /// D-Bus well-known name
+    #[inline]
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_owned();
+        self
+    }
+    /// D-Bus object path
+    #[inline]
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = path.to_owned();
+        self
+    }
+    /// elbus topic mask, frames of which are emitted as D-Bus signals, can be specified
+    /// multiple times
+    #[inline]
+    pub fn topic(mut self, mask: &str) -> Self {
+        self.topics.push(mask.to_owned());
+        self
+    }
+    /// elbus RPC target mask, allowed for D-Bus calls, can be specified multiple times
+    #[inline]
+    pub fn target(mut self, mask: &str) -> Self {
+        self.targets.push(mask.to_owned());
+        self
+    }
+    /// elbus topic mask, allowed for D-Bus publications, can be specified multiple times
+    #[inline]
+    pub fn publish(mut self, mask: &str) -> Self {
+        self.publish.push(mask.to_owned());
+        self
+    }
+    /// D-Bus match rule (e.g. "type='signal',interface='org.freedesktop.login1.Manager'"),
+    /// signals of which are published to elbus, can be specified multiple times
+    #[inline]
+    pub fn signal(mut self, rule: &str) -> Self {
+        self.signals.push(rule.to_owned());
+        self
+    }
+    /// elbus topic prefix for D-Bus signals
+    #[inline]
+    pub fn topic_prefix(mut self, prefix: &str) -> Self {
+        self.topic_prefix = prefix.to_owned();
+        self
+    }
+    /// Connects to D-Bus and runs the bridge until the elbus client is disconnected
+    pub async fn run(self, client: impl AsyncClient + 'static) -> Result<(), Error> {
+        let builder = match self.bus {
+            DbusBus::Session => ConnectionBuilder::session(),
+            DbusBus::System => ConnectionBuilder::system(),
+            DbusBus::Address(ref addr) => ConnectionBuilder::address(addr.as_str()),
+        }
+        .map_err(Error::io)?;
+        let conn = builder.build().await.map_err(Error::io)?;
+        let handlers = BridgeHandlers {
+            conn: conn.clone(),
+            path: self.path.clone(),
+        };
+        let rpc = Arc::new(RpcClient::new(client, handlers));
+        if !self.topics.is_empty() {
+            let topics: Vec<&str> = self.topics.iter().map(String::as_str).collect();
+            rpc.client()
+                .lock()
+                .await
+                .subscribe_bulk(&topics, QoS::Processed)
+                .await?;
+        }
+        let mut targets = AclMap::new().separator('.').wildcard("*").match_any("?");
+        for mask in &self.targets {
+            targets.insert(mask);
+        }
+        let mut publish = AclMap::new().separator('/').wildcard("#").match_any("+");
+        for mask in &self.publish {
+            publish.insert(mask);
+        }
+        conn.object_server()
+            .at(
+                self.path.as_str(),
+                BridgeInterface {
+                    rpc: rpc.clone(),
+                    targets,
+                    publish,
+                },
+            )
+            .await
+            .map_err(Error::io)?;
+        conn.request_name(self.name.as_str())
+            .await
+            .map_err(Error::io)?;
+        info!("D-Bus bridge {} started", self.name);
+        let mut futs = Vec::new();
+        for rule in &self.signals {
+            let mut stream =
+                MessageStream::for_match_rule(rule.as_str(), &conn, Some(SIGNAL_QUEUE_SIZE))
+                    .await
+                    .map_err(Error::io)?;
+            let rpc = rpc.clone();
+            let prefix = self.topic_prefix.clone();
+            futs.push(tokio::spawn(async move {
+                while let Some(msg) = stream.next().await {
+                    let msg = match msg {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!("D-Bus: {}", e);
+                            continue;
+                        }
+                    };
+                    let header = match msg.header() {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!("D-Bus: {}", e);
+                            continue;
+                        }
+                    };
+                    let (Ok(Some(iface)), Ok(Some(member))) = (header.interface(), header.member())
+                    else {
+                        continue;
+                    };
+                    let topic = format!("{}/{}/{}", prefix, iface, member);
+                    let args = match signal_args(&msg) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!("D-Bus signal {}: {}", topic, e);
+                            continue;
+                        }
+                    };
+                    if let Err(e) = rpc
+                        .notify_topic(&topic, Cow::Owned(args), QoS::Processed)
+                        .await
+                    {
+                        error!("unable to publish D-Bus signal {}: {}", topic, e);
+                    }
+                }
+            }));
+        }
+        while rpc.is_connected() {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        for fut in futs {
+            fut.abort();
+        }
+        Ok(())
+    }
+}
+
+/// Packs signal arguments as a MessagePack array
+fn signal_args(msg: &zbus::Message) -> Result<Vec<u8>, Error> {
+    let args = if msg.body_signature().map_or(true, |s| s.is_empty()) {
+        Vec::new()
+    } else {
+        msg.body::<zbus::zvariant::Structure>()
+            .map_err(Error::data)?
+            .into_fields()
+            .iter()
+            .map(value_to_serde)
+            .collect()
+    };
+    rmp_serde::to_vec_named(&serde_value::Value::Seq(args)).map_err(Error::data)
+}
+
+/// Converts a D-Bus value to a serde one. Dicts with non-string keys are converted as lists of
+/// entries
+fn value_to_serde(value: &Value) -> serde_value::Value {
+    match value {
+        Value::U8(v) => serde_value::Value::U8(*v),
+        Value::Bool(v) => serde_value::Value::Bool(*v),
+        Value::I16(v) => serde_value::Value::I16(*v),
+        Value::U16(v) => serde_value::Value::U16(*v),
+        Value::I32(v) => serde_value::Value::I32(*v),
+        Value::U32(v) => serde_value::Value::U32(*v),
+        Value::I64(v) => serde_value::Value::I64(*v),
+        Value::U64(v) => serde_value::Value::U64(*v),
+        Value::F64(v) => serde_value::Value::F64(*v),
+        Value::Str(v) => serde_value::Value::String(v.to_string()),
+        Value::Signature(v) => serde_value::Value::String(v.to_string()),
+        Value::ObjectPath(v) => serde_value::Value::String(v.to_string()),
+        Value::Value(v) => value_to_serde(v),
+        Value::Array(v) => {
+            if v.element_signature() == "y" {
+                serde_value::Value::Bytes(
+                    v.get()
+                        .iter()
+                        .filter_map(|b| u8::try_from(b).ok())
+                        .collect(),
+                )
+            } else {
+                serde_value::Value::Seq(v.get().iter().map(value_to_serde).collect())
+            }
+        }
+        Value::Dict(v) => {
+            if let Ok(map) = HashMap::<String, OwnedValue>::try_from(v.clone()) {
+                serde_value::Value::Map(
+                    map.iter()
+                        .map(|(k, v)| (serde_value::Value::String(k.clone()), value_to_serde(v)))
+                        .collect(),
+                )
+            } else {
+                serde_value::to_value(v).unwrap_or(serde_value::Value::Unit)
+            }
+        }
+        Value::Structure(v) => {
+            serde_value::Value::Seq(v.fields().iter().map(value_to_serde).collect())
+        }
+        Value::Fd(_) => serde_value::Value::Unit,
+    }
+}
+
+struct BridgeHandlers {
+    conn: Connection,
+    path: String,
+}
+
+#[async_trait]
+impl RpcHandlers for BridgeHandlers {
+    async fn handle_call(&self, _event: RpcEvent) -> RpcResult {
+        Err(RpcError::method(None))
+    }
+    async fn handle_notification(&self, _event: RpcEvent) {}
+    async fn handle_frame(&self, frame: Frame) {
+        if frame.kind() != FrameKind::Publish {
+            return;
+        }
+        let result = match SignalContext::new(&self.conn, self.path.as_str()) {
+            Ok(ctxt) => {
+                BridgeInterface::frame(
+                    &ctxt,
+                    frame.sender(),
+                    frame.topic().unwrap_or_default(),
+                    frame.payload(),
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("unable to emit D-Bus signal: {}", e);
+        }
+    }
+}
+
+struct BridgeInterface {
+    rpc: Arc<RpcClient>,
+    targets: AclMap,
+    publish: AclMap,
+}
+
+#[dbus_interface(name = "org.elbus.Bridge1")]
+impl BridgeInterface {
+    async fn call(
+        &self,
+        target: &str,
+        method: &str,
+        params: Vec<u8>,
+    ) -> zbus::fdo::Result<Vec<u8>> {
+        if !self.targets.matches(target) {
+            return Err(zbus::fdo::Error::AccessDenied(format!(
+                "calls to {} are not allowed",
+                target
+            )));
+        }
+        match self
+            .rpc
+            .call(target, method, Cow::Owned(params), QoS::Processed)
+            .await
+        {
+            Ok(event) => Ok(event.payload().to_vec()),
+            Err(e) => Err(zbus::fdo::Error::Failed(rpc_error_message(&e))),
+        }
+    }
+    async fn publish(&self, topic: &str, payload: Vec<u8>) -> zbus::fdo::Result<()> {
+        if !self.publish.matches(topic) {
+            return Err(zbus::fdo::Error::AccessDenied(format!(
+                "publishing to {} is not allowed",
+                topic
+            )));
+        }
+        self.rpc
+            .notify_topic(topic, Cow::Owned(payload), QoS::Processed)
+            .await
+            .map_err(|e| zbus::fdo::Error::Failed(e.to_string()))?;
+        Ok(())
+    }
+    #[dbus_interface(signal)]
+    async fn frame(
+        ctxt: &SignalContext<'_>,
+        sender: &str,
+        topic: &str,
+        payload: &[u8],
+    ) -> zbus::Result<()>;
+}
+
+fn rpc_error_message(e: &RpcError) -> String {
+    match e.data().map(std::str::from_utf8) {
+        Some(Ok(msg)) if !msg.is_empty() => format!("elbus RPC error {}: {}", e.code(), msg),
+        _ => format!("elbus RPC error {}", e.code()),
+    }
+}
@@ -0,0 +1,164 @@
+use crate::common::{RpcBalance, ServiceInfo, ServiceList, RPC_TARGET_PREFIX};
+use crate::rpc::{ContentType, Rpc, RpcError};
+use crate::QoS;
+use serde::Serialize;
+
+const BROKER_NAME: &str = ".broker";
+
+#[derive(Serialize)]
+struct NameParams<'a> {
+    name: &'a str,
+}
+
+#[derive(Serialize, Default)]
+struct DiscoverParams<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    method: Option<&'a str>,
+}
+
+/// Service capabilities of the client, registered in the broker so RPC callers can find
+/// providers with [`discover`]. Registrations are removed by the broker when the client is
+/// disconnected, so they must be repeated after reconnects
+///
+/// Providers of the service form its load-balanced RPC target (see [`target`])
+#[derive(Debug, Clone, Serialize)]
+pub struct Service {
+    name: String,
+    methods: Vec<String>,
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    balance: Option<RpcBalance>,
+}
+
+impl Service {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            methods: Vec::new(),
+            tags: Vec::new(),
+            balance: None,
+        }
+    }
+    #[inline]
+    pub fn method(mut self, method: &str) -> Self {
+        self.methods.push(method.to_owned());
+        self
+    }
+    #[inline]
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.tags.push(tag.to_owned());
+        self
+    }
+    /// Distribution of calls among providers, must match the policy of providers, which have
+    /// registered the service before (round-robin if not set by the first one)
+    #[inline]
+    pub fn balance(mut self, balance: RpcBalance) -> Self {
+        self.balance.replace(balance);
+        self
+    }
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Registers the service, a service with the same name, registered by the client before, is
+    /// replaced
+    pub async fn register<R: Rpc + ?Sized>(&self, rpc: &R) -> Result<(), RpcError> {
+        let params = ContentType::MsgPack.pack(self)?;
+        rpc.call(
+            BROKER_NAME,
+            "service.register",
+            params.into(),
+            QoS::Processed,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// Returns the load-balanced RPC target of the service. Calls to the target are distributed by
+/// the broker among providers. If a provider is disconnected, its pending calls are failed with
+/// [`RPC_ERROR_CODE_UNAVAILABLE`](crate::rpc::RPC_ERROR_CODE_UNAVAILABLE) and can be retried.
+/// Calls of stateful sessions are pinned to providers with
+/// [`CallOptions::session`](crate::rpc::CallOptions::session)
+#[inline]
+pub fn target(name: &str) -> String {
+    format!("{}{}", RPC_TARGET_PREFIX, name)
+}
+
+/// Unregisters a service of the client
+pub async fn unregister<R: Rpc + ?Sized>(rpc: &R, name: &str) -> Result<(), RpcError> {
+    let params = ContentType::MsgPack.pack(&NameParams { name })?;
+    rpc.call(
+        BROKER_NAME,
+        "service.unregister",
+        params.into(),
+        QoS::Processed,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Returns services with the tag, sorted by names and providers
+pub async fn discover<R: Rpc + ?Sized>(rpc: &R, tag: &str) -> Result<Vec<ServiceInfo>, RpcError> {
+    query(
+        rpc,
+        &DiscoverParams {
+            tag: Some(tag),
+            ..DiscoverParams::default()
+        },
+    )
+    .await
+}
+
+/// Returns providers of the service, which have registered the method
+pub async fn discover_method<R: Rpc + ?Sized>(
+    rpc: &R,
+    name: &str,
+    method: &str,
+) -> Result<Vec<ServiceInfo>, RpcError> {
+    query(
+        rpc,
+        &DiscoverParams {
+            name: Some(name),
+            method: Some(method),
+            ..DiscoverParams::default()
+        },
+    )
+    .await
+}
+
+/// Returns providers of the service
+pub async fn discover_service<R: Rpc + ?Sized>(
+    rpc: &R,
+    name: &str,
+) -> Result<Vec<ServiceInfo>, RpcError> {
+    query(
+        rpc,
+        &DiscoverParams {
+            name: Some(name),
+            ..DiscoverParams::default()
+        },
+    )
+    .await
+}
+
+async fn query<R: Rpc + ?Sized>(
+    rpc: &R,
+    params: &DiscoverParams<'_>,
+) -> Result<Vec<ServiceInfo>, RpcError> {
+    let params = ContentType::MsgPack.pack(params)?;
+    let event = rpc
+        .call(
+            BROKER_NAME,
+            "service.discover",
+            params.into(),
+            QoS::Processed,
+        )
+        .await?;
+    let list: ServiceList = event.unpack()?;
+    Ok(list.services)
+}
@@ -0,0 +1,404 @@
+use crate::borrow::Cow;
+use crate::broker::MaskFormat;
+use crate::client::AsyncClient;
+use crate::rpc::{Rpc, RpcClient, RpcError, RpcEvent, RpcHandlers, RpcResult};
+use crate::{Error, Frame, FrameKind, QoS};
+use async_trait::async_trait;
+use futures_util::{SinkExt, StreamExt};
+use log::trace;
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::atomic;
+use std::sync::Arc;
+use submap::AclMap;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// Max number of JSON messages, queued per gateway connection. If a browser can not keep up,
+/// received frames are dropped
+const GATEWAY_QUEUE_SIZE: usize = 1024;
+
+static CLIENT_ID: atomic::AtomicU64 = atomic::AtomicU64::new(0);
+
+/// Generates a unique name for a gateway connection client
+pub(crate) fn client_name() -> String {
+    format!(
+        ".wsgw.{}",
+        CLIENT_ID.fetch_add(1, atomic::Ordering::SeqCst) + 1
+    )
+}
+
+/// Browser-facing JSON gateway (requires "websocket" and "rpc" features)
+///
+/// Each WebSocket connection is served by a dedicated broker client. Requests are JSON text
+/// messages, an optional "id" field is copied into the reply:
+///
+/// * {"op":"subscribe","topics":["MASK",...]}, {"op":"unsubscribe","topics":["MASK",...]}
+///
+/// * {"op":"publish","topic":"TOPIC","payload":...}
+///
+/// * {"op":"send","target":"CLIENT","payload":...}, {"op":"broadcast","target":"MASK",...}
+///
+/// * {"op":"rpc","target":"CLIENT","method":"METHOD","params":...}
+///
+/// Replies are {"op":"reply","id":...,"ok":true[,"result":...]} or
+/// {"op":"reply","id":...,"ok":false,"error":"..."[,"code":N]}. Received frames are forwarded
+/// as {"op":"message|broadcast|publish|notification","sender":"...",["topic":"...",]
+/// "payload":...}
+///
+/// Outgoing payloads and params are packed with MessagePack (null - an empty payload). Incoming
+/// payloads are unpacked from MessagePack, if not possible - forwarded as UTF-8 strings or
+/// arrays of bytes
+///
+/// Nothing is allowed by default, topics, targets and publish topics must be set explicitly
+#[derive(Debug, Clone, Default)]
+pub struct WsGateway {
+    topics: Vec<String>,
+    targets: Vec<String>,
+    publish: Vec<String>,
+}
+
+impl WsGateway {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Topic mask, allowed for subscriptions, can be specified multiple times
+    #[inline]
+    pub fn topic(mut self, mask: &str) -> Self {
+        self.topics.push(mask.to_owned());
+        self
+    }
+    /// Client mask, allowed for messages, broadcasts and RPC calls, can be specified multiple
+    /// times
+    #[inline]
+    pub fn target(mut self, mask: &str) -> Self {
+        self.targets.push(mask.to_owned());
+        self
+    }
+    /// Topic mask, allowed for publications, can be specified multiple times
+    #[inline]
+    pub fn publish(mut self, mask: &str) -> Self {
+        self.publish.push(mask.to_owned());
+        self
+    }
+    pub(crate) fn acl(
+        &self,
+        broadcast_format: &MaskFormat,
+        topic_format: &MaskFormat,
+    ) -> GatewayAcl {
+        let acl_map = |format: &MaskFormat, masks: &[String]| {
+            let mut acl = format.acl_map();
+            for mask in masks {
+                acl.insert(mask);
+            }
+            acl
+        };
+        GatewayAcl {
+            topics: acl_map(topic_format, &self.topics),
+            targets: acl_map(broadcast_format, &self.targets),
+            publish: acl_map(topic_format, &self.publish),
+        }
+    }
+}
+
+pub(crate) struct GatewayAcl {
+    topics: AclMap,
+    targets: AclMap,
+    publish: AclMap,
+}
+
+impl GatewayAcl {
+    fn check(acl: &AclMap, what: &str, value: &str) -> Result<(), Error> {
+        if acl.matches(value) {
+            Ok(())
+        } else {
+            Err(Error::access(format!("{} {} is not allowed", what, value)))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase", deny_unknown_fields)]
+enum Request {
+    Subscribe {
+        topics: Vec<String>,
+    },
+    Unsubscribe {
+        topics: Vec<String>,
+    },
+    Publish {
+        topic: String,
+        #[serde(default)]
+        payload: Value,
+    },
+    Send {
+        target: String,
+        #[serde(default)]
+        payload: Value,
+    },
+    Broadcast {
+        target: String,
+        #[serde(default)]
+        payload: Value,
+    },
+    Rpc {
+        target: String,
+        method: String,
+        #[serde(default)]
+        params: Value,
+    },
+}
+
+fn pack(value: &Value) -> Result<Vec<u8>, Error> {
+    if value.is_null() {
+        Ok(Vec::new())
+    } else {
+        rmp_serde::to_vec_named(value).map_err(Error::data)
+    }
+}
+
+fn unpack(payload: &[u8]) -> Value {
+    if payload.is_empty() {
+        return Value::Null;
+    }
+    let mut de = rmp_serde::Deserializer::new(std::io::Cursor::new(payload));
+    if let Ok(value) = Value::deserialize(&mut de) {
+        // the whole payload must be consumed, otherwise it is likely a string
+        if de.get_ref().position() == payload.len() as u64 {
+            return value;
+        }
+    }
+    if let Ok(s) = std::str::from_utf8(payload) {
+        s.into()
+    } else {
+        payload.into()
+    }
+}
+
+fn reply(id: Value, result: Result<Option<Value>, Error>) -> Value {
+    let mut response = serde_json::Map::new();
+    response.insert("op".to_owned(), "reply".into());
+    response.insert("id".to_owned(), id);
+    match result {
+        Ok(result) => {
+            response.insert("ok".to_owned(), true.into());
+            if let Some(result) = result {
+                response.insert("result".to_owned(), result);
+            }
+        }
+        Err(e) => {
+            response.insert("ok".to_owned(), false.into());
+            response.insert("error".to_owned(), e.to_string().into());
+        }
+    }
+    response.into()
+}
+
+fn rpc_reply(id: Value, result: Result<RpcEvent, RpcError>) -> Value {
+    match result {
+        Ok(event) => reply(id, Ok(Some(unpack(event.payload())))),
+        Err(e) => {
+            let mut response = reply(id, Ok(None));
+            response["ok"] = false.into();
+            response["code"] = e.code().into();
+            response["error"] = e
+                .data()
+                .filter(|d| !d.is_empty())
+                .map_or_else(
+                    || "RPC error".to_owned(),
+                    |d| String::from_utf8_lossy(d).into(),
+                )
+                .into();
+            response
+        }
+    }
+}
+
+fn frame_message(op: &str, frame: &Frame, payload: &[u8]) -> String {
+    let mut message = serde_json::Map::new();
+    message.insert("op".to_owned(), op.into());
+    message.insert("sender".to_owned(), frame.sender().into());
+    if let Some(topic) = frame.topic() {
+        message.insert("topic".to_owned(), topic.into());
+    }
+    message.insert("payload".to_owned(), unpack(payload));
+    Value::from(message).to_string()
+}
+
+struct GatewayHandlers {
+    tx: async_channel::Sender<String>,
+}
+
+impl GatewayHandlers {
+    fn forward(&self, message: String) {
+        if self.tx.try_send(message).is_err() {
+            trace!("gateway queue is full, frame dropped");
+        }
+    }
+}
+
+#[async_trait]
+impl RpcHandlers for GatewayHandlers {
+    async fn handle_call(&self, _event: RpcEvent) -> RpcResult {
+        Err(RpcError::method(None))
+    }
+    async fn handle_notification(&self, event: RpcEvent) {
+        self.forward(frame_message(
+            "notification",
+            event.frame(),
+            event.payload(),
+        ));
+    }
+    async fn handle_frame(&self, frame: Frame) {
+        let op = match frame.kind() {
+            FrameKind::Message => "message",
+            FrameKind::Broadcast => "broadcast",
+            FrameKind::Publish => "publish",
+            _ => return,
+        };
+        self.forward(frame_message(op, &frame, frame.payload()));
+    }
+}
+
+/// Processes a request and returns the reply
+async fn process(
+    rpc: &RpcClient,
+    acl: &GatewayAcl,
+    id: &Value,
+    request: Request,
+) -> Result<Value, Error> {
+    let client = rpc.client();
+    match request {
+        Request::Subscribe { topics } => {
+            for topic in &topics {
+                GatewayAcl::check(&acl.topics, "topic", topic)?;
+            }
+            let topics: Vec<&str> = topics.iter().map(String::as_str).collect();
+            client
+                .lock()
+                .await
+                .subscribe_bulk(&topics, QoS::Processed)
+                .await?;
+        }
+        Request::Unsubscribe { topics } => {
+            let topics: Vec<&str> = topics.iter().map(String::as_str).collect();
+            client
+                .lock()
+                .await
+                .unsubscribe_bulk(&topics, QoS::Processed)
+                .await?;
+        }
+        Request::Publish { topic, payload } => {
+            GatewayAcl::check(&acl.publish, "topic", &topic)?;
+            client
+                .lock()
+                .await
+                .publish(&topic, pack(&payload)?.into(), QoS::Processed)
+                .await?;
+        }
+        Request::Send { target, payload } => {
+            GatewayAcl::check(&acl.targets, "target", &target)?;
+            client
+                .lock()
+                .await
+                .send(&target, pack(&payload)?.into(), QoS::Processed)
+                .await?;
+        }
+        Request::Broadcast { target, payload } => {
+            GatewayAcl::check(&acl.targets, "target", &target)?;
+            client
+                .lock()
+                .await
+                .send_broadcast(&target, pack(&payload)?.into(), QoS::Processed)
+                .await?;
+        }
+        Request::Rpc {
+            target,
+            method,
+            params,
+        } => {
+            GatewayAcl::check(&acl.targets, "target", &target)?;
+            let result = rpc
+                .call(&target, &method, Cow::Owned(pack(&params)?), QoS::Processed)
+                .await;
+            return Ok(rpc_reply(id.clone(), result));
+        }
+    }
+    Ok(reply(id.clone(), Ok(None)))
+}
+
+/// Serves a gateway connection until it is closed. Requests, except RPC calls, are processed
+/// in order, RPC calls are processed in parallel and may be replied in any order
+pub(crate) async fn serve<S>(
+    ws: WebSocketStream<S>,
+    client: impl AsyncClient + 'static,
+    acl: Arc<GatewayAcl>,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sink, mut stream) = ws.split();
+    let (tx, rx) = async_channel::bounded::<String>(GATEWAY_QUEUE_SIZE);
+    let rpc = Arc::new(RpcClient::new(client, GatewayHandlers { tx: tx.clone() }));
+    let writer = tokio::spawn(async move {
+        while let Ok(message) = rx.recv().await {
+            if let Err(e) = sink.send(Message::Text(message)).await {
+                trace!("gateway write error: {}", e);
+                break;
+            }
+        }
+    });
+    let result = async {
+        while let Some(message) = stream.next().await {
+            let text = match message.map_err(Error::io)? {
+                Message::Text(text) => text,
+                Message::Binary(_) => {
+                    return Err(Error::data("binary messages are not supported"));
+                }
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            let (id, request) = match serde_json::from_str::<Value>(&text) {
+                Ok(mut value) => {
+                    let id = value
+                        .as_object_mut()
+                        .and_then(|o| o.remove("id"))
+                        .unwrap_or_default();
+                    (
+                        id,
+                        serde_json::from_value::<Request>(value).map_err(Error::data),
+                    )
+                }
+                Err(e) => (Value::Null, Err(Error::data(e))),
+            };
+            match request {
+                Ok(request @ Request::Rpc { .. }) => {
+                    let rpc = rpc.clone();
+                    let acl = acl.clone();
+                    let tx = tx.clone();
+                    tokio::spawn(async move {
+                        let response = process(&rpc, &acl, &id, request)
+                            .await
+                            .unwrap_or_else(|e| reply(id, Err(e)));
+                        let _r = tx.send(response.to_string()).await;
+                    });
+                }
+                Ok(request) => {
+                    let response = process(&rpc, &acl, &id, request)
+                        .await
+                        .unwrap_or_else(|e| reply(id, Err(e)));
+                    let _r = tx.send(response.to_string()).await;
+                }
+                Err(e) => {
+                    let _r = tx.send(reply(id, Err(e)).to_string()).await;
+                }
+            }
+        }
+        Ok(())
+    }
+    .await;
+    writer.abort();
+    result
+}
@@ -1,24 +1,43 @@
 use crate::borrow::Cow;
-use crate::comm::{Flush, TtlBufWriter};
+#[cfg(windows)]
+use crate::comm::{connect_named_pipe, NAMED_PIPE_PREFIX};
+#[cfg(feature = "vsock")]
+use crate::comm::{parse_vsock_addr, VSOCK_PREFIX};
+use crate::comm::{Flush, TcpOptions, TtlBufWriter};
+use crate::common::{check_trace_id, health_payload};
+#[cfg(feature = "rpc")]
+use crate::common::{HelloExtensions, HelloExtensionsReply};
+use crate::proxy::Proxy;
+use crate::DeliveryMode;
 use crate::Error;
+use crate::ErrorKind;
 use crate::EventChannel;
 use crate::IntoElbusResult;
 use crate::OpConfirm;
 use crate::QoS;
 use crate::GREETINGS;
+#[cfg(feature = "rpc")]
+use crate::HELLO_EXTENSIONS_VERSION;
 use crate::PING_FRAME;
 use crate::PROTOCOL_VERSION;
 use crate::RESPONSE_OK;
 use crate::SECONDARY_SEP;
 use crate::{Frame, FrameData, FrameKind, FrameOp};
-use std::collections::BTreeMap;
+use crate::{FRAME_FLAG_DELIVERY, FRAME_FLAG_REALTIME, FRAME_FLAG_TIMESTAMP};
+use crate::{HELLO_EXTENSIONS_FLAG, HELLO_EXTENSIONS_MAX_SIZE};
+use crate::{HELLO_TOKEN_FLAG, HELLO_TOKEN_MAX_LEN};
+use crate::{TRANSACTION_ABORT, TRANSACTION_BEGIN, TRANSACTION_COMMIT};
+use std::collections::{btree_map, BTreeMap};
+use std::fmt;
 use std::marker::Unpin;
+use std::path::Path;
 use std::sync::atomic;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::net::unix;
-use tokio::net::{tcp, TcpStream, UnixStream};
+use tokio::net::{tcp, TcpStream};
+#[cfg(unix)]
+use tokio::net::{unix, UnixStream};
 use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
 
@@ -29,44 +48,284 @@ use log::{error, trace, warn};
 use async_trait::async_trait;
 
 type ResponseMap = Arc<Mutex<BTreeMap<u32, oneshot::Sender<Result<(), Error>>>>>;
+type SubscriptionMap = Arc<Mutex<BTreeMap<String, usize>>>;
+
+#[cfg(feature = "tls")]
+type TlsStream = tokio_rustls::client::TlsStream<TcpStream>;
+
+#[cfg(feature = "tls")]
+const TLS_PREFIX: &str = "tls://";
+
+#[cfg(feature = "quic")]
+const QUIC_PREFIX: &str = "quic://";
+
+/// QUIC connections are kept alive with pings, as clients may be idle for a long time
+#[cfg(feature = "quic")]
+const QUIC_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Payloads, passed via shared memory, are replaced with descriptors: the magic bytes, the
+/// payload size (u64 LE) and the file path (UTF-8)
+pub const SHM_MAGIC: [u8; 8] = *b"\x00ELBSHM\x01";
+#[cfg(unix)]
+pub const DEFAULT_SHM_DIR: &str = "/dev/shm";
+
+#[cfg(unix)]
+static SHM_COUNTER: atomic::AtomicU64 = atomic::AtomicU64::new(0);
 
 enum Writer {
+    #[cfg(unix)]
     Unix(TtlBufWriter<unix::OwnedWriteHalf>),
+    #[cfg(windows)]
+    NamedPipe(TtlBufWriter<tokio::io::WriteHalf<tokio::net::windows::named_pipe::NamedPipeClient>>),
     Tcp(TtlBufWriter<tcp::OwnedWriteHalf>),
+    #[cfg(feature = "tls")]
+    Tls(TtlBufWriter<tokio::io::WriteHalf<TlsStream>>),
+    #[cfg(feature = "quic")]
+    Quic(TtlBufWriter<quinn::SendStream>),
+    #[cfg(feature = "vsock")]
+    Vsock(TtlBufWriter<tokio::io::WriteHalf<tokio_vsock::VsockStream>>),
 }
 
 impl Writer {
     pub async fn write(&mut self, buf: &[u8], flush: Flush) -> Result<(), Error> {
         match self {
+            #[cfg(unix)]
             Writer::Unix(w) => w.write(buf, flush).await.map_err(Into::into),
+            #[cfg(windows)]
+            Writer::NamedPipe(w) => w.write(buf, flush).await.map_err(Into::into),
             Writer::Tcp(w) => w.write(buf, flush).await.map_err(Into::into),
+            #[cfg(feature = "tls")]
+            Writer::Tls(w) => w.write(buf, flush).await.map_err(Into::into),
+            #[cfg(feature = "quic")]
+            Writer::Quic(w) => w.write(buf, flush).await.map_err(Into::into),
+            #[cfg(feature = "vsock")]
+            Writer::Vsock(w) => w.write(buf, flush).await.map_err(Into::into),
+        }
+    }
+}
+
+/// TLS connection options (requires "tls" feature)
+#[cfg(feature = "tls")]
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    ca: Option<String>,
+    server_name: Option<String>,
+    verify_hostname: bool,
+}
+
+#[cfg(feature = "tls")]
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            ca: None,
+            server_name: None,
+            verify_hostname: true,
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+impl TlsConfig {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// CA bundle (PEM) to verify the broker certificate with (default: Mozilla root
+    /// certificates)
+    #[inline]
+    pub fn ca(mut self, path: &str) -> Self {
+        self.ca.replace(path.to_owned());
+        self
+    }
+    /// The name the broker certificate is verified for (default: the host of the path)
+    #[inline]
+    pub fn server_name(mut self, name: &str) -> Self {
+        self.server_name.replace(name.to_owned());
+        self
+    }
+    /// If disabled, the broker certificate is verified with the CA only and may be issued for
+    /// any name (e.g. when brokers are connected by IP addresses)
+    #[inline]
+    pub fn verify_hostname(mut self, verify: bool) -> Self {
+        self.verify_hostname = verify;
+        self
+    }
+    fn connector(&self) -> Result<tokio_rustls::TlsConnector, Error> {
+        Ok(tokio_rustls::TlsConnector::from(Arc::new(
+            self.client_config()?,
+        )))
+    }
+    fn client_config(&self) -> Result<tokio_rustls::rustls::ClientConfig, Error> {
+        use tokio_rustls::rustls;
+        let mut roots = rustls::RootCertStore::empty();
+        if let Some(ref ca) = self.ca {
+            let (_, ignored) = roots.add_parsable_certificates(&crate::comm::load_certs(ca)?);
+            if ignored > 0 {
+                warn!("{} certificates of {} are ignored", ignored, ca);
+            }
+        } else {
+            roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+                rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    ta.subject,
+                    ta.spki,
+                    ta.name_constraints,
+                )
+            }));
+        }
+        let builder = rustls::ClientConfig::builder().with_safe_defaults();
+        Ok(if self.verify_hostname {
+            builder.with_root_certificates(roots).with_no_client_auth()
+        } else {
+            builder
+                .with_custom_certificate_verifier(Arc::new(CaOnlyVerifier(roots)))
+                .with_no_client_auth()
+        })
+    }
+    fn host_for<'a>(&'a self, addr: &'a str) -> &'a str {
+        if let Some(ref name) = self.server_name {
+            name.as_str()
+        } else {
+            addr.rsplit_once(':')
+                .map_or(addr, |(host, _)| host)
+                .trim_start_matches('[')
+                .trim_end_matches(']')
         }
     }
+    fn server_name_for(&self, addr: &str) -> Result<tokio_rustls::rustls::ServerName, Error> {
+        let name = self.host_for(addr);
+        tokio_rustls::rustls::ServerName::try_from(name)
+            .map_err(|e| Error::data(format!("invalid server name {}: {}", name, e)))
+    }
+    #[cfg(feature = "quic")]
+    async fn quic_connect(
+        &self,
+        addr: &str,
+        timeout: Duration,
+    ) -> Result<quinn::Connection, Error> {
+        let remote = tokio::net::lookup_host(addr)
+            .await?
+            .next()
+            .ok_or_else(|| Error::data(format!("unable to resolve {}", addr)))?;
+        let mut crypto = self.client_config()?;
+        crypto.alpn_protocols = vec![crate::comm::QUIC_ALPN.to_vec()];
+        let mut client_config = quinn::ClientConfig::new(Arc::new(crypto));
+        let mut transport = quinn::TransportConfig::default();
+        transport.keep_alive_interval(Some(QUIC_KEEP_ALIVE_INTERVAL));
+        client_config.transport_config(Arc::new(transport));
+        let local: std::net::SocketAddr = if remote.is_ipv4() {
+            (std::net::Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let endpoint = quinn::Endpoint::client(local)?;
+        let connecting = endpoint
+            .connect_with(client_config, remote, self.host_for(addr))
+            .map_err(Error::io)?;
+        tokio::time::timeout(timeout, connecting)
+            .await?
+            .map_err(Error::io)
+    }
+}
+
+/// Verifies server certificates with the CA only, the server name is not checked
+#[cfg(feature = "tls")]
+struct CaOnlyVerifier(tokio_rustls::rustls::RootCertStore);
+
+#[cfg(feature = "tls")]
+impl tokio_rustls::rustls::client::ServerCertVerifier for CaOnlyVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &tokio_rustls::rustls::Certificate,
+        intermediates: &[tokio_rustls::rustls::Certificate],
+        _server_name: &tokio_rustls::rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        now: std::time::SystemTime,
+    ) -> Result<tokio_rustls::rustls::client::ServerCertVerified, tokio_rustls::rustls::Error> {
+        let cert = tokio_rustls::rustls::server::ParsedCertificate::try_from(end_entity)?;
+        tokio_rustls::rustls::client::verify_server_cert_signed_by_trust_anchor(
+            &cert,
+            &self.0,
+            intermediates,
+            now,
+        )?;
+        Ok(tokio_rustls::rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+/// The token is never printed
+#[derive(Clone)]
+struct Token(String);
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("***")
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Config {
     path: String,
     name: String,
+    groups: Vec<String>,
     buf_size: usize,
     buf_ttl: Duration,
     queue_size: usize,
     timeout: Duration,
+    tcp_options: TcpOptions,
+    proxy: Option<Proxy>,
+    token: Option<Token>,
+    batch: Option<(u32, Duration)>,
+    timestamps: bool,
+    pressure: bool,
+    delivery: bool,
+    // the payload size threshold and the file TTL
+    #[cfg(unix)]
+    shm: Option<(usize, Duration)>,
+    #[cfg(unix)]
+    shm_dir: String,
+    #[cfg(feature = "rpc")]
+    extensions: Option<HelloExtensions>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
 }
 
 impl Config {
-    /// path - /path/to/socket (must end with .sock .socket or .ipc) or host:port,
-    /// name - an unique client name
+    /// path - /path/to/socket (must end with .sock .socket or .ipc, Unix only),
+    /// \\.\pipe\name (Windows named pipe), host:port, tls://host:port (requires "tls"
+    /// feature, the default TLS options are used), quic://host:port (requires "quic" feature)
+    /// or vsock://cid:port (requires "vsock" feature), name - an unique client name
     pub fn new(path: &str, name: &str) -> Self {
         Self {
             path: path.to_owned(),
             name: name.to_owned(),
+            groups: Vec::new(),
             buf_size: crate::DEFAULT_BUF_SIZE,
             buf_ttl: crate::DEFAULT_BUF_TTL,
             queue_size: crate::DEFAULT_QUEUE_SIZE,
             timeout: crate::DEFAULT_TIMEOUT,
+            tcp_options: TcpOptions::default(),
+            proxy: None,
+            token: None,
+            batch: None,
+            timestamps: false,
+            pressure: false,
+            delivery: false,
+            #[cfg(unix)]
+            shm: None,
+            #[cfg(unix)]
+            shm_dir: DEFAULT_SHM_DIR.to_owned(),
+            #[cfg(feature = "rpc")]
+            extensions: None,
+            #[cfg(feature = "tls")]
+            tls: None,
         }
     }
+    /// Client groups to join at registration (secondary clients join the same groups)
+    pub fn groups(mut self, groups: &[&str]) -> Self {
+        self.groups = groups.iter().map(|&g| g.to_owned()).collect();
+        self
+    }
     pub fn buf_size(mut self, size: usize) -> Self {
         self.buf_size = size;
         self
@@ -83,6 +342,134 @@ impl Config {
         self.timeout = timeout;
         self
     }
+    /// Asks the broker to pack queued incoming frames into batch frames, up to the max size
+    /// (bytes) and waiting for more frames up to the max delay. Reduces per-frame overhead for
+    /// high-rate subscribers, real-time frames are never delayed. Requires broker support
+    pub fn batch(mut self, max_size: u32, max_delay: Duration) -> Self {
+        self.batch.replace((max_size, max_delay));
+        self
+    }
+    /// Asks the broker to deliver receive timestamps of frames (Frame::timestamp), the broker
+    /// must have frame timestamping enabled
+    pub fn timestamps(mut self) -> Self {
+        self.timestamps = true;
+        self
+    }
+    /// Asks the broker to report the queue pressure of subscribers in acknowledgements of
+    /// publications (Client::pressure). Requires broker support
+    pub fn pressure(mut self) -> Self {
+        self.pressure = true;
+        self
+    }
+    /// Enables delivery modes: messages can be sent with QoS::Delivered and QoS::Consumed, frames,
+    /// sent to the client with QoS::Consumed, must be confirmed (AsyncClient::confirm). Requires
+    /// broker support
+    pub fn delivery(mut self) -> Self {
+        self.delivery = true;
+        self
+    }
+    /// Passes payloads, larger than the threshold (bytes), via shared memory (UNIX sockets only):
+    /// the payload is written to a file in the shared memory directory and only its descriptor
+    /// goes through the broker. Files are removed after the TTL, recipients must enable the mode
+    /// as well to resolve descriptors. Frames, sent with zc_send, are always sent inline
+    #[cfg(unix)]
+    pub fn shm(mut self, threshold: usize, ttl: Duration) -> Self {
+        self.shm.replace((threshold, ttl));
+        self
+    }
+    /// The shared memory directory (default: /dev/shm), descriptors of files in other
+    /// directories are not resolved
+    #[cfg(unix)]
+    pub fn shm_dir(mut self, dir: &str) -> Self {
+        self.shm_dir = dir.to_owned();
+        self
+    }
+    /// Hello extensions (will, metadata, keepalive, compression), sent to the broker at
+    /// registration, the block version is set automatically. The broker reply is available with
+    /// Client::extensions_reply
+    #[cfg(feature = "rpc")]
+    pub fn extensions(mut self, mut extensions: HelloExtensions) -> Self {
+        extensions.v = HELLO_EXTENSIONS_VERSION;
+        self.extensions.replace(extensions);
+        self
+    }
+    /// TCP socket options, ignored for UNIX sockets and named pipes
+    pub fn tcp_options(mut self, tcp_options: TcpOptions) -> Self {
+        self.tcp_options = tcp_options;
+        self
+    }
+    /// Presents the secret token to the broker at registration, required if the broker has token
+    /// authentication enabled (1-1024 bytes)
+    pub fn token(mut self, token: &str) -> Self {
+        self.token.replace(Token(token.to_owned()));
+        self
+    }
+    /// Connects to TCP and TLS brokers through a SOCKS5 or HTTP CONNECT proxy, ignored for other
+    /// transports
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy.replace(proxy);
+        self
+    }
+    /// Connects to host:port with TLS (requires "tls" feature). For quic://host:port paths, sets
+    /// the TLS options of QUIC connections
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls.replace(tls);
+        self
+    }
+    async fn tcp_connect(&self, addr: &str) -> Result<TcpStream, Error> {
+        let stream = if let Some(ref proxy) = self.proxy {
+            proxy.connect(addr, self.timeout).await?
+        } else {
+            TcpStream::connect(addr).await?
+        };
+        self.tcp_options.apply(&stream)?;
+        Ok(stream)
+    }
+    #[allow(clippy::case_sensitive_file_extension_comparisons)]
+    fn transport(&self) -> Transport {
+        #[cfg(feature = "quic")]
+        if self.path.starts_with(QUIC_PREFIX) {
+            return Transport::Quic(self.tls.clone().unwrap_or_default());
+        }
+        #[cfg(feature = "vsock")]
+        if self.path.starts_with(VSOCK_PREFIX) {
+            return Transport::Vsock;
+        }
+        #[cfg(windows)]
+        if self.path.starts_with(NAMED_PIPE_PREFIX) {
+            return Transport::NamedPipe;
+        }
+        #[cfg(feature = "tls")]
+        if let Some(ref tls) = self.tls {
+            return Transport::Tls(tls.clone());
+        } else if self.path.starts_with(TLS_PREFIX) {
+            return Transport::Tls(TlsConfig::default());
+        }
+        #[cfg(unix)]
+        if self.path.ends_with(".sock")
+            || self.path.ends_with(".socket")
+            || self.path.ends_with(".ipc")
+            || self.path.starts_with('/')
+        {
+            return Transport::Unix;
+        }
+        Transport::Tcp
+    }
+}
+
+enum Transport {
+    #[cfg(unix)]
+    Unix,
+    #[cfg(windows)]
+    NamedPipe,
+    Tcp,
+    #[cfg(feature = "tls")]
+    Tls(TlsConfig),
+    #[cfg(feature = "quic")]
+    Quic(TlsConfig),
+    #[cfg(feature = "vsock")]
+    Vsock,
 }
 
 pub struct Client {
@@ -96,13 +483,35 @@ pub struct Client {
     timeout: Duration,
     config: Config,
     secondary_counter: atomic::AtomicUsize,
+    pressure: Arc<atomic::AtomicU8>,
+    // delivery modes, enabled at connect
+    delivery: bool,
+    // shared memory payloads, enabled for UNIX sockets only
+    #[cfg(unix)]
+    shm: Option<(usize, Duration)>,
+    // subscription reference counts, masks are sent to the broker only when added or removed
+    subscriptions: SubscriptionMap,
+    // the heartbeat payload, sent instead of pings if the health is set
+    health: Option<Vec<u8>>,
+    // QUIC connection, shared with secondary clients
+    #[cfg(feature = "quic")]
+    quic: Option<quinn::Connection>,
+    #[cfg(feature = "rpc")]
+    extensions_reply: Option<HelloExtensionsReply>,
 }
 
 macro_rules! prepare_frame_buf {
     ($self: expr, $op: expr, $qos: expr) => {{
+        if $qos.delivery_mode() != DeliveryMode::Queued
+            && ($op != FrameOp::Message || !$self.delivery)
+        {
+            return Err(Error::not_supported(
+                "delivery modes require messages and Config::delivery",
+            ));
+        }
         $self.increment_frame_id();
         let mut buf = $self.frame_id.to_le_bytes().to_vec();
-        buf.push($op as u8 | ($qos as u8) << 6);
+        buf.push($op as u8 | $qos.wire_bits() << 6);
         buf
     }};
 }
@@ -144,19 +553,29 @@ macro_rules! send_frame_and_confirm {
 macro_rules! send_frame {
     ($self: expr, $target: expr, $payload: expr, $op: expr, $qos: expr) => {{
         let mut buf = prepare_frame_buf!($self, $op, $qos);
+        // large payloads are replaced with shared memory descriptors if enabled
+        let descriptor = $self.shm_store($payload).await?;
+        let payload: &[u8] = descriptor.as_deref().unwrap_or($payload);
+        let mode = $self.delivery_mode_byte($op, $qos);
         let t = $target.as_bytes();
-        buf.extend_from_slice(&((t.len() + $payload.len() + 1) as u32).to_le_bytes());
+        buf.extend_from_slice(
+            &((usize::from(mode.is_some()) + t.len() + payload.len() + 1) as u32).to_le_bytes(),
+        );
+        buf.extend(mode);
         buf.extend_from_slice(t);
         buf.push(0x00);
         trace!("sending elbus {:?} to {} QoS={:?}", $op, $target, $qos);
-        send_frame_and_confirm!($self, &buf, $payload, $qos)
+        send_frame_and_confirm!($self, &buf, payload, $qos)
     }};
     ($self: expr, $target: expr, $header: expr, $payload: expr, $op: expr, $qos: expr) => {{
         let mut buf = prepare_frame_buf!($self, $op, $qos);
+        let mode = $self.delivery_mode_byte($op, $qos);
         let t = $target.as_bytes();
         buf.extend_from_slice(
-            &((t.len() + $payload.len() + $header.len() + 1) as u32).to_le_bytes(),
+            &((usize::from(mode.is_some()) + t.len() + $payload.len() + $header.len() + 1) as u32)
+                .to_le_bytes(),
         );
+        buf.extend(mode);
         buf.extend_from_slice(t);
         buf.push(0x00);
         buf.extend_from_slice($header);
@@ -171,81 +590,288 @@ macro_rules! send_frame {
 }
 
 macro_rules! connect_broker {
-    ($name: expr, $reader: expr, $writer: expr,
-         $responses: expr, $connected: expr, $timeout: expr, $queue_size: expr) => {{
-        chat($name, &mut $reader, &mut $writer).await?;
+    ($name: expr, $groups: expr, $token: expr, $extensions: expr, $reader: expr, $writer: expr,
+         $responses: expr, $connected: expr, $pressure: expr, $timeout: expr,
+         $queue_size: expr) => {{
+        connect_broker!(
+            $name,
+            $groups,
+            $token,
+            $extensions,
+            $reader,
+            $writer,
+            $responses,
+            $connected,
+            $pressure,
+            $timeout,
+            $queue_size,
+            None
+        )
+    }};
+    ($name: expr, $groups: expr, $token: expr, $extensions: expr, $reader: expr, $writer: expr,
+         $responses: expr, $connected: expr, $pressure: expr, $timeout: expr,
+         $queue_size: expr, $shm_dir: expr) => {{
+        let extensions_reply = chat(
+            $name,
+            $groups,
+            $token,
+            $extensions,
+            &mut $reader,
+            &mut $writer,
+        )
+        .await?;
         let (tx, rx) = async_channel::bounded($queue_size);
         let reader_responses = $responses.clone();
         let rconn = $connected.clone();
+        let pressure = $pressure.clone();
         let timeout = $timeout.clone();
+        let shm_dir: Option<String> = $shm_dir;
         let reader_fut = tokio::spawn(async move {
-            if let Err(e) = handle_read($reader, tx, timeout, reader_responses).await {
+            if let Err(e) =
+                handle_read($reader, tx, timeout, reader_responses, pressure, shm_dir).await
+            {
                 error!("elbus client reader error: {}", e);
             }
             rconn.store(false, atomic::Ordering::SeqCst);
         });
-        (reader_fut, rx)
+        (reader_fut, rx, extensions_reply)
     }};
 }
 
 impl Client {
+    #[inline]
     pub async fn connect(config: &Config) -> Result<Self, Error> {
+        Self::connect_stream(
+            config,
+            #[cfg(feature = "quic")]
+            None,
+        )
+        .await
+    }
+    /// Connects the client, a QUIC client opens a new stream on the connection if specified
+    async fn connect_stream(
+        config: &Config,
+        #[cfg(feature = "quic")] mut quic: Option<quinn::Connection>,
+    ) -> Result<Self, Error> {
         let responses: ResponseMap = <_>::default();
         let connected = Arc::new(atomic::AtomicBool::new(true));
-        #[allow(clippy::case_sensitive_file_extension_comparisons)]
-        let (writer, reader_fut, rx) = if config.path.ends_with(".sock")
-            || config.path.ends_with(".socket")
-            || config.path.ends_with(".ipc")
-            || config.path.starts_with('/')
-        {
-            let stream = UnixStream::connect(&config.path).await?;
-            let (r, mut writer) = stream.into_split();
-            let mut reader = BufReader::with_capacity(config.buf_size, r);
-            let (reader_fut, rx) = connect_broker!(
-                &config.name,
-                reader,
-                writer,
-                responses,
-                connected,
-                config.timeout,
-                config.queue_size
-            );
-            (
-                Writer::Unix(TtlBufWriter::new(
+        let pressure = Arc::new(atomic::AtomicU8::new(0));
+        #[cfg(feature = "rpc")]
+        let extensions = config
+            .extensions
+            .as_ref()
+            .map(rmp_serde::to_vec_named)
+            .transpose()
+            .map_err(Error::data)?;
+        #[cfg(not(feature = "rpc"))]
+        let extensions: Option<Vec<u8>> = None;
+        let (writer, reader_fut, rx, extensions_reply) = match config.transport() {
+            #[cfg(unix)]
+            Transport::Unix => {
+                let stream = UnixStream::connect(&config.path).await?;
+                let (r, mut writer) = stream.into_split();
+                let mut reader = BufReader::with_capacity(config.buf_size, r);
+                let (reader_fut, rx, extensions_reply) = connect_broker!(
+                    &config.name,
+                    &config.groups,
+                    config.token.as_ref().map(|t| t.0.as_str()),
+                    extensions.as_deref(),
+                    reader,
                     writer,
-                    config.buf_size,
-                    config.buf_ttl,
+                    responses,
+                    connected,
+                    pressure,
                     config.timeout,
-                )),
-                reader_fut,
-                rx,
-            )
-        } else {
-            let stream = TcpStream::connect(&config.path).await?;
-            stream.set_nodelay(true)?;
-            let (r, mut writer) = stream.into_split();
-            let mut reader = BufReader::with_capacity(config.buf_size, r);
-            let (reader_fut, rx) = connect_broker!(
-                &config.name,
-                reader,
-                writer,
-                responses,
-                connected,
-                config.timeout,
-                config.queue_size
-            );
-            (
-                Writer::Tcp(TtlBufWriter::new(
+                    config.queue_size,
+                    config.shm.map(|_| config.shm_dir.clone())
+                );
+                (
+                    Writer::Unix(TtlBufWriter::new(
+                        writer,
+                        config.buf_size,
+                        config.buf_ttl,
+                        config.timeout,
+                    )),
+                    reader_fut,
+                    rx,
+                    extensions_reply,
+                )
+            }
+            #[cfg(windows)]
+            Transport::NamedPipe => {
+                let stream = tokio::time::timeout(config.timeout, connect_named_pipe(&config.path))
+                    .await??;
+                let (r, mut writer) = tokio::io::split(stream);
+                let mut reader = BufReader::with_capacity(config.buf_size, r);
+                let (reader_fut, rx, extensions_reply) = connect_broker!(
+                    &config.name,
+                    &config.groups,
+                    config.token.as_ref().map(|t| t.0.as_str()),
+                    extensions.as_deref(),
+                    reader,
                     writer,
-                    config.buf_size,
-                    config.buf_ttl,
+                    responses,
+                    connected,
+                    pressure,
                     config.timeout,
-                )),
-                reader_fut,
-                rx,
-            )
+                    config.queue_size
+                );
+                (
+                    Writer::NamedPipe(TtlBufWriter::new(
+                        writer,
+                        config.buf_size,
+                        config.buf_ttl,
+                        config.timeout,
+                    )),
+                    reader_fut,
+                    rx,
+                    extensions_reply,
+                )
+            }
+            Transport::Tcp => {
+                let stream = config.tcp_connect(&config.path).await?;
+                let (r, mut writer) = stream.into_split();
+                let mut reader = BufReader::with_capacity(config.buf_size, r);
+                let (reader_fut, rx, extensions_reply) = connect_broker!(
+                    &config.name,
+                    &config.groups,
+                    config.token.as_ref().map(|t| t.0.as_str()),
+                    extensions.as_deref(),
+                    reader,
+                    writer,
+                    responses,
+                    connected,
+                    pressure,
+                    config.timeout,
+                    config.queue_size
+                );
+                (
+                    Writer::Tcp(TtlBufWriter::new(
+                        writer,
+                        config.buf_size,
+                        config.buf_ttl,
+                        config.timeout,
+                    )),
+                    reader_fut,
+                    rx,
+                    extensions_reply,
+                )
+            }
+            #[cfg(feature = "tls")]
+            Transport::Tls(tls) => {
+                let addr = config.path.strip_prefix(TLS_PREFIX).unwrap_or(&config.path);
+                let server_name = tls.server_name_for(addr)?;
+                let connector = tls.connector()?;
+                let stream = config.tcp_connect(addr).await?;
+                let stream =
+                    tokio::time::timeout(config.timeout, connector.connect(server_name, stream))
+                        .await??;
+                let (r, mut writer) = tokio::io::split(stream);
+                let mut reader = BufReader::with_capacity(config.buf_size, r);
+                let (reader_fut, rx, extensions_reply) = connect_broker!(
+                    &config.name,
+                    &config.groups,
+                    config.token.as_ref().map(|t| t.0.as_str()),
+                    extensions.as_deref(),
+                    reader,
+                    writer,
+                    responses,
+                    connected,
+                    pressure,
+                    config.timeout,
+                    config.queue_size
+                );
+                (
+                    Writer::Tls(TtlBufWriter::new(
+                        writer,
+                        config.buf_size,
+                        config.buf_ttl,
+                        config.timeout,
+                    )),
+                    reader_fut,
+                    rx,
+                    extensions_reply,
+                )
+            }
+            #[cfg(feature = "quic")]
+            Transport::Quic(tls) => {
+                let conn = if let Some(ref conn) = quic {
+                    conn.clone()
+                } else {
+                    let addr = config
+                        .path
+                        .strip_prefix(QUIC_PREFIX)
+                        .unwrap_or(&config.path);
+                    let conn = tls.quic_connect(addr, config.timeout).await?;
+                    quic.replace(conn.clone());
+                    conn
+                };
+                let (mut writer, r) = conn.open_bi().await.map_err(Error::io)?;
+                // the stream is not visible to the broker until data is sent
+                writer.write_all(&GREETINGS).await.map_err(Error::io)?;
+                let mut reader = BufReader::with_capacity(config.buf_size, r);
+                let (reader_fut, rx, extensions_reply) = connect_broker!(
+                    &config.name,
+                    &config.groups,
+                    config.token.as_ref().map(|t| t.0.as_str()),
+                    extensions.as_deref(),
+                    reader,
+                    writer,
+                    responses,
+                    connected,
+                    pressure,
+                    config.timeout,
+                    config.queue_size
+                );
+                (
+                    Writer::Quic(TtlBufWriter::new(
+                        writer,
+                        config.buf_size,
+                        config.buf_ttl,
+                        config.timeout,
+                    )),
+                    reader_fut,
+                    rx,
+                    extensions_reply,
+                )
+            }
+            #[cfg(feature = "vsock")]
+            Transport::Vsock => {
+                let (cid, port) = parse_vsock_addr(&config.path)?;
+                let stream = tokio::time::timeout(
+                    config.timeout,
+                    tokio_vsock::VsockStream::connect(cid, port),
+                )
+                .await??;
+                let (r, mut writer) = tokio::io::split(stream);
+                let mut reader = BufReader::with_capacity(config.buf_size, r);
+                let (reader_fut, rx, extensions_reply) = connect_broker!(
+                    &config.name,
+                    &config.groups,
+                    config.token.as_ref().map(|t| t.0.as_str()),
+                    extensions.as_deref(),
+                    reader,
+                    writer,
+                    responses,
+                    connected,
+                    pressure,
+                    config.timeout,
+                    config.queue_size
+                );
+                (
+                    Writer::Vsock(TtlBufWriter::new(
+                        writer,
+                        config.buf_size,
+                        config.buf_ttl,
+                        config.timeout,
+                    )),
+                    reader_fut,
+                    rx,
+                    extensions_reply,
+                )
+            }
         };
-        Ok(Self {
+        let mut client = Self {
             name: config.name.clone(),
             writer,
             reader_fut,
@@ -256,7 +882,123 @@ impl Client {
             timeout: config.timeout,
             config: config.clone(),
             secondary_counter: atomic::AtomicUsize::new(0),
-        })
+            pressure,
+            delivery: false,
+            #[cfg(unix)]
+            shm: config
+                .shm
+                .filter(|_| matches!(config.transport(), Transport::Unix)),
+            subscriptions: <_>::default(),
+            health: None,
+            #[cfg(feature = "quic")]
+            quic,
+            #[cfg(feature = "rpc")]
+            extensions_reply: extensions_reply
+                .filter(|reply| !reply.is_empty())
+                .map(|reply| rmp_serde::from_slice(&reply))
+                .transpose()
+                .map_err(Error::data)?,
+        };
+        #[cfg(not(feature = "rpc"))]
+        drop(extensions_reply);
+        if let Some((max_size, max_delay)) = config.batch {
+            let mut payload = max_size.to_le_bytes().to_vec();
+            let delay = u32::try_from(max_delay.as_micros())
+                .map_err(|_| Error::data("batch delay is too long"))?;
+            payload.extend_from_slice(&delay.to_le_bytes());
+            let result: Result<OpConfirm, Error> =
+                send_frame!(client, payload.as_slice(), FrameOp::Batch, QoS::Processed);
+            if let Some(rx) = result? {
+                tokio::time::timeout(config.timeout, rx)
+                    .await?
+                    .map_err(Error::io)??;
+            }
+        }
+        if config.timestamps {
+            let result: Result<OpConfirm, Error> =
+                send_frame!(client, &[1], FrameOp::Timestamps, QoS::Processed);
+            if let Some(rx) = result? {
+                tokio::time::timeout(config.timeout, rx)
+                    .await?
+                    .map_err(Error::io)??;
+            }
+        }
+        if config.pressure {
+            let result: Result<OpConfirm, Error> =
+                send_frame!(client, &[1], FrameOp::Pressure, QoS::Processed);
+            if let Some(rx) = result? {
+                tokio::time::timeout(config.timeout, rx)
+                    .await?
+                    .map_err(Error::io)??;
+            }
+        }
+        if config.delivery {
+            let result: Result<OpConfirm, Error> =
+                send_frame!(client, &[1], FrameOp::Delivery, QoS::Processed);
+            if let Some(rx) = result? {
+                tokio::time::timeout(config.timeout, rx)
+                    .await?
+                    .map_err(Error::io)??;
+            }
+            client.delivery = true;
+        }
+        Ok(client)
+    }
+    /// Writes the payload to a shared memory file if it exceeds the threshold, returns the
+    /// descriptor, which is sent instead
+    #[cfg_attr(not(unix), allow(clippy::unused_async, unused_variables))]
+    async fn shm_store(&self, payload: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        #[cfg(unix)]
+        if let Some((threshold, ttl)) = self.shm {
+            if payload.len() > threshold {
+                let path = format!(
+                    "{}/elbus-{}-{}",
+                    self.config.shm_dir.trim_end_matches('/'),
+                    std::process::id(),
+                    SHM_COUNTER.fetch_add(1, atomic::Ordering::SeqCst)
+                );
+                tokio::fs::write(&path, payload).await?;
+                let mut descriptor = Vec::with_capacity(SHM_MAGIC.len() + 8 + path.len());
+                descriptor.extend(SHM_MAGIC);
+                descriptor.extend((payload.len() as u64).to_le_bytes());
+                descriptor.extend(path.as_bytes());
+                tokio::spawn(async move {
+                    tokio::time::sleep(ttl).await;
+                    let _r = tokio::fs::remove_file(path).await;
+                });
+                return Ok(Some(descriptor));
+            }
+        }
+        Ok(None)
+    }
+    /// With delivery modes enabled, acknowledged messages carry the mode byte before the target
+    #[inline]
+    fn delivery_mode_byte(&self, op: FrameOp, qos: QoS) -> Option<u8> {
+        (self.delivery && op == FrameOp::Message && qos.needs_ack())
+            .then(|| qos.delivery_mode() as u8)
+    }
+    async fn send_delivery_ack(&mut self, frame: &Frame, code: u8) -> Result<(), Error> {
+        let id = frame
+            .delivery_id()
+            .ok_or_else(|| Error::data("the frame has no delivery id"))?;
+        let mut payload = id.to_le_bytes().to_vec();
+        payload.push(code);
+        let result: Result<OpConfirm, Error> =
+            send_frame!(self, payload.as_slice(), FrameOp::DeliveryAck, QoS::No);
+        result.map(|_| ())
+    }
+    /// The max queue fill (percents) of subscribers, reported by the broker in the last
+    /// acknowledgement of a publication (requires Config::pressure and QoS with confirmation)
+    #[inline]
+    pub fn pressure(&self) -> u8 {
+        self.pressure.load(atomic::Ordering::SeqCst)
+    }
+    /// The broker reply to the hello extensions, none if the extensions have not been sent or
+    /// the broker does not support them
+    #[cfg(feature = "rpc")]
+    #[inline]
+    pub fn extensions_reply(&self) -> Option<&HelloExtensionsReply> {
+        self.extensions_reply.as_ref()
     }
     pub async fn register_secondary(&self) -> Result<Self, Error> {
         if self.name.contains(SECONDARY_SEP) {
@@ -268,8 +1010,33 @@ impl Client {
             let secondary_name = format!("{}{}{}", self.name, SECONDARY_SEP, secondary_id);
             let mut config = self.config.clone();
             config.name = secondary_name;
-            Self::connect(&config).await
+            Self::connect_stream(
+                &config,
+                #[cfg(feature = "quic")]
+                self.quic.clone(),
+            )
+            .await
+        }
+    }
+    /// Topic masks the client is subscribed to, with reference counts
+    #[inline]
+    pub fn subscriptions(&self) -> BTreeMap<String, usize> {
+        self.subscriptions.lock().unwrap().clone()
+    }
+    async fn send_topics(
+        &mut self,
+        topics: &[&str],
+        op: FrameOp,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error> {
+        let mut payload = Vec::new();
+        for topic in topics {
+            if !payload.is_empty() {
+                payload.push(0x00);
+            }
+            payload.extend(topic.as_bytes());
         }
+        send_frame!(self, &payload, op, qos)
     }
     #[inline]
     fn increment_frame_id(&mut self) {
@@ -326,6 +1093,42 @@ impl AsyncClient for Client {
     ) -> Result<OpConfirm, Error> {
         send_frame!(self, target, payload.as_slice(), FrameOp::Broadcast, qos)
     }
+    async fn send_broadcast_mandatory(
+        &mut self,
+        target: &str,
+        payload: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error> {
+        send_frame!(
+            self,
+            target,
+            payload.as_slice(),
+            FrameOp::BroadcastMandatory,
+            qos
+        )
+    }
+    async fn send_group(
+        &mut self,
+        group: &str,
+        payload: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error> {
+        send_frame!(self, group, payload.as_slice(), FrameOp::GroupMessage, qos)
+    }
+    async fn send_group_broadcast(
+        &mut self,
+        group: &str,
+        payload: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error> {
+        send_frame!(
+            self,
+            group,
+            payload.as_slice(),
+            FrameOp::GroupBroadcast,
+            qos
+        )
+    }
     async fn publish(
         &mut self,
         target: &str,
@@ -334,34 +1137,115 @@ impl AsyncClient for Client {
     ) -> Result<OpConfirm, Error> {
         send_frame!(self, target, payload.as_slice(), FrameOp::PublishTopic, qos)
     }
+    async fn publish_mandatory(
+        &mut self,
+        target: &str,
+        payload: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error> {
+        send_frame!(
+            self,
+            target,
+            payload.as_slice(),
+            FrameOp::PublishTopicMandatory,
+            qos
+        )
+    }
+    #[inline]
     async fn subscribe(&mut self, topic: &str, qos: QoS) -> Result<OpConfirm, Error> {
-        send_frame!(self, topic.as_bytes(), FrameOp::SubscribeTopic, qos)
+        self.subscribe_bulk(&[topic], qos).await
     }
+    #[inline]
     async fn unsubscribe(&mut self, topic: &str, qos: QoS) -> Result<OpConfirm, Error> {
-        send_frame!(self, topic.as_bytes(), FrameOp::UnsubscribeTopic, qos)
+        self.unsubscribe_bulk(&[topic], qos).await
     }
+    /// Subscriptions are reference-counted, masks the client is already subscribed to are not
+    /// sent to the broker again. If the broker rejects the subscription, references are
+    /// released when the error is confirmed (with QoS::No the result is unknown to the client)
     async fn subscribe_bulk(&mut self, topics: &[&str], qos: QoS) -> Result<OpConfirm, Error> {
-        let mut payload = Vec::new();
-        for topic in topics {
-            if !payload.is_empty() {
-                payload.push(0x00);
+        let mut added = Vec::new();
+        {
+            let mut subscriptions = self.subscriptions.lock().unwrap();
+            for &topic in topics {
+                let count = subscriptions.entry(topic.to_owned()).or_default();
+                *count += 1;
+                if *count == 1 {
+                    added.push(topic);
+                }
+            }
+        }
+        if added.is_empty() {
+            return Ok(confirmed(qos));
+        }
+        let result = self.send_topics(&added, FrameOp::SubscribeTopic, qos).await;
+        let topics: Vec<String> = topics.iter().map(|&t| t.to_owned()).collect();
+        match result {
+            Ok(confirm) => {
+                let subscriptions = self.subscriptions.clone();
+                let added: Vec<String> = added.into_iter().map(ToOwned::to_owned).collect();
+                Ok(on_confirm_error(confirm, move || {
+                    release_subscriptions(&subscriptions, &topics);
+                    // the masks are not subscribed, even if referenced again while the
+                    // confirmation has been pending
+                    let mut subscriptions = subscriptions.lock().unwrap();
+                    for topic in &added {
+                        subscriptions.remove(topic);
+                    }
+                }))
+            }
+            Err(e) => {
+                release_subscriptions(&self.subscriptions, &topics);
+                Err(e)
             }
-            payload.extend(topic.as_bytes());
         }
-        send_frame!(self, &payload, FrameOp::SubscribeTopic, qos)
     }
+    /// Masks are unsubscribed at the broker when the last reference is released. Masks, unknown
+    /// to the client, are sent as-is. If the broker rejects the request, references are restored
+    /// when the error is confirmed
     async fn unsubscribe_bulk(&mut self, topics: &[&str], qos: QoS) -> Result<OpConfirm, Error> {
-        let mut payload = Vec::new();
-        for topic in topics {
-            if !payload.is_empty() {
-                payload.push(0x00);
+        let mut removed = Vec::new();
+        let mut released = Vec::new();
+        {
+            let mut subscriptions = self.subscriptions.lock().unwrap();
+            for &topic in topics {
+                if let Some(count) = subscriptions.get_mut(topic) {
+                    *count -= 1;
+                    if *count == 0 {
+                        subscriptions.remove(topic);
+                        removed.push(topic);
+                    }
+                    released.push(topic.to_owned());
+                } else {
+                    removed.push(topic);
+                }
+            }
+        }
+        if removed.is_empty() {
+            return Ok(confirmed(qos));
+        }
+        let result = self
+            .send_topics(&removed, FrameOp::UnsubscribeTopic, qos)
+            .await;
+        match result {
+            Ok(confirm) => {
+                let subscriptions = self.subscriptions.clone();
+                Ok(on_confirm_error(confirm, move || {
+                    restore_subscriptions(&subscriptions, &released);
+                }))
+            }
+            Err(e) => {
+                restore_subscriptions(&self.subscriptions, &released);
+                Err(e)
             }
-            payload.extend(topic.as_bytes());
         }
-        send_frame!(self, &payload, FrameOp::UnsubscribeTopic, qos)
     }
     #[inline]
     async fn ping(&mut self) -> Result<(), Error> {
+        if let Some(health) = self.health.clone() {
+            let result: Result<OpConfirm, Error> =
+                send_frame!(self, &health, FrameOp::Heartbeat, QoS::No);
+            return result.map(|_| ());
+        }
         send_data_or_mark_disconnected!(self, PING_FRAME, Flush::Instant);
         Ok(())
     }
@@ -377,6 +1261,33 @@ impl AsyncClient for Client {
     fn get_name(&self) -> &str {
         self.name.as_str()
     }
+    #[inline]
+    async fn confirm(&mut self, frame: &Frame) -> Result<(), Error> {
+        self.send_delivery_ack(frame, RESPONSE_OK).await
+    }
+    #[inline]
+    async fn reject(&mut self, frame: &Frame, kind: ErrorKind) -> Result<(), Error> {
+        self.send_delivery_ack(frame, kind as u8).await
+    }
+    async fn trace(&mut self, id: &str) -> Result<(), Error> {
+        check_trace_id(id)?;
+        let result: Result<OpConfirm, Error> =
+            send_frame!(self, id.as_bytes(), FrameOp::Trace, QoS::No);
+        result.map(|_| ())
+    }
+    async fn set_health(&mut self, status: &str, load: u8) -> Result<(), Error> {
+        self.health.replace(health_payload(status, load)?);
+        self.ping().await
+    }
+    async fn begin_transaction(&mut self, qos: QoS) -> Result<OpConfirm, Error> {
+        send_frame!(self, &[TRANSACTION_BEGIN], FrameOp::Transaction, qos)
+    }
+    async fn commit_transaction(&mut self, qos: QoS) -> Result<OpConfirm, Error> {
+        send_frame!(self, &[TRANSACTION_COMMIT], FrameOp::Transaction, qos)
+    }
+    async fn abort_transaction(&mut self, qos: QoS) -> Result<OpConfirm, Error> {
+        send_frame!(self, &[TRANSACTION_ABORT], FrameOp::Transaction, qos)
+    }
 }
 
 impl Drop for Client {
@@ -385,11 +1296,64 @@ impl Drop for Client {
     }
 }
 
+/// Returns a confirmation for operations, which are completed locally
+fn release_subscriptions(subscriptions: &SubscriptionMap, topics: &[String]) {
+    let mut subscriptions = subscriptions.lock().unwrap();
+    for topic in topics {
+        if let btree_map::Entry::Occupied(mut entry) = subscriptions.entry(topic.clone()) {
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+    }
+}
+
+fn restore_subscriptions(subscriptions: &SubscriptionMap, topics: &[String]) {
+    let mut subscriptions = subscriptions.lock().unwrap();
+    for topic in topics {
+        *subscriptions.entry(topic.clone()).or_default() += 1;
+    }
+}
+
+/// Calls the function if the operation is failed by the broker, the result is passed to the
+/// returned confirmation
+fn on_confirm_error<F>(confirm: OpConfirm, f: F) -> OpConfirm
+where
+    F: FnOnce() + Send + 'static,
+{
+    let rx = confirm?;
+    let (tx, result_rx) = oneshot::channel();
+    tokio::spawn(async move {
+        // if the sender is dropped (the client is disconnected), the returned receiver gets the
+        // same error
+        if let Ok(result) = rx.await {
+            if result.is_err() {
+                f();
+            }
+            let _r = tx.send(result);
+        }
+    });
+    Some(result_rx)
+}
+
+fn confirmed(qos: QoS) -> OpConfirm {
+    if qos.needs_ack() {
+        let (tx, rx) = oneshot::channel();
+        let _r = tx.send(Ok(()));
+        Some(rx)
+    } else {
+        None
+    }
+}
+
 async fn handle_read<R>(
     mut reader: R,
     tx: async_channel::Sender<Frame>,
     timeout: Duration,
     responses: ResponseMap,
+    pressure: Arc<atomic::AtomicU8>,
+    shm_dir: Option<String>,
 ) -> Result<(), Error>
 where
     R: AsyncReadExt + Unpin,
@@ -398,64 +1362,253 @@ where
         let mut buf = vec![0; 6];
         reader.read_exact(&mut buf).await?;
         let frame_type: FrameKind = buf[0].try_into()?;
-        let realtime = buf[5] != 0;
         match frame_type {
             FrameKind::Nop => {}
-            FrameKind::Acknowledge => {
-                let ack_id = u32::from_le_bytes(buf[1..5].try_into().unwrap());
-                let tx_channel = { responses.lock().unwrap().remove(&ack_id) };
-                if let Some(tx) = tx_channel {
-                    let _r = tx.send(buf[5].to_elbus_result());
-                } else {
-                    warn!("orphaned elbus op ack {}", ack_id);
-                }
+            FrameKind::Acknowledge => process_ack(&buf, &responses),
+            FrameKind::AcknowledgePressure => {
+                let mut p = [0; 1];
+                tokio::time::timeout(timeout, reader.read_exact(&mut p)).await??;
+                pressure.store(p[0], atomic::Ordering::SeqCst);
+                process_ack(&buf, &responses);
             }
             _ => {
                 let frame_len = u32::from_le_bytes(buf[1..5].try_into().unwrap());
-                let mut buf = vec![0; frame_len as usize];
-                tokio::time::timeout(timeout, reader.read_exact(&mut buf)).await??;
-                let (sender, topic, payload_pos) = {
-                    if frame_type == FrameKind::Publish {
-                        let mut sp = buf.splitn(3, |c| *c == 0);
-                        let s = sp.next().ok_or_else(|| Error::data("broken frame"))?;
-                        let sender = std::str::from_utf8(s)?.to_owned();
-                        let t = sp.next().ok_or_else(|| Error::data("broken frame"))?;
-                        let topic = std::str::from_utf8(t)?.to_owned();
-                        sp.next().ok_or_else(|| Error::data("broken frame"))?;
-                        let payload_pos = s.len() + t.len() + 2;
-                        (Some(sender), Some(topic), payload_pos)
-                    } else {
-                        let mut sp = buf.splitn(2, |c| *c == 0);
-                        let s = sp.next().ok_or_else(|| Error::data("broken frame"))?;
-                        let sender = std::str::from_utf8(s)?.to_owned();
-                        sp.next().ok_or_else(|| Error::data("broken frame"))?;
-                        let payload_pos = s.len() + 1;
-                        (Some(sender), None, payload_pos)
+                let mut body = vec![0; frame_len as usize];
+                tokio::time::timeout(timeout, reader.read_exact(&mut body)).await??;
+                if frame_type == FrameKind::Batch {
+                    for (kind, header, data) in split_batch(&body)? {
+                        match kind {
+                            FrameKind::Nop => {}
+                            FrameKind::Acknowledge => process_ack(header, &responses),
+                            FrameKind::AcknowledgePressure => {
+                                pressure.store(data[0], atomic::Ordering::SeqCst);
+                                process_ack(header, &responses);
+                            }
+                            tp => {
+                                let frame = parse_frame(tp, header[5], data.to_vec())?;
+                                if let Some(frame) = resolve_frame(frame, shm_dir.as_deref()).await
+                                {
+                                    tx.send(frame).await.map_err(Error::io)?;
+                                }
+                            }
+                        }
                     }
-                };
-                let frame = Arc::new(FrameData::new(
-                    frame_type,
-                    sender,
-                    topic,
-                    None,
-                    buf,
-                    payload_pos,
-                    realtime,
-                ));
-                tx.send(frame).await.map_err(Error::io)?;
+                } else {
+                    let frame = parse_frame(frame_type, buf[5], body)?;
+                    if let Some(frame) = resolve_frame(frame, shm_dir.as_deref()).await {
+                        tx.send(frame).await.map_err(Error::io)?;
+                    }
+                }
             }
         }
     }
 }
 
-async fn chat<R, W>(name: &str, reader: &mut R, writer: &mut W) -> Result<(), Error>
+/// A frame, packed into a batch: the kind, the 6-byte header and the body
+pub(crate) type BatchItem<'a> = (FrameKind, &'a [u8], &'a [u8]);
+
+/// Splits a batch frame body into frames. Acknowledgements have no bodies, except the pressure
+/// byte of pressure ones
+pub(crate) fn split_batch(body: &[u8]) -> Result<Vec<BatchItem<'_>>, Error> {
+    let mut frames = Vec::new();
+    let mut pos = 0;
+    while pos < body.len() {
+        let header = body
+            .get(pos..pos + 6)
+            .ok_or_else(|| Error::data("broken batch frame"))?;
+        pos += 6;
+        let kind: FrameKind = header[0].try_into()?;
+        let len = match kind {
+            FrameKind::Acknowledge => 0,
+            FrameKind::AcknowledgePressure => 1,
+            FrameKind::Batch => return Err(Error::data("nested batch frame")),
+            _ => u32::from_le_bytes(header[1..5].try_into().unwrap()) as usize,
+        };
+        let data = body
+            .get(pos..pos + len)
+            .ok_or_else(|| Error::data("broken batch frame"))?;
+        pos += len;
+        frames.push((kind, header, data));
+    }
+    Ok(frames)
+}
+
+/// Resolves shared memory payloads if enabled, frames, which can not be resolved, are dropped
+async fn resolve_frame(frame: Frame, shm_dir: Option<&str>) -> Option<Frame> {
+    let Some(dir) = shm_dir else {
+        return Some(frame);
+    };
+    match resolve_shm(frame, dir).await {
+        Ok(frame) => Some(frame),
+        Err(e) => {
+            error!("elbus shared memory payload dropped: {}", e);
+            None
+        }
+    }
+}
+
+/// Replaces the shared memory descriptor with the payload, other frames are returned as-is.
+/// Files outside of the shared memory directory are not read
+async fn resolve_shm(frame: Frame, shm_dir: &str) -> Result<Frame, Error> {
+    let payload = frame.payload();
+    let pos = SHM_MAGIC.len() + 8;
+    if payload.len() <= pos || payload[..SHM_MAGIC.len()] != SHM_MAGIC {
+        return Ok(frame);
+    }
+    let size = u64::from_le_bytes(payload[SHM_MAGIC.len()..pos].try_into().unwrap());
+    let path = Path::new(std::str::from_utf8(&payload[pos..])?);
+    if path.parent() != Some(Path::new(shm_dir)) {
+        return Err(Error::access(format!(
+            "{} is outside of the shared memory directory",
+            path.display()
+        )));
+    }
+    let data = tokio::fs::read(path).await?;
+    if data.len() as u64 != size {
+        return Err(Error::data(format!(
+            "{} size mismatch, expected {} bytes",
+            path.display(),
+            size
+        )));
+    }
+    let mut resolved = FrameData::new(
+        frame.kind,
+        frame.sender.clone(),
+        frame.topic.clone(),
+        None,
+        data,
+        0,
+        frame.realtime,
+    );
+    resolved.timestamp = frame.timestamp;
+    resolved.delivery = frame.delivery;
+    Ok(Arc::new(resolved))
+}
+
+fn process_ack(header: &[u8], responses: &ResponseMap) {
+    let ack_id = u32::from_le_bytes(header[1..5].try_into().unwrap());
+    let tx_channel = { responses.lock().unwrap().remove(&ack_id) };
+    if let Some(tx) = tx_channel {
+        let _r = tx.send(header[5].to_elbus_result());
+    } else {
+        warn!("orphaned elbus op ack {}", ack_id);
+    }
+}
+
+pub(crate) fn parse_frame(frame_type: FrameKind, flags: u8, buf: Vec<u8>) -> Result<Frame, Error> {
+    let (timestamp, start) = if flags & FRAME_FLAG_TIMESTAMP == 0 {
+        (None, 0)
+    } else {
+        let ts = buf
+            .get(..8)
+            .ok_or_else(|| Error::data("broken frame"))?
+            .try_into()
+            .unwrap();
+        (Some(u64::from_le_bytes(ts)), 8)
+    };
+    let (delivery_id, start) = if flags & FRAME_FLAG_DELIVERY == 0 {
+        (None, start)
+    } else {
+        let id = buf
+            .get(start..start + 4)
+            .ok_or_else(|| Error::data("broken frame"))?
+            .try_into()
+            .unwrap();
+        (Some(u32::from_le_bytes(id)), start + 4)
+    };
+    let (sender, topic, payload_pos) = {
+        if frame_type == FrameKind::Publish {
+            let mut sp = buf[start..].splitn(3, |c| *c == 0);
+            let s = sp.next().ok_or_else(|| Error::data("broken frame"))?;
+            let sender = std::str::from_utf8(s)?.to_owned();
+            let t = sp.next().ok_or_else(|| Error::data("broken frame"))?;
+            let topic = std::str::from_utf8(t)?.to_owned();
+            sp.next().ok_or_else(|| Error::data("broken frame"))?;
+            let payload_pos = start + s.len() + t.len() + 2;
+            (Some(sender), Some(topic), payload_pos)
+        } else {
+            let mut sp = buf[start..].splitn(2, |c| *c == 0);
+            let s = sp.next().ok_or_else(|| Error::data("broken frame"))?;
+            let sender = std::str::from_utf8(s)?.to_owned();
+            sp.next().ok_or_else(|| Error::data("broken frame"))?;
+            let payload_pos = start + s.len() + 1;
+            (Some(sender), None, payload_pos)
+        }
+    };
+    let mut frame = FrameData::new(
+        frame_type,
+        sender,
+        topic,
+        None,
+        buf,
+        payload_pos,
+        flags & FRAME_FLAG_REALTIME != 0,
+    );
+    frame.timestamp = timestamp;
+    frame.delivery = delivery_id.map(|id| (id, DeliveryMode::Consumed));
+    Ok(Arc::new(frame))
+}
+
+/// Encodes the client hello: LEN (u16 LE, with the token and extension flags) NAME [0x00 GROUP]..
+/// [TOKEN_LEN (u16 LE) TOKEN] [EXT_LEN (u32 LE) EXTENSIONS]
+pub(crate) fn hello(
+    name: &str,
+    groups: &[String],
+    token: Option<&str>,
+    extensions: Option<&[u8]>,
+) -> Result<Vec<u8>, Error> {
+    // groups are sent after the name, separated with zero bytes
+    let mut n = name.as_bytes().to_vec();
+    for group in groups {
+        if group.is_empty() || group.contains('\0') {
+            return Err(Error::data(format!("invalid group name: {:?}", group)));
+        }
+        n.push(0x00);
+        n.extend_from_slice(group.as_bytes());
+    }
+    if n.len() >= HELLO_TOKEN_FLAG as usize {
+        return Err(Error::data("name too long"));
+    }
+    if token.is_some_and(|t| t.is_empty() || t.len() > HELLO_TOKEN_MAX_LEN) {
+        return Err(Error::data("invalid token"));
+    }
+    if extensions.is_some_and(|ext| ext.len() > HELLO_EXTENSIONS_MAX_SIZE as usize) {
+        return Err(Error::data("hello extensions too long"));
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    let mut len = n.len() as u16;
+    // the token is sent after the name, before the extension block
+    if let Some(token) = token {
+        len |= HELLO_TOKEN_FLAG;
+        #[allow(clippy::cast_possible_truncation)]
+        n.extend_from_slice(&(token.len() as u16).to_le_bytes());
+        n.extend_from_slice(token.as_bytes());
+    }
+    if let Some(ext) = extensions {
+        len |= HELLO_EXTENSIONS_FLAG;
+        #[allow(clippy::cast_possible_truncation)]
+        n.extend_from_slice(&(ext.len() as u32).to_le_bytes());
+        n.extend_from_slice(ext);
+    }
+    let mut buf = len.to_le_bytes().to_vec();
+    buf.extend(n);
+    Ok(buf)
+}
+
+/// Returns the broker reply to the hello extensions if sent (empty if not supported)
+async fn chat<R, W>(
+    name: &str,
+    groups: &[String],
+    token: Option<&str>,
+    extensions: Option<&[u8]>,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<Option<Vec<u8>>, Error>
 where
     R: AsyncReadExt + Unpin,
     W: AsyncWriteExt + Unpin,
 {
-    if name.len() > u16::MAX as usize {
-        return Err(Error::data("name too long"));
-    }
+    let hello = hello(name, groups, token, extensions)?;
     let mut buf = vec![0; 3];
     reader.read_exact(&mut buf).await?;
     if buf[0] != GREETINGS[0] {
@@ -473,10 +1626,7 @@ where
             Some(format!("Server greetings response: {:?}", buf[0])),
         ));
     }
-    let n = name.as_bytes().to_vec();
-    #[allow(clippy::cast_possible_truncation)]
-    writer.write_all(&(name.len() as u16).to_le_bytes()).await?;
-    writer.write_all(&n).await?;
+    writer.write_all(&hello).await?;
     let mut buf = vec![0; 1];
     reader.read_exact(&mut buf).await?;
     if buf[0] != RESPONSE_OK {
@@ -485,5 +1635,17 @@ where
             Some(format!("Server registration response: {:?}", buf[0])),
         ));
     }
-    Ok(())
+    if extensions.is_some() {
+        let mut len = [0; 4];
+        reader.read_exact(&mut len).await?;
+        let len = u32::from_le_bytes(len);
+        if len > HELLO_EXTENSIONS_MAX_SIZE {
+            return Err(Error::data("hello extensions reply too long"));
+        }
+        let mut reply = vec![0; len as usize];
+        reader.read_exact(&mut reply).await?;
+        Ok(Some(reply))
+    } else {
+        Ok(None)
+    }
 }
@@ -6,12 +6,52 @@ pub const OP_NOP: u8 = 0x00;
 pub const OP_PUBLISH: u8 = 0x01;
 pub const OP_SUBSCRIBE: u8 = 0x02;
 pub const OP_UNSUBSCRIBE: u8 = 0x03;
+pub const OP_PUBLISH_MANDATORY: u8 = 0x04;
 pub const OP_MESSAGE: u8 = 0x12;
 pub const OP_BROADCAST: u8 = 0x13;
+pub const OP_GROUP_MESSAGE: u8 = 0x14;
+pub const OP_GROUP_BROADCAST: u8 = 0x15;
+pub const OP_BROADCAST_MANDATORY: u8 = 0x16;
+pub const OP_BATCH: u8 = 0x20;
+pub const OP_TIMESTAMPS: u8 = 0x21;
+pub const OP_PRESSURE: u8 = 0x22;
+/// enables delivery modes of messages (QoS::Delivered, QoS::Consumed) for the connection
+pub const OP_DELIVERY: u8 = 0x23;
+/// confirms (or rejects) a frame, received with a delivery id
+pub const OP_DELIVERY_ACK: u8 = 0x24;
+/// traces routing of the next message, broadcast or publication of the client
+pub const OP_TRACE: u8 = 0x25;
+/// begins, commits or aborts a transaction of routed frames
+pub const OP_TRANSACTION: u8 = 0x26;
+/// ping with the client health (the load and the status)
+pub const OP_HEARTBEAT: u8 = 0x27;
 pub const OP_ACK: u8 = 0xFE;
+/// acknowledgement, followed by the queue pressure byte
+pub const OP_ACK_PRESSURE: u8 = 0xFD;
+
+/// incoming frame flags (the reserved byte): real-time frame
+pub const FRAME_FLAG_REALTIME: u8 = 0b01;
+/// the frame body starts with the broker receive timestamp (u64 LE, nanoseconds)
+pub const FRAME_FLAG_TIMESTAMP: u8 = 0b10;
+/// the frame body continues (after the timestamp) with the delivery id (u32 LE), the frame must
+/// be confirmed by the recipient
+pub const FRAME_FLAG_DELIVERY: u8 = 0b100;
 
 pub const PROTOCOL_VERSION: u16 = 0x01;
 
+/// Max length of frame trace (correlation) ids
+pub const TRACE_ID_MAX_LEN: usize = 255;
+
+/// OP_TRANSACTION payloads
+pub const TRANSACTION_ABORT: u8 = 0x00;
+pub const TRANSACTION_BEGIN: u8 = 0x01;
+pub const TRANSACTION_COMMIT: u8 = 0x02;
+
+/// Max length of client health statuses
+pub const HEALTH_STATUS_MAX_LEN: usize = 255;
+/// Client health loads are percents
+pub const HEALTH_LOAD_MAX: u8 = 100;
+
 pub const RESPONSE_OK: u8 = 0x01;
 
 pub const PING_FRAME: &[u8] = &[0, 0, 0, 0, 0, 0, 0, 0, 0];
@@ -25,9 +65,23 @@ pub const ERR_BUSY: u8 = 0x76;
 pub const ERR_NOT_DELIVERED: u8 = 0x77;
 pub const ERR_TIMEOUT: u8 = 0x78;
 pub const ERR_ACCESS: u8 = 0x79;
+pub const ERR_LIMIT: u8 = 0x7A;
+pub const ERR_UNROUTED: u8 = 0x7B;
 
 pub const GREETINGS: [u8; 1] = [0xEB];
 
+/// Set in the client name length, if the name is followed by the hello extension block
+pub const HELLO_EXTENSIONS_FLAG: u16 = 0x8000;
+/// The hello extension block version, supported by the crate
+pub const HELLO_EXTENSIONS_VERSION: u8 = 1;
+/// Max size of the hello extension block
+pub const HELLO_EXTENSIONS_MAX_SIZE: u32 = 65536;
+/// Set in the client name length, if the name is followed by the token (u16 LE length and
+/// the token)
+pub const HELLO_TOKEN_FLAG: u16 = 0x4000;
+/// Max length of client tokens
+pub const HELLO_TOKEN_MAX_LEN: usize = 1024;
+
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 pub static AUTHOR: &str = "(c) 2022 Bohemia Automation / Altertech";
@@ -38,6 +92,12 @@ pub const DEFAULT_BUF_SIZE: usize = 8192;
 
 pub const DEFAULT_QUEUE_SIZE: usize = 8192;
 
+/// Max size of a batch frame, the broker packs queued frames into, when the client enables
+/// batching
+pub const BATCH_MAX_SIZE: u32 = 1024 * 1024;
+/// Max time (microseconds) the broker can wait for more frames to fill a batch
+pub const BATCH_MAX_DELAY: u32 = 1_000_000;
+
 pub const SECONDARY_SEP: &str = "%%";
 
 /// When a frame is sent, methods do not wait for the result, but they return OpConfirm type to let
@@ -73,6 +133,8 @@ pub enum ErrorKind {
     Busy = ERR_BUSY,
     NotDelivered = ERR_NOT_DELIVERED,
     Access = ERR_ACCESS,
+    Limit = ERR_LIMIT,
+    Unrouted = ERR_UNROUTED,
     Other = ERR_OTHER,
     Eof = 0xff,
 }
@@ -87,6 +149,8 @@ impl From<u8> for ErrorKind {
             ERR_BUSY => ErrorKind::Busy,
             ERR_NOT_DELIVERED => ErrorKind::NotDelivered,
             ERR_ACCESS => ErrorKind::Access,
+            ERR_LIMIT => ErrorKind::Limit,
+            ERR_UNROUTED => ErrorKind::Unrouted,
             _ => ErrorKind::Other,
         }
     }
@@ -107,6 +171,8 @@ impl fmt::Display for ErrorKind {
                 ErrorKind::NotDelivered => "Frame not delivered",
                 ErrorKind::Other => "Error",
                 ErrorKind::Access => "Access denied",
+                ErrorKind::Limit => "Limit exceeded",
+                ErrorKind::Unrouted => "Frame not routed",
                 ErrorKind::Eof => "Eof",
             }
         )
@@ -159,6 +225,13 @@ impl Error {
         }
     }
     #[inline]
+    pub fn limit(e: impl fmt::Display) -> Self {
+        Self {
+            kind: ErrorKind::Limit,
+            message: Some(e.to_string()),
+        }
+    }
+    #[inline]
     pub fn not_supported(e: impl fmt::Display) -> Self {
         Self {
             kind: ErrorKind::NotSupported,
@@ -180,6 +253,13 @@ impl Error {
         }
     }
     #[inline]
+    pub fn unrouted() -> Self {
+        Self {
+            kind: ErrorKind::Unrouted,
+            message: None,
+        }
+    }
+    #[inline]
     pub fn timeout() -> Self {
         Self {
             kind: ErrorKind::Timeout,
@@ -285,15 +365,39 @@ impl From<tokio::sync::oneshot::error::RecvError> for Error {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
 #[repr(u8)]
 pub enum FrameOp {
     Nop = OP_NOP,
     Message = OP_MESSAGE,
     Broadcast = OP_BROADCAST,
+    /// broadcast, unrouted if no clients match the mask
+    BroadcastMandatory = OP_BROADCAST_MANDATORY,
+    /// to one member of the group (round-robin)
+    GroupMessage = OP_GROUP_MESSAGE,
+    /// to all members of the group
+    GroupBroadcast = OP_GROUP_BROADCAST,
     PublishTopic = OP_PUBLISH,
+    /// publish, unrouted if the topic has no subscribers
+    PublishTopicMandatory = OP_PUBLISH_MANDATORY,
     SubscribeTopic = OP_SUBSCRIBE,
     UnsubscribeTopic = OP_UNSUBSCRIBE,
+    /// sets the batching mode of incoming frames
+    Batch = OP_BATCH,
+    /// asks the broker to deliver receive timestamps of frames
+    Timestamps = OP_TIMESTAMPS,
+    /// asks the broker to report queue pressure in acknowledgements of publications
+    Pressure = OP_PRESSURE,
+    /// enables delivery modes of messages
+    Delivery = OP_DELIVERY,
+    /// confirms a frame, received with a delivery id
+    DeliveryAck = OP_DELIVERY_ACK,
+    /// traces routing of the next frame
+    Trace = OP_TRACE,
+    /// begins, commits or aborts a transaction
+    Transaction = OP_TRANSACTION,
+    /// ping with the client health
+    Heartbeat = OP_HEARTBEAT,
 }
 
 impl TryFrom<u8> for FrameOp {
@@ -303,21 +407,45 @@ impl TryFrom<u8> for FrameOp {
             OP_NOP => Ok(FrameOp::Nop),
             OP_MESSAGE => Ok(FrameOp::Message),
             OP_BROADCAST => Ok(FrameOp::Broadcast),
+            OP_BROADCAST_MANDATORY => Ok(FrameOp::BroadcastMandatory),
+            OP_GROUP_MESSAGE => Ok(FrameOp::GroupMessage),
+            OP_GROUP_BROADCAST => Ok(FrameOp::GroupBroadcast),
             OP_PUBLISH => Ok(FrameOp::PublishTopic),
+            OP_PUBLISH_MANDATORY => Ok(FrameOp::PublishTopicMandatory),
             OP_SUBSCRIBE => Ok(FrameOp::SubscribeTopic),
             OP_UNSUBSCRIBE => Ok(FrameOp::UnsubscribeTopic),
+            OP_BATCH => Ok(FrameOp::Batch),
+            OP_TIMESTAMPS => Ok(FrameOp::Timestamps),
+            OP_PRESSURE => Ok(FrameOp::Pressure),
+            OP_DELIVERY => Ok(FrameOp::Delivery),
+            OP_DELIVERY_ACK => Ok(FrameOp::DeliveryAck),
+            OP_TRACE => Ok(FrameOp::Trace),
+            OP_TRANSACTION => Ok(FrameOp::Transaction),
+            OP_HEARTBEAT => Ok(FrameOp::Heartbeat),
             _ => Err(Error::data(format!("Invalid frame type: {}", tp))),
         }
     }
 }
 
+/// Bits 0-1 are sent in frame flags (the acknowledgement and real-time delivery), bits 2-3 set
+/// the delivery mode of acknowledged messages
 #[derive(Debug, Copy, Clone)]
 #[repr(u8)]
 pub enum QoS {
     No = 0,
+    /// acknowledged when the frame is queued for the recipient
     Processed = 1,
     Realtime = 2,
     RealtimeProcessed = 3,
+    /// acknowledged when the frame is written to the recipient connection (messages only,
+    /// requires ipc::Config::delivery for IPC clients)
+    Delivered = 0b0101,
+    RealtimeDelivered = 0b0111,
+    /// acknowledged when the recipient application confirms the frame with
+    /// AsyncClient::confirm (messages only, requires ipc::Config::delivery for IPC clients and
+    /// recipients)
+    Consumed = 0b1001,
+    RealtimeConsumed = 0b1011,
 }
 
 impl QoS {
@@ -329,6 +457,19 @@ impl QoS {
     pub fn needs_ack(self) -> bool {
         self as u8 & 0b1 != 0
     }
+    /// The bits, sent in frame flags
+    #[inline]
+    pub fn wire_bits(self) -> u8 {
+        self as u8 & 0b11
+    }
+    #[inline]
+    pub fn delivery_mode(self) -> DeliveryMode {
+        match self as u8 >> 2 {
+            1 => DeliveryMode::Delivered,
+            2 => DeliveryMode::Consumed,
+            _ => DeliveryMode::Queued,
+        }
+    }
 }
 
 impl TryFrom<u8> for QoS {
@@ -339,11 +480,37 @@ impl TryFrom<u8> for QoS {
             1 => Ok(QoS::Processed),
             2 => Ok(QoS::Realtime),
             3 => Ok(QoS::RealtimeProcessed),
+            5 => Ok(QoS::Delivered),
+            7 => Ok(QoS::RealtimeDelivered),
+            9 => Ok(QoS::Consumed),
+            11 => Ok(QoS::RealtimeConsumed),
             _ => Err(Error::data(format!("Invalid QoS: {}", q))),
         }
     }
 }
 
+/// When an acknowledged message is confirmed to the sender. With delivery modes negotiated, the
+/// mode byte is sent before the target of acknowledged messages
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[repr(u8)]
+pub enum DeliveryMode {
+    Queued = 0,
+    Delivered = 1,
+    Consumed = 2,
+}
+
+impl TryFrom<u8> for DeliveryMode {
+    type Error = Error;
+    fn try_from(m: u8) -> Result<Self, Error> {
+        match m {
+            0 => Ok(DeliveryMode::Queued),
+            1 => Ok(DeliveryMode::Delivered),
+            2 => Ok(DeliveryMode::Consumed),
+            _ => Err(Error::data(format!("Invalid delivery mode: {}", m))),
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 #[repr(u8)]
 pub enum FrameKind {
@@ -352,7 +519,11 @@ pub enum FrameKind {
     Broadcast = OP_BROADCAST,
     Publish = OP_PUBLISH,
     Acknowledge = OP_ACK,
+    /// acknowledgement with the queue pressure of the publication subscribers
+    AcknowledgePressure = OP_ACK_PRESSURE,
     Nop = OP_NOP,
+    /// multiple frames, packed by the broker into a single wire frame
+    Batch = OP_BATCH,
 }
 
 impl TryFrom<u8> for FrameKind {
@@ -363,13 +534,17 @@ impl TryFrom<u8> for FrameKind {
             OP_BROADCAST => Ok(FrameKind::Broadcast),
             OP_PUBLISH => Ok(FrameKind::Publish),
             OP_ACK => Ok(FrameKind::Acknowledge),
+            OP_ACK_PRESSURE => Ok(FrameKind::AcknowledgePressure),
             OP_NOP => Ok(FrameKind::Nop),
+            OP_BATCH => Ok(FrameKind::Batch),
             _ => Err(Error::data(format!("Invalid frame type: {:x}", code))),
         }
     }
 }
 
-#[derive(Debug)]
+/// Max payload bytes, shown by the frame Debug and Display implementations
+pub const FRAME_PREVIEW_LEN: usize = 64;
+
 pub struct FrameData {
     kind: FrameKind,
     sender: Option<String>,
@@ -378,6 +553,15 @@ pub struct FrameData {
     buf: Vec<u8>,
     payload_pos: usize,
     realtime: bool,
+    identity: Option<Arc<ClientIdentity>>,
+    // broker receive time, nanoseconds since the UNIX epoch
+    timestamp: Option<u64>,
+    // the delivery id and mode of messages, acknowledged after delivery or confirmation
+    delivery: Option<(u32, DeliveryMode)>,
+    // broker memory accounting, released when the frame is dropped
+    #[cfg(feature = "broker")]
+    #[allow(dead_code)]
+    memory: Option<broker::FrameMemory>,
 }
 
 impl FrameData {
@@ -399,6 +583,11 @@ impl FrameData {
             buf,
             payload_pos,
             realtime,
+            identity: None,
+            timestamp: None,
+            delivery: None,
+            #[cfg(feature = "broker")]
+            memory: None,
         }
     }
     #[inline]
@@ -411,6 +600,11 @@ impl FrameData {
             buf: Vec::new(),
             payload_pos: 0,
             realtime: false,
+            identity: None,
+            timestamp: None,
+            delivery: None,
+            #[cfg(feature = "broker")]
+            memory: None,
         }
     }
     #[inline]
@@ -459,23 +653,321 @@ impl FrameData {
     pub fn is_realtime(&self) -> bool {
         self.realtime
     }
+    /// The sender identity, attached by the broker. Filled for inter-thread communications
+    /// only, the sender name of frames, received via IPC, can not be verified by the receiver
+    #[inline]
+    pub fn sender_identity(&self) -> Option<&ClientIdentity> {
+        self.identity.as_deref()
+    }
+    /// The time (nanoseconds since the UNIX epoch), when the frame was received by the broker.
+    /// Filled if the broker stamps frames, IPC clients must request timestamps in the config
+    #[inline]
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+    /// Set if the frame has been sent with QoS::Consumed, the recipient must confirm it with
+    /// AsyncClient::confirm (or reject)
+    #[inline]
+    pub fn delivery_id(&self) -> Option<u32> {
+        self.delivery
+            .filter(|(_, mode)| *mode == DeliveryMode::Consumed)
+            .map(|(id, _)| id)
+    }
+    /// The payload as a string
+    ///
+    /// # Errors
+    ///
+    /// Data - if the payload is not valid UTF-8
+    #[inline]
+    pub fn as_str(&self) -> Result<&str, Error> {
+        std::str::from_utf8(self.payload()).map_err(Error::data)
+    }
+    /// Deserializes a MessagePack payload. Frames carry no content type, JSON payloads can be
+    /// decoded with unpack_as
+    #[cfg(feature = "rpc")]
+    #[inline]
+    pub fn unpack<'de, T: serde::Deserialize<'de>>(&'de self) -> Result<T, Error> {
+        self.unpack_as(rpc::ContentType::MsgPack)
+    }
+    /// Deserializes the payload of the content type
+    #[cfg(feature = "rpc")]
+    pub fn unpack_as<'de, T: serde::Deserialize<'de>>(
+        &'de self,
+        content_type: rpc::ContentType,
+    ) -> Result<T, Error> {
+        match content_type {
+            rpc::ContentType::MsgPack => rmp_serde::from_slice(self.payload()).map_err(Error::data),
+            rpc::ContentType::Json => serde_json::from_slice(self.payload()).map_err(Error::data),
+        }
+    }
+    /// Returns the payload preview, truncated to max_len bytes: valid UTF-8 payloads are
+    /// returned as strings, others are hex-encoded
+    pub fn payload_preview(&self, max_len: usize) -> String {
+        let payload = self.payload();
+        let truncated = payload.len() > max_len;
+        let mut preview = match std::str::from_utf8(payload) {
+            Ok(s) if !s.bytes().any(|c| c < 9) => {
+                let mut pos = max_len.min(s.len());
+                while !s.is_char_boundary(pos) {
+                    pos -= 1;
+                }
+                format!("{:?}", &s[..pos])
+            }
+            _ => payload[..max_len.min(payload.len())]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+        };
+        if truncated {
+            preview.push_str("...");
+        }
+        preview
+    }
+}
+
+impl fmt::Debug for FrameData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameData")
+            .field("kind", &self.kind)
+            .field("sender", &self.sender)
+            .field("topic", &self.topic)
+            .field("header", &self.header.as_ref().map(Vec::len))
+            .field("payload_len", &self.payload().len())
+            .field(
+                "payload",
+                &format_args!("{}", self.payload_preview(FRAME_PREVIEW_LEN)),
+            )
+            .field("realtime", &self.realtime)
+            .field("identity", &self.identity)
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
+/// Prints the frame kind, the sender, the topic (for publications), the payload size and the
+/// truncated payload
+impl fmt::Display for FrameData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.kind)?;
+        if let Some(ref sender) = self.sender {
+            write!(f, " from {}", sender)?;
+        }
+        if let Some(ref topic) = self.topic {
+            write!(f, " to {}", topic)?;
+        }
+        let payload = self.payload();
+        if payload.is_empty() {
+            write!(f, ", empty")
+        } else {
+            write!(
+                f,
+                ", {} byte(s): {}",
+                payload.len(),
+                self.payload_preview(FRAME_PREVIEW_LEN)
+            )
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ClientKind {
+    Internal,
+    LocalIpc,
+    Tcp,
+}
+
+impl ClientKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ClientKind::Internal => "internal",
+            ClientKind::LocalIpc => "local_ipc",
+            ClientKind::Tcp => "tcp",
+        }
+    }
+}
+
+impl fmt::Display for ClientKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Client access control list, checked by the broker
+pub trait ClientAcl: Send + Sync + fmt::Debug {
+    fn p2p_allowed(&self, target: &str) -> bool;
+    fn publish_allowed(&self, topic: &str) -> bool;
+    fn subscribe_allowed(&self, topic: &str) -> bool;
+    fn broadcast_allowed(&self, target: &str) -> bool;
+    /// Monitoring of the broker traffic
+    fn wiretap_allowed(&self) -> bool;
+    /// Administrative broker RPC methods, which affect other clients (client.drain, benchmark)
+    fn admin_allowed(&self) -> bool {
+        false
+    }
+}
+
+/// Authenticated client identity, created by the broker when the client is registered
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    name: String,
+    primary_name: String,
+    kind: ClientKind,
+    source: Option<String>,
+    acl: Option<Arc<dyn ClientAcl>>,
+}
+
+impl ClientIdentity {
+    #[inline]
+    pub fn new(
+        name: &str,
+        primary_name: &str,
+        kind: ClientKind,
+        source: Option<String>,
+        acl: Option<Arc<dyn ClientAcl>>,
+    ) -> Self {
+        Self {
+            name: name.to_owned(),
+            primary_name: primary_name.to_owned(),
+            kind,
+            source,
+            acl,
+        }
+    }
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    #[inline]
+    pub fn primary_name(&self) -> &str {
+        &self.primary_name
+    }
+    #[inline]
+    pub fn kind(&self) -> ClientKind {
+        self.kind
+    }
+    /// Client IP address or socket path, None for internal clients
+    #[inline]
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+    /// The client ACL, None if the client has no restrictions
+    #[inline]
+    pub fn acl(&self) -> Option<&dyn ClientAcl> {
+        self.acl.as_deref()
+    }
+    #[inline]
+    pub fn p2p_allowed(&self, target: &str) -> bool {
+        self.acl.as_ref().is_none_or(|acl| acl.p2p_allowed(target))
+    }
+    #[inline]
+    pub fn publish_allowed(&self, topic: &str) -> bool {
+        self.acl
+            .as_ref()
+            .is_none_or(|acl| acl.publish_allowed(topic))
+    }
+    #[inline]
+    pub fn subscribe_allowed(&self, topic: &str) -> bool {
+        self.acl
+            .as_ref()
+            .is_none_or(|acl| acl.subscribe_allowed(topic))
+    }
+    #[inline]
+    pub fn broadcast_allowed(&self, target: &str) -> bool {
+        self.acl
+            .as_ref()
+            .is_none_or(|acl| acl.broadcast_allowed(target))
+    }
+    /// Clients without ACLs are not allowed to monitor the broker traffic
+    #[inline]
+    pub fn wiretap_allowed(&self) -> bool {
+        self.acl.as_ref().is_some_and(|acl| acl.wiretap_allowed())
+    }
+    /// Clients without ACLs are not allowed to call administrative methods
+    #[inline]
+    pub fn admin_allowed(&self) -> bool {
+        self.acl.as_ref().is_some_and(|acl| acl.admin_allowed())
+    }
 }
 
 pub mod borrow;
+pub mod capture;
 pub mod common;
 pub mod tools {
     #[cfg(any(feature = "rpc", feature = "broker", feature = "ipc"))]
     pub mod pubsub;
+    #[cfg(any(feature = "rpc", feature = "broker", feature = "ipc"))]
+    mod services;
+    #[cfg(feature = "rpc")]
+    pub use services::BenchResponder;
+    #[cfg(any(feature = "rpc", feature = "broker", feature = "ipc"))]
+    pub use services::{EchoService, SinkService, SinkStats};
 }
 
 #[cfg(feature = "broker")]
 pub mod broker;
+#[cfg(all(unix, feature = "rpc"))]
+pub mod channel;
+#[cfg(feature = "testing")]
+pub mod chaos;
+#[cfg(feature = "dbus")]
+pub mod dbus;
+#[cfg(feature = "rpc")]
+pub mod discovery;
+#[cfg(all(feature = "websocket", feature = "rpc"))]
+pub mod gateway;
 #[cfg(feature = "ipc")]
 pub mod ipc;
+#[cfg(feature = "broker")]
+pub mod metrics;
+#[cfg(feature = "broker")]
+pub mod persistence;
+#[cfg(feature = "ipc")]
+pub mod proxy;
 #[cfg(feature = "rpc")]
 pub mod rpc;
+#[cfg(feature = "serial")]
+pub mod serial;
+#[cfg(feature = "broker")]
+pub mod sink;
+#[cfg(all(target_os = "linux", feature = "uring"))]
+mod uring;
 
 #[cfg(any(feature = "rpc", feature = "broker", feature = "ipc"))]
 pub mod client;
 #[cfg(any(feature = "broker", feature = "ipc"))]
 pub mod comm;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QOS_ALL: [QoS; 8] = [
+        QoS::No,
+        QoS::Processed,
+        QoS::Realtime,
+        QoS::RealtimeProcessed,
+        QoS::Delivered,
+        QoS::RealtimeDelivered,
+        QoS::Consumed,
+        QoS::RealtimeConsumed,
+    ];
+
+    #[test]
+    fn qos_wire_bits_round_trip() {
+        for qos in QOS_ALL {
+            assert_eq!(QoS::try_from(qos as u8).unwrap() as u8, qos as u8);
+            let flags = OP_MESSAGE | qos.wire_bits() << 6;
+            assert_eq!(flags & 0b0011_1111, OP_MESSAGE, "{:?}", qos);
+            let wire = QoS::try_from(flags >> 6).unwrap();
+            assert_eq!(wire.needs_ack(), qos.needs_ack(), "{:?}", qos);
+            assert_eq!(wire.is_realtime(), qos.is_realtime(), "{:?}", qos);
+            assert_eq!(wire.delivery_mode(), DeliveryMode::Queued, "{:?}", qos);
+            // the delivery mode is sent in a separate byte
+            let mode = DeliveryMode::try_from(qos.delivery_mode() as u8).unwrap();
+            let restored = QoS::try_from(wire as u8 | (mode as u8) << 2).unwrap();
+            assert_eq!(restored as u8, qos as u8);
+        }
+        assert!(QoS::try_from(4).is_err());
+        assert!(DeliveryMode::try_from(3).is_err());
+    }
+}
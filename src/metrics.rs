@@ -0,0 +1,170 @@
+use crate::Error;
+use log::trace;
+use parking_lot::{Mutex, RwLock};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::net::UdpSocket;
+
+/// Max statsd packet size, fits into a single Ethernet frame
+const STATSD_PACKET_SIZE: usize = 1432;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MetricKind {
+    /// monotonic, the value is the total since the broker (or the client) start
+    Counter,
+    /// the current value
+    Gauge,
+}
+
+/// Broker metric. Per-client metrics are prefixed with "client_" and have the client name set
+#[derive(Debug, Clone)]
+pub struct Metric {
+    pub name: &'static str,
+    pub kind: MetricKind,
+    pub client: Option<String>,
+    pub value: u64,
+}
+
+/// Metrics backend. The broker collects counters and gauges periodically and reports them all
+/// at once, so backends may replace previously reported values (clients come and go)
+pub trait Metrics: Send + Sync {
+    fn report(&self, metrics: &[Metric]);
+}
+
+/// Keeps the last reported metrics and renders them in the Prometheus text exposition format,
+/// so they can be appended to the output of an existing metrics endpoint
+pub struct PrometheusMetrics {
+    prefix: String,
+    metrics: RwLock<Vec<Metric>>,
+}
+
+impl Default for PrometheusMetrics {
+    fn default() -> Self {
+        Self::new("elbus_")
+    }
+}
+
+impl PrometheusMetrics {
+    /// The prefix is prepended to metric names, e.g. "elbus_"
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_owned(),
+            metrics: <_>::default(),
+        }
+    }
+    pub fn render(&self) -> String {
+        let metrics = self.metrics.read();
+        let mut by_name: BTreeMap<&str, Vec<&Metric>> = BTreeMap::new();
+        for metric in metrics.iter() {
+            by_name.entry(metric.name).or_default().push(metric);
+        }
+        let mut result = String::new();
+        for (name, metrics) in by_name {
+            let kind = match metrics[0].kind {
+                MetricKind::Counter => "counter",
+                MetricKind::Gauge => "gauge",
+            };
+            let _ = writeln!(result, "# TYPE {}{} {}", self.prefix, name, kind);
+            for metric in metrics {
+                if let Some(ref client) = metric.client {
+                    let _ = writeln!(
+                        result,
+                        "{}{}{{client=\"{}\"}} {}",
+                        self.prefix,
+                        name,
+                        client.replace('\\', "\\\\").replace('"', "\\\""),
+                        metric.value
+                    );
+                } else {
+                    let _ = writeln!(result, "{}{} {}", self.prefix, name, metric.value);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Metrics for PrometheusMetrics {
+    fn report(&self, metrics: &[Metric]) {
+        *self.metrics.write() = metrics.to_vec();
+    }
+}
+
+/// Sends metrics to a statsd server (UDP). Counters are sent as increments since the previous
+/// report, client names are put into metric names with dots replaced by underscores
+pub struct StatsdMetrics {
+    socket: UdpSocket,
+    prefix: String,
+    counters: Mutex<HashMap<(&'static str, Option<String>), u64>>,
+}
+
+impl StatsdMetrics {
+    /// The prefix is prepended to metric names, e.g. "elbus."
+    pub fn connect(addr: &str, prefix: &str) -> Result<Self, Error> {
+        let socket = UdpSocket::bind("0.0.0.0:0").map_err(Error::io)?;
+        socket.connect(addr).map_err(Error::io)?;
+        // reports are sent from the async runtime and must never block it
+        socket.set_nonblocking(true).map_err(Error::io)?;
+        Ok(Self {
+            socket,
+            prefix: prefix.to_owned(),
+            counters: <_>::default(),
+        })
+    }
+    fn send(&self, packet: &str) {
+        if let Err(e) = self.socket.send(packet.as_bytes()) {
+            trace!("unable to send statsd metrics: {}", e);
+        }
+    }
+}
+
+impl Metrics for StatsdMetrics {
+    fn report(&self, metrics: &[Metric]) {
+        let mut counters = self.counters.lock();
+        let mut last = HashMap::with_capacity(counters.len());
+        let mut packet = String::new();
+        for metric in metrics {
+            let line = match metric.kind {
+                MetricKind::Counter => {
+                    let key = (metric.name, metric.client.clone());
+                    // the counter is reset if the client has been re-connected
+                    let prev = counters.get(&key).copied().unwrap_or_default();
+                    let delta = if metric.value >= prev {
+                        metric.value - prev
+                    } else {
+                        metric.value
+                    };
+                    last.insert(key, metric.value);
+                    if delta == 0 {
+                        continue;
+                    }
+                    format!("{}|c", delta)
+                }
+                MetricKind::Gauge => format!("{}|g", metric.value),
+            };
+            let name = if let Some(ref client) = metric.client {
+                format!(
+                    "{}{}.{}",
+                    self.prefix,
+                    metric.name,
+                    client.replace(['.', ':', '|', '@'], "_")
+                )
+            } else {
+                format!("{}{}", self.prefix, metric.name)
+            };
+            if !packet.is_empty() && packet.len() + name.len() + line.len() + 2 > STATSD_PACKET_SIZE
+            {
+                self.send(&packet);
+                packet.clear();
+            }
+            if !packet.is_empty() {
+                packet.push('\n');
+            }
+            let _ = write!(packet, "{}:{}", name, line);
+        }
+        if !packet.is_empty() {
+            self.send(&packet);
+        }
+        *counters = last;
+    }
+}
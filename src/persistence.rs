@@ -0,0 +1,144 @@
+use crate::Error;
+use parking_lot::RwLock;
+use std::collections::{BTreeMap, HashMap};
+
+/// Key-value pairs, ordered by key
+pub type PersistencePairs = Vec<(Vec<u8>, Vec<u8>)>;
+
+type MemoryTree = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Key-value storage for durable broker data, saved by broker snapshots. Data is organized in
+/// named trees of binary key/value pairs, keys are ordered, so e.g. per-client data can be
+/// stored with the client name as the key prefix
+pub trait Persistence: Send + Sync {
+    fn get(&self, tree: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+    fn insert(&self, tree: &str, key: &[u8], value: &[u8]) -> Result<(), Error>;
+    fn remove(&self, tree: &str, key: &[u8]) -> Result<(), Error>;
+    /// Returns pairs of the tree, which keys start with the prefix (empty - all pairs)
+    fn list(&self, tree: &str, prefix: &[u8]) -> Result<PersistencePairs, Error>;
+    /// Removes all pairs of the tree
+    fn clear(&self, tree: &str) -> Result<(), Error>;
+    /// Returns names of all trees
+    fn trees(&self) -> Result<Vec<String>, Error>;
+    /// Flushes pending writes to the underlying storage
+    fn flush(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// The default storage, the data is lost when the broker is stopped
+#[derive(Default)]
+pub struct MemoryPersistence {
+    trees: RwLock<HashMap<String, MemoryTree>>,
+}
+
+impl MemoryPersistence {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Persistence for MemoryPersistence {
+    fn get(&self, tree: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self
+            .trees
+            .read()
+            .get(tree)
+            .and_then(|t| t.get(key).cloned()))
+    }
+    fn insert(&self, tree: &str, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.trees
+            .write()
+            .entry(tree.to_owned())
+            .or_default()
+            .insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+    fn remove(&self, tree: &str, key: &[u8]) -> Result<(), Error> {
+        if let Some(t) = self.trees.write().get_mut(tree) {
+            t.remove(key);
+        }
+        Ok(())
+    }
+    fn list(&self, tree: &str, prefix: &[u8]) -> Result<PersistencePairs, Error> {
+        Ok(self.trees.read().get(tree).map_or_else(Vec::new, |t| {
+            t.range(prefix.to_vec()..)
+                .take_while(|(k, _)| k.starts_with(prefix))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect()
+        }))
+    }
+    fn clear(&self, tree: &str) -> Result<(), Error> {
+        self.trees.write().remove(tree);
+        Ok(())
+    }
+    fn trees(&self) -> Result<Vec<String>, Error> {
+        Ok(self.trees.read().keys().cloned().collect())
+    }
+}
+
+/// sled-backed storage (requires "sled" feature)
+#[cfg(feature = "sled")]
+pub struct SledPersistence {
+    db: sled::Db,
+}
+
+#[cfg(feature = "sled")]
+impl SledPersistence {
+    /// Opens (or creates) the database in the directory
+    pub fn open(path: &str) -> Result<Self, Error> {
+        Ok(Self {
+            db: sled::open(path).map_err(Error::io)?,
+        })
+    }
+    #[inline]
+    fn tree(&self, tree: &str) -> Result<sled::Tree, Error> {
+        self.db.open_tree(tree).map_err(Error::io)
+    }
+}
+
+#[cfg(feature = "sled")]
+impl Persistence for SledPersistence {
+    fn get(&self, tree: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self
+            .tree(tree)?
+            .get(key)
+            .map_err(Error::io)?
+            .map(|v| v.to_vec()))
+    }
+    fn insert(&self, tree: &str, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.tree(tree)?.insert(key, value).map_err(Error::io)?;
+        Ok(())
+    }
+    fn remove(&self, tree: &str, key: &[u8]) -> Result<(), Error> {
+        self.tree(tree)?.remove(key).map_err(Error::io)?;
+        Ok(())
+    }
+    fn list(&self, tree: &str, prefix: &[u8]) -> Result<PersistencePairs, Error> {
+        self.tree(tree)?
+            .scan_prefix(prefix)
+            .map(|r| r.map(|(k, v)| (k.to_vec(), v.to_vec())).map_err(Error::io))
+            .collect()
+    }
+    fn clear(&self, tree: &str) -> Result<(), Error> {
+        self.tree(tree)?.clear().map_err(Error::io)
+    }
+    fn trees(&self) -> Result<Vec<String>, Error> {
+        let default_tree = self.db.name();
+        self.db
+            .tree_names()
+            .into_iter()
+            .filter(|name| *name != default_tree)
+            .map(|name| {
+                std::str::from_utf8(&name)
+                    .map(ToOwned::to_owned)
+                    .map_err(Error::data)
+            })
+            .collect()
+    }
+    fn flush(&self) -> Result<(), Error> {
+        self.db.flush().map_err(Error::io)?;
+        Ok(())
+    }
+}
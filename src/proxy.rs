@@ -0,0 +1,288 @@
+use crate::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const SOCKS5_PREFIX: &str = "socks5://";
+const HTTP_PREFIX: &str = "http://";
+
+const SOCKS5_VERSION: u8 = 0x05;
+const SOCKS5_AUTH_NONE: u8 = 0x00;
+const SOCKS5_AUTH_PASSWORD: u8 = 0x02;
+const SOCKS5_AUTH_UNACCEPTABLE: u8 = 0xff;
+const SOCKS5_PASSWORD_VERSION: u8 = 0x01;
+const SOCKS5_CMD_CONNECT: u8 = 0x01;
+const SOCKS5_ATYP_IPV4: u8 = 0x01;
+const SOCKS5_ATYP_DOMAIN: u8 = 0x03;
+const SOCKS5_ATYP_IPV6: u8 = 0x04;
+
+const HTTP_MAX_RESPONSE_HEADER: usize = 8192;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ProxyKind {
+    Socks5,
+    /// HTTP CONNECT
+    Http,
+}
+
+impl fmt::Display for ProxyKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                ProxyKind::Socks5 => "socks5",
+                ProxyKind::Http => "http",
+            }
+        )
+    }
+}
+
+/// A proxy, TCP and TLS client connections are tunneled through. Parsed from
+/// socks5://[user:password@]host:port or http://[user:password@]host:port (HTTP CONNECT)
+#[derive(Clone)]
+pub struct Proxy {
+    kind: ProxyKind,
+    addr: String,
+    auth: Option<(String, String)>,
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Proxy")
+            .field("kind", &self.kind)
+            .field("addr", &self.addr)
+            .field("auth", &self.auth.as_ref().map(|(user, _)| user))
+            .finish()
+    }
+}
+
+impl FromStr for Proxy {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, rest) = if let Some(rest) = s.strip_prefix(SOCKS5_PREFIX) {
+            (ProxyKind::Socks5, rest)
+        } else if let Some(rest) = s.strip_prefix(HTTP_PREFIX) {
+            (ProxyKind::Http, rest)
+        } else {
+            return Err(Error::data(format!("unsupported proxy: {}", s)));
+        };
+        let rest = rest.trim_end_matches('/');
+        let (auth, addr) = if let Some((creds, addr)) = rest.rsplit_once('@') {
+            let (user, password) = creds.split_once(':').unwrap_or((creds, ""));
+            (Some((user.to_owned(), password.to_owned())), addr)
+        } else {
+            (None, rest)
+        };
+        if addr.is_empty() || !addr.contains(':') {
+            return Err(Error::data(format!("invalid proxy address: {}", addr)));
+        }
+        let mut proxy = Self::new(kind, addr);
+        proxy.auth = auth;
+        Ok(proxy)
+    }
+}
+
+impl Proxy {
+    /// addr - the proxy host:port
+    pub fn new(kind: ProxyKind, addr: &str) -> Self {
+        Self {
+            kind,
+            addr: addr.to_owned(),
+            auth: None,
+        }
+    }
+    /// Username/password authentication (SOCKS5) or basic authentication (HTTP CONNECT)
+    pub fn auth(mut self, user: &str, password: &str) -> Self {
+        self.auth.replace((user.to_owned(), password.to_owned()));
+        self
+    }
+    #[inline]
+    pub fn kind(&self) -> ProxyKind {
+        self.kind
+    }
+    #[inline]
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+    /// Connects to the proxy and opens a tunnel to the target host:port, the greeting exchange
+    /// is performed over the returned stream
+    pub async fn connect(&self, target: &str, timeout: Duration) -> Result<TcpStream, Error> {
+        let mut stream = tokio::time::timeout(timeout, TcpStream::connect(&self.addr)).await??;
+        let fut = async {
+            match self.kind {
+                ProxyKind::Socks5 => self.socks5_handshake(&mut stream, target).await,
+                ProxyKind::Http => self.http_connect(&mut stream, target).await,
+            }
+        };
+        tokio::time::timeout(timeout, fut).await??;
+        Ok(stream)
+    }
+    async fn socks5_handshake(&self, stream: &mut TcpStream, target: &str) -> Result<(), Error> {
+        let (host, port) = split_host_port(target)?;
+        if self.auth.is_some() {
+            stream
+                .write_all(&[SOCKS5_VERSION, 2, SOCKS5_AUTH_NONE, SOCKS5_AUTH_PASSWORD])
+                .await?;
+        } else {
+            stream
+                .write_all(&[SOCKS5_VERSION, 1, SOCKS5_AUTH_NONE])
+                .await?;
+        }
+        let mut buf = [0_u8; 2];
+        stream.read_exact(&mut buf).await?;
+        if buf[0] != SOCKS5_VERSION {
+            return Err(Error::io("invalid SOCKS5 proxy reply"));
+        }
+        match buf[1] {
+            SOCKS5_AUTH_NONE => {}
+            SOCKS5_AUTH_PASSWORD => {
+                let (user, password) = self
+                    .auth
+                    .as_ref()
+                    .ok_or_else(|| Error::io("invalid SOCKS5 proxy reply"))?;
+                if user.len() > u8::MAX as usize || password.len() > u8::MAX as usize {
+                    return Err(Error::data("SOCKS5 credentials are too long"));
+                }
+                let mut req = Vec::with_capacity(3 + user.len() + password.len());
+                req.push(SOCKS5_PASSWORD_VERSION);
+                #[allow(clippy::cast_possible_truncation)]
+                req.push(user.len() as u8);
+                req.extend(user.as_bytes());
+                #[allow(clippy::cast_possible_truncation)]
+                req.push(password.len() as u8);
+                req.extend(password.as_bytes());
+                stream.write_all(&req).await?;
+                stream.read_exact(&mut buf).await?;
+                if buf[1] != 0 {
+                    return Err(Error::access("SOCKS5 proxy authentication failed"));
+                }
+            }
+            SOCKS5_AUTH_UNACCEPTABLE => {
+                return Err(Error::access(
+                    "no acceptable SOCKS5 proxy authentication methods",
+                ));
+            }
+            v => {
+                return Err(Error::not_supported(format!(
+                    "SOCKS5 proxy authentication method {}",
+                    v
+                )));
+            }
+        }
+        let mut req = vec![SOCKS5_VERSION, SOCKS5_CMD_CONNECT, 0];
+        match host.parse::<std::net::IpAddr>() {
+            Ok(std::net::IpAddr::V4(ip)) => {
+                req.push(SOCKS5_ATYP_IPV4);
+                req.extend(ip.octets());
+            }
+            Ok(std::net::IpAddr::V6(ip)) => {
+                req.push(SOCKS5_ATYP_IPV6);
+                req.extend(ip.octets());
+            }
+            Err(_) => {
+                if host.len() > u8::MAX as usize {
+                    return Err(Error::data(format!("host name is too long: {}", host)));
+                }
+                req.push(SOCKS5_ATYP_DOMAIN);
+                #[allow(clippy::cast_possible_truncation)]
+                req.push(host.len() as u8);
+                req.extend(host.as_bytes());
+            }
+        }
+        req.extend(port.to_be_bytes());
+        stream.write_all(&req).await?;
+        let mut reply = [0_u8; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != SOCKS5_VERSION {
+            return Err(Error::io("invalid SOCKS5 proxy reply"));
+        }
+        if reply[1] != 0 {
+            return Err(Error::io(format!(
+                "SOCKS5 proxy is unable to connect to {}, code {}",
+                target, reply[1]
+            )));
+        }
+        // skip the bound address and port
+        let addr_len = match reply[3] {
+            SOCKS5_ATYP_IPV4 => 4,
+            SOCKS5_ATYP_IPV6 => 16,
+            SOCKS5_ATYP_DOMAIN => {
+                let mut len = [0_u8; 1];
+                stream.read_exact(&mut len).await?;
+                len[0] as usize
+            }
+            _ => return Err(Error::io("invalid SOCKS5 proxy reply")),
+        };
+        let mut bound = vec![0_u8; addr_len + 2];
+        stream.read_exact(&mut bound).await?;
+        Ok(())
+    }
+    async fn http_connect(&self, stream: &mut TcpStream, target: &str) -> Result<(), Error> {
+        let mut req = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", target, target);
+        if let Some((ref user, ref password)) = self.auth {
+            req.push_str("Proxy-Authorization: Basic ");
+            req.push_str(&base64_encode(format!("{}:{}", user, password).as_bytes()));
+            req.push_str("\r\n");
+        }
+        req.push_str("\r\n");
+        stream.write_all(req.as_bytes()).await?;
+        // the response is read byte-by-byte, the data after the header belongs to the broker
+        let mut response = Vec::new();
+        let mut byte = [0_u8; 1];
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() >= HTTP_MAX_RESPONSE_HEADER {
+                return Err(Error::io("HTTP proxy response header is too large"));
+            }
+            stream.read_exact(&mut byte).await?;
+            response.push(byte[0]);
+        }
+        let status_line = std::str::from_utf8(&response)?
+            .lines()
+            .next()
+            .unwrap_or_default();
+        let mut sp = status_line.split_whitespace();
+        let status = sp.nth(1).unwrap_or_default();
+        match status {
+            "200" => Ok(()),
+            "407" => Err(Error::access("HTTP proxy authentication required")),
+            _ => Err(Error::io(format!(
+                "HTTP proxy is unable to connect to {}: {}",
+                target, status_line
+            ))),
+        }
+    }
+}
+
+fn split_host_port(addr: &str) -> Result<(&str, u16), Error> {
+    let (host, port) = addr
+        .rsplit_once(':')
+        .ok_or_else(|| Error::data(format!("invalid address: {}", addr)))?;
+    let port = port
+        .parse()
+        .map_err(|e| Error::data(format!("invalid port {}: {}", port, e)))?;
+    Ok((host.trim_start_matches('[').trim_end_matches(']'), port))
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut result = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or_default(),
+            chunk.get(2).copied().unwrap_or_default(),
+        ];
+        let n = u32::from(b[0]) << 16 | u32::from(b[1]) << 8 | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(ALPHABET[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
@@ -1,18 +1,24 @@
 use crate::borrow::Cow;
 use crate::client::AsyncClient;
 use crate::EventChannel;
-use crate::{Error, Frame, FrameKind, OpConfirm, QoS};
+use crate::{ClientIdentity, Error, Frame, FrameKind, OpConfirm, QoS};
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt;
+use std::future::Future;
+use std::ops::Range;
+use std::pin::Pin;
 use std::sync::atomic;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use tokio::sync::oneshot;
 use tokio::sync::Mutex;
+use tokio::sync::Notify;
+use tokio::sync::Semaphore;
 use tokio::task::JoinHandle;
 
 use log::{error, trace, warn};
+use serde::{Deserialize, Serialize};
 
 use async_trait::async_trait;
 
@@ -20,12 +26,28 @@ pub const RPC_NOTIFICATION: u8 = 0x00;
 pub const RPC_REQUEST: u8 = 0x01;
 pub const RPC_REPLY: u8 = 0x11;
 pub const RPC_ERROR: u8 = 0x12;
+pub const RPC_PROGRESS: u8 = 0x13;
+
+/// If set in the event type byte, the frame contains the headers block
+pub const RPC_FLAG_HEADERS: u8 = 0x80;
+
+pub const RPC_HEADER_CONTENT_TYPE: &str = "ct";
+/// Correlation/trace ID header, copied from requests to responses
+pub const RPC_HEADER_TRACE_ID: &str = "trace";
+/// Session key header, calls to a load-balanced target with the same key are routed by the
+/// broker to the same provider while it is connected
+pub const RPC_HEADER_SESSION: &str = "session";
+
+pub const CONTENT_TYPE_MSGPACK: &str = "msgpack";
+pub const CONTENT_TYPE_JSON: &str = "json";
 
 pub const RPC_ERROR_CODE_PARSE: i16 = -32700;
 pub const RPC_ERROR_CODE_INVALID_REQUEST: i16 = -32600;
 pub const RPC_ERROR_CODE_METHOD_NOT_FOUND: i16 = -32601;
 pub const RPC_ERROR_CODE_INVALID_METHOD_PARAMS: i16 = -32602;
 pub const RPC_ERROR_CODE_INTERNAL: i16 = -32603;
+/// The RPC server is shutting down, the call can be retried with another instance
+pub const RPC_ERROR_CODE_UNAVAILABLE: i16 = -32001;
 
 /// By default, RPC frame and notification handlers are launched in background, which allows
 /// non-blocking event processing, however events can be processed in random order
@@ -42,6 +64,13 @@ pub const RPC_ERROR_CODE_INTERNAL: i16 = -32603;
 pub struct Options {
     blocking_notifications: bool,
     blocking_frames: bool,
+    content_type: Option<ContentType>,
+    retry_policy: Option<RetryPolicy>,
+    method_limits: HashMap<String, MethodLimits>,
+    call_stats: bool,
+    slow_call_threshold: Option<Duration>,
+    cache_policy: Option<CachePolicy>,
+    codec: Option<Arc<dyn RpcCodec>>,
 }
 
 impl Options {
@@ -59,6 +88,684 @@ impl Options {
         self.blocking_frames = true;
         self
     }
+    /// The default content type for calls. If set, the content type header is sent with all
+    /// calls, unless overridden with CallOptions
+    #[inline]
+    pub fn content_type(mut self, content_type: ContentType) -> Self {
+        self.content_type.replace(content_type);
+        self
+    }
+    /// The default retry policy for calls, unless overridden with CallOptions
+    #[inline]
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy.replace(policy);
+        self
+    }
+    /// Execution limits for the incoming calls of the method
+    #[inline]
+    pub fn method_limits(mut self, method: &str, limits: MethodLimits) -> Self {
+        self.method_limits.insert(method.to_owned(), limits);
+        self
+    }
+    /// Collect per-method statistics of outgoing calls (see RpcClient::stats)
+    #[inline]
+    pub fn call_stats(mut self) -> Self {
+        self.call_stats = true;
+        self
+    }
+    /// Log outgoing calls, which take longer than the threshold
+    #[inline]
+    pub fn slow_call_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_call_threshold.replace(threshold);
+        self
+    }
+    /// Cache responses of the idempotent methods, listed in the policy
+    #[inline]
+    pub fn cache_policy(mut self, policy: CachePolicy) -> Self {
+        self.cache_policy.replace(policy);
+        self
+    }
+    /// RPC frame envelope codec, ElbusCodec if not set. All clients, which communicate with
+    /// each other, must use the same codec
+    #[inline]
+    pub fn codec(mut self, codec: Arc<dyn RpcCodec>) -> Self {
+        self.codec.replace(codec);
+        self
+    }
+}
+
+/// Caller-side response cache policy
+///
+/// Successful responses of the listed methods are cached by (target, method, content type,
+/// params) for the TTL. Only methods, which are idempotent and do not depend on anything else
+/// except params, should be cached.
+///
+/// Example:
+///
+/// ```rust,ignore
+/// use elbus::rpc::{CachePolicy, Options};
+/// use std::time::Duration;
+///
+/// let opts = Options::new().cache_policy(
+///     CachePolicy::new(Duration::from_secs(10), 1000)
+///         .method("config.get")
+///         .method("item.lookup"),
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct CachePolicy {
+    ttl: Duration,
+    max_entries: usize,
+    methods: BTreeSet<String>,
+}
+
+impl CachePolicy {
+    #[inline]
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            methods: <_>::default(),
+        }
+    }
+    /// Marks the method as cacheable
+    #[inline]
+    pub fn method(mut self, method: &str) -> Self {
+        self.methods.insert(method.to_owned());
+        self
+    }
+    #[inline]
+    pub fn is_cacheable(&self, method: &str) -> bool {
+        self.methods.contains(method)
+    }
+}
+
+#[derive(Hash, Eq, PartialEq, Clone)]
+struct CacheKey {
+    target: String,
+    method: String,
+    content_type: Option<ContentType>,
+    params: Vec<u8>,
+}
+
+struct ResponseCache {
+    policy: CachePolicy,
+    entries: std::sync::Mutex<HashMap<CacheKey, (Instant, RpcEvent)>>,
+}
+
+impl ResponseCache {
+    fn new(policy: CachePolicy) -> Self {
+        Self {
+            policy,
+            entries: <_>::default(),
+        }
+    }
+    /// # Panics
+    ///
+    /// Will panic on poisoned mutex
+    fn get(&self, key: &CacheKey) -> Option<RpcEvent> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((created, event)) if created.elapsed() < self.policy.ttl => Some(event.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+    fn insert(&self, key: CacheKey, event: RpcEvent) {
+        if self.policy.max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.policy.max_entries && !entries.contains_key(&key) {
+            let ttl = self.policy.ttl;
+            entries.retain(|_, (created, _)| created.elapsed() < ttl);
+            if entries.len() >= self.policy.max_entries {
+                // drop the oldest entry
+                if let Some(k) = entries
+                    .iter()
+                    .min_by_key(|(_, (created, _))| *created)
+                    .map(|(k, _)| k.clone())
+                {
+                    entries.remove(&k);
+                }
+            }
+        }
+        entries.insert(key, (Instant::now(), event));
+    }
+    fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Number of the latest call latencies, kept per method to calculate percentiles
+const CALL_STATS_SAMPLES: usize = 1000;
+
+/// Outgoing call statistics of an RPC method
+///
+/// Latency percentiles are calculated for the latest calls only, in microseconds
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RpcMethodStats {
+    pub calls: u64,
+    pub errors: u64,
+    pub error_rate: f64,
+    pub latency_p50: u64,
+    pub latency_p90: u64,
+    pub latency_p99: u64,
+    pub latency_max: u64,
+}
+
+#[derive(Default)]
+struct MethodStatsCollector {
+    calls: u64,
+    errors: u64,
+    // ring buffer of the latest latencies (us)
+    samples: Vec<u64>,
+    pos: usize,
+}
+
+impl MethodStatsCollector {
+    fn record(&mut self, latency: Duration, is_error: bool) {
+        self.calls += 1;
+        if is_error {
+            self.errors += 1;
+        }
+        let us = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        if self.samples.len() < CALL_STATS_SAMPLES {
+            self.samples.push(us);
+        } else {
+            self.samples[self.pos] = us;
+            self.pos = (self.pos + 1) % CALL_STATS_SAMPLES;
+        }
+    }
+    #[allow(clippy::cast_precision_loss)]
+    fn stats(&self) -> RpcMethodStats {
+        let mut samples = self.samples.clone();
+        samples.sort_unstable();
+        let percentile = |p: usize| {
+            if samples.is_empty() {
+                0
+            } else {
+                samples[(samples.len() * p / 100).min(samples.len() - 1)]
+            }
+        };
+        RpcMethodStats {
+            calls: self.calls,
+            errors: self.errors,
+            error_rate: if self.calls == 0 {
+                0.0
+            } else {
+                self.errors as f64 / self.calls as f64
+            },
+            latency_p50: percentile(50),
+            latency_p90: percentile(90),
+            latency_p99: percentile(99),
+            latency_max: samples.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+/// Server-side execution limits for an RPC method
+///
+/// If the max number of concurrent executions is reached, the incoming calls are queued. If the
+/// queue is full, the calls are rejected with "busy" error. If the handler does not finish in
+/// time, "timeout" error is replied (the handler future is dropped).
+///
+/// Example:
+///
+/// ```rust,ignore
+/// use elbus::rpc::{MethodLimits, Options};
+/// use std::time::Duration;
+///
+/// let opts = Options::new().method_limits(
+///     "report.generate",
+///     MethodLimits::new()
+///         .max_concurrent(2)
+///         .queue_size(10)
+///         .timeout(Duration::from_secs(30)),
+/// );
+/// ```
+#[derive(Default, Clone, Debug)]
+pub struct MethodLimits {
+    max_concurrent: Option<usize>,
+    queue_size: Option<usize>,
+    timeout: Option<Duration>,
+}
+
+impl MethodLimits {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Max concurrent executions of the method handler
+    #[inline]
+    pub fn max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.max_concurrent.replace(max_concurrent.max(1));
+        self
+    }
+    /// Max number of calls, waiting for execution (unlimited by default)
+    #[inline]
+    pub fn queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size.replace(queue_size);
+        self
+    }
+    /// Handler execution timeout
+    #[inline]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout.replace(timeout);
+        self
+    }
+}
+
+struct MethodLimiter {
+    semaphore: Option<Arc<Semaphore>>,
+    queue_size: Option<usize>,
+    queued: atomic::AtomicUsize,
+    timeout: Option<Duration>,
+}
+
+impl From<&MethodLimits> for MethodLimiter {
+    fn from(limits: &MethodLimits) -> Self {
+        Self {
+            semaphore: limits.max_concurrent.map(|n| Arc::new(Semaphore::new(n))),
+            queue_size: limits.queue_size,
+            queued: atomic::AtomicUsize::new(0),
+            timeout: limits.timeout,
+        }
+    }
+}
+
+impl MethodLimiter {
+    async fn handle_call<H>(&self, handlers: &H, event: RpcEvent) -> RpcResult
+    where
+        H: RpcHandlers + Send + Sync,
+    {
+        let _permit = if let Some(ref semaphore) = self.semaphore {
+            if let Ok(permit) = semaphore.clone().try_acquire_owned() {
+                Some(permit)
+            } else {
+                let queued = self.queued.fetch_add(1, atomic::Ordering::SeqCst);
+                if self.queue_size.is_some_and(|size| queued >= size) {
+                    self.queued.fetch_sub(1, atomic::Ordering::SeqCst);
+                    return Err(Error::busy("RPC method queue is full").into());
+                }
+                let permit = semaphore.clone().acquire_owned().await;
+                self.queued.fetch_sub(1, atomic::Ordering::SeqCst);
+                // the semaphore is closed when the server is shutting down
+                Some(permit.map_err(|_| RpcError::unavailable())?)
+            }
+        } else {
+            None
+        };
+        if let Some(timeout) = self.timeout {
+            tokio::time::timeout(timeout, handlers.handle_call(event))
+                .await
+                .map_err(Into::<Error>::into)?
+        } else {
+            handlers.handle_call(event).await
+        }
+    }
+    /// Rejects queued calls
+    fn close(&self) {
+        if let Some(ref semaphore) = self.semaphore {
+            semaphore.close();
+        }
+    }
+}
+
+/// Incoming calls, which handlers are being executed
+#[derive(Default)]
+struct InFlightCalls {
+    shutdown: atomic::AtomicBool,
+    count: atomic::AtomicUsize,
+    idle: Notify,
+}
+
+impl InFlightCalls {
+    fn enter(self: &Arc<Self>) -> InFlightGuard {
+        self.count.fetch_add(1, atomic::Ordering::SeqCst);
+        InFlightGuard(self.clone())
+    }
+    /// Waits until all handlers are finished, returns the number of handlers left if the timeout
+    /// has been expired
+    async fn wait(&self, timeout: Duration) -> usize {
+        let _r = tokio::time::timeout(timeout, async {
+            loop {
+                let idle = self.idle.notified();
+                if self.count.load(atomic::Ordering::SeqCst) == 0 {
+                    break;
+                }
+                idle.await;
+            }
+        })
+        .await;
+        self.count.load(atomic::Ordering::SeqCst)
+    }
+}
+
+struct InFlightGuard(Arc<InFlightCalls>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, atomic::Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+/// RPC call retry policy
+///
+/// A call is retried if the request has not been delivered (transport errors). Calls of
+/// idempotent methods are retried on any error, except error replies from the handler (the
+/// internal error code is retried as well), including lost responses and attempt timeouts.
+///
+/// Example:
+///
+/// ```rust,ignore
+/// use elbus::rpc::{Options, RetryPolicy};
+/// use std::time::Duration;
+///
+/// let policy = RetryPolicy::new(3)
+///     .backoff(Duration::from_millis(50))
+///     .attempt_timeout(Duration::from_secs(2))
+///     .idempotent("get");
+/// let rpc = RpcClient::create0(client, Options::new().retry_policy(policy));
+/// ```
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Duration,
+    backoff_max: Duration,
+    attempt_timeout: Option<Duration>,
+    idempotent: BTreeSet<String>,
+}
+
+impl Default for RetryPolicy {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff: Duration::from_millis(100),
+            backoff_max: Duration::from_secs(5),
+            attempt_timeout: None,
+            idempotent: <_>::default(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Creates a policy with the specified max number of attempts (including the first one)
+    #[inline]
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            ..Self::default()
+        }
+    }
+    /// Delay before the first retry, doubled for each next one (default: 100ms)
+    #[inline]
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+    /// Max delay between attempts (default: 5s)
+    #[inline]
+    pub fn backoff_max(mut self, backoff_max: Duration) -> Self {
+        self.backoff_max = backoff_max;
+        self
+    }
+    /// Max time to wait for a response to a single attempt. By default, a response is waited
+    /// forever
+    #[inline]
+    pub fn attempt_timeout(mut self, timeout: Duration) -> Self {
+        self.attempt_timeout.replace(timeout);
+        self
+    }
+    /// Marks the method as idempotent
+    #[inline]
+    pub fn idempotent(mut self, method: &str) -> Self {
+        self.idempotent.insert(method.to_owned());
+        self
+    }
+    #[inline]
+    pub fn is_idempotent(&self, method: &str) -> bool {
+        self.idempotent.contains(method)
+    }
+    #[inline]
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff
+            .checked_mul(1 << (attempt - 1).min(16))
+            .map_or(self.backoff_max, |d| d.min(self.backoff_max))
+    }
+}
+
+/// Per-call options
+///
+/// Example:
+///
+/// ```rust,ignore
+/// use elbus::rpc::{CallOptions, ContentType};
+///
+/// let opts = CallOptions::new().content_type(ContentType::Json);
+/// let result = rpc
+///     .call_with_options("target", "method", r#"{"a":1}"#.as_bytes().into(), QoS::Processed, opts)
+///     .await?;
+/// let value: serde_json::Value = result.unpack()?;
+/// ```
+#[derive(Default, Clone, Debug)]
+pub struct CallOptions {
+    content_type: Option<ContentType>,
+    headers: RpcHeaders,
+    progress_tx: Option<async_channel::Sender<RpcEvent>>,
+    retry_policy: Option<RetryPolicy>,
+    idempotent: bool,
+    no_cache: bool,
+}
+
+impl CallOptions {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Params content type, the handler is expected to reply with the same one
+    #[inline]
+    pub fn content_type(mut self, content_type: ContentType) -> Self {
+        self.content_type.replace(content_type);
+        self
+    }
+    /// Sets the trace (correlation) ID, which is copied by the handler to responses
+    #[inline]
+    pub fn trace_id(mut self, trace_id: &str) -> Self {
+        self.headers.set(RPC_HEADER_TRACE_ID, trace_id);
+        self
+    }
+    /// Continues the request chain: copies the trace ID from the incoming event (if set)
+    #[inline]
+    pub fn trace_from(self, event: &RpcEvent) -> Self {
+        if let Some(trace_id) = event.trace_id() {
+            self.trace_id(trace_id)
+        } else {
+            self
+        }
+    }
+    /// Sets the session key, which pins calls to load-balanced targets to the same provider
+    #[inline]
+    pub fn session(mut self, key: &str) -> Self {
+        self.headers.set(RPC_HEADER_SESSION, key);
+        self
+    }
+    /// Set a custom header
+    #[inline]
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.headers.set(key, value);
+        self
+    }
+    /// Progress events, sent by the handler, are pushed into the channel. If the channel is full,
+    /// progress events are dropped
+    #[inline]
+    pub fn progress(mut self, tx: async_channel::Sender<RpcEvent>) -> Self {
+        self.progress_tx.replace(tx);
+        self
+    }
+    /// Overrides the client retry policy
+    #[inline]
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy.replace(policy);
+        self
+    }
+    /// Marks the call as idempotent, regardless of the method list of the retry policy
+    #[inline]
+    pub fn idempotent(mut self) -> Self {
+        self.idempotent = true;
+        self
+    }
+    /// Bypasses the response cache for the call
+    #[inline]
+    pub fn no_cache(mut self) -> Self {
+        self.no_cache = true;
+        self
+    }
+}
+
+/// RPC payload content type
+///
+/// The RPC layer itself does not encode/decode payloads, the content type is sent as a header
+/// and is used by the pack/unpack helpers. If no content type header is set, MessagePack is
+/// assumed
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+pub enum ContentType {
+    MsgPack,
+    Json,
+}
+
+impl Default for ContentType {
+    #[inline]
+    fn default() -> Self {
+        ContentType::MsgPack
+    }
+}
+
+impl ContentType {
+    #[inline]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContentType::MsgPack => CONTENT_TYPE_MSGPACK,
+            ContentType::Json => CONTENT_TYPE_JSON,
+        }
+    }
+    /// Serializes the value
+    #[inline]
+    pub fn pack<T: Serialize>(self, value: &T) -> Result<Vec<u8>, RpcError> {
+        match self {
+            ContentType::MsgPack => rmp_serde::to_vec_named(value).map_err(Into::into),
+            ContentType::Json => serde_json::to_vec(value)
+                .map_err(|e| RpcError::internal(Some(RpcError::convert_data(e)))),
+        }
+    }
+    /// Deserializes the value
+    #[inline]
+    pub fn unpack<'de, T: Deserialize<'de>>(self, data: &'de [u8]) -> Result<T, RpcError> {
+        match self {
+            ContentType::MsgPack => rmp_serde::from_slice(data).map_err(Into::into),
+            ContentType::Json => serde_json::from_slice(data)
+                .map_err(|e| RpcError::parse(Some(RpcError::convert_data(e)))),
+        }
+    }
+}
+
+impl TryFrom<&str> for ContentType {
+    type Error = Error;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            CONTENT_TYPE_MSGPACK => Ok(ContentType::MsgPack),
+            CONTENT_TYPE_JSON => Ok(ContentType::Json),
+            _ => Err(Error::data(format!("Unsupported content type: {}", s))),
+        }
+    }
+}
+
+/// RPC frame headers
+///
+/// Headers are optional and are sent only if not empty. On the wire, the headers block is
+/// encoded as u16 (LE) length + key 0x00 value 0x00 pairs
+#[derive(Default, Clone, Debug, Eq, PartialEq)]
+pub struct RpcHeaders {
+    items: Vec<(String, String)>,
+}
+
+impl RpcHeaders {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    #[inline]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.items
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+    /// Sets the header, replacing the existing one if present
+    pub fn set(&mut self, key: &str, value: &str) {
+        if let Some(item) = self.items.iter_mut().find(|(k, _)| k == key) {
+            item.1 = value.to_owned();
+        } else {
+            self.items.push((key.to_owned(), value.to_owned()));
+        }
+    }
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.items.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), Error> {
+        let mut block = Vec::new();
+        for (k, v) in &self.items {
+            if k.is_empty() || k.contains('\0') || v.contains('\0') {
+                return Err(Error::data("Invalid RPC header"));
+            }
+            block.extend_from_slice(k.as_bytes());
+            block.push(0x00);
+            block.extend_from_slice(v.as_bytes());
+            block.push(0x00);
+        }
+        let len: u16 = block
+            .len()
+            .try_into()
+            .map_err(|_| Error::data("RPC headers block is too long"))?;
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend(block);
+        Ok(())
+    }
+    fn decode(block: &[u8]) -> Result<Self, Error> {
+        let mut items = Vec::new();
+        let mut sp = block.split(|c| *c == 0);
+        while let Some(k) = sp.next() {
+            if k.is_empty() {
+                break;
+            }
+            let v = sp
+                .next()
+                .ok_or_else(|| Error::data("Invalid RPC headers block"))?;
+            items.push((
+                std::str::from_utf8(k)?.to_owned(),
+                std::str::from_utf8(v)?.to_owned(),
+            ));
+        }
+        Ok(Self { items })
+    }
 }
 
 #[allow(clippy::module_name_repetitions)]
@@ -69,39 +776,317 @@ pub enum RpcEventKind {
     Request = RPC_REQUEST,
     Reply = RPC_REPLY,
     ErrorReply = RPC_ERROR,
+    Progress = RPC_PROGRESS,
 }
 
 #[allow(clippy::module_name_repetitions)]
-#[inline]
-pub fn rpc_err_str(v: impl fmt::Display) -> Option<Vec<u8>> {
-    Some(v.to_string().as_bytes().to_vec())
+#[inline]
+pub fn rpc_err_str(v: impl fmt::Display) -> Option<Vec<u8>> {
+    Some(v.to_string().as_bytes().to_vec())
+}
+
+impl fmt::Display for RpcEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                RpcEventKind::Notification => "notifcation",
+                RpcEventKind::Request => "request",
+                RpcEventKind::Reply => "reply",
+                RpcEventKind::ErrorReply => "error reply",
+                RpcEventKind::Progress => "progress",
+            }
+        )
+    }
+}
+
+/// A part of an RPC frame, located by the codec
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum FrameSlice {
+    /// Range in the frame header (zero-copy prefix of inter-thread frames)
+    Header(Range<usize>),
+    /// Range in the frame payload
+    Payload(Range<usize>),
+    /// Data, copied or converted by the codec
+    Owned(Vec<u8>),
+}
+
+impl Default for FrameSlice {
+    #[inline]
+    fn default() -> Self {
+        FrameSlice::Owned(Vec::new())
+    }
+}
+
+/// Outgoing RPC envelope
+#[derive(Debug, Clone)]
+pub struct RpcEnvelope<'a> {
+    pub kind: RpcEventKind,
+    /// Call ID, zero for notifications and calls with no response required
+    pub id: u32,
+    /// Method, for requests only
+    pub method: Option<&'a str>,
+    /// Error code, for error replies only
+    pub code: i16,
+    pub headers: Option<&'a RpcHeaders>,
+}
+
+impl RpcEnvelope<'_> {
+    #[inline]
+    fn notification() -> Self {
+        Self {
+            kind: RpcEventKind::Notification,
+            id: 0,
+            method: None,
+            code: 0,
+            headers: None,
+        }
+    }
+}
+
+/// Decoded incoming RPC envelope
+#[derive(Debug, Clone)]
+pub struct DecodedEnvelope {
+    pub kind: RpcEventKind,
+    pub id: u32,
+    pub code: i16,
+    pub method: Option<FrameSlice>,
+    pub payload: FrameSlice,
+    pub headers: Option<RpcHeaders>,
+}
+
+/// RPC frame envelope codec
+///
+/// The codec only encodes and decodes frame envelopes, call id management, the pending call
+/// table, headers propagation and handler dispatching are performed by the RPC client. The
+/// default codec is ElbusCodec, a custom one can be set with RPC client Options.
+///
+/// Frame slices, returned by the decoder, must be within the frame header/payload, otherwise
+/// they are processed as empty
+#[allow(clippy::module_name_repetitions)]
+pub trait RpcCodec: Send + Sync + fmt::Debug {
+    /// Encodes the envelope, returns the frame header and the payload, which are sent with a
+    /// single frame
+    fn encode<'a>(
+        &self,
+        envelope: &RpcEnvelope<'_>,
+        payload: Cow<'a>,
+    ) -> Result<(Vec<u8>, Cow<'a>), Error>;
+    fn decode(&self, frame: &Frame) -> Result<DecodedEnvelope, Error>;
 }
 
-impl fmt::Display for RpcEventKind {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                RpcEventKind::Notification => "notifcation",
-                RpcEventKind::Request => "request",
-                RpcEventKind::Reply => "reply",
-                RpcEventKind::ErrorReply => "error reply",
+/// The default elbus RPC envelope codec
+///
+/// See https://elbus.readthedocs.io/en/latest/rpc_protocol.html
+#[derive(Debug, Default, Clone)]
+pub struct ElbusCodec {}
+
+impl RpcCodec for ElbusCodec {
+    fn encode<'a>(
+        &self,
+        envelope: &RpcEnvelope<'_>,
+        payload: Cow<'a>,
+    ) -> Result<(Vec<u8>, Cow<'a>), Error> {
+        let header = match envelope.kind {
+            RpcEventKind::Request => prepare_call_payload(
+                envelope.method.unwrap_or_default(),
+                &envelope.id.to_le_bytes(),
+                envelope.headers,
+            )?,
+            RpcEventKind::Notification => {
+                if let Some(h) = envelope.headers {
+                    let mut header = vec![RPC_NOTIFICATION | RPC_FLAG_HEADERS];
+                    h.encode(&mut header)?;
+                    header
+                } else {
+                    vec![RPC_NOTIFICATION]
+                }
             }
-        )
+            RpcEventKind::ErrorReply => prepare_response_header(
+                RPC_ERROR,
+                envelope.id,
+                Some(envelope.code),
+                envelope.headers,
+            )?,
+            kind => prepare_response_header(kind as u8, envelope.id, None, envelope.headers)?,
+        };
+        Ok((header, payload))
+    }
+    #[allow(clippy::too_many_lines)]
+    fn decode(&self, frame: &Frame) -> Result<DecodedEnvelope, Error> {
+        let (body, use_header) = frame
+            .header()
+            .map_or_else(|| (frame.payload(), false), |h| (h, true));
+        if body.is_empty() {
+            return Err(Error::data("Empty RPC frame"));
+        }
+        macro_rules! check_len {
+            ($len: expr) => {
+                if body.len() < $len {
+                    return Err(Error::data("Invalid RPC frame"));
+                }
+            };
+        }
+        // parses the headers block at the position if the flag is set
+        macro_rules! parse_headers {
+            ($pos: expr) => {
+                if body[0] & RPC_FLAG_HEADERS == 0 {
+                    (0, None)
+                } else {
+                    check_len!($pos + 2);
+                    let len = u16::from_le_bytes(body[$pos..$pos + 2].try_into().unwrap()) as usize;
+                    check_len!($pos + 2 + len);
+                    (
+                        len + 2,
+                        Some(RpcHeaders::decode(&body[$pos + 2..$pos + 2 + len])?),
+                    )
+                }
+            };
+        }
+        // the payload, which follows the envelope
+        let payload_from = |pos: usize| {
+            if use_header {
+                FrameSlice::Payload(0..frame.payload().len())
+            } else {
+                FrameSlice::Payload(pos..frame.payload().len())
+            }
+        };
+        macro_rules! read_id {
+            () => {
+                u32::from_le_bytes(body[1..5].try_into().unwrap())
+            };
+        }
+        match body[0] & !RPC_FLAG_HEADERS {
+            RPC_NOTIFICATION => {
+                let (ext_len, headers) = parse_headers!(1);
+                Ok(DecodedEnvelope {
+                    kind: RpcEventKind::Notification,
+                    id: 0,
+                    code: 0,
+                    method: None,
+                    payload: payload_from(1 + ext_len),
+                    headers,
+                })
+            }
+            RPC_REQUEST => {
+                check_len!(6);
+                let (ext_len, headers) = parse_headers!(5);
+                check_len!(6 + ext_len);
+                let mut sp = body[5 + ext_len..].splitn(2, |c| *c == 0);
+                let method = sp.next().ok_or_else(|| Error::data("No RPC method"))?;
+                let method_range = 5 + ext_len..5 + ext_len + method.len();
+                let payload_pos = 6 + ext_len + method.len();
+                if !use_header {
+                    sp.next()
+                        .ok_or_else(|| Error::data("No RPC params block"))?;
+                }
+                Ok(DecodedEnvelope {
+                    kind: RpcEventKind::Request,
+                    id: read_id!(),
+                    code: 0,
+                    method: Some(if use_header {
+                        FrameSlice::Header(method_range)
+                    } else {
+                        FrameSlice::Payload(method_range)
+                    }),
+                    payload: payload_from(payload_pos),
+                    headers,
+                })
+            }
+            RPC_REPLY | RPC_PROGRESS => {
+                check_len!(5);
+                let (ext_len, headers) = parse_headers!(5);
+                Ok(DecodedEnvelope {
+                    kind: if body[0] & !RPC_FLAG_HEADERS == RPC_REPLY {
+                        RpcEventKind::Reply
+                    } else {
+                        RpcEventKind::Progress
+                    },
+                    id: read_id!(),
+                    code: 0,
+                    method: None,
+                    payload: payload_from(5 + ext_len),
+                    headers,
+                })
+            }
+            RPC_ERROR => {
+                check_len!(7);
+                let (ext_len, headers) = parse_headers!(7);
+                Ok(DecodedEnvelope {
+                    kind: RpcEventKind::ErrorReply,
+                    id: read_id!(),
+                    code: i16::from_le_bytes(body[5..7].try_into().unwrap()),
+                    method: None,
+                    payload: payload_from(7 + ext_len),
+                    headers,
+                })
+            }
+            v => Err(Error::data(format!("Unsupported RPC frame code {}", v))),
+        }
     }
 }
 
 #[allow(clippy::module_name_repetitions)]
-#[derive(Debug)]
+#[derive(Clone)]
 pub struct RpcEvent {
     kind: RpcEventKind,
     frame: Frame,
-    payload_pos: usize,
-    use_header: bool,
+    id: u32,
+    code: i16,
+    method: Option<FrameSlice>,
+    payload: FrameSlice,
+    headers: Option<RpcHeaders>,
+    // set by the processor for requests which require a response
+    responder: Option<Arc<Mutex<dyn AsyncClient>>>,
+    // the codec of the processor, the default one if not set
+    codec: Option<Arc<dyn RpcCodec>>,
+}
+
+impl fmt::Debug for RpcEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcEvent")
+            .field("kind", &self.kind)
+            .field("frame", &self.frame)
+            .field("id", &self.id)
+            .field("code", &self.code)
+            .field("method", &self.method)
+            .field("payload", &self.payload)
+            .field("headers", &self.headers)
+            .finish()
+    }
 }
 
 impl RpcEvent {
+    /// Decodes the frame with the specified codec
+    pub fn decode(frame: Frame, codec: &dyn RpcCodec) -> Result<Self, Error> {
+        let envelope = codec.decode(&frame)?;
+        Ok(Self {
+            kind: envelope.kind,
+            frame,
+            id: envelope.id,
+            code: envelope.code,
+            method: envelope.method,
+            payload: envelope.payload,
+            headers: envelope.headers,
+            responder: None,
+            codec: None,
+        })
+    }
+    #[inline]
+    fn slice<'a>(&'a self, slice: &'a FrameSlice) -> &'a [u8] {
+        match slice {
+            FrameSlice::Header(range) => self
+                .frame
+                .header()
+                .and_then(|h| h.get(range.clone()))
+                .unwrap_or_default(),
+            FrameSlice::Payload(range) => {
+                self.frame.payload().get(range.clone()).unwrap_or_default()
+            }
+            FrameSlice::Owned(v) => v,
+        }
+    }
     #[inline]
     pub fn kind(&self) -> RpcEventKind {
         self.kind
@@ -118,133 +1103,135 @@ impl RpcEvent {
     pub fn primary_sender(&self) -> &str {
         self.frame.primary_sender()
     }
+    /// The caller identity and permissions, attached by the broker. Available for handlers of
+    /// inter-thread clients only
+    #[inline]
+    pub fn sender_identity(&self) -> Option<&ClientIdentity> {
+        self.frame.sender_identity()
+    }
     #[inline]
     pub fn payload(&self) -> &[u8] {
-        &self.frame().payload()[self.payload_pos..]
+        self.slice(&self.payload)
     }
-    /// # Panics
-    ///
-    /// Should not panic
     #[inline]
     pub fn id(&self) -> u32 {
-        u32::from_le_bytes(
-            if self.use_header {
-                &self.frame.header().unwrap()[1..5]
-            } else {
-                &self.frame.payload()[1..5]
-            }
-            .try_into()
-            .unwrap(),
-        )
+        self.id
     }
     #[inline]
     pub fn is_response_required(&self) -> bool {
         self.id() != 0
     }
-    /// # Panics
-    ///
-    /// Should not panic
+    /// Method of the request, empty for other events
     #[inline]
     pub fn method(&self) -> &[u8] {
-        if self.use_header {
-            let header = self.frame.header.as_ref().unwrap();
-            &header[5..header.len() - 1]
+        self.method.as_ref().map_or(&[], |m| self.slice(m))
+    }
+    #[inline]
+    pub fn headers(&self) -> Option<&RpcHeaders> {
+        self.headers.as_ref()
+    }
+    #[inline]
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.as_ref().and_then(|h| h.get(key))
+    }
+    /// Trace (correlation) ID, if set by the caller
+    #[inline]
+    pub fn trace_id(&self) -> Option<&str> {
+        self.header(RPC_HEADER_TRACE_ID)
+    }
+    /// Payload content type, MessagePack if not specified
+    ///
+    /// Errors
+    ///
+    /// Data - if the content type header is not supported
+    #[inline]
+    pub fn content_type(&self) -> Result<ContentType, Error> {
+        self.header(RPC_HEADER_CONTENT_TYPE)
+            .map_or(Ok(ContentType::MsgPack), TryInto::try_into)
+    }
+    /// Deserializes the payload according to the content type
+    #[inline]
+    pub fn unpack<'de, T: Deserialize<'de>>(&'de self) -> Result<T, RpcError> {
+        self.content_type()?.unpack(self.payload())
+    }
+    /// Serializes a reply according to the request content type
+    #[inline]
+    pub fn pack<T: Serialize>(&self, value: &T) -> RpcResult {
+        Ok(Some(self.content_type()?.pack(value)?))
+    }
+    #[inline]
+    fn codec(&self) -> &dyn RpcCodec {
+        self.codec
+            .as_deref()
+            .unwrap_or(&ElbusCodec {} as &dyn RpcCodec)
+    }
+    /// Headers, which are automatically copied from a request to its responses
+    fn response_headers(&self) -> Option<RpcHeaders> {
+        let mut headers = RpcHeaders::new();
+        for key in [RPC_HEADER_CONTENT_TYPE, RPC_HEADER_TRACE_ID] {
+            if let Some(value) = self.header(key) {
+                headers.set(key, value);
+            }
+        }
+        if headers.is_empty() {
+            None
         } else {
-            &self.frame().payload()[5..self.payload_pos - 1]
+            Some(headers)
         }
     }
     #[inline]
     pub fn parse_method(&self) -> Result<&str, Error> {
         std::str::from_utf8(self.method()).map_err(Into::into)
     }
-    /// # Panics
+    /// Sends an intermediate progress event to the caller. The caller receives progress events
+    /// only if the call has been made with "call_with_progress"
+    ///
+    /// Errors
     ///
-    /// Should not panic
+    /// NotSupported - if the event is not a request or no response is required
+    pub async fn progress(&self, data: Cow<'_>) -> Result<(), Error> {
+        if self.kind != RpcEventKind::Request || !self.is_response_required() {
+            return Err(Error::not_supported(
+                "progress requires a call with response",
+            ));
+        }
+        let responder = self
+            .responder
+            .as_ref()
+            .ok_or_else(|| Error::not_supported("no responder for the event"))?;
+        let qos = if self.frame.is_realtime() {
+            QoS::RealtimeProcessed
+        } else {
+            QoS::Processed
+        };
+        let headers = self.response_headers();
+        let envelope = RpcEnvelope {
+            kind: RpcEventKind::Progress,
+            id: self.id(),
+            method: None,
+            code: 0,
+            headers: headers.as_ref(),
+        };
+        let (header, data) = self.codec().encode(&envelope, data)?;
+        responder
+            .lock()
+            .await
+            .zc_send(self.frame.sender(), header.into(), data, qos)
+            .await?;
+        Ok(())
+    }
+    /// Error code of the error reply, zero for other events
     #[inline]
     pub fn code(&self) -> i16 {
-        if self.kind == RpcEventKind::ErrorReply {
-            i16::from_le_bytes(
-                if self.use_header {
-                    &self.frame.header().unwrap()[5..7]
-                } else {
-                    &self.frame.payload()[5..7]
-                }
-                .try_into()
-                .unwrap(),
-            )
-        } else {
-            0
-        }
+        self.code
     }
 }
 
 impl TryFrom<Frame> for RpcEvent {
     type Error = Error;
+    #[inline]
     fn try_from(frame: Frame) -> Result<Self, Self::Error> {
-        let (body, use_header) = frame
-            .header()
-            .map_or_else(|| (frame.payload(), false), |h| (h, true));
-        if body.is_empty() {
-            Err(Error::data("Empty RPC frame"))
-        } else {
-            macro_rules! check_len {
-                ($len: expr) => {
-                    if body.len() < $len {
-                        return Err(Error::data("Invalid RPC frame"));
-                    }
-                };
-            }
-            match body[0] {
-                RPC_NOTIFICATION => Ok(RpcEvent {
-                    kind: RpcEventKind::Notification,
-                    frame,
-                    payload_pos: if use_header { 0 } else { 1 },
-                    use_header: false,
-                }),
-                RPC_REQUEST => {
-                    check_len!(6);
-                    if use_header {
-                        Ok(RpcEvent {
-                            kind: RpcEventKind::Request,
-                            frame,
-                            payload_pos: 0,
-                            use_header: true,
-                        })
-                    } else {
-                        let mut sp = body[5..].splitn(2, |c| *c == 0);
-                        let method = sp.next().ok_or_else(|| Error::data("No RPC method"))?;
-                        let payload_pos = 6 + method.len();
-                        sp.next()
-                            .ok_or_else(|| Error::data("No RPC params block"))?;
-                        Ok(RpcEvent {
-                            kind: RpcEventKind::Request,
-                            frame,
-                            payload_pos,
-                            use_header: false,
-                        })
-                    }
-                }
-                RPC_REPLY => {
-                    check_len!(5);
-                    Ok(RpcEvent {
-                        kind: RpcEventKind::Reply,
-                        frame,
-                        payload_pos: if use_header { 0 } else { 5 },
-                        use_header,
-                    })
-                }
-                RPC_ERROR => {
-                    check_len!(7);
-                    Ok(RpcEvent {
-                        kind: RpcEventKind::ErrorReply,
-                        frame,
-                        payload_pos: if use_header { 0 } else { 7 },
-                        use_header,
-                    })
-                }
-                v => Err(Error::data(format!("Unsupported RPC frame code {}", v))),
-            }
-        }
+        RpcEvent::decode(frame, &ElbusCodec::default())
     }
 }
 
@@ -266,11 +1253,383 @@ impl RpcHandlers for DummyHandlers {
             Some("RPC handler is not implemented".as_bytes().to_vec()),
         ))
     }
-    async fn handle_notification(&self, _event: RpcEvent) {}
-    async fn handle_frame(&self, _frame: Frame) {}
+    async fn handle_notification(&self, _event: RpcEvent) {}
+    async fn handle_frame(&self, _frame: Frame) {}
+}
+
+/// RPC middleware layer
+///
+/// Layers are wrapped around RPC handlers with LayeredHandlers and allow to implement
+/// authentication, metrics, request logging, rate limiting etc. once per service.
+///
+/// "before" hooks are called in the order the layers have been added, "after" hooks - in the
+/// reverse order
+#[allow(clippy::module_name_repetitions)]
+#[async_trait]
+pub trait RpcLayer: Send + Sync {
+    /// Called before an RPC call handler. If an error is returned, the handler is not called and
+    /// the error is sent back to the caller
+    async fn before_call(&self, _event: &RpcEvent) -> Result<(), RpcError> {
+        Ok(())
+    }
+    /// Called after an RPC call handler, the layer can inspect or replace the result
+    async fn after_call(&self, _event: &RpcEvent, result: RpcResult) -> RpcResult {
+        result
+    }
+    /// Called before a notification handler. If false is returned, the notification is dropped
+    async fn before_notification(&self, _event: &RpcEvent) -> bool {
+        true
+    }
+    /// Called before a frame handler. If false is returned, the frame is dropped
+    async fn before_frame(&self, _frame: &Frame) -> bool {
+        true
+    }
+}
+
+/// RPC handlers, wrapped with middleware layers
+///
+/// Example:
+///
+/// ```rust,ignore
+/// let handlers = LayeredHandlers::new(MyHandlers {})
+///     .layer(AuthLayer {})
+///     .layer(MetricsLayer::default());
+/// let rpc = RpcClient::new(client, handlers);
+/// ```
+pub struct LayeredHandlers<H> {
+    handlers: H,
+    layers: Vec<Box<dyn RpcLayer>>,
+}
+
+impl<H> LayeredHandlers<H>
+where
+    H: RpcHandlers + Send + Sync,
+{
+    #[inline]
+    pub fn new(handlers: H) -> Self {
+        Self {
+            handlers,
+            layers: Vec::new(),
+        }
+    }
+    #[inline]
+    pub fn layer(mut self, layer: impl RpcLayer + 'static) -> Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+}
+
+#[async_trait]
+impl<H> RpcHandlers for LayeredHandlers<H>
+where
+    H: RpcHandlers + Send + Sync,
+{
+    async fn handle_call(&self, event: RpcEvent) -> RpcResult {
+        for layer in &self.layers {
+            layer.before_call(&event).await?;
+        }
+        if self.layers.is_empty() {
+            self.handlers.handle_call(event).await
+        } else {
+            let mut result = self.handlers.handle_call(event.clone()).await;
+            for layer in self.layers.iter().rev() {
+                result = layer.after_call(&event, result).await;
+            }
+            result
+        }
+    }
+    async fn handle_notification(&self, event: RpcEvent) {
+        for layer in &self.layers {
+            if !layer.before_notification(&event).await {
+                return;
+            }
+        }
+        self.handlers.handle_notification(event).await;
+    }
+    async fn handle_frame(&self, frame: Frame) {
+        for layer in &self.layers {
+            if !layer.before_frame(&frame).await {
+                return;
+            }
+        }
+        self.handlers.handle_frame(frame).await;
+    }
+}
+
+type MethodFn =
+    Arc<dyn Fn(RpcEvent) -> Pin<Box<dyn Future<Output = RpcResult> + Send>> + Send + Sync>;
+type NotificationFn =
+    Arc<dyn Fn(RpcEvent) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+type FrameFn = Arc<dyn Fn(Frame) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+#[derive(Default)]
+struct MethodMapInner {
+    methods: HashMap<String, MethodFn>,
+    notification_handler: Option<NotificationFn>,
+    frame_handler: Option<FrameFn>,
+}
+
+/// RPC handlers, where methods are registered by name at runtime
+///
+/// The map can be cloned, all clones share the same methods, so methods can be registered or
+/// unregistered after the map has been given to RPC client. Calls of unknown methods get "method
+/// not found" error replies, notifications and frames are ignored unless handlers are set.
+///
+/// Example:
+///
+/// ```rust,ignore
+/// let methods = MethodMap::new();
+/// methods.register("echo", |event: RpcEvent| async move {
+///     Ok(Some(event.payload().to_vec()))
+/// });
+/// let rpc = RpcClient::new(client, methods.clone());
+/// methods.register("ping", |_event| async move { Ok(None) });
+/// ```
+#[derive(Default, Clone)]
+pub struct MethodMap {
+    inner: Arc<RwLock<MethodMapInner>>,
+}
+
+impl MethodMap {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Registers the method handler, replacing the existing one if present
+    ///
+    /// # Panics
+    ///
+    /// Will panic on poisoned lock
+    pub fn register<F, Fut>(&self, method: &str, f: F)
+    where
+        F: Fn(RpcEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = RpcResult> + Send + 'static,
+    {
+        let f: MethodFn = Arc::new(move |event| Box::pin(f(event)));
+        self.inner
+            .write()
+            .unwrap()
+            .methods
+            .insert(method.to_owned(), f);
+    }
+    /// Unregisters the method handler, returns false if the method has not been registered
+    ///
+    /// # Panics
+    ///
+    /// Will panic on poisoned lock
+    pub fn unregister(&self, method: &str) -> bool {
+        self.inner.write().unwrap().methods.remove(method).is_some()
+    }
+    /// # Panics
+    ///
+    /// Will panic on poisoned lock
+    pub fn contains(&self, method: &str) -> bool {
+        self.inner.read().unwrap().methods.contains_key(method)
+    }
+    /// Registered method names, sorted
+    ///
+    /// # Panics
+    ///
+    /// Will panic on poisoned lock
+    pub fn methods(&self) -> Vec<String> {
+        let mut methods: Vec<String> = self.inner.read().unwrap().methods.keys().cloned().collect();
+        methods.sort();
+        methods
+    }
+    /// Sets the notification handler
+    ///
+    /// # Panics
+    ///
+    /// Will panic on poisoned lock
+    pub fn on_notification<F, Fut>(&self, f: F)
+    where
+        F: Fn(RpcEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let f: NotificationFn = Arc::new(move |event| Box::pin(f(event)));
+        self.inner.write().unwrap().notification_handler.replace(f);
+    }
+    /// Sets the handler for non-RPC frames (broadcasts, topics)
+    ///
+    /// # Panics
+    ///
+    /// Will panic on poisoned lock
+    pub fn on_frame<F, Fut>(&self, f: F)
+    where
+        F: Fn(Frame) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let f: FrameFn = Arc::new(move |frame| Box::pin(f(frame)));
+        self.inner.write().unwrap().frame_handler.replace(f);
+    }
+}
+
+#[async_trait]
+impl RpcHandlers for MethodMap {
+    async fn handle_call(&self, event: RpcEvent) -> RpcResult {
+        let f = {
+            let method = event.parse_method()?;
+            self.inner.read().unwrap().methods.get(method).cloned()
+        };
+        if let Some(f) = f {
+            f(event).await
+        } else {
+            Err(RpcError::method(None))
+        }
+    }
+    async fn handle_notification(&self, event: RpcEvent) {
+        let f = self.inner.read().unwrap().notification_handler.clone();
+        if let Some(f) = f {
+            f(event).await;
+        }
+    }
+    async fn handle_frame(&self, frame: Frame) {
+        let f = self.inner.read().unwrap().frame_handler.clone();
+        if let Some(f) = f {
+            f(frame).await;
+        }
+    }
+}
+
+/// Progress events queue of a forwarded call
+const FORWARD_PROGRESS_QUEUE: usize = 128;
+
+/// RPC handlers, which forward calls to the target via another (uplink) RPC client
+///
+/// Allows to make a service, connected to a different broker, available for local clients: the
+/// forwarder is registered on the local broker under the service name and relays incoming calls
+/// with their headers and progress events, responses and errors are routed back to the callers.
+/// Notifications are forwarded as-is, other frames are ignored.
+///
+/// Example:
+///
+/// ```rust,ignore
+/// // uplink is connected to the core broker
+/// let uplink = Arc::new(RpcClient::new0(ipc_client));
+/// let local = broker.register_client("core.registry").await?;
+/// let forwarder = RpcClient::new(local, RpcForwarder::new(uplink, "registry"));
+/// ```
+#[allow(clippy::module_name_repetitions)]
+pub struct RpcForwarder<R> {
+    uplink: Arc<R>,
+    target: String,
+}
+
+impl<R> RpcForwarder<R>
+where
+    R: Rpc + Send + Sync + 'static,
+{
+    #[inline]
+    pub fn new(uplink: Arc<R>, target: &str) -> Self {
+        Self {
+            uplink,
+            target: target.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl<R> RpcHandlers for RpcForwarder<R>
+where
+    R: Rpc + Send + Sync + 'static,
+{
+    async fn handle_call(&self, event: RpcEvent) -> RpcResult {
+        let method = event.parse_method()?;
+        let qos = if event.frame().is_realtime() {
+            QoS::RealtimeProcessed
+        } else {
+            QoS::Processed
+        };
+        if !event.is_response_required() {
+            self.uplink
+                .call0(&self.target, method, event.payload().into(), qos)
+                .await?;
+            return Ok(None);
+        }
+        let mut opts = CallOptions::new();
+        if let Some(headers) = event.headers() {
+            opts.headers = headers.clone();
+            if event.header(RPC_HEADER_CONTENT_TYPE).is_some() {
+                opts.content_type = Some(event.content_type()?);
+            }
+        }
+        let (tx, rx) = async_channel::bounded(FORWARD_PROGRESS_QUEUE);
+        opts.progress_tx = Some(tx);
+        let ev = event.clone();
+        let relay = tokio::spawn(async move {
+            while let Ok(progress) = rx.recv().await {
+                if let Err(e) = ev.progress(progress.payload().into()).await {
+                    warn!("unable to forward RPC progress: {}", e);
+                }
+            }
+        });
+        let result = self
+            .uplink
+            .call_with_options(&self.target, method, event.payload().into(), qos, opts)
+            .await;
+        // the progress channel is closed as soon as the call is finished
+        let _r = relay.await;
+        Ok(Some(result?.payload().to_vec()))
+    }
+    async fn handle_notification(&self, event: RpcEvent) {
+        let qos = if event.frame().is_realtime() {
+            QoS::Realtime
+        } else {
+            QoS::No
+        };
+        if let Err(e) = self
+            .uplink
+            .notify(&self.target, event.payload().into(), qos)
+            .await
+        {
+            warn!("unable to forward RPC notification: {}", e);
+        }
+    }
+    async fn handle_frame(&self, _frame: Frame) {}
+}
+
+struct PendingCall {
+    tx: oneshot::Sender<RpcEvent>,
+    progress_tx: Option<async_channel::Sender<RpcEvent>>,
+}
+
+const CALL_MAP_SHARDS: usize = 64;
+
+/// Pending calls, sharded by call id to avoid contention when there are many calls in-flight
+struct PendingCalls {
+    shards: Vec<std::sync::Mutex<HashMap<u32, PendingCall>>>,
+}
+
+impl Default for PendingCalls {
+    fn default() -> Self {
+        Self {
+            shards: (0..CALL_MAP_SHARDS).map(|_| <_>::default()).collect(),
+        }
+    }
+}
+
+impl PendingCalls {
+    #[inline]
+    fn shard(&self, id: u32) -> std::sync::MutexGuard<'_, HashMap<u32, PendingCall>> {
+        self.shards[id as usize % CALL_MAP_SHARDS].lock().unwrap()
+    }
+    #[inline]
+    fn insert(&self, id: u32, call: PendingCall) {
+        self.shard(id).insert(id, call);
+    }
+    #[inline]
+    fn remove(&self, id: u32) -> Option<PendingCall> {
+        self.shard(id).remove(&id)
+    }
+    #[inline]
+    fn progress_tx(&self, id: u32) -> Option<async_channel::Sender<RpcEvent>> {
+        self.shard(id)
+            .get(&id)
+            .and_then(|call| call.progress_tx.clone())
+    }
 }
 
-type CallMap = Arc<std::sync::Mutex<BTreeMap<u32, oneshot::Sender<RpcEvent>>>>;
+type CallMap = Arc<PendingCalls>;
 
 #[async_trait]
 pub trait Rpc {
@@ -279,13 +1638,52 @@ pub trait Rpc {
     ///
     /// This mehtod allows to get the containered-client back, to call its methods directly (manage
     /// pub/sub and send broadcast messages)
-    fn client(&self) -> Arc<Mutex<(dyn AsyncClient + 'static)>>;
+    fn client(&self) -> Arc<Mutex<dyn AsyncClient + 'static>>;
     async fn notify(
         &self,
         target: &str,
         data: Cow<'async_trait>,
         qos: QoS,
     ) -> Result<OpConfirm, Error>;
+    /// Send the notification to multiple targets, the client is locked once
+    ///
+    /// The default implementation sends the notifications one by one
+    async fn notify_bulk(
+        &self,
+        targets: &[&str],
+        data: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<Vec<OpConfirm>, Error> {
+        let mut result = Vec::with_capacity(targets.len());
+        for target in targets {
+            result.push(self.notify(target, data.clone(), qos).await?);
+        }
+        Ok(result)
+    }
+    /// Publish the payload to the topic (topic frames are processed as-is, with no RPC prefix)
+    async fn notify_topic(
+        &self,
+        topic: &str,
+        data: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error> {
+        self.client().lock().await.publish(topic, data, qos).await
+    }
+    /// Publish the payload to multiple topics, the client is locked once
+    async fn notify_topic_bulk(
+        &self,
+        topics: &[&str],
+        data: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<Vec<OpConfirm>, Error> {
+        let client = self.client();
+        let mut client = client.lock().await;
+        let mut result = Vec::with_capacity(topics.len());
+        for topic in topics {
+            result.push(client.publish(topic, data.clone(), qos).await?);
+        }
+        Ok(result)
+    }
     /// Call the method, no response is required
     async fn call0(
         &self,
@@ -302,18 +1700,83 @@ pub trait Rpc {
         params: Cow<'async_trait>,
         qos: QoS,
     ) -> Result<RpcEvent, RpcError>;
+    /// Call the method and get the response, intermediate progress events, sent by the handler,
+    /// are pushed into the provided channel. If the channel is full, progress events are dropped
+    ///
+    /// The default implementation performs a regular call, no progress events are pushed
+    async fn call_with_progress(
+        &self,
+        target: &str,
+        method: &str,
+        params: Cow<'async_trait>,
+        qos: QoS,
+        _progress_tx: async_channel::Sender<RpcEvent>,
+    ) -> Result<RpcEvent, RpcError> {
+        self.call(target, method, params, qos).await
+    }
+    /// Call the method with the specified options and get the response
+    ///
+    /// The default implementation ignores the options and performs a regular call
+    async fn call_with_options(
+        &self,
+        target: &str,
+        method: &str,
+        params: Cow<'async_trait>,
+        qos: QoS,
+        _opts: CallOptions,
+    ) -> Result<RpcEvent, RpcError> {
+        self.call(target, method, params, qos).await
+    }
     fn is_connected(&self) -> bool;
 }
 
 #[allow(clippy::module_name_repetitions)]
 pub struct RpcClient {
-    call_id: std::sync::Mutex<u32>,
+    call_id: atomic::AtomicU32,
     timeout: Option<Duration>,
     client: Arc<Mutex<dyn AsyncClient>>,
     processor_fut: Arc<std::sync::Mutex<JoinHandle<()>>>,
     pinger_fut: Option<JoinHandle<()>>,
     calls: CallMap,
     connected: Option<Arc<atomic::AtomicBool>>,
+    content_type: Option<ContentType>,
+    retry_policy: Option<RetryPolicy>,
+    call_stats: Option<std::sync::Mutex<HashMap<String, MethodStatsCollector>>>,
+    slow_call_threshold: Option<Duration>,
+    cache: Option<ResponseCache>,
+    codec: Arc<dyn RpcCodec>,
+    limiters: Arc<HashMap<String, Arc<MethodLimiter>>>,
+    in_flight: Arc<InFlightCalls>,
+}
+
+// a failed call attempt
+enum AttemptError {
+    // the request has not been delivered
+    Transport(Error),
+    // the request has been delivered, but no response received
+    Lost(Error),
+    // error reply from the handler
+    Reply(RpcError),
+}
+
+impl AttemptError {
+    fn is_retryable(&self, idempotent: bool) -> bool {
+        match self {
+            AttemptError::Transport(_) => true,
+            AttemptError::Lost(_) => idempotent,
+            AttemptError::Reply(e) => idempotent && e.code == RPC_ERROR_CODE_INTERNAL,
+        }
+    }
+}
+
+impl From<AttemptError> for RpcError {
+    #[inline]
+    fn from(e: AttemptError) -> RpcError {
+        match e {
+            AttemptError::Transport(e) | AttemptError::Lost(e) => e.into(),
+            AttemptError::Reply(e) => e,
+        }
+    }
 }
 
 #[allow(clippy::too_many_lines)]
@@ -323,13 +1786,19 @@ async fn processor<C, H>(
     calls: CallMap,
     handlers: Arc<H>,
     opts: Options,
+    limiters: Arc<HashMap<String, Arc<MethodLimiter>>>,
+    in_flight: Arc<InFlightCalls>,
 ) where
     C: AsyncClient + 'static,
     H: RpcHandlers + Send + Sync + 'static,
 {
+    let codec: Arc<dyn RpcCodec> = opts
+        .codec
+        .clone()
+        .unwrap_or_else(|| Arc::new(ElbusCodec::default()));
     while let Ok(frame) = rx.recv().await {
         if frame.kind() == FrameKind::Message {
-            match TryInto::<RpcEvent>::try_into(frame) {
+            match RpcEvent::decode(frame, codec.as_ref()) {
                 Ok(event) => match event.kind() {
                     RpcEventKind::Notification => {
                         trace!("RPC notification from {}", event.frame().sender());
@@ -345,46 +1814,52 @@ async fn processor<C, H>(
                     RpcEventKind::Request => {
                         let id = event.id();
                         trace!(
-                            "RPC request from {}, id: {}, method: {:?}",
+                            "RPC request from {}, id: {}, method: {:?}, trace: {:?}",
                             event.frame().sender(),
                             id,
-                            event.method()
+                            event.method(),
+                            event.trace_id()
                         );
+                        let mut event = event;
+                        event.codec = opts.codec.clone();
+                        let codec = codec.clone();
                         let ev = if id > 0 {
-                            Some((event.frame().sender().to_owned(), processor_client.clone()))
+                            event.responder = Some(processor_client.clone());
+                            Some((
+                                event.frame().sender().to_owned(),
+                                processor_client.clone(),
+                                event.response_headers(),
+                            ))
                         } else {
                             None
                         };
                         let h = handlers.clone();
+                        let limiter = event
+                            .parse_method()
+                            .ok()
+                            .and_then(|method| limiters.get(method).cloned());
+                        let shutdown = in_flight.shutdown.load(atomic::Ordering::SeqCst);
+                        let guard = (!shutdown).then(|| in_flight.enter());
                         tokio::spawn(async move {
                             let qos = if event.frame().is_realtime() {
                                 QoS::RealtimeProcessed
                             } else {
                                 QoS::Processed
                             };
-                            let res = h.handle_call(event).await;
-                            if let Some((target, cl)) = ev {
-                                macro_rules! send_reply {
-                                    ($payload: expr, $result: expr) => {{
-                                        let mut client = cl.lock().await;
-                                        if let Some(result) = $result {
-                                            client
-                                                .zc_send(&target, $payload, result.into(), qos)
-                                                .await
-                                        } else {
-                                            client
-                                                .zc_send(&target, $payload, (&[][..]).into(), qos)
-                                                .await
-                                        }
-                                    }};
-                                }
-                                match res {
+                            let res = if shutdown {
+                                Err(RpcError::unavailable())
+                            } else if let Some(limiter) = limiter {
+                                limiter.handle_call(h.as_ref(), event).await
+                            } else {
+                                h.handle_call(event).await
+                            };
+                            // the reply is sent after the handler is finished
+                            drop(guard);
+                            if let Some((target, cl, headers)) = ev {
+                                let (kind, code, result) = match res {
                                     Ok(v) => {
                                         trace!("Sending RPC reply id {} to {}", id, target);
-                                        let mut payload = Vec::with_capacity(5);
-                                        payload.push(RPC_REPLY);
-                                        payload.extend_from_slice(&id.to_le_bytes());
-                                        let _r = send_reply!(payload.into(), v);
+                                        (RpcEventKind::Reply, 0, v)
                                     }
                                     Err(e) => {
                                         trace!(
@@ -393,12 +1868,26 @@ async fn processor<C, H>(
                                             id,
                                             target,
                                         );
-                                        let mut payload = Vec::with_capacity(7);
-                                        payload.push(RPC_ERROR);
-                                        payload.extend_from_slice(&id.to_le_bytes());
-                                        payload.extend_from_slice(&e.code.to_le_bytes());
-                                        let _r = send_reply!(payload.into(), e.data);
+                                        (RpcEventKind::ErrorReply, e.code, e.data)
+                                    }
+                                };
+                                let envelope = RpcEnvelope {
+                                    kind,
+                                    id,
+                                    method: None,
+                                    code,
+                                    headers: headers.as_ref(),
+                                };
+                                let payload = result.map_or_else(|| (&[][..]).into(), Into::into);
+                                match codec.encode(&envelope, payload) {
+                                    Ok((header, payload)) => {
+                                        let _r = cl
+                                            .lock()
+                                            .await
+                                            .zc_send(&target, header.into(), payload, qos)
+                                            .await;
                                     }
+                                    Err(e) => error!("{}", e),
                                 }
                             }
                         });
@@ -406,17 +1895,27 @@ async fn processor<C, H>(
                     RpcEventKind::Reply | RpcEventKind::ErrorReply => {
                         let id = event.id();
                         trace!(
-                            "RPC {} from {}, id: {}",
+                            "RPC {} from {}, id: {}, trace: {:?}",
                             event.kind(),
                             event.frame().sender(),
-                            id
+                            id,
+                            event.trace_id()
                         );
-                        if let Some(tx) = { calls.lock().unwrap().remove(&id) } {
-                            let _r = tx.send(event);
+                        if let Some(call) = calls.remove(id) {
+                            let _r = call.tx.send(event);
                         } else {
                             warn!("orphaned RPC response: {}", id);
                         }
                     }
+                    RpcEventKind::Progress => {
+                        let id = event.id();
+                        trace!("RPC progress from {}, id: {}", event.frame().sender(), id);
+                        if let Some(tx) = calls.progress_tx(id) {
+                            if tx.try_send(event).is_err() {
+                                warn!("RPC progress event dropped: {}", id);
+                            }
+                        }
+                    }
                 },
                 Err(e) => {
                     error!("{}", e);
@@ -433,15 +1932,57 @@ async fn processor<C, H>(
     }
 }
 
+/// Converts an owned payload to a referenced one, to share it between multiple frames
+#[inline]
+fn shared_payload(payload: Cow<'_>) -> Cow<'_> {
+    match payload {
+        Cow::Owned(v) => Cow::Referenced(Arc::new(v)),
+        v => v,
+    }
+}
+
 #[inline]
-fn prepare_call_payload(method: &str, id_bytes: &[u8]) -> Vec<u8> {
+fn prepare_call_payload(
+    method: &str,
+    id_bytes: &[u8],
+    headers: Option<&RpcHeaders>,
+) -> Result<Vec<u8>, Error> {
     let m = method.as_bytes();
     let mut payload = Vec::with_capacity(m.len() + 6);
-    payload.push(RPC_REQUEST);
-    payload.extend(id_bytes);
+    if let Some(h) = headers {
+        payload.push(RPC_REQUEST | RPC_FLAG_HEADERS);
+        payload.extend(id_bytes);
+        h.encode(&mut payload)?;
+    } else {
+        payload.push(RPC_REQUEST);
+        payload.extend(id_bytes);
+    }
     payload.extend(m);
     payload.push(0x00);
-    payload
+    Ok(payload)
+}
+
+#[inline]
+fn prepare_response_header(
+    kind: u8,
+    id: u32,
+    code: Option<i16>,
+    headers: Option<&RpcHeaders>,
+) -> Result<Vec<u8>, Error> {
+    let mut payload = Vec::with_capacity(7);
+    payload.push(if headers.is_some() {
+        kind | RPC_FLAG_HEADERS
+    } else {
+        kind
+    });
+    payload.extend_from_slice(&id.to_le_bytes());
+    if let Some(c) = code {
+        payload.extend_from_slice(&c.to_le_bytes());
+    }
+    if let Some(h) = headers {
+        h.encode(&mut payload)?;
+    }
+    Ok(payload)
 }
 
 impl RpcClient {
@@ -480,12 +2021,34 @@ impl RpcClient {
         let connected = client.get_connected_beacon();
         let client = Arc::new(Mutex::new(client));
         let calls: CallMap = <_>::default();
+        let content_type = opts.content_type;
+        let retry_policy = opts.retry_policy.clone();
+        let call_stats = if opts.call_stats {
+            Some(<_>::default())
+        } else {
+            None
+        };
+        let slow_call_threshold = opts.slow_call_threshold;
+        let cache = opts.cache_policy.clone().map(ResponseCache::new);
+        let codec: Arc<dyn RpcCodec> = opts
+            .codec
+            .clone()
+            .unwrap_or_else(|| Arc::new(ElbusCodec::default()));
+        let limiters: Arc<HashMap<String, Arc<MethodLimiter>>> = Arc::new(
+            opts.method_limits
+                .iter()
+                .map(|(method, limits)| (method.clone(), Arc::new(limits.into())))
+                .collect(),
+        );
+        let in_flight: Arc<InFlightCalls> = <_>::default();
         let processor_fut = Arc::new(std::sync::Mutex::new(tokio::spawn(processor(
             rx,
             client.clone(),
             calls.clone(),
             Arc::new(handlers),
             opts,
+            limiters.clone(),
+            in_flight.clone(),
         ))));
         let pinger_client = client.clone();
         let pfut = processor_fut.clone();
@@ -502,108 +2065,428 @@ impl RpcClient {
             })
         });
         Self {
-            call_id: std::sync::Mutex::new(0),
+            call_id: atomic::AtomicU32::new(0),
             timeout,
             client,
             processor_fut,
             pinger_fut,
             calls,
             connected,
+            content_type,
+            retry_policy,
+            call_stats,
+            slow_call_threshold,
+            cache,
+            codec,
+            limiters,
+            in_flight,
         }
     }
-}
-
-#[async_trait]
-impl Rpc for RpcClient {
-    #[inline]
-    fn client(&self) -> Arc<Mutex<(dyn AsyncClient + 'static)>> {
-        self.client.clone()
+    /// Gracefully stops serving incoming calls: new calls and calls, queued by method limits, are
+    /// replied with the "unavailable" error, handlers being executed are waited up to the
+    /// timeout. Returns the number of handlers left if the timeout has been expired
+    ///
+    /// The client keeps processing replies to outgoing calls and rejecting incoming calls until
+    /// dropped
+    pub async fn shutdown(&self, timeout: Duration) -> usize {
+        self.in_flight
+            .shutdown
+            .store(true, atomic::Ordering::SeqCst);
+        for limiter in self.limiters.values() {
+            limiter.close();
+        }
+        self.in_flight.wait(timeout).await
     }
+    /// Returns true if the shutdown has been started
     #[inline]
-    async fn notify(
+    pub fn is_shutdown(&self) -> bool {
+        self.in_flight.shutdown.load(atomic::Ordering::SeqCst)
+    }
+    /// Per-method statistics of outgoing calls, empty unless enabled in options
+    ///
+    /// # Panics
+    ///
+    /// Will panic on poisoned mutex
+    pub fn stats(&self) -> BTreeMap<String, RpcMethodStats> {
+        self.call_stats
+            .as_ref()
+            .map_or_else(BTreeMap::new, |call_stats| {
+                call_stats
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(method, c)| (method.clone(), c.stats()))
+                    .collect()
+            })
+    }
+    /// Clears the collected call statistics
+    ///
+    /// # Panics
+    ///
+    /// Will panic on poisoned mutex
+    pub fn reset_stats(&self) {
+        if let Some(ref call_stats) = self.call_stats {
+            call_stats.lock().unwrap().clear();
+        }
+    }
+    /// Clears the response cache
+    ///
+    /// # Panics
+    ///
+    /// Will panic on poisoned mutex
+    pub fn clear_cache(&self) {
+        if let Some(ref cache) = self.cache {
+            cache.clear();
+        }
+    }
+    /// Call headers: the content type (per-call or the default one) + custom headers
+    fn call_headers(&self, opts: &CallOptions) -> Option<RpcHeaders> {
+        let mut headers = opts.headers.clone();
+        if let Some(ct) = opts.content_type.or(self.content_type) {
+            headers.set(RPC_HEADER_CONTENT_TYPE, ct.as_str());
+        }
+        if headers.is_empty() {
+            None
+        } else {
+            Some(headers)
+        }
+    }
+    /// # Panics
+    ///
+    /// Will panic on poisoned mutex
+    async fn call_with(
         &self,
         target: &str,
-        data: Cow<'async_trait>,
+        method: &str,
+        params: Cow<'_>,
         qos: QoS,
-    ) -> Result<OpConfirm, Error> {
-        self.client
-            .lock()
-            .await
-            .zc_send(target, (&[RPC_NOTIFICATION][..]).into(), data, qos)
-            .await
+        opts: CallOptions,
+    ) -> Result<RpcEvent, RpcError> {
+        let cache = self.cache.as_ref().filter(|c| {
+            !opts.no_cache && opts.progress_tx.is_none() && c.policy.is_cacheable(method)
+        });
+        if let Some(cache) = cache {
+            let key = CacheKey {
+                target: target.to_owned(),
+                method: method.to_owned(),
+                content_type: opts.content_type.or(self.content_type),
+                params: params.as_slice().to_vec(),
+            };
+            if let Some(event) = cache.get(&key) {
+                trace!("RPC cache hit {}::{}", target, method);
+                return Ok(event);
+            }
+            let result = self.call_timed(target, method, params, qos, opts).await;
+            if let Ok(ref event) = result {
+                cache.insert(key, event.clone());
+            }
+            result
+        } else {
+            self.call_timed(target, method, params, qos, opts).await
+        }
     }
-    async fn call0(
+    async fn call_timed(
         &self,
         target: &str,
         method: &str,
-        params: Cow<'async_trait>,
+        params: Cow<'_>,
         qos: QoS,
-    ) -> Result<OpConfirm, Error> {
-        let payload = prepare_call_payload(method, &[0, 0, 0, 0]);
-        self.client
-            .lock()
-            .await
-            .zc_send(target, payload.into(), params, qos)
-            .await
+        opts: CallOptions,
+    ) -> Result<RpcEvent, RpcError> {
+        if self.call_stats.is_none() && self.slow_call_threshold.is_none() {
+            return self.call_retry(target, method, params, qos, opts).await;
+        }
+        let started = Instant::now();
+        let result = self.call_retry(target, method, params, qos, opts).await;
+        let elapsed = started.elapsed();
+        if let Some(ref call_stats) = self.call_stats {
+            call_stats
+                .lock()
+                .unwrap()
+                .entry(method.to_owned())
+                .or_default()
+                .record(elapsed, result.is_err());
+        }
+        if let Some(threshold) = self.slow_call_threshold {
+            if elapsed > threshold {
+                warn!(
+                    "slow RPC call {}::{}: {:?}{}",
+                    target,
+                    method,
+                    elapsed,
+                    if result.is_err() { " (failed)" } else { "" }
+                );
+            }
+        }
+        result
+    }
+    async fn call_retry(
+        &self,
+        target: &str,
+        method: &str,
+        params: Cow<'_>,
+        qos: QoS,
+        opts: CallOptions,
+    ) -> Result<RpcEvent, RpcError> {
+        let headers = self.call_headers(&opts);
+        let policy = opts.retry_policy.as_ref().or(self.retry_policy.as_ref());
+        let Some(policy) = policy.filter(|p| p.max_attempts > 1) else {
+            return self
+                .call_attempt(
+                    target,
+                    method,
+                    params,
+                    qos,
+                    headers.as_ref(),
+                    opts.progress_tx,
+                    policy.and_then(|p| p.attempt_timeout),
+                )
+                .await
+                .map_err(Into::into);
+        };
+        let idempotent = opts.idempotent || policy.is_idempotent(method);
+        let params = shared_payload(params);
+        let mut attempt = 1;
+        loop {
+            match self
+                .call_attempt(
+                    target,
+                    method,
+                    params.clone(),
+                    qos,
+                    headers.as_ref(),
+                    opts.progress_tx.clone(),
+                    policy.attempt_timeout,
+                )
+                .await
+            {
+                Ok(v) => return Ok(v),
+                Err(e) if attempt < policy.max_attempts && e.is_retryable(idempotent) => {
+                    let delay = policy.delay(attempt);
+                    warn!(
+                        "RPC call {}::{} attempt {} failed, retrying in {:?}",
+                        target, method, attempt, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
     }
     /// # Panics
     ///
     /// Will panic on poisoned mutex
-    async fn call(
+    #[allow(clippy::too_many_arguments)]
+    async fn call_attempt(
         &self,
         target: &str,
         method: &str,
-        params: Cow<'async_trait>,
+        params: Cow<'_>,
         qos: QoS,
-    ) -> Result<RpcEvent, RpcError> {
-        let call_id = {
-            let mut ci = self.call_id.lock().unwrap();
-            let mut call_id = *ci;
-            if call_id == u32::MAX {
-                call_id = 1;
-            } else {
-                call_id += 1;
-            }
-            *ci = call_id;
-            call_id
+        headers: Option<&RpcHeaders>,
+        progress_tx: Option<async_channel::Sender<RpcEvent>>,
+        attempt_timeout: Option<Duration>,
+    ) -> Result<RpcEvent, AttemptError> {
+        // zero call id means no response is required, skip it on wrap
+        let mut call_id = self
+            .call_id
+            .fetch_add(1, atomic::Ordering::Relaxed)
+            .wrapping_add(1);
+        if call_id == 0 {
+            call_id = self
+                .call_id
+                .fetch_add(1, atomic::Ordering::Relaxed)
+                .wrapping_add(1);
+        }
+        let envelope = RpcEnvelope {
+            kind: RpcEventKind::Request,
+            id: call_id,
+            method: Some(method),
+            code: 0,
+            headers,
         };
-        let payload = prepare_call_payload(method, &call_id.to_le_bytes());
+        let (header, params) = self
+            .codec
+            .encode(&envelope, params)
+            .map_err(AttemptError::Transport)?;
         let (tx, rx) = oneshot::channel();
-        self.calls.lock().unwrap().insert(call_id, tx);
+        self.calls.insert(call_id, PendingCall { tx, progress_tx });
         macro_rules! unwrap_or_cancel {
-            ($result: expr) => {
+            ($result: expr, $kind: path) => {
                 match $result {
                     Ok(v) => v,
                     Err(e) => {
-                        self.calls.lock().unwrap().remove(&call_id);
-                        return Err(Into::<Error>::into(e).into());
+                        self.calls.remove(call_id);
+                        return Err($kind(e.into()));
                     }
                 }
             };
         }
         let opc = {
             let mut client = self.client.lock().await;
-            let fut = client.zc_send(target, payload.into(), params, qos);
+            let fut = client.zc_send(target, header.into(), params, qos);
             if let Some(timeout) = self.timeout {
-                unwrap_or_cancel!(unwrap_or_cancel!(tokio::time::timeout(timeout, fut).await))
+                unwrap_or_cancel!(
+                    unwrap_or_cancel!(
+                        tokio::time::timeout(timeout, fut).await,
+                        AttemptError::Transport
+                    ),
+                    AttemptError::Transport
+                )
             } else {
-                unwrap_or_cancel!(fut.await)
+                unwrap_or_cancel!(fut.await, AttemptError::Transport)
             }
         };
         if let Some(c) = opc {
-            unwrap_or_cancel!(unwrap_or_cancel!(c.await));
+            unwrap_or_cancel!(
+                unwrap_or_cancel!(c.await, AttemptError::Transport),
+                AttemptError::Transport
+            );
+        }
+        let result = if let Some(timeout) = attempt_timeout {
+            unwrap_or_cancel!(tokio::time::timeout(timeout, rx).await, AttemptError::Lost)
+        } else {
+            rx.await
         }
-        let result = rx.await.map_err(Into::<Error>::into)?;
+        .map_err(|e| AttemptError::Lost(e.into()))?;
         if let Ok(e) = TryInto::<RpcError>::try_into(&result) {
-            Err(e)
+            Err(AttemptError::Reply(e))
         } else {
             Ok(result)
         }
     }
+}
+
+#[async_trait]
+impl Rpc for RpcClient {
+    #[inline]
+    fn client(&self) -> Arc<Mutex<dyn AsyncClient + 'static>> {
+        self.client.clone()
+    }
+    #[inline]
+    async fn notify(
+        &self,
+        target: &str,
+        data: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error> {
+        let (header, data) = self.codec.encode(&RpcEnvelope::notification(), data)?;
+        self.client
+            .lock()
+            .await
+            .zc_send(target, header.into(), data, qos)
+            .await
+    }
+    async fn notify_bulk(
+        &self,
+        targets: &[&str],
+        data: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<Vec<OpConfirm>, Error> {
+        let (header, data) = self.codec.encode(&RpcEnvelope::notification(), data)?;
+        let header = shared_payload(header.into());
+        let data = shared_payload(data);
+        let mut result = Vec::with_capacity(targets.len());
+        let mut client = self.client.lock().await;
+        for target in targets {
+            result.push(
+                client
+                    .zc_send(target, header.clone(), data.clone(), qos)
+                    .await?,
+            );
+        }
+        Ok(result)
+    }
+    #[inline]
+    async fn notify_topic(
+        &self,
+        topic: &str,
+        data: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error> {
+        self.client.lock().await.publish(topic, data, qos).await
+    }
+    async fn notify_topic_bulk(
+        &self,
+        topics: &[&str],
+        data: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<Vec<OpConfirm>, Error> {
+        let data = shared_payload(data);
+        let mut result = Vec::with_capacity(topics.len());
+        let mut client = self.client.lock().await;
+        for topic in topics {
+            result.push(client.publish(topic, data.clone(), qos).await?);
+        }
+        Ok(result)
+    }
+    async fn call0(
+        &self,
+        target: &str,
+        method: &str,
+        params: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<OpConfirm, Error> {
+        let headers = self.call_headers(&CallOptions::default());
+        let envelope = RpcEnvelope {
+            kind: RpcEventKind::Request,
+            id: 0,
+            method: Some(method),
+            code: 0,
+            headers: headers.as_ref(),
+        };
+        let (header, params) = self.codec.encode(&envelope, params)?;
+        self.client
+            .lock()
+            .await
+            .zc_send(target, header.into(), params, qos)
+            .await
+    }
+    #[inline]
+    async fn call(
+        &self,
+        target: &str,
+        method: &str,
+        params: Cow<'async_trait>,
+        qos: QoS,
+    ) -> Result<RpcEvent, RpcError> {
+        self.call_with(target, method, params, qos, CallOptions::default())
+            .await
+    }
+    #[inline]
+    async fn call_with_progress(
+        &self,
+        target: &str,
+        method: &str,
+        params: Cow<'async_trait>,
+        qos: QoS,
+        progress_tx: async_channel::Sender<RpcEvent>,
+    ) -> Result<RpcEvent, RpcError> {
+        self.call_with(
+            target,
+            method,
+            params,
+            qos,
+            CallOptions::new().progress(progress_tx),
+        )
+        .await
+    }
+    #[inline]
+    async fn call_with_options(
+        &self,
+        target: &str,
+        method: &str,
+        params: Cow<'async_trait>,
+        qos: QoS,
+        opts: CallOptions,
+    ) -> Result<RpcEvent, RpcError> {
+        self.call_with(target, method, params, qos, opts).await
+    }
     fn is_connected(&self) -> bool {
         self.connected
             .as_ref()
-            .map_or(true, |b| b.load(atomic::Ordering::SeqCst))
+            .is_none_or(|b| b.load(atomic::Ordering::SeqCst))
     }
 }
 
@@ -681,6 +2564,13 @@ impl RpcError {
             data: err,
         }
     }
+    #[inline]
+    pub fn unavailable() -> Self {
+        Self {
+            code: RPC_ERROR_CODE_UNAVAILABLE,
+            data: rpc_err_str("RPC server is shutting down"),
+        }
+    }
     /// Converts displayable to Vec<u8>
     #[inline]
     pub fn convert_data(v: impl fmt::Display) -> Vec<u8> {
@@ -737,3 +2627,112 @@ impl fmt::Display for RpcError {
 
 #[allow(clippy::module_name_repetitions)]
 pub type RpcResult = Result<Option<Vec<u8>>, RpcError>;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use serde::ser::{SerializeMap, Serializer};
+    pub use serde::Serialize;
+}
+
+/// Generates a typed async client for RPC methods
+///
+/// Params are sent as a MessagePack map with argument names as keys (no payload if the method has
+/// no arguments), responses are deserialized according to the reply content type. Methods are
+/// called by the function name, unless a different one is specified. If the return type is
+/// omitted, the response payload is ignored.
+///
+/// Example:
+///
+/// ```rust,ignore
+/// elbus::rpc_client! {
+///     /// Registry service client
+///     pub struct RegistryClient {
+///         /// Gets the key value
+///         fn get(key: String) -> serde_value::Value;
+///         fn set(key: String, value: serde_value::Value) = "key.set";
+///         fn list() -> Vec<String>;
+///     }
+/// }
+///
+/// let registry = RegistryClient::new(&rpc, "registry");
+/// registry.set("a".to_owned(), serde_value::Value::U8(1)).await?;
+/// let keys = registry.list().await?;
+/// ```
+#[macro_export]
+macro_rules! rpc_client {
+    (
+        $(#[$meta: meta])*
+        $vis: vis struct $client: ident {
+            $(
+                $(#[$fn_meta: meta])*
+                fn $fn_name: ident ($($arg: ident : $arg_ty: ty),* $(,)?)
+                    $(-> $ret: ty)? $(= $method: literal)?;
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $client<'a, R: $crate::rpc::Rpc + ?Sized> {
+            rpc: &'a R,
+            target: &'a str,
+        }
+
+        impl<'a, R: $crate::rpc::Rpc + ?Sized> $client<'a, R> {
+            #[inline]
+            pub fn new(rpc: &'a R, target: &'a str) -> Self {
+                Self { rpc, target }
+            }
+            #[inline]
+            pub fn target(&self) -> &str {
+                self.target
+            }
+            $(
+                $(#[$fn_meta])*
+                #[allow(clippy::unused_unit)]
+                pub async fn $fn_name(
+                    &self,
+                    $($arg: $arg_ty),*
+                ) -> Result<$crate::rpc_client!(@ret $($ret)?), $crate::rpc::RpcError> {
+                    let method = $crate::rpc_client!(@method $fn_name $($method)?);
+                    let payload: Vec<u8> = $crate::rpc_client!(@params $($arg: $arg_ty),*);
+                    #[allow(unused_variables)]
+                    let event = self
+                        .rpc
+                        .call(self.target, method, payload.into(), $crate::QoS::Processed)
+                        .await?;
+                    $crate::rpc_client!(@unpack event $($ret)?)
+                }
+            )*
+        }
+    };
+    (@params) => { Vec::new() };
+    (@params $($arg: ident : $arg_ty: ty),+) => {{
+        struct Params<'p> {
+            $($arg: &'p $arg_ty,)*
+        }
+        impl<'p> $crate::rpc::__private::Serialize for Params<'p> {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: $crate::rpc::__private::Serializer,
+            {
+                use $crate::rpc::__private::SerializeMap;
+                let mut map = serializer.serialize_map(Some([$(stringify!($arg)),*].len()))?;
+                $(map.serialize_entry(stringify!($arg), self.$arg)?;)*
+                map.end()
+            }
+        }
+        $crate::rpc::ContentType::MsgPack.pack(&Params { $($arg: &$arg),* })?
+    }};
+    (@ret $ret: ty) => { $ret };
+    (@ret) => { () };
+    (@method $fn_name: ident $method: literal) => { $method };
+    (@method $fn_name: ident) => { stringify!($fn_name) };
+    (@unpack $event: ident $ret: ty) => {
+        if $event.payload().is_empty() {
+            // empty reply is unpacked as nil, to support unit and optional types
+            $crate::rpc::ContentType::MsgPack.unpack::<$ret>(&[0xc0])
+        } else {
+            $event.unpack::<$ret>()
+        }
+    };
+    (@unpack $event: ident) => { Ok(()) };
+}
@@ -0,0 +1,225 @@
+use crate::broker::Client;
+use crate::client::AsyncClient;
+use crate::{Error, EventChannel, FrameKind, QoS};
+use log::{error, info, warn};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+pub const DEFAULT_SERIAL_BAUD_RATE: u32 = 115_200;
+pub const DEFAULT_SERIAL_MAX_FRAME_SIZE: usize = 65_535;
+
+/// The port is reopened with the delay if the device is gone (e.g. a USB adapter is unplugged)
+const SERIAL_REOPEN_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum SerialFraming {
+    /// Lines, terminated with "\n" ("\r\n" and "\n" are stripped, empty lines are skipped)
+    #[default]
+    Lines,
+    /// Packets, terminated with the byte
+    Delimiter(u8),
+    /// Packets, prefixed with the length (u16 BE)
+    Length,
+}
+
+/// Parses "lines", "length" or "delimiter:HEX", e.g. "delimiter:00"
+impl FromStr for SerialFraming {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lines" => Ok(SerialFraming::Lines),
+            "length" => Ok(SerialFraming::Length),
+            _ => {
+                if let Some(d) = s.strip_prefix("delimiter:") {
+                    u8::from_str_radix(d, 16)
+                        .map(SerialFraming::Delimiter)
+                        .map_err(|e| Error::data(format!("invalid delimiter {}: {}", d, e)))
+                } else {
+                    Err(Error::data(format!("invalid serial framing: {}", s)))
+                }
+            }
+        }
+    }
+}
+
+/// Exposes a serial device as an internal broker client (requires "serial" feature, spawned with
+/// Broker::spawn_serial): frames, arriving on the port, are published to the topic, payloads of
+/// messages and broadcasts, sent to the client, are written to the port
+#[derive(Debug, Clone)]
+pub struct SerialBridge {
+    device: String,
+    topic: String,
+    baud_rate: u32,
+    framing: SerialFraming,
+    max_frame_size: usize,
+}
+
+impl SerialBridge {
+    /// device - the serial device path (e.g. /dev/ttyUSB0 or COM3), topic - the topic incoming
+    /// frames are published to
+    pub fn new(device: &str, topic: &str) -> Self {
+        Self {
+            device: device.to_owned(),
+            topic: topic.to_owned(),
+            baud_rate: DEFAULT_SERIAL_BAUD_RATE,
+            framing: SerialFraming::default(),
+            max_frame_size: DEFAULT_SERIAL_MAX_FRAME_SIZE,
+        }
+    }
+    #[inline]
+    pub fn baud_rate(mut self, baud_rate: u32) -> Self {
+        self.baud_rate = baud_rate;
+        self
+    }
+    #[inline]
+    pub fn framing(mut self, framing: SerialFraming) -> Self {
+        self.framing = framing;
+        self
+    }
+    /// Incoming lines and delimited packets, larger than the limit, are dropped
+    #[inline]
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = size;
+        self
+    }
+    #[inline]
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+    pub(crate) fn open(&self) -> Result<SerialStream, Error> {
+        tokio_serial::new(&self.device, self.baud_rate)
+            .open_native_async()
+            .map_err(|e| Error::io(format!("{}: {}", self.device, e)))
+    }
+    /// Runs the bridge, the port is reopened on errors
+    pub(crate) async fn run(self, mut client: Client, rx: EventChannel, mut port: SerialStream) {
+        loop {
+            let (r, w) = tokio::io::split(port);
+            let writer = tokio::spawn(write_frames(
+                w,
+                rx.clone(),
+                self.framing,
+                self.device.clone(),
+            ));
+            if let Err(e) = self.read_frames(r, &mut client).await {
+                error!("serial {}: {}", self.device, e);
+            }
+            writer.abort();
+            loop {
+                tokio::time::sleep(SERIAL_REOPEN_DELAY).await;
+                if let Ok(p) = self.open() {
+                    info!("serial {} reopened", self.device);
+                    port = p;
+                    break;
+                }
+            }
+        }
+    }
+    async fn read_frames(
+        &self,
+        r: ReadHalf<SerialStream>,
+        client: &mut Client,
+    ) -> Result<(), Error> {
+        let mut reader = BufReader::new(r);
+        let mut buf = Vec::new();
+        // the rest of an oversized frame is skipped up to the delimiter
+        let mut skipping = false;
+        loop {
+            buf.clear();
+            match self.framing {
+                SerialFraming::Lines | SerialFraming::Delimiter(_) => {
+                    let delimiter = if let SerialFraming::Delimiter(d) = self.framing {
+                        d
+                    } else {
+                        b'\n'
+                    };
+                    let n = (&mut reader)
+                        .take(self.max_frame_size as u64 + 1)
+                        .read_until(delimiter, &mut buf)
+                        .await?;
+                    if n == 0 {
+                        return Err(Error::io("the port is closed"));
+                    }
+                    if buf.last() == Some(&delimiter) {
+                        buf.pop();
+                        if skipping {
+                            skipping = false;
+                            continue;
+                        }
+                    } else {
+                        if !skipping {
+                            warn!(
+                                "serial {}: frame is larger than {} bytes, dropped",
+                                self.device, self.max_frame_size
+                            );
+                        }
+                        skipping = true;
+                        continue;
+                    }
+                    if self.framing == SerialFraming::Lines {
+                        if buf.last() == Some(&b'\r') {
+                            buf.pop();
+                        }
+                        if buf.is_empty() {
+                            continue;
+                        }
+                    }
+                }
+                SerialFraming::Length => {
+                    let mut len = [0_u8; 2];
+                    reader.read_exact(&mut len).await?;
+                    buf.resize(usize::from(u16::from_be_bytes(len)), 0);
+                    reader.read_exact(&mut buf).await?;
+                }
+            }
+            client
+                .publish(&self.topic, buf.as_slice().into(), QoS::No)
+                .await?;
+        }
+    }
+}
+
+async fn write_frames(
+    mut w: WriteHalf<SerialStream>,
+    rx: EventChannel,
+    framing: SerialFraming,
+    device: String,
+) {
+    while let Ok(frame) = rx.recv().await {
+        if frame.kind() != FrameKind::Message && frame.kind() != FrameKind::Broadcast {
+            continue;
+        }
+        let payload = frame.payload();
+        let mut buf = Vec::with_capacity(payload.len() + 2);
+        match framing {
+            SerialFraming::Lines => {
+                buf.extend_from_slice(payload);
+                if !payload.ends_with(b"\n") {
+                    buf.push(b'\n');
+                }
+            }
+            SerialFraming::Delimiter(d) => {
+                buf.extend_from_slice(payload);
+                buf.push(d);
+            }
+            SerialFraming::Length => {
+                let Ok(len) = u16::try_from(payload.len()) else {
+                    warn!(
+                        "serial {}: payload of {} is too large, dropped",
+                        device,
+                        frame.sender()
+                    );
+                    continue;
+                };
+                buf.extend_from_slice(&len.to_be_bytes());
+                buf.extend_from_slice(payload);
+            }
+        }
+        if let Err(e) = w.write_all(&buf).await {
+            error!("serial {}: {}", device, e);
+            break;
+        }
+    }
+}
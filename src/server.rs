@@ -1,7 +1,7 @@
 #[macro_use]
 extern crate lazy_static;
 
-#[cfg(not(feature = "std-alloc"))]
+#[cfg(all(unix, not(feature = "std-alloc")))]
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
@@ -10,8 +10,9 @@ use clap::Parser;
 use colored::Colorize;
 use log::{error, info, trace};
 use log::{Level, LevelFilter};
-use std::sync::atomic;
+use std::sync::{atomic, Arc};
 use std::time::Duration;
+#[cfg(unix)]
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::Mutex;
 use tokio::time::sleep;
@@ -19,12 +20,33 @@ use tokio::time::sleep;
 #[cfg(feature = "rpc")]
 use elbus::broker::BrokerEvent;
 
-use elbus::broker::{Broker, ServerConfig};
+use elbus::broker::{
+    Broker, ClientNamePolicy, MaskFormat, MemoryOverflowPolicy, ServerConfig, SubscriptionLimits,
+    TokenList,
+};
+#[cfg(feature = "testing")]
+use elbus::chaos::ChaosRule;
+use elbus::comm::TcpOptions;
+use elbus::metrics::StatsdMetrics;
+#[cfg(feature = "serial")]
+use elbus::serial::SerialBridge;
+use elbus::sink::FileSink;
+#[cfg(feature = "sql")]
+use elbus::sink::SqlSink;
+#[cfg(unix)]
+use elbus::sink::SyslogSink;
+#[cfg(feature = "webhook")]
+use elbus::sink::WebhookSink;
+
+const DEFAULT_STATSD_INTERVAL: Duration = Duration::from_secs(10);
+
+const NAMED_PIPE_PREFIX: &str = r"\\.\pipe\";
 
 static SERVER_ACTIVE: atomic::AtomicBool = atomic::AtomicBool::new(true);
 
 lazy_static! {
     static ref PID_FILE: Mutex<Option<String>> = Mutex::new(None);
+    static ref STATE_FILE: Mutex<Option<String>> = Mutex::new(None);
     static ref SOCK_FILES: Mutex<Vec<String>> = Mutex::new(Vec::new());
     static ref BROKER: Mutex<Option<Broker>> = Mutex::new(None);
 }
@@ -67,21 +89,174 @@ fn set_verbose_logger(filter: LevelFilter) {
         .unwrap();
 }
 
+#[cfg(unix)]
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Sends records to the systemd journal with the native protocol, so structured fields (CLIENT,
+/// SOURCE, EVENT etc.) can be used in journalctl queries
+#[cfg(unix)]
+struct JournalLogger {
+    socket: std::os::unix::net::UnixDatagram,
+}
+
+#[cfg(unix)]
+impl JournalLogger {
+    fn connect() -> std::io::Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        socket.connect(JOURNAL_SOCKET)?;
+        Ok(Self { socket })
+    }
+}
+
+#[cfg(unix)]
+fn append_journal_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(b'\n');
+}
+
+#[cfg(unix)]
+struct JournalFields<'a>(&'a mut Vec<u8>);
+
+#[cfg(unix)]
+impl<'kvs> log::kv::VisitSource<'kvs> for JournalFields<'_> {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        // journal field names may contain only uppercase letters, digits and underscores
+        let name: String = key
+            .as_str()
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        append_journal_field(self.0, name.trim_start_matches('_'), &value.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl log::Log for JournalLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        let priority = match record.level() {
+            Level::Error => "3",
+            Level::Warn => "4",
+            Level::Info => "6",
+            Level::Debug | Level::Trace => "7",
+        };
+        let mut buf = Vec::new();
+        append_journal_field(&mut buf, "MESSAGE", &record.args().to_string());
+        append_journal_field(&mut buf, "PRIORITY", priority);
+        append_journal_field(&mut buf, "SYSLOG_IDENTIFIER", "elbusd");
+        let _r = record.key_values().visit(&mut JournalFields(&mut buf));
+        let _r = self.socket.send(&buf);
+    }
+
+    fn flush(&self) {}
+}
+
+/// Logs to the systemd journal if available, to syslog otherwise
+#[cfg(unix)]
+fn set_system_logger() {
+    if let Ok(logger) = JournalLogger::connect() {
+        log::set_boxed_logger(Box::new(logger))
+            .map(|()| log::set_max_level(LevelFilter::Info))
+            .unwrap();
+        return;
+    }
+    let formatter = syslog::Formatter3164 {
+        facility: syslog::Facility::LOG_USER,
+        hostname: None,
+        process: "elbusd".into(),
+        pid: 0,
+    };
+    match syslog::unix(formatter) {
+        Ok(logger) => {
+            log::set_boxed_logger(Box::new(syslog::BasicLogger::new(logger)))
+                .map(|()| log::set_max_level(LevelFilter::Info))
+                .unwrap();
+        }
+        Err(_) => {
+            set_verbose_logger(LevelFilter::Info);
+        }
+    }
+}
+
 #[derive(Parser)]
 struct Opts {
     #[clap(
         short = 'B',
         long = "bind",
         required = true,
-        help = "Unix socket path, IP:PORT or fifo:path, can be specified multiple times"
+        help = "Unix socket path, IP:PORT, tls://IP:PORT, quic://IP:PORT, ws://IP:PORT, wsgw://IP:PORT (JSON WebSocket gateway), udp://IP:PORT (fire-and-forget frames), vsock://CID:PORT (VM guests), uring://IP:PORT (TCP with io_uring peer I/O, Linux only), \\\\.\\pipe\\NAME (Windows named pipe) or fifo:path[:response_path] (Unix only), can be specified multiple times. Listener options can be overridden: PATH,timeout=SEC,buf=BYTES,buf_ttl=USEC, for TCP/TLS/WebSocket/io_uring: nodelay=0|1,keepalive=SEC,keepalive_interval=SEC,keepalive_count=N,sndbuf=BYTES,rcvbuf=BYTES"
     )]
     path: Vec<String>,
+    #[cfg(feature = "tls")]
+    #[clap(long = "tls-cert", help = "TLS/QUIC listener certificate chain (PEM)")]
+    tls_cert: Option<String>,
+    #[cfg(feature = "tls")]
+    #[clap(long = "tls-key", help = "TLS/QUIC listener private key (PEM)")]
+    tls_key: Option<String>,
+    #[cfg(all(feature = "websocket", feature = "rpc"))]
+    #[clap(
+        long = "ws-gateway-topic",
+        help = "Topic mask, allowed for JSON WebSocket gateway subscriptions, can be specified multiple times"
+    )]
+    ws_gateway_topic: Vec<String>,
+    #[cfg(all(feature = "websocket", feature = "rpc"))]
+    #[clap(
+        long = "ws-gateway-target",
+        help = "Client mask, allowed for JSON WebSocket gateway messages and RPC calls, can be specified multiple times"
+    )]
+    ws_gateway_target: Vec<String>,
+    #[cfg(all(feature = "websocket", feature = "rpc"))]
+    #[clap(
+        long = "ws-gateway-publish",
+        help = "Topic mask, allowed for JSON WebSocket gateway publications, can be specified multiple times"
+    )]
+    ws_gateway_publish: Vec<String>,
+    #[cfg(unix)]
+    #[clap(
+        long = "fifo-group",
+        help = "Make fifo channels writable (response fifos - readable) by the group (name or GID), by default they are accessible by the owner only"
+    )]
+    fifo_group: Option<String>,
+    #[cfg(unix)]
+    #[clap(
+        long = "fifo-payload-dir",
+        help = "Allow fifo commands to load payloads from files (@FILE) in the directory, file payloads are denied by default"
+    )]
+    fifo_payload_dir: Option<String>,
+    #[clap(
+        long = "dial-out",
+        help = "Connect out to the remote HOST:PORT and serve the protocol over the connection (for NAT-ed sites), can be specified multiple times. TCP options can be set as for listeners: HOST:PORT,timeout=SEC,nodelay=0|1..."
+    )]
+    dial_out: Vec<String>,
     #[clap(short = 'P', long = "pid-file")]
     pid_file: Option<String>,
     #[clap(long = "verbose", help = "Verbose logging")]
     verbose: bool,
+    #[cfg(unix)]
     #[clap(short = 'D')]
     daemonize: bool,
+    #[cfg(unix)]
     #[clap(long = "log-syslog", help = "Force log to syslog")]
     log_syslog: bool,
     #[clap(short = 'w', default_value = "4")]
@@ -106,166 +281,2261 @@ struct Opts {
         help = "frame queue size, per client"
     )]
     queue_size: usize,
+    #[clap(
+        long = "fanout-budget",
+        default_value = "256",
+        help = "Deliveries, routed by a client before it yields to others (0 - unlimited)"
+    )]
+    fanout_budget: usize,
+    #[clap(
+        long = "memory-limit",
+        default_value = "0",
+        help = "Max bytes, buffered in all client queues (0 - unlimited)"
+    )]
+    memory_limit: u64,
+    #[clap(
+        long = "frame-deadline",
+        help = "Max time (seconds) a frame can wait in a client queue, expired frames are dropped"
+    )]
+    frame_deadline: Option<f64>,
+    #[clap(
+        long = "watchdog",
+        help = "Recycle clients, which writers have not taken frames from non-empty queues for the time (seconds)"
+    )]
+    watchdog: Option<f64>,
+    #[clap(
+        long = "max-frame-size",
+        help = "Max size (bytes) of incoming frames, larger frames are rejected"
+    )]
+    max_frame_size: Option<u32>,
+    #[clap(
+        long = "memory-overflow",
+        default_value = "drop",
+        parse(try_from_str = parse_memory_overflow),
+        help = "Memory limit overflow policy: drop (frames) or disconnect (frames are dropped, the client with the largest queue is disconnected)"
+    )]
+    memory_overflow: MemoryOverflowPolicy,
+    #[clap(
+        long = "max-subscriptions",
+        default_value = "0",
+        help = "Max topics a client can be subscribed to (0 - unlimited)"
+    )]
+    max_subscriptions: usize,
+    #[clap(
+        long = "max-wildcard-subscriptions",
+        default_value = "0",
+        help = "Max subscriptions with wildcards, per client (0 - unlimited)"
+    )]
+    max_wildcard_subscriptions: usize,
+    #[clap(
+        long = "max-topic-depth",
+        default_value = "0",
+        help = "Max segments of a subscribed topic (0 - unlimited)"
+    )]
+    max_topic_depth: usize,
+    #[clap(
+        long = "max-topic-length",
+        default_value = "0",
+        help = "Max length of a subscribed topic (0 - unlimited)"
+    )]
+    max_topic_length: usize,
+    #[clap(
+        long = "broadcast-format",
+        default_value = ".,?,*",
+        parse(try_from_str = parse_mask_format),
+        help = "Broadcast mask separator and wildcards: SEPARATOR,MATCH_ANY,WILDCARD"
+    )]
+    broadcast_format: MaskFormat,
+    #[clap(
+        long = "topic-format",
+        default_value = "/,+,#",
+        parse(try_from_str = parse_mask_format),
+        help = "Topic separator and wildcards: SEPARATOR,MATCH_ANY,WILDCARD (e.g. .,*,> for NATS-style topics)"
+    )]
+    topic_format: MaskFormat,
+    #[clap(
+        long = "client-name-policy",
+        help = "Client naming convention, checked when clients connect: [max_length=N][,charset=CHARS][,separator=CHAR][,segments=MIN-MAX] (charset - characters allowed besides ASCII letters, digits and the separator)"
+    )]
+    client_name_policy: Option<String>,
+    #[clap(
+        long = "token-file",
+        help = "Require clients to present a token from the file (one token per line, lines starting with # are skipped), can not be combined with wsgw:// and udp:// listeners"
+    )]
+    token_file: Option<String>,
+    #[clap(
+        long = "statsd",
+        help = "Send broker metrics to a statsd server: HOST:PORT[,prefix=PREFIX][,interval=SEC] (default prefix: elbus., interval: 10 seconds)"
+    )]
+    statsd: Option<String>,
+    #[clap(
+        long = "timestamps",
+        help = "Stamp routed frames with the broker receive time"
+    )]
+    timestamps: bool,
+    #[cfg(feature = "testing")]
+    #[clap(
+        long = "chaos",
+        help = "Inject faults into the delivery path (soak tests), can be specified multiple times: [client=MASK][,topic=MASK][,latency=SEC][,jitter=SEC][,drop=RATE][,reorder=RATE]"
+    )]
+    chaos: Vec<String>,
+    #[clap(
+        long = "control",
+        help = "Admin control socket path, a named pipe on Windows (commands: status, drain, kick CLIENT, loglevel LEVEL)"
+    )]
+    control: Option<String>,
+    #[clap(
+        long = "state-file",
+        help = "Restore the broker state (persistence data, client ACLs) from the file at startup, save it on shutdown"
+    )]
+    state_file: Option<String>,
+    #[clap(
+        long = "sink",
+        help = "Append frames, published to the topics, to a file, can be specified multiple times: PATH,topic=MASK[,topic=MASK...][,format=ndjson|binary][,max_size=BYTES][,max_files=N]"
+    )]
+    sink: Vec<String>,
+    #[cfg(unix)]
+    #[clap(
+        long = "syslog-sink",
+        help = "Forward frames, published to the topics, to the local syslog, can be specified multiple times: topic=MASK[,topic=MASK...][,facility=NAME][,severity=DEFAULT][,socket=PATH]"
+    )]
+    syslog_sink: Vec<String>,
+    #[cfg(feature = "webhook")]
+    #[clap(
+        long = "webhook",
+        help = "POST frames, published to the topics, to a URL, can be specified multiple times: URL,topic=MASK[,topic=MASK...][,batch=N][,interval=SEC][,retries=N][,timeout=SEC][,buffer=N][,header=NAME:VALUE...]"
+    )]
+    webhook: Vec<String>,
+    #[cfg(feature = "sql")]
+    #[clap(
+        long = "sql-sink",
+        help = "Archive frames, published to the topics, into a PostgreSQL/SQLite table, can be specified multiple times: URL,topic=MASK[,topic=MASK...][,table=NAME][,batch=N][,interval=SEC][,retries=N]"
+    )]
+    sql_sink: Vec<String>,
+    #[cfg(feature = "serial")]
+    #[clap(
+        long = "serial",
+        help = "Expose a serial device as a client, can be specified multiple times: DEVICE,topic=TOPIC[,name=CLIENT][,baud=N][,framing=lines|length|delimiter:HEX][,max_frame=BYTES]"
+    )]
+    serial: Vec<String>,
+    #[clap(
+        long = "cpu-affinity",
+        help = "Pin worker threads to CPUs (e.g. 0-3,6). Listeners can be served by dedicated runtimes with cpus=LIST (e.g. 2-3:6) and workers=N flags"
+    )]
+    cpu_affinity: Option<String>,
+    #[clap(
+        long = "sandbox",
+        help = "Deny file writes outside of listener/control/pid/state/sink file directories and execution with landlock (applied at startup), deny exec, ptrace, mount, module loading and other admin syscalls with seccomp (applied after listeners are bound, x86_64/aarch64). File reads are not restricted (Linux only)"
+    )]
+    sandbox: bool,
+    #[clap(
+        long = "check",
+        help = "Validate options and listener paths, then exit (0 - ok, 1 - errors found)"
+    )]
+    check: bool,
 }
 
-async fn terminate(allow_log: bool) {
-    if let Some(f) = PID_FILE.lock().await.as_ref() {
-        // do not log anything on C-ref() {
-        if allow_log {
-            trace!("removing pid file {}", f);
+fn parse_memory_overflow(s: &str) -> Result<MemoryOverflowPolicy, String> {
+    s.parse().map_err(|e: elbus::Error| e.to_string())
+}
+
+fn parse_mask_format(s: &str) -> Result<MaskFormat, String> {
+    s.parse().map_err(|e: elbus::Error| e.to_string())
+}
+
+enum Listener<'a> {
+    Fifo(&'a str, Option<&'a str>),
+    Unix(&'a str),
+    Tcp(&'a str),
+    Tls(&'a str),
+    Quic(&'a str),
+    WebSocket(&'a str),
+    WsGateway(&'a str),
+    Udp(&'a str),
+    Vsock(&'a str),
+    Uring(&'a str),
+    NamedPipe(&'a str),
+}
+
+impl<'a> Listener<'a> {
+    fn parse(path: &'a str) -> Self {
+        #[allow(clippy::case_sensitive_file_extension_comparisons)]
+        if let Some(fifo) = path.strip_prefix("fifo:") {
+            let (fifo, resp_fifo) = fifo
+                .split_once(':')
+                .map_or((fifo, None), |(f, r)| (f, Some(r)));
+            Listener::Fifo(fifo, resp_fifo)
+        } else if let Some(addr) = path.strip_prefix("tls://") {
+            Listener::Tls(addr)
+        } else if let Some(addr) = path.strip_prefix("quic://") {
+            Listener::Quic(addr)
+        } else if let Some(addr) = path.strip_prefix("ws://") {
+            Listener::WebSocket(addr)
+        } else if let Some(addr) = path.strip_prefix("wsgw://") {
+            Listener::WsGateway(addr)
+        } else if let Some(addr) = path.strip_prefix("udp://") {
+            Listener::Udp(addr)
+        } else if let Some(addr) = path.strip_prefix("vsock://") {
+            Listener::Vsock(addr)
+        } else if let Some(addr) = path.strip_prefix("uring://") {
+            Listener::Uring(addr)
+        } else if path.starts_with(NAMED_PIPE_PREFIX) {
+            Listener::NamedPipe(path)
+        } else if path.ends_with(".sock")
+            || path.ends_with(".socket")
+            || path.ends_with(".ipc")
+            || path.starts_with('/')
+        {
+            Listener::Unix(path)
+        } else {
+            Listener::Tcp(path)
         }
-        let _r = std::fs::remove_file(&f);
     }
-    for f in SOCK_FILES.lock().await.iter() {
-        if allow_log {
-            trace!("removing sock file {}", f);
+}
+
+/// File sink: PATH,topic=MASK[,topic=MASK...],format=ndjson|binary,max_size=BYTES,max_files=N,
+/// returns the file path and the sink
+fn parse_sink(s: &str) -> Result<(&str, FileSink), String> {
+    let mut sp = s.split(',');
+    let path = sp.next().unwrap_or_default();
+    if path.is_empty() {
+        return Err(format!("sink {}: no file path specified", s));
+    }
+    let mut sink = FileSink::new(path);
+    let mut topics = 0;
+    for flag in sp {
+        let (key, value) = flag
+            .split_once('=')
+            .ok_or_else(|| format!("sink {}: invalid flag: {}", path, flag))?;
+        let invalid = |e: &dyn std::fmt::Display| format!("sink {}: invalid {}: {}", path, key, e);
+        match key {
+            "topic" => {
+                if value.is_empty() {
+                    return Err(invalid(&"empty mask"));
+                }
+                sink = sink.topic(value);
+                topics += 1;
+            }
+            "format" => sink = sink.format(value.parse().map_err(|e| invalid(&e))?),
+            "max_size" => sink = sink.max_size(value.parse().map_err(|e| invalid(&e))?),
+            "max_files" => sink = sink.max_files(value.parse().map_err(|e| invalid(&e))?),
+            _ => return Err(format!("sink {}: unknown flag: {}", path, key)),
         }
-        let _r = std::fs::remove_file(&f);
     }
-    if allow_log {
-        info!("terminating");
+    if topics == 0 {
+        return Err(format!("sink {}: no topics specified", path));
     }
-    #[cfg(feature = "rpc")]
-    if let Some(broker) = BROKER.lock().await.as_ref() {
-        if let Err(e) = broker.announce(BrokerEvent::shutdown()).await {
-            error!("{}", e);
+    Ok((path, sink))
+}
+
+/// Webhook sink: URL,topic=MASK[,topic=MASK...],batch=N,interval=SEC,retries=N,timeout=SEC,
+/// header=NAME:VALUE, returns the URL and the sink
+#[cfg(feature = "webhook")]
+fn parse_webhook(s: &str) -> Result<(&str, WebhookSink), String> {
+    let mut sp = s.split(',');
+    let url = sp.next().unwrap_or_default();
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(format!("webhook {}: invalid URL", url));
+    }
+    let mut sink = WebhookSink::new(url);
+    let mut topics = 0;
+    for flag in sp {
+        let (key, value) = flag
+            .split_once('=')
+            .ok_or_else(|| format!("webhook {}: invalid flag: {}", url, flag))?;
+        let invalid =
+            |e: &dyn std::fmt::Display| format!("webhook {}: invalid {}: {}", url, key, e);
+        let secs = |value: &str| -> Result<Duration, String> {
+            let secs: f64 = value.parse().map_err(|e| invalid(&e))?;
+            if !secs.is_finite() || secs <= 0.0 {
+                return Err(invalid(&value));
+            }
+            Ok(Duration::from_secs_f64(secs))
+        };
+        match key {
+            "topic" => {
+                if value.is_empty() {
+                    return Err(invalid(&"empty mask"));
+                }
+                sink = sink.topic(value);
+                topics += 1;
+            }
+            "batch" => {
+                let size: usize = value.parse().map_err(|e| invalid(&e))?;
+                if size == 0 {
+                    return Err(invalid(&value));
+                }
+                sink = sink.batch_size(size);
+            }
+            "interval" => sink = sink.batch_interval(secs(value)?),
+            "retries" => sink = sink.retries(value.parse().map_err(|e| invalid(&e))?),
+            "timeout" => sink = sink.timeout(secs(value)?),
+            "buffer" => {
+                let size: usize = value.parse().map_err(|e| invalid(&e))?;
+                if size == 0 {
+                    return Err(invalid(&value));
+                }
+                sink = sink.buffer_size(size);
+            }
+            "header" => {
+                let (name, value) = value
+                    .split_once(':')
+                    .ok_or_else(|| invalid(&"NAME:VALUE expected"))?;
+                sink = sink.header(name.trim(), value.trim());
+            }
+            _ => return Err(format!("webhook {}: unknown flag: {}", url, key)),
         }
     }
-    SERVER_ACTIVE.store(false, atomic::Ordering::SeqCst);
-    #[cfg(feature = "rpc")]
-    sleep(Duration::from_secs(1)).await;
+    if topics == 0 {
+        return Err(format!("webhook {}: no topics specified", url));
+    }
+    Ok((url, sink))
 }
 
-macro_rules! handle_term_signal {
-    ($kind: expr, $allow_log: expr) => {
-        tokio::spawn(async move {
-            trace!("starting handler for {:?}", $kind);
-            loop {
-                match signal($kind) {
-                    Ok(mut v) => {
-                        v.recv().await;
-                    }
-                    Err(e) => {
-                        error!("Unable to bind to signal {:?}: {}", $kind, e);
-                        break;
-                    }
+/// SQL sink: URL,topic=MASK[,topic=MASK...],table=NAME,batch=N,interval=SEC,retries=N, returns
+/// the URL and the sink
+#[cfg(feature = "sql")]
+fn parse_sql_sink(s: &str) -> Result<(&str, SqlSink), String> {
+    let mut sp = s.split(',');
+    let url = sp.next().unwrap_or_default();
+    if url.is_empty() {
+        return Err(format!("SQL sink {}: no database URL specified", s));
+    }
+    let mut sink = SqlSink::new(url);
+    let mut topics = 0;
+    for flag in sp {
+        let (key, value) = flag
+            .split_once('=')
+            .ok_or_else(|| format!("SQL sink {}: invalid flag: {}", url, flag))?;
+        let invalid =
+            |e: &dyn std::fmt::Display| format!("SQL sink {}: invalid {}: {}", url, key, e);
+        match key {
+            "topic" => {
+                if value.is_empty() {
+                    return Err(invalid(&"empty mask"));
                 }
-                // do not log anything on C-c
-                if $allow_log {
-                    trace!("got termination signal");
+                sink = sink.topic(value);
+                topics += 1;
+            }
+            "table" => sink = sink.table(value),
+            "batch" => {
+                let size: usize = value.parse().map_err(|e| invalid(&e))?;
+                if size == 0 {
+                    return Err(invalid(&value));
                 }
-                terminate($allow_log).await
+                sink = sink.batch_size(size);
             }
-        });
-    };
+            "interval" => {
+                let secs: f64 = value.parse().map_err(|e| invalid(&e))?;
+                if !secs.is_finite() || secs <= 0.0 {
+                    return Err(invalid(&value));
+                }
+                sink = sink.batch_interval(Duration::from_secs_f64(secs));
+            }
+            "retries" => sink = sink.retries(value.parse().map_err(|e| invalid(&e))?),
+            _ => return Err(format!("SQL sink {}: unknown flag: {}", url, key)),
+        }
+    }
+    if topics == 0 {
+        return Err(format!("SQL sink {}: no topics specified", url));
+    }
+    Ok((url, sink))
 }
 
-#[allow(clippy::too_many_lines)]
-fn main() {
-    let opts: Opts = Opts::parse();
-    if opts.verbose {
-        set_verbose_logger(LevelFilter::Trace);
-    } else if (!opts.daemonize
-        || std::env::var("DISABLE_SYSLOG").unwrap_or_else(|_| "0".to_owned()) == "1")
-        && !opts.log_syslog
-    {
-        set_verbose_logger(LevelFilter::Info);
-    } else {
-        let formatter = syslog::Formatter3164 {
-            facility: syslog::Facility::LOG_USER,
-            hostname: None,
-            process: "elbusd".into(),
-            pid: 0,
-        };
-        match syslog::unix(formatter) {
-            Ok(logger) => {
-                log::set_boxed_logger(Box::new(syslog::BasicLogger::new(logger)))
-                    .map(|()| log::set_max_level(LevelFilter::Info))
-                    .unwrap();
+/// Serial bridge: DEVICE,topic=TOPIC,name=CLIENT,baud=N,framing=lines|length|delimiter:HEX,
+/// max_frame=BYTES, returns the client name (if set) and the bridge
+#[cfg(feature = "serial")]
+fn parse_serial(s: &str) -> Result<(Option<&str>, SerialBridge), String> {
+    let mut sp = s.split(',');
+    let device = sp.next().unwrap_or_default();
+    if device.is_empty() {
+        return Err(format!("serial {}: no device specified", s));
+    }
+    let mut name = None;
+    let mut topic = None;
+    let mut baud_rate = None;
+    let mut framing = None;
+    let mut max_frame_size = None;
+    for flag in sp {
+        let (key, value) = flag
+            .split_once('=')
+            .ok_or_else(|| format!("serial {}: invalid flag: {}", device, flag))?;
+        let invalid =
+            |e: &dyn std::fmt::Display| format!("serial {}: invalid {}: {}", device, key, e);
+        match key {
+            "topic" => {
+                if value.is_empty() {
+                    return Err(invalid(&"empty topic"));
+                }
+                topic.replace(value);
+            }
+            "name" => {
+                if value.is_empty() {
+                    return Err(invalid(&"empty name"));
+                }
+                name.replace(value);
+            }
+            "baud" => {
+                baud_rate.replace(value.parse::<u32>().map_err(|e| invalid(&e))?);
             }
-            Err(_) => {
-                set_verbose_logger(LevelFilter::Info);
+            "framing" => {
+                framing.replace(value.parse().map_err(|e| invalid(&e))?);
+            }
+            "max_frame" => {
+                let size: usize = value.parse().map_err(|e| invalid(&e))?;
+                if size == 0 {
+                    return Err(invalid(&value));
+                }
+                max_frame_size.replace(size);
             }
+            _ => return Err(format!("serial {}: unknown flag: {}", device, key)),
         }
     }
-    let timeout = Duration::from_secs_f64(opts.timeout);
-    let buf_ttl = Duration::from_micros(opts.buf_ttl);
-    info!("starting elbus server");
-    info!("workers: {}", opts.workers);
-    info!("buf size: {}", opts.buf_size);
-    info!("buf ttl: {:?}", buf_ttl);
-    info!("queue size: {}", opts.queue_size);
-    info!("timeout: {:?}", timeout);
-    if opts.daemonize {
-        if let Ok(fork::Fork::Child) = fork::daemon(true, false) {
-            std::process::exit(0);
+    let topic = topic.ok_or_else(|| format!("serial {}: no topic specified", device))?;
+    let mut bridge = SerialBridge::new(device, topic);
+    if let Some(baud_rate) = baud_rate {
+        bridge = bridge.baud_rate(baud_rate);
+    }
+    if let Some(framing) = framing {
+        bridge = bridge.framing(framing);
+    }
+    if let Some(size) = max_frame_size {
+        bridge = bridge.max_frame_size(size);
+    }
+    Ok((name, bridge))
+}
+
+/// Fault injection rule: client=MASK,topic=MASK,latency=SEC,jitter=SEC,drop=RATE,reorder=RATE
+#[cfg(feature = "testing")]
+fn parse_chaos(s: &str) -> Result<ChaosRule, String> {
+    let mut rule = ChaosRule::new();
+    for flag in s.split(',') {
+        let (key, value) = flag
+            .split_once('=')
+            .ok_or_else(|| format!("chaos {}: invalid flag: {}", s, flag))?;
+        let invalid = |e: &dyn std::fmt::Display| format!("chaos {}: invalid {}: {}", s, key, e);
+        let secs = |value: &str| -> Result<Duration, String> {
+            let secs: f64 = value.parse().map_err(|e| invalid(&e))?;
+            if !secs.is_finite() || secs < 0.0 {
+                return Err(invalid(&value));
+            }
+            Ok(Duration::from_secs_f64(secs))
+        };
+        let rate = |value: &str| -> Result<f64, String> {
+            let rate: f64 = value.parse().map_err(|e| invalid(&e))?;
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(invalid(&value));
+            }
+            Ok(rate)
+        };
+        match key {
+            "client" => rule = rule.client(value),
+            "topic" => rule = rule.topic(value),
+            "latency" => rule = rule.latency(secs(value)?),
+            "jitter" => rule = rule.jitter(secs(value)?),
+            "drop" => rule = rule.drop_rate(rate(value)?),
+            "reorder" => rule = rule.reorder_rate(rate(value)?),
+            _ => return Err(format!("chaos {}: unknown flag: {}", s, key)),
         }
     }
-    let rt = tokio::runtime::Builder::new_multi_thread()
-        .worker_threads(opts.workers)
-        .enable_all()
-        .build()
-        .unwrap();
-    rt.block_on(async move {
-        if let Some(pid_file) = opts.pid_file {
-            let pid = std::process::id().to_string();
-            tokio::fs::write(&pid_file, pid)
-                .await
-                .expect("Unable to write pid file");
-            info!("created pid file {}", pid_file);
-            PID_FILE.lock().await.replace(pid_file);
+    Ok(rule)
+}
+
+fn parse_client_name_policy(s: &str) -> Result<ClientNamePolicy, String> {
+    let mut policy = ClientNamePolicy::new();
+    for flag in s.split(',') {
+        let (key, value) = flag
+            .split_once('=')
+            .ok_or_else(|| format!("client name policy: invalid flag: {}", flag))?;
+        let invalid =
+            |e: &dyn std::fmt::Display| format!("client name policy: invalid {}: {}", key, e);
+        match key {
+            "max_length" => policy = policy.max_length(value.parse().map_err(|e| invalid(&e))?),
+            "charset" => policy = policy.charset(value),
+            "separator" => {
+                let mut chars = value.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(ch), None) => policy = policy.separator(ch),
+                    _ => return Err(invalid(&value)),
+                }
+            }
+            "segments" => {
+                let (min, max) = value.split_once('-').unwrap_or((value, value));
+                let min: usize = min.parse().map_err(|e| invalid(&e))?;
+                let max: usize = max.parse().map_err(|e| invalid(&e))?;
+                if max > 0 && min > max {
+                    return Err(invalid(&value));
+                }
+                policy = policy.segments(min, max);
+            }
+            _ => return Err(format!("client name policy: unknown flag: {}", key)),
         }
-        handle_term_signal!(SignalKind::interrupt(), false);
-        handle_term_signal!(SignalKind::terminate(), true);
-        let mut broker = Broker::new();
-        #[cfg(feature = "rpc")]
-        broker.init_default_core_rpc().await.unwrap();
-        broker.set_queue_size(opts.queue_size);
-        let mut sock_files = SOCK_FILES.lock().await;
-        for path in opts.path {
-            info!("binding at {}", path);
-            #[allow(clippy::case_sensitive_file_extension_comparisons)]
-            if let Some(_fifo) = path.strip_prefix("fifo:") {
-                #[cfg(feature = "rpc")]
-                {
-                    broker
-                        .spawn_fifo(_fifo, opts.buf_size)
-                        .await
-                        .expect("unable to start fifo server");
-                    sock_files.push(_fifo.to_owned());
-                }
-            } else {
-                let server_config = ServerConfig::new()
-                    .buf_size(opts.buf_size)
-                    .buf_ttl(buf_ttl)
-                    .timeout(timeout);
-                if path.ends_with(".sock")
-                    || path.ends_with(".socket")
-                    || path.ends_with(".ipc")
-                    || path.starts_with('/')
-                {
-                    broker
-                        .spawn_unix_server(&path, server_config)
-                        .await
-                        .expect("Unable to start unix server");
-                    sock_files.push(path);
-                } else {
-                    broker
-                        .spawn_tcp_server(&path, server_config)
-                        .await
-                        .expect("Unable to start tcp server");
+    }
+    Ok(policy)
+}
+
+fn parse_statsd(s: &str) -> Result<(&str, &str, Duration), String> {
+    let mut parts = s.split(',');
+    let addr = parts.next().unwrap_or_default();
+    if addr.is_empty() {
+        return Err("statsd: no address specified".to_owned());
+    }
+    let mut prefix = "elbus.";
+    let mut interval = DEFAULT_STATSD_INTERVAL;
+    for flag in parts {
+        let (key, value) = flag
+            .split_once('=')
+            .ok_or_else(|| format!("statsd: invalid flag: {}", flag))?;
+        let invalid = |e: &dyn std::fmt::Display| format!("statsd: invalid {}: {}", key, e);
+        match key {
+            "prefix" => prefix = value,
+            "interval" => {
+                let sec: f64 = value.parse().map_err(|e| invalid(&e))?;
+                if !(sec > 0.0 && sec.is_finite()) {
+                    return Err(invalid(&value));
                 }
+                interval = Duration::from_secs_f64(sec);
             }
+            _ => return Err(format!("statsd: unknown flag: {}", key)),
         }
-        drop(sock_files);
-        BROKER.lock().await.replace(broker);
-        info!("elbus broker started");
-        let sleep_step = Duration::from_millis(100);
-        loop {
-            if !SERVER_ACTIVE.load(atomic::Ordering::SeqCst) {
-                break;
+    }
+    Ok((addr, prefix, interval))
+}
+
+/// Returns the database file path of a SQLite URL
+#[cfg(feature = "sql")]
+fn sqlite_path(url: &str) -> Option<&str> {
+    let path = url.strip_prefix("sqlite:")?;
+    let path = path.strip_prefix("//").unwrap_or(path);
+    let path = path.split_once('?').map_or(path, |(p, _)| p);
+    if path.is_empty() || path == ":memory:" {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+/// Syslog sink: topic=MASK[,topic=MASK...],facility=NAME,severity=DEFAULT,socket=PATH
+#[cfg(unix)]
+fn parse_syslog_sink(s: &str) -> Result<SyslogSink, String> {
+    let mut sink = SyslogSink::new();
+    let mut topics = 0;
+    for flag in s.split(',') {
+        let (key, value) = flag
+            .split_once('=')
+            .ok_or_else(|| format!("syslog sink: invalid flag: {}", flag))?;
+        let invalid = |e: &dyn std::fmt::Display| format!("syslog sink: invalid {}: {}", key, e);
+        match key {
+            "topic" => {
+                if value.is_empty() {
+                    return Err(invalid(&"empty mask"));
+                }
+                sink = sink.topic(value);
+                topics += 1;
             }
-            sleep(sleep_step).await;
+            "facility" => {
+                sink = sink.facility(value.parse().map_err(|()| invalid(&value))?);
+            }
+            "severity" => sink = sink.default_severity(value.parse().map_err(|e| invalid(&e))?),
+            "socket" => sink = sink.socket(value),
+            _ => return Err(format!("syslog sink: unknown flag: {}", key)),
         }
-    });
+    }
+    if topics == 0 {
+        return Err("syslog sink: no topics specified".to_owned());
+    }
+    Ok(sink)
+}
+
+/// Bind path with per-listener overrides: PATH,timeout=SEC,buf=BYTES,buf_ttl=USEC, TCP
+/// listeners also accept nodelay=0|1,keepalive=SEC,keepalive_interval=SEC,keepalive_count=N,
+/// sndbuf=BYTES,rcvbuf=BYTES
+struct ListenerConfig<'a> {
+    listener: Listener<'a>,
+    buf_size: Option<usize>,
+    buf_ttl: Option<Duration>,
+    timeout: Option<Duration>,
+    tcp_options: TcpOptions,
+    tcp_options_set: bool,
+    cpus: Option<Vec<usize>>,
+    workers: Option<usize>,
+}
+
+impl<'a> ListenerConfig<'a> {
+    fn parse(s: &'a str) -> Result<Self, String> {
+        let mut sp = s.split(',');
+        let path = sp.next().unwrap_or_default();
+        let mut config = Self {
+            listener: Listener::parse(path),
+            buf_size: None,
+            buf_ttl: None,
+            timeout: None,
+            tcp_options: TcpOptions::new(),
+            tcp_options_set: false,
+            cpus: None,
+            workers: None,
+        };
+        for flag in sp {
+            let (key, value) = flag
+                .split_once('=')
+                .ok_or_else(|| format!("{}: invalid listener flag: {}", path, flag))?;
+            let invalid = |e: &dyn std::fmt::Display| format!("{}: invalid {}: {}", path, key, e);
+            match key {
+                "timeout" => {
+                    let timeout: f64 = value.parse().map_err(|e| invalid(&e))?;
+                    if !timeout.is_finite() || timeout <= 0.0 {
+                        return Err(invalid(&value));
+                    }
+                    config.timeout.replace(Duration::from_secs_f64(timeout));
+                }
+                "buf" => {
+                    let buf_size: usize = value.parse().map_err(|e| invalid(&e))?;
+                    if buf_size == 0 {
+                        return Err(invalid(&value));
+                    }
+                    config.buf_size.replace(buf_size);
+                }
+                "buf_ttl" => {
+                    config.buf_ttl.replace(Duration::from_micros(
+                        value.parse().map_err(|e| invalid(&e))?,
+                    ));
+                }
+                "nodelay" => {
+                    let nodelay = match value {
+                        "1" | "true" => true,
+                        "0" | "false" => false,
+                        v => return Err(invalid(&v)),
+                    };
+                    config.tcp_options = config.tcp_options.nodelay(nodelay);
+                    config.tcp_options_set = true;
+                }
+                "keepalive" | "keepalive_interval" => {
+                    let secs: f64 = value.parse().map_err(|e| invalid(&e))?;
+                    if !secs.is_finite() || secs <= 0.0 {
+                        return Err(invalid(&value));
+                    }
+                    let d = Duration::from_secs_f64(secs);
+                    config.tcp_options = if key == "keepalive" {
+                        config.tcp_options.keepalive_idle(d)
+                    } else {
+                        config.tcp_options.keepalive_interval(d)
+                    };
+                    config.tcp_options_set = true;
+                }
+                "keepalive_count" => {
+                    let count = value.parse().map_err(|e| invalid(&e))?;
+                    config.tcp_options = config.tcp_options.keepalive_count(count);
+                    config.tcp_options_set = true;
+                }
+                "sndbuf" | "rcvbuf" => {
+                    let size: usize = value.parse().map_err(|e| invalid(&e))?;
+                    config.tcp_options = if key == "sndbuf" {
+                        config.tcp_options.send_buffer_size(size)
+                    } else {
+                        config.tcp_options.recv_buffer_size(size)
+                    };
+                    config.tcp_options_set = true;
+                }
+                "cpus" => {
+                    config
+                        .cpus
+                        .replace(parse_cpu_list(value).map_err(|e| invalid(&e))?);
+                }
+                "workers" => {
+                    let workers: usize = value.parse().map_err(|e| invalid(&e))?;
+                    if workers == 0 {
+                        return Err(invalid(&value));
+                    }
+                    config.workers.replace(workers);
+                }
+                "tls" => {
+                    return Err(format!(
+                        "{}: use the tls:// prefix to bind a TLS listener",
+                        path
+                    ))
+                }
+                _ => return Err(format!("{}: unknown listener flag: {}", path, key)),
+            }
+        }
+        if matches!(config.listener, Listener::Fifo(..))
+            && (config.timeout.is_some()
+                || config.buf_ttl.is_some()
+                || config.cpus.is_some()
+                || config.workers.is_some())
+        {
+            return Err(format!("{}: only the buf flag is supported for fifo", path));
+        }
+        if matches!(config.listener, Listener::Udp(_))
+            && (config.buf_size.is_some()
+                || config.timeout.is_some()
+                || config.buf_ttl.is_some()
+                || config.cpus.is_some()
+                || config.workers.is_some()
+                || config.tcp_options_set)
+        {
+            return Err(format!(
+                "{}: listener flags are not supported for UDP",
+                path
+            ));
+        }
+        if config.tcp_options_set
+            && !matches!(
+                config.listener,
+                Listener::Tcp(_)
+                    | Listener::Tls(_)
+                    | Listener::WebSocket(_)
+                    | Listener::WsGateway(_)
+                    | Listener::Uring(_)
+            )
+        {
+            return Err(format!(
+                "{}: TCP socket flags are supported for TCP/TLS/WebSocket/io_uring only",
+                path
+            ));
+        }
+        // io_uring listeners have own threads
+        if matches!(config.listener, Listener::Uring(_))
+            && (config.cpus.is_some() || config.workers.is_some())
+        {
+            return Err(format!(
+                "{}: cpus and workers flags are not supported for io_uring",
+                path
+            ));
+        }
+        Ok(config)
+    }
+    fn server_config(&self, opts: &Opts) -> ServerConfig {
+        let config = ServerConfig::new()
+            .buf_size(self.buf_size.unwrap_or(opts.buf_size))
+            .buf_ttl(
+                self.buf_ttl
+                    .unwrap_or_else(|| Duration::from_micros(opts.buf_ttl)),
+            )
+            .timeout(
+                self.timeout
+                    .unwrap_or_else(|| Duration::from_secs_f64(opts.timeout)),
+            )
+            .tcp_options(self.tcp_options.clone());
+        let config = if let Some(deadline) = opts.frame_deadline {
+            config.frame_deadline(Duration::from_secs_f64(deadline))
+        } else {
+            config
+        };
+        if let Some(size) = opts.max_frame_size {
+            config.max_frame_size(size)
+        } else {
+            config
+        }
+    }
+    /// Dial-out connections use the listener syntax, TCP only, served by the main runtime
+    fn parse_dial_out(s: &'a str) -> Result<Self, String> {
+        let config = Self::parse(s)?;
+        match config.listener {
+            Listener::Tcp(addr) => {
+                if config.cpus.is_some() || config.workers.is_some() {
+                    Err(format!(
+                        "dial-out {}: cpus and workers are not supported",
+                        addr
+                    ))
+                } else {
+                    Ok(config)
+                }
+            }
+            _ => Err(format!("dial-out {}: HOST:PORT expected", s)),
+        }
+    }
+    /// Listeners with cpus or workers set are served by dedicated runtimes
+    fn runtime(&self, path: &str) -> Option<tokio::runtime::Runtime> {
+        if self.cpus.is_none() && self.workers.is_none() {
+            return None;
+        }
+        let workers = self
+            .workers
+            .unwrap_or_else(|| self.cpus.as_ref().map_or(1, Vec::len));
+        Some(
+            create_runtime(workers, self.cpus.clone())
+                .unwrap_or_else(|e| panic!("Unable to create runtime for {}: {}", path, e)),
+        )
+    }
+}
+
+/// Parses CPU lists: 0-3,6 (":" can be used as a separator as well, e.g. in listener flags)
+fn parse_cpu_list(s: &str) -> Result<Vec<usize>, String> {
+    let mut cpus = Vec::new();
+    for item in s.split(&[',', ':'][..]) {
+        if let Some((from, to)) = item.split_once('-') {
+            let from: usize = from.parse().map_err(|e| format!("{}: {}", item, e))?;
+            let to: usize = to.parse().map_err(|e| format!("{}: {}", item, e))?;
+            if from > to {
+                return Err(format!("invalid CPU range: {}", item));
+            }
+            cpus.extend(from..=to);
+        } else {
+            cpus.push(item.parse().map_err(|e| format!("{}: {}", item, e))?);
+        }
+    }
+    cpus.sort_unstable();
+    cpus.dedup();
+    Ok(cpus)
+}
+
+/// Pins the current thread to the specified CPUs
+#[cfg(target_os = "linux")]
+fn set_cpu_affinity(cpus: &[usize]) -> Result<(), std::io::Error> {
+    let mut cpu_set = nix::sched::CpuSet::new();
+    for cpu in cpus {
+        cpu_set.set(*cpu)?;
+    }
+    nix::sched::sched_setaffinity(nix::unistd::Pid::from_raw(0), &cpu_set).map_err(Into::into)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_cpu_affinity(_cpus: &[usize]) -> Result<(), std::io::Error> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "CPU affinity is supported on Linux only",
+    ))
+}
+
+fn create_runtime(
+    workers: usize,
+    cpus: Option<Vec<usize>>,
+) -> Result<tokio::runtime::Runtime, std::io::Error> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.worker_threads(workers).enable_all();
+    if let Some(cpus) = cpus {
+        builder.on_thread_start(move || {
+            if let Err(e) = set_cpu_affinity(&cpus) {
+                error!("unable to set CPU affinity: {}", e);
+            }
+        });
+    }
+    builder.build()
+}
+
+fn check_tcp_addr(kind: &str, addr: &str, errors: &mut Vec<String>) {
+    use std::net::ToSocketAddrs;
+    match addr.to_socket_addrs() {
+        Ok(mut addrs) => {
+            if addrs.next().is_none() {
+                errors.push(format!("{} {}: address not resolved", kind, addr));
+            }
+        }
+        Err(e) => errors.push(format!("{} {}: {}", kind, addr, e)),
+    }
+}
+
+fn check_file_path(kind: &str, path: &str, errors: &mut Vec<String>) {
+    let p = std::path::Path::new(path);
+    if path.is_empty() {
+        errors.push(format!("{}: empty path", kind));
+        return;
+    }
+    if p.is_dir() {
+        errors.push(format!("{} {}: is a directory", kind, path));
+    }
+    let parent = p
+        .parent()
+        .filter(|v| !v.as_os_str().is_empty())
+        .unwrap_or_else(|| std::path::Path::new("."));
+    if !parent.is_dir() {
+        errors.push(format!(
+            "{} {}: directory {} does not exist",
+            kind,
+            path,
+            parent.display()
+        ));
+    }
+}
+
+fn check_named_pipe(kind: &str, path: &str, errors: &mut Vec<String>) {
+    if cfg!(not(windows)) {
+        errors.push(format!(
+            "{} {}: named pipes are supported on Windows only",
+            kind, path
+        ));
+    }
+    match path.strip_prefix(NAMED_PIPE_PREFIX) {
+        Some(name) if !name.is_empty() && !name.contains('\\') => {}
+        _ => errors.push(format!(
+            "{} {}: invalid path, {}NAME expected",
+            kind, path, NAMED_PIPE_PREFIX
+        )),
+    }
+}
+
+/// Validates options without binding anything, returns the list of errors found
+fn check_config(opts: &Opts) -> Vec<String> {
+    let mut errors = Vec::new();
+    if opts.workers == 0 {
+        errors.push("the number of workers must be greater than zero".to_owned());
+    }
+    if !opts.timeout.is_finite() || opts.timeout <= 0.0 {
+        errors.push(format!("invalid timeout: {}", opts.timeout));
+    }
+    if let Some(deadline) = opts.frame_deadline {
+        if !deadline.is_finite() || deadline <= 0.0 {
+            errors.push(format!("invalid frame deadline: {}", deadline));
+        }
+    }
+    if let Some(watchdog) = opts.watchdog {
+        if !watchdog.is_finite() || watchdog <= 0.0 {
+            errors.push(format!("invalid watchdog timeout: {}", watchdog));
+        }
+    }
+    if opts.buf_size == 0 {
+        errors.push("buf size must be greater than zero".to_owned());
+    }
+    if opts.queue_size == 0 {
+        errors.push("queue size must be greater than zero".to_owned());
+    }
+    if let Some(ref pid_file) = opts.pid_file {
+        check_file_path("pid file", pid_file, &mut errors);
+    }
+    if let Some(ref state_file) = opts.state_file {
+        check_file_path("state file", state_file, &mut errors);
+    }
+    for dial_out in &opts.dial_out {
+        if let Err(e) = ListenerConfig::parse_dial_out(dial_out) {
+            errors.push(e);
+        }
+    }
+    for sink in &opts.sink {
+        match parse_sink(sink) {
+            Ok((path, _)) => check_file_path("sink file", path, &mut errors),
+            Err(e) => errors.push(e),
+        }
+    }
+    #[cfg(feature = "webhook")]
+    for webhook in &opts.webhook {
+        if let Err(e) = parse_webhook(webhook) {
+            errors.push(e);
+        }
+    }
+    if let Some(ref policy) = opts.client_name_policy {
+        if let Err(e) = parse_client_name_policy(policy) {
+            errors.push(e);
+        }
+    }
+    if let Some(ref path) = opts.token_file {
+        match TokenList::load(path) {
+            Ok(tokens) if tokens.is_empty() => {
+                errors.push(format!("token file {}: no tokens", path));
+            }
+            Ok(_) => {}
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+    if let Some(ref statsd) = opts.statsd {
+        if let Err(e) = parse_statsd(statsd) {
+            errors.push(e);
+        }
+    }
+    #[cfg(feature = "testing")]
+    for chaos in &opts.chaos {
+        if let Err(e) = parse_chaos(chaos) {
+            errors.push(e);
+        }
+    }
+    #[cfg(feature = "sql")]
+    for sql_sink in &opts.sql_sink {
+        match parse_sql_sink(sql_sink) {
+            Ok((url, _)) => {
+                if let Some(path) = sqlite_path(url) {
+                    check_file_path("SQLite database", path, &mut errors);
+                }
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+    #[cfg(feature = "serial")]
+    for serial in &opts.serial {
+        if let Err(e) = parse_serial(serial) {
+            errors.push(e);
+        }
+    }
+    #[cfg(unix)]
+    for syslog_sink in &opts.syslog_sink {
+        if let Err(e) = parse_syslog_sink(syslog_sink) {
+            errors.push(e);
+        }
+    }
+    #[cfg(unix)]
+    if let Some(ref group) = opts.fifo_group {
+        if let Err(e) = parse_group(group) {
+            errors.push(e);
+        }
+    }
+    #[cfg(unix)]
+    if let Some(ref dir) = opts.fifo_payload_dir {
+        if !std::path::Path::new(dir).is_dir() {
+            errors.push(format!("fifo payload directory {} not found", dir));
+        }
+    }
+    if let Some(ref cpu_affinity) = opts.cpu_affinity {
+        // pinning the checking thread validates the CPU set against the allowed one
+        if let Err(e) = parse_cpu_list(cpu_affinity)
+            .and_then(|cpus| set_cpu_affinity(&cpus).map_err(|e| e.to_string()))
+        {
+            errors.push(format!("invalid CPU affinity {}: {}", cpu_affinity, e));
+        }
+    }
+    if let Some(ref control) = opts.control {
+        #[cfg(unix)]
+        check_file_path("control socket", control, &mut errors);
+        #[cfg(windows)]
+        check_named_pipe("control socket", control, &mut errors);
+    }
+    let mut paths = std::collections::HashSet::new();
+    for path in &opts.path {
+        if !paths.insert(path) {
+            errors.push(format!("listener {} is specified more than once", path));
+        }
+        let config = match ListenerConfig::parse(path) {
+            Ok(v) => v,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        if let Some(ref cpus) = config.cpus {
+            if let Err(e) = set_cpu_affinity(cpus) {
+                errors.push(format!("{}: invalid cpus: {}", path, e));
+            }
+        }
+        match config.listener {
+            Listener::Fifo(fifo, resp_fifo) => {
+                if cfg!(not(unix)) {
+                    errors.push(format!(
+                        "fifo {}: fifo channels are supported on Unix only",
+                        fifo
+                    ));
+                }
+                if cfg!(not(feature = "rpc")) {
+                    errors.push(format!("fifo {}: rpc feature is not enabled", fifo));
+                }
+                check_file_path("fifo", fifo, &mut errors);
+                if let Some(r) = resp_fifo {
+                    check_file_path("response fifo", r, &mut errors);
+                    if r == fifo {
+                        errors.push(format!("fifo {}: the response fifo path is the same", fifo));
+                    }
+                }
+            }
+            Listener::Unix(socket) => {
+                if cfg!(not(unix)) {
+                    errors.push(format!(
+                        "unix socket {}: unix sockets are not supported, use named pipes",
+                        socket
+                    ));
+                }
+                check_file_path("unix socket", socket, &mut errors);
+            }
+            Listener::NamedPipe(pipe) => check_named_pipe("named pipe", pipe, &mut errors),
+            Listener::Tcp(addr) => check_tcp_addr("tcp", addr, &mut errors),
+            Listener::Tls(addr) => {
+                #[cfg(feature = "tls")]
+                for (kind, path) in [("cert", &opts.tls_cert), ("key", &opts.tls_key)] {
+                    match path {
+                        Some(p) if !std::path::Path::new(p).is_file() => {
+                            errors.push(format!("tls {}: {} file {} not found", addr, kind, p));
+                        }
+                        Some(_) => {}
+                        None => errors.push(format!("tls {}: --tls-{} is not set", addr, kind)),
+                    }
+                }
+                #[cfg(not(feature = "tls"))]
+                errors.push(format!("tls {}: TLS support is not compiled in", addr));
+                check_tcp_addr("tls", addr, &mut errors);
+            }
+            Listener::Quic(addr) => {
+                #[cfg(feature = "quic")]
+                for (kind, path) in [("cert", &opts.tls_cert), ("key", &opts.tls_key)] {
+                    match path {
+                        Some(p) if !std::path::Path::new(p).is_file() => {
+                            errors.push(format!("quic {}: {} file {} not found", addr, kind, p));
+                        }
+                        Some(_) => {}
+                        None => errors.push(format!("quic {}: --tls-{} is not set", addr, kind)),
+                    }
+                }
+                #[cfg(not(feature = "quic"))]
+                errors.push(format!("quic {}: QUIC support is not compiled in", addr));
+                check_tcp_addr("quic", addr, &mut errors);
+            }
+            Listener::WebSocket(addr) => {
+                #[cfg(not(feature = "websocket"))]
+                errors.push(format!("ws {}: WebSocket support is not compiled in", addr));
+                check_tcp_addr("ws", addr, &mut errors);
+            }
+            Listener::WsGateway(addr) => {
+                #[cfg(all(feature = "websocket", feature = "rpc"))]
+                if opts.ws_gateway_topic.is_empty()
+                    && opts.ws_gateway_target.is_empty()
+                    && opts.ws_gateway_publish.is_empty()
+                {
+                    errors.push(format!(
+                        "wsgw {}: no --ws-gateway-topic, --ws-gateway-target or --ws-gateway-publish masks set",
+                        addr
+                    ));
+                }
+                #[cfg(not(all(feature = "websocket", feature = "rpc")))]
+                errors.push(format!(
+                    "wsgw {}: WebSocket gateway support is not compiled in (websocket and rpc features)",
+                    addr
+                ));
+                if opts.token_file.is_some() {
+                    errors.push(format!(
+                        "wsgw {}: gateway clients can not be authenticated, --token-file is set",
+                        addr
+                    ));
+                }
+                check_tcp_addr("wsgw", addr, &mut errors);
+            }
+            Listener::Udp(addr) => {
+                if opts.token_file.is_some() {
+                    errors.push(format!(
+                        "udp {}: UDP sources can not be authenticated, --token-file is set",
+                        addr
+                    ));
+                }
+                check_tcp_addr("udp", addr, &mut errors);
+            }
+            Listener::Uring(addr) => {
+                #[cfg(not(all(target_os = "linux", feature = "uring")))]
+                errors.push(format!(
+                    "uring {}: io_uring support is not compiled in (Linux only)",
+                    addr
+                ));
+                check_tcp_addr("uring", addr, &mut errors);
+            }
+            Listener::Vsock(addr) => {
+                #[cfg(not(feature = "vsock"))]
+                errors.push(format!("vsock {}: vsock support is not compiled in", addr));
+                let valid = addr.split_once(':').is_some_and(|(cid, port)| {
+                    (cid == "any" || cid.parse::<u32>().is_ok()) && port.parse::<u32>().is_ok()
+                });
+                if !valid {
+                    errors.push(format!(
+                        "vsock {}: invalid address, CID:PORT expected (CID can be \"any\")",
+                        addr
+                    ));
+                }
+            }
+        }
+    }
+    errors
+}
+
+async fn terminate(allow_log: bool) {
+    if let Some(f) = PID_FILE.lock().await.as_ref() {
+        // do not log anything on C-ref() {
+        if allow_log {
+            trace!("removing pid file {}", f);
+        }
+        let _r = std::fs::remove_file(f);
+    }
+    for f in SOCK_FILES.lock().await.iter() {
+        if allow_log {
+            trace!("removing sock file {}", f);
+        }
+        let _r = std::fs::remove_file(f);
+    }
+    if allow_log {
+        info!("terminating");
+    }
+    if let Some(f) = STATE_FILE.lock().await.as_ref() {
+        if let Some(broker) = BROKER.lock().await.as_ref() {
+            match broker.snapshot(f) {
+                Ok(()) => {
+                    if allow_log {
+                        info!("broker state saved to {}", f);
+                    }
+                }
+                Err(e) => error!("unable to save the broker state to {}: {}", f, e),
+            }
+        }
+    }
+    #[cfg(feature = "rpc")]
+    if let Some(broker) = BROKER.lock().await.as_ref() {
+        if let Err(e) = broker.announce(BrokerEvent::shutdown()).await {
+            error!("{}", e);
+        }
+    }
+    SERVER_ACTIVE.store(false, atomic::Ordering::SeqCst);
+    #[cfg(feature = "rpc")]
+    sleep(Duration::from_secs(1)).await;
+}
+
+/// Optional process hardening. Landlock rules are applied at startup, before the runtime is
+/// started and listeners are bound, as they are inherited only by threads created afterwards.
+/// The rules allow to write, create and remove files in directories of listener, control socket,
+/// pid, state and sink files only, execution is denied everywhere. File reads are not
+/// restricted. The seccomp filter (applied to all threads after listeners are bound, x86_64 and
+/// aarch64 only) is a denylist: syscalls the broker never needs (exec, ptrace, mount, module
+/// loading etc.) fail with EPERM, others are allowed
+#[cfg(target_os = "linux")]
+mod sandbox {
+    use log::warn;
+    use nix::libc;
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    const SYS_LANDLOCK_CREATE_RULESET: libc::c_long = 444;
+    const SYS_LANDLOCK_ADD_RULE: libc::c_long = 445;
+    const SYS_LANDLOCK_RESTRICT_SELF: libc::c_long = 446;
+    const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+    const LANDLOCK_ACCESS_FS_EXECUTE: u64 = 1 << 0;
+    const LANDLOCK_ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const LANDLOCK_ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+    const LANDLOCK_ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+    const LANDLOCK_ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+    const LANDLOCK_ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+    const LANDLOCK_ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+    const LANDLOCK_ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+    const LANDLOCK_ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+    const LANDLOCK_ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+    const LANDLOCK_ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+
+    const HANDLED_ACCESS: u64 = LANDLOCK_ACCESS_FS_EXECUTE
+        | LANDLOCK_ACCESS_FS_WRITE_FILE
+        | LANDLOCK_ACCESS_FS_REMOVE_DIR
+        | LANDLOCK_ACCESS_FS_REMOVE_FILE
+        | LANDLOCK_ACCESS_FS_MAKE_CHAR
+        | LANDLOCK_ACCESS_FS_MAKE_DIR
+        | LANDLOCK_ACCESS_FS_MAKE_REG
+        | LANDLOCK_ACCESS_FS_MAKE_SOCK
+        | LANDLOCK_ACCESS_FS_MAKE_FIFO
+        | LANDLOCK_ACCESS_FS_MAKE_BLOCK
+        | LANDLOCK_ACCESS_FS_MAKE_SYM;
+
+    const ALLOWED_ACCESS: u64 = LANDLOCK_ACCESS_FS_WRITE_FILE
+        | LANDLOCK_ACCESS_FS_REMOVE_FILE
+        | LANDLOCK_ACCESS_FS_MAKE_REG
+        | LANDLOCK_ACCESS_FS_MAKE_SOCK
+        | LANDLOCK_ACCESS_FS_MAKE_FIFO;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+    #[repr(C)]
+    struct LandlockRulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct LandlockPathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: libc::c_int,
+    }
+
+    struct Fd(libc::c_int);
+
+    impl Drop for Fd {
+        fn drop(&mut self) {
+            unsafe { libc::close(self.0) };
+        }
+    }
+
+    fn check(res: libc::c_long) -> Result<libc::c_long, io::Error> {
+        if res < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(res)
+        }
+    }
+
+    /// Restricts file system modifications to the directories of the specified paths. Kernels
+    /// without landlock support are reported with a warning
+    pub fn apply_landlock(paths: &[&str]) -> Result<(), io::Error> {
+        let attr = LandlockRulesetAttr {
+            handled_access_fs: HANDLED_ACCESS,
+        };
+        let ruleset = match check(unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_CREATE_RULESET,
+                &attr as *const LandlockRulesetAttr,
+                std::mem::size_of::<LandlockRulesetAttr>(),
+                0,
+            )
+        }) {
+            #[allow(clippy::cast_possible_truncation)]
+            Ok(fd) => Fd(fd as libc::c_int),
+            Err(e) if matches!(e.raw_os_error(), Some(libc::ENOSYS | libc::EOPNOTSUPP)) => {
+                warn!(
+                    "landlock is not supported by the kernel, file system access is not restricted"
+                );
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        for path in paths {
+            let dir = Path::new(path)
+                .parent()
+                .filter(|v| !v.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."));
+            let c_dir = CString::new(dir.as_os_str().as_bytes())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let dir_fd = Fd(check(unsafe {
+                libc::open(c_dir.as_ptr(), libc::O_PATH | libc::O_CLOEXEC).into()
+            })
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", dir.display(), e)))?
+                as libc::c_int);
+            let rule = LandlockPathBeneathAttr {
+                allowed_access: ALLOWED_ACCESS,
+                parent_fd: dir_fd.0,
+            };
+            check(unsafe {
+                libc::syscall(
+                    SYS_LANDLOCK_ADD_RULE,
+                    ruleset.0,
+                    LANDLOCK_RULE_PATH_BENEATH,
+                    &rule as *const LandlockPathBeneathAttr,
+                    0,
+                )
+            })?;
+        }
+        set_no_new_privs()?;
+        check(unsafe { libc::syscall(SYS_LANDLOCK_RESTRICT_SELF, ruleset.0, 0) })?;
+        Ok(())
+    }
+
+    fn set_no_new_privs() -> Result<(), io::Error> {
+        check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) }.into())?;
+        Ok(())
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    const BLOCKED_SYSCALLS: &[libc::c_long] = &[
+        libc::SYS_execve,
+        libc::SYS_execveat,
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_reboot,
+        libc::SYS_kexec_load,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_kexec_file_load,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_iopl,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_ioperm,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_personality,
+        libc::SYS_setns,
+        libc::SYS_unshare,
+        libc::SYS_userfaultfd,
+        libc::SYS_acct,
+        libc::SYS_settimeofday,
+        libc::SYS_clock_settime,
+        libc::SYS_clock_adjtime,
+        libc::SYS_adjtimex,
+        libc::SYS_sethostname,
+        libc::SYS_setdomainname,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_quotactl,
+        libc::SYS_vhangup,
+        libc::SYS_syslog,
+    ];
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    #[allow(clippy::cast_possible_truncation)]
+    fn bpf_stmt(code: u32, k: u32) -> libc::sock_filter {
+        libc::sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    #[allow(clippy::cast_possible_truncation)]
+    fn bpf_jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter {
+            code: code as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    /// Blocks the denied syscalls for all threads of the process
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn apply_seccomp() -> Result<(), io::Error> {
+        // offsets in seccomp_data
+        const NR: u32 = 0;
+        const ARCH: u32 = 4;
+        let mut filter = vec![
+            bpf_stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, ARCH),
+            bpf_jump(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                AUDIT_ARCH,
+                1,
+                0,
+            ),
+            bpf_stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            bpf_stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, NR),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        {
+            // deny x32 ABI syscalls
+            filter.push(bpf_jump(
+                libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+                0x4000_0000,
+                BLOCKED_SYSCALLS.len() as u8 + 1,
+                0,
+            ));
+        }
+        for (i, nr) in BLOCKED_SYSCALLS.iter().enumerate() {
+            // jump to the EPERM return
+            let jt = (BLOCKED_SYSCALLS.len() - i) as u8;
+            filter.push(bpf_jump(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                *nr as u32,
+                jt,
+                0,
+            ));
+        }
+        filter.push(bpf_stmt(
+            libc::BPF_RET | libc::BPF_K,
+            libc::SECCOMP_RET_ALLOW,
+        ));
+        filter.push(bpf_stmt(
+            libc::BPF_RET | libc::BPF_K,
+            libc::SECCOMP_RET_ERRNO | (libc::EPERM as u32 & libc::SECCOMP_RET_DATA),
+        ));
+        let prog = libc::sock_fprog {
+            len: filter.len() as libc::c_ushort,
+            filter: filter.as_mut_ptr(),
+        };
+        set_no_new_privs()?;
+        check(unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                libc::SECCOMP_FILTER_FLAG_TSYNC,
+                &prog as *const libc::sock_fprog,
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Syscall numbers and the audit architecture are known for x86_64 and aarch64 only
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    #[allow(clippy::unnecessary_wraps)]
+    pub fn apply_seccomp() -> Result<(), io::Error> {
+        warn!("seccomp filter is not supported on this architecture, syscalls are not restricted");
+        Ok(())
+    }
+}
+
+/// Processes an admin control command, returns a response line
+async fn control_cmd(line: &str) -> String {
+    let mut sp = line.split_whitespace();
+    let cmd = if let Some(cmd) = sp.next() {
+        cmd
+    } else {
+        return String::new();
+    };
+    let arg = sp.next();
+    if sp.next().is_some() {
+        return "ERR too many arguments".to_owned();
+    }
+    let mut broker = BROKER.lock().await;
+    let broker = if let Some(broker) = broker.as_mut() {
+        broker
+    } else {
+        return "ERR broker is not started".to_owned();
+    };
+    match (cmd, arg) {
+        ("status", None) => {
+            let stats = broker.stats();
+            let listeners = broker.listeners();
+            format!(
+                "OK uptime={} clients={} listeners={}/{} r_frames={} r_bytes={} w_frames={} w_bytes={} queued_bytes={} loglevel={}",
+                stats.uptime,
+                broker.client_count(),
+                listeners.iter().filter(|l| l.active).count(),
+                listeners.len(),
+                stats.r_frames,
+                stats.r_bytes,
+                stats.w_frames,
+                stats.w_bytes,
+                stats.queued_bytes,
+                log::max_level().as_str().to_lowercase()
+            )
+        }
+        ("reload", None) => "ERR nothing to reload, elbusd has no configuration file".to_owned(),
+        ("drain", None) => {
+            broker.drain();
+            info!("draining, all listeners stopped");
+            "OK".to_owned()
+        }
+        ("kick", Some(client)) => match broker.force_disconnect(client) {
+            Ok(()) => {
+                info!("client {} kicked", client);
+                "OK".to_owned()
+            }
+            Err(e) => format!("ERR {}", e),
+        },
+        ("loglevel", Some(level)) => match level.parse::<LevelFilter>() {
+            Ok(filter) => {
+                log::set_max_level(filter);
+                "OK".to_owned()
+            }
+            Err(_) => format!("ERR invalid log level: {}", level),
+        },
+        ("status" | "reload" | "drain", Some(_)) => format!("ERR {}: unexpected argument", cmd),
+        ("kick" | "loglevel", None) => format!("ERR {}: argument required", cmd),
+        _ => format!("ERR unknown command: {}", cmd),
+    }
+}
+
+async fn serve_control_connection<S>(stream: S)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite,
+{
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let mut response = control_cmd(&line).await;
+        if response.is_empty() {
+            continue;
+        }
+        response.push('\n');
+        if writer.write_all(response.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn spawn_control_socket(path: &str) -> Result<(), std::io::Error> {
+    use std::os::unix::fs::PermissionsExt;
+    let _r = tokio::fs::remove_file(path).await;
+    let listener = tokio::net::UnixListener::bind(path)?;
+    tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).await?;
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve_control_connection(stream));
+                }
+                Err(e) => error!("control socket: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// The control channel is a named pipe on Windows, pipe instances are created one per connection
+#[cfg(windows)]
+#[allow(clippy::unused_async)]
+async fn spawn_control_socket(path: &str) -> Result<(), std::io::Error> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(path)?;
+    let path = path.to_owned();
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = server.connect().await {
+                error!("control socket: {}", e);
+                continue;
+            }
+            match ServerOptions::new().create(&path) {
+                Ok(next) => {
+                    let stream = std::mem::replace(&mut server, next);
+                    tokio::spawn(serve_control_connection(stream));
+                }
+                Err(e) => {
+                    error!("control socket: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(unix)]
+macro_rules! handle_term_signal {
+    ($kind: expr, $allow_log: expr) => {
+        tokio::spawn(async move {
+            trace!("starting handler for {:?}", $kind);
+            loop {
+                match signal($kind) {
+                    Ok(mut v) => {
+                        v.recv().await;
+                    }
+                    Err(e) => {
+                        error!("Unable to bind to signal {:?}: {}", $kind, e);
+                        break;
+                    }
+                }
+                // do not log anything on C-c
+                if $allow_log {
+                    trace!("got termination signal");
+                }
+                terminate($allow_log).await
+            }
+        });
+    };
+}
+
+/// Parses a group name or GID
+#[cfg(unix)]
+fn parse_group(group: &str) -> Result<u32, String> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    nix::unistd::Group::from_name(group)
+        .map_err(|e| format!("group {}: {}", group, e))?
+        .map(|g| g.gid.as_raw())
+        .ok_or_else(|| format!("group {} not found", group))
+}
+
+#[allow(clippy::too_many_lines)]
+fn main() {
+    let opts: Opts = Opts::parse();
+    if opts.check {
+        let errors = check_config(&opts);
+        if errors.is_empty() {
+            println!("configuration OK");
+            std::process::exit(0);
+        }
+        for e in errors {
+            eprintln!("{}", e);
+        }
+        std::process::exit(1);
+    }
+    let listeners = opts
+        .path
+        .iter()
+        .map(|path| ListenerConfig::parse(path))
+        .collect::<Result<Vec<ListenerConfig>, String>>()
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let dial_outs = opts
+        .dial_out
+        .iter()
+        .map(|s| ListenerConfig::parse_dial_out(s))
+        .collect::<Result<Vec<ListenerConfig>, String>>()
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let sinks = opts
+        .sink
+        .iter()
+        .map(|s| parse_sink(s))
+        .collect::<Result<Vec<(&str, FileSink)>, String>>()
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let client_name_policy = opts
+        .client_name_policy
+        .as_deref()
+        .map(parse_client_name_policy)
+        .transpose()
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let tokens = opts
+        .token_file
+        .as_deref()
+        .map(TokenList::load)
+        .transpose()
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    if tokens.is_some() {
+        for config in &listeners {
+            let (kind, addr) = match config.listener {
+                Listener::WsGateway(addr) => ("wsgw", addr),
+                Listener::Udp(addr) => ("udp", addr),
+                _ => continue,
+            };
+            eprintln!(
+                "{} {}: clients of the listener can not be authenticated, --token-file is set",
+                kind, addr
+            );
+            std::process::exit(1);
+        }
+    }
+    #[cfg(all(unix, feature = "rpc"))]
+    let fifo_group = opts
+        .fifo_group
+        .as_deref()
+        .map(parse_group)
+        .transpose()
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let statsd = opts
+        .statsd
+        .as_deref()
+        .map(parse_statsd)
+        .transpose()
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    #[cfg(feature = "testing")]
+    let chaos_rules = opts
+        .chaos
+        .iter()
+        .map(|s| parse_chaos(s))
+        .collect::<Result<Vec<ChaosRule>, String>>()
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    #[cfg(feature = "webhook")]
+    let webhooks = opts
+        .webhook
+        .iter()
+        .map(|s| parse_webhook(s))
+        .collect::<Result<Vec<(&str, WebhookSink)>, String>>()
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    #[cfg(feature = "sql")]
+    let sql_sinks = opts
+        .sql_sink
+        .iter()
+        .map(|s| parse_sql_sink(s))
+        .collect::<Result<Vec<(&str, SqlSink)>, String>>()
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    #[cfg(feature = "serial")]
+    let serial_bridges = opts
+        .serial
+        .iter()
+        .map(|s| parse_serial(s))
+        .collect::<Result<Vec<(Option<&str>, SerialBridge)>, String>>()
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    #[cfg(unix)]
+    let syslog_sinks = opts
+        .syslog_sink
+        .iter()
+        .map(|s| parse_syslog_sink(s))
+        .collect::<Result<Vec<SyslogSink>, String>>()
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    #[cfg(unix)]
+    let log_system = (opts.daemonize
+        && std::env::var("DISABLE_SYSLOG").unwrap_or_else(|_| "0".to_owned()) != "1")
+        || opts.log_syslog;
+    // there is no syslog on Windows, the output is collected by the service wrapper
+    #[cfg(not(unix))]
+    let log_system = false;
+    if opts.verbose {
+        set_verbose_logger(LevelFilter::Trace);
+    } else if log_system {
+        #[cfg(unix)]
+        set_system_logger();
+    } else {
+        set_verbose_logger(LevelFilter::Info);
+    }
+    let timeout = Duration::from_secs_f64(opts.timeout);
+    let buf_ttl = Duration::from_micros(opts.buf_ttl);
+    info!("starting elbus server");
+    info!("workers: {}", opts.workers);
+    info!("buf size: {}", opts.buf_size);
+    info!("buf ttl: {:?}", buf_ttl);
+    info!("queue size: {}", opts.queue_size);
+    if opts.fanout_budget == 0 {
+        info!("fan-out budget: unlimited");
+    } else {
+        info!("fan-out budget: {}", opts.fanout_budget);
+    }
+    if let Some(deadline) = opts.frame_deadline {
+        info!("frame deadline: {:?}", Duration::from_secs_f64(deadline));
+    }
+    if let Some(size) = opts.max_frame_size {
+        info!("max frame size: {}", size);
+    }
+    if let Some(watchdog) = opts.watchdog {
+        info!("watchdog: {:?}", Duration::from_secs_f64(watchdog));
+    }
+    if opts.memory_limit > 0 {
+        info!(
+            "memory limit: {} ({:?})",
+            opts.memory_limit, opts.memory_overflow
+        );
+    }
+    let subscription_limits = SubscriptionLimits {
+        max_subscriptions: opts.max_subscriptions,
+        max_wildcard_subscriptions: opts.max_wildcard_subscriptions,
+        max_topic_depth: opts.max_topic_depth,
+        max_topic_length: opts.max_topic_length,
+    };
+    if subscription_limits != SubscriptionLimits::default() {
+        info!("subscription limits: {:?}", subscription_limits);
+    }
+    if opts.timestamps {
+        info!("frame timestamps enabled");
+    }
+    if opts.broadcast_format != MaskFormat::broadcast() {
+        info!("broadcast format: {:?}", opts.broadcast_format);
+    }
+    if opts.topic_format != MaskFormat::topic() {
+        info!("topic format: {:?}", opts.topic_format);
+    }
+    info!("timeout: {:?}", timeout);
+    #[cfg(unix)]
+    if opts.daemonize {
+        if let Ok(fork::Fork::Child) = fork::daemon(true, false) {
+            std::process::exit(0);
+        }
+    }
+    if opts.sandbox {
+        #[cfg(target_os = "linux")]
+        {
+            let mut paths: Vec<&str> = Vec::new();
+            for listener in &listeners {
+                match listener.listener {
+                    Listener::Fifo(fifo, resp_fifo) => {
+                        paths.push(fifo);
+                        paths.extend(resp_fifo);
+                    }
+                    Listener::Unix(socket) => paths.push(socket),
+                    Listener::NamedPipe(_)
+                    | Listener::Tcp(_)
+                    | Listener::Tls(_)
+                    | Listener::Quic(_)
+                    | Listener::WebSocket(_)
+                    | Listener::WsGateway(_)
+                    | Listener::Udp(_)
+                    | Listener::Vsock(_)
+                    | Listener::Uring(_) => {}
+                }
+            }
+            paths.extend(opts.control.as_deref());
+            paths.extend(opts.pid_file.as_deref());
+            paths.extend(opts.state_file.as_deref());
+            paths.extend(sinks.iter().map(|(path, _)| *path));
+            #[cfg(feature = "sql")]
+            paths.extend(sql_sinks.iter().filter_map(|(url, _)| sqlite_path(url)));
+            sandbox::apply_landlock(&paths).expect("Unable to apply landlock rules");
+            info!("landlock rules applied");
+        }
+        #[cfg(not(target_os = "linux"))]
+        panic!("sandboxing is supported on Linux only");
+    }
+    let cpu_affinity = opts.cpu_affinity.as_ref().map(|v| {
+        parse_cpu_list(v).unwrap_or_else(|e| {
+            eprintln!("invalid CPU affinity: {}", e);
+            std::process::exit(1);
+        })
+    });
+    let rt = create_runtime(opts.workers, cpu_affinity).unwrap();
+    // dedicated listener runtimes must be dropped outside of the async context
+    let listener_runtimes: Vec<Option<tokio::runtime::Runtime>> = listeners
+        .iter()
+        .zip(&opts.path)
+        .map(|(l, path)| l.runtime(path))
+        .collect();
+    rt.block_on(async {
+        if let Some(ref pid_file) = opts.pid_file {
+            let pid = std::process::id().to_string();
+            tokio::fs::write(&pid_file, pid)
+                .await
+                .expect("Unable to write pid file");
+            info!("created pid file {}", pid_file);
+            PID_FILE.lock().await.replace(pid_file.clone());
+        }
+        #[cfg(unix)]
+        {
+            handle_term_signal!(SignalKind::interrupt(), false);
+            handle_term_signal!(SignalKind::terminate(), true);
+        }
+        #[cfg(windows)]
+        tokio::spawn(async move {
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                error!("Unable to bind to Ctrl-C: {}", e);
+                return;
+            }
+            terminate(true).await;
+        });
+        let mut broker =
+            Broker::with_mask_formats(opts.broadcast_format.clone(), opts.topic_format.clone())
+                .unwrap();
+        #[cfg(feature = "rpc")]
+        broker.init_default_core_rpc().await.unwrap();
+        broker.set_queue_size(opts.queue_size);
+        broker.set_fanout_budget(opts.fanout_budget);
+        broker.set_memory_limit(opts.memory_limit, opts.memory_overflow);
+        broker.set_subscription_limits(subscription_limits);
+        broker.set_frame_timestamps(opts.timestamps);
+        if let Some(watchdog) = opts.watchdog {
+            let stall_timeout = Duration::from_secs_f64(watchdog);
+            broker.set_watchdog(stall_timeout / 4, stall_timeout);
+        }
+        if let Some(policy) = client_name_policy {
+            info!("client name policy: {:?}", policy);
+            broker.set_client_name_validator(Arc::new(policy));
+        }
+        #[cfg(all(unix, feature = "rpc"))]
+        {
+            broker.set_fifo_group(fifo_group);
+            broker
+                .set_fifo_payload_dir(opts.fifo_payload_dir.as_deref())
+                .expect("invalid fifo payload directory");
+        }
+        if let Some(tokens) = tokens {
+            info!("token authentication enabled, {} token(s)", tokens.len());
+            broker.set_token_verifier(Arc::new(tokens));
+        }
+        if let Some((addr, prefix, interval)) = statsd {
+            let metrics =
+                StatsdMetrics::connect(addr, prefix).expect("Unable to connect to statsd");
+            broker.set_metrics(Arc::new(metrics), interval);
+            info!("statsd metrics to {}, interval: {:?}", addr, interval);
+        }
+        #[cfg(feature = "testing")]
+        for rule in chaos_rules {
+            info!("chaos rule: {:?}", rule);
+            broker.add_chaos_rule(rule).unwrap();
+        }
+        // named pipes are removed by the system
+        #[cfg_attr(not(unix), allow(unused_mut))]
+        let mut sock_files = SOCK_FILES.lock().await;
+        for (listener, runtime) in listeners.into_iter().zip(&listener_runtimes) {
+            let mut server_config = listener.server_config(&opts);
+            if let Some(rt) = runtime {
+                server_config = server_config.runtime(rt.handle().clone());
+            }
+            match listener.listener {
+                Listener::Fifo(_fifo, _resp_fifo) => {
+                    info!("binding at fifo:{}", _fifo);
+                    #[cfg(not(unix))]
+                    panic!("fifo channels are supported on Unix only");
+                    #[cfg(all(unix, feature = "rpc"))]
+                    {
+                        broker
+                            .spawn_fifo_with_responses(
+                                _fifo,
+                                _resp_fifo,
+                                listener.buf_size.unwrap_or(opts.buf_size),
+                            )
+                            .await
+                            .expect("unable to start fifo server");
+                        sock_files.push(_fifo.to_owned());
+                        if let Some(r) = _resp_fifo {
+                            sock_files.push(r.to_owned());
+                        }
+                    }
+                }
+                Listener::Unix(socket) => {
+                    info!("binding at {} ({:?})", socket, server_config);
+                    #[cfg(not(unix))]
+                    panic!("unix sockets are not supported, use named pipes");
+                    #[cfg(unix)]
+                    {
+                        broker
+                            .spawn_unix_server(socket, server_config)
+                            .await
+                            .expect("Unable to start unix server");
+                        sock_files.push(socket.to_owned());
+                    }
+                }
+                Listener::NamedPipe(pipe) => {
+                    info!("binding at {} ({:?})", pipe, server_config);
+                    #[cfg(not(windows))]
+                    panic!("named pipes are supported on Windows only");
+                    #[cfg(windows)]
+                    broker
+                        .spawn_named_pipe_server(pipe, server_config)
+                        .await
+                        .expect("Unable to start named pipe server");
+                }
+                Listener::Tcp(addr) => {
+                    info!("binding at {} ({:?})", addr, server_config);
+                    broker
+                        .spawn_tcp_server(addr, server_config)
+                        .await
+                        .expect("Unable to start tcp server");
+                }
+                Listener::Tls(_addr) => {
+                    #[cfg(feature = "tls")]
+                    {
+                        info!("binding at tls://{} ({:?})", _addr, server_config);
+                        broker
+                            .spawn_tls_server(
+                                _addr,
+                                opts.tls_cert.as_deref().unwrap_or_default(),
+                                opts.tls_key.as_deref().unwrap_or_default(),
+                                server_config,
+                            )
+                            .await
+                            .expect("Unable to start tls server");
+                    }
+                }
+                Listener::Quic(_addr) => {
+                    #[cfg(feature = "quic")]
+                    {
+                        info!("binding at quic://{} ({:?})", _addr, server_config);
+                        broker
+                            .spawn_quic_server(
+                                _addr,
+                                opts.tls_cert.as_deref().unwrap_or_default(),
+                                opts.tls_key.as_deref().unwrap_or_default(),
+                                server_config,
+                            )
+                            .await
+                            .expect("Unable to start quic server");
+                    }
+                }
+                Listener::WebSocket(_addr) => {
+                    #[cfg(feature = "websocket")]
+                    {
+                        info!("binding at ws://{} ({:?})", _addr, server_config);
+                        broker
+                            .spawn_websocket_server(_addr, server_config)
+                            .await
+                            .expect("Unable to start websocket server");
+                    }
+                }
+                Listener::WsGateway(_addr) => {
+                    #[cfg(all(feature = "websocket", feature = "rpc"))]
+                    {
+                        info!("binding at wsgw://{} ({:?})", _addr, server_config);
+                        let mut gateway = elbus::gateway::WsGateway::new();
+                        for mask in &opts.ws_gateway_topic {
+                            gateway = gateway.topic(mask);
+                        }
+                        for mask in &opts.ws_gateway_target {
+                            gateway = gateway.target(mask);
+                        }
+                        for mask in &opts.ws_gateway_publish {
+                            gateway = gateway.publish(mask);
+                        }
+                        broker
+                            .spawn_websocket_gateway(_addr, gateway, server_config)
+                            .await
+                            .expect("Unable to start websocket gateway");
+                    }
+                }
+                Listener::Vsock(_addr) => {
+                    #[cfg(feature = "vsock")]
+                    {
+                        info!("binding at vsock://{} ({:?})", _addr, server_config);
+                        broker
+                            .spawn_vsock_server(_addr, server_config)
+                            .await
+                            .expect("Unable to start vsock server");
+                    }
+                }
+                Listener::Uring(_addr) => {
+                    #[cfg(all(target_os = "linux", feature = "uring"))]
+                    {
+                        info!("binding at uring://{} ({:?})", _addr, server_config);
+                        broker
+                            .spawn_uring_server(_addr, server_config)
+                            .await
+                            .expect("Unable to start io_uring server");
+                    }
+                }
+                Listener::Udp(addr) => {
+                    info!("binding at udp://{}", addr);
+                    broker
+                        .spawn_udp_server(addr)
+                        .await
+                        .expect("Unable to start udp server");
+                }
+            }
+        }
+        for dial_out in dial_outs {
+            if let Listener::Tcp(addr) = dial_out.listener {
+                let server_config = dial_out.server_config(&opts);
+                info!("dialing out to {} ({:?})", addr, server_config);
+                broker.spawn_tcp_dialout(addr, server_config);
+            }
+        }
+        if let Some(ref state_file) = opts.state_file {
+            if std::path::Path::new(state_file).exists() {
+                broker
+                    .restore(state_file)
+                    .expect("Unable to restore the broker state");
+                info!("broker state restored from {}", state_file);
+            }
+            STATE_FILE.lock().await.replace(state_file.clone());
+        }
+        for (i, (path, sink)) in sinks.into_iter().enumerate() {
+            sink.spawn(&broker, &format!(".sink.{}", i + 1))
+                .await
+                .expect("Unable to start file sink");
+            info!("file sink {}", path);
+        }
+        #[cfg(unix)]
+        for (i, sink) in syslog_sinks.into_iter().enumerate() {
+            sink.spawn(&broker, &format!(".syslog.{}", i + 1))
+                .await
+                .expect("Unable to start syslog sink");
+            info!("syslog sink started");
+        }
+        #[cfg(feature = "webhook")]
+        for (i, (url, sink)) in webhooks.into_iter().enumerate() {
+            sink.spawn(&broker, &format!(".webhook.{}", i + 1))
+                .await
+                .expect("Unable to start webhook sink");
+            info!("webhook sink {}", url);
+        }
+        #[cfg(feature = "sql")]
+        for (i, (url, sink)) in sql_sinks.into_iter().enumerate() {
+            sink.spawn(&broker, &format!(".sql.{}", i + 1))
+                .await
+                .expect("Unable to start SQL sink");
+            // do not log credentials
+            info!(
+                "SQL sink {}",
+                url.split_once(':').map_or(url, |(scheme, _)| scheme)
+            );
+        }
+        #[cfg(feature = "serial")]
+        for (i, (name, bridge)) in serial_bridges.into_iter().enumerate() {
+            let name = name.map_or_else(|| format!(".serial.{}", i + 1), ToOwned::to_owned);
+            let device = bridge.device().to_owned();
+            broker
+                .spawn_serial(&name, bridge)
+                .await
+                .expect("Unable to start serial bridge");
+            info!("serial bridge {} at {}", name, device);
+        }
+        let capabilities = broker.capabilities();
+        info!(
+            "elbus {}, protocol version {}, features: {}",
+            capabilities.version,
+            capabilities.protocol_version,
+            capabilities.features.join(", ")
+        );
+        BROKER.lock().await.replace(broker);
+        if let Some(ref control) = opts.control {
+            info!("control socket at {}", control);
+            spawn_control_socket(control)
+                .await
+                .expect("Unable to start control socket");
+            #[cfg(unix)]
+            sock_files.push(control.clone());
+        }
+        drop(sock_files);
+        #[cfg(target_os = "linux")]
+        if opts.sandbox {
+            sandbox::apply_seccomp().expect("Unable to apply seccomp filter");
+            info!("seccomp filter applied");
+        }
+        info!("elbus broker started");
+        let sleep_step = Duration::from_millis(100);
+        loop {
+            if !SERVER_ACTIVE.load(atomic::Ordering::SeqCst) {
+                break;
+            }
+            sleep(sleep_step).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listener_config_overrides() {
+        let config =
+            ListenerConfig::parse("/tmp/elbus.sock,timeout=0.5,buf=4096,buf_ttl=10").unwrap();
+        assert!(matches!(config.listener, Listener::Unix("/tmp/elbus.sock")));
+        assert_eq!(config.timeout, Some(Duration::from_millis(500)));
+        assert_eq!(config.buf_size, Some(4096));
+        assert_eq!(config.buf_ttl, Some(Duration::from_micros(10)));
+        let config = ListenerConfig::parse("fifo:/tmp/elbus.fifo:/tmp/elbus.resp,buf=100").unwrap();
+        assert!(matches!(
+            config.listener,
+            Listener::Fifo("/tmp/elbus.fifo", Some("/tmp/elbus.resp"))
+        ));
+        for path in [
+            "/tmp/elbus.sock,timeout",
+            "/tmp/elbus.sock,timeout=0",
+            "/tmp/elbus.sock,timeout=-1",
+            "/tmp/elbus.sock,timeout=inf",
+            "/tmp/elbus.sock,timeout=NaN",
+            "/tmp/elbus.sock,buf=0",
+            "/tmp/elbus.sock,buf=x",
+            "/tmp/elbus.sock,buf_ttl=-1",
+            "/tmp/elbus.sock,unknown=1",
+            "fifo:/tmp/elbus.fifo,timeout=1",
+            "udp://0.0.0.0:7777,buf=100",
+        ] {
+            assert!(ListenerConfig::parse(path).is_err(), "{}", path);
+        }
+    }
+    #[test]
+    fn listener_config_tcp_flags() {
+        let config = ListenerConfig::parse(
+            "0.0.0.0:7777,nodelay=0,keepalive=30,keepalive_interval=5,keepalive_count=3,\
+             sndbuf=65536,rcvbuf=65536",
+        )
+        .unwrap();
+        assert!(matches!(config.listener, Listener::Tcp("0.0.0.0:7777")));
+        assert!(config.tcp_options_set);
+        assert!(
+            ListenerConfig::parse("ws://0.0.0.0:7777,keepalive=1")
+                .unwrap()
+                .tcp_options_set
+        );
+        assert!(
+            !ListenerConfig::parse("0.0.0.0:7777")
+                .unwrap()
+                .tcp_options_set
+        );
+        for path in [
+            "0.0.0.0:7777,nodelay=yes",
+            "0.0.0.0:7777,keepalive=0",
+            "0.0.0.0:7777,keepalive_interval=-1",
+            "0.0.0.0:7777,keepalive_count=-1",
+            "0.0.0.0:7777,sndbuf=x",
+            "0.0.0.0:7777,tls=1",
+            "/tmp/elbus.sock,nodelay=1",
+            "fifo:/tmp/elbus.fifo,keepalive=1",
+            "udp://0.0.0.0:7777,rcvbuf=65536",
+        ] {
+            assert!(ListenerConfig::parse(path).is_err(), "{}", path);
+        }
+    }
 }
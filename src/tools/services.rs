@@ -0,0 +1,201 @@
+use crate::client::AsyncClient;
+#[cfg(feature = "rpc")]
+use crate::rpc::{RpcClient, RpcError, RpcEvent, RpcHandlers, RpcResult};
+#[cfg(feature = "rpc")]
+use crate::Frame;
+use crate::{Error, EventChannel, FrameKind, QoS};
+#[cfg(feature = "rpc")]
+use async_trait::async_trait;
+use log::trace;
+use std::sync::atomic;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+#[inline]
+fn take_event_channel(client: &mut impl AsyncClient) -> Result<EventChannel, Error> {
+    client
+        .take_event_channel()
+        .ok_or_else(|| Error::not_supported("the client event channel has been already taken"))
+}
+
+/// Sends payloads of all received frames (messages, broadcasts, publications) back to senders as
+/// direct messages. Frames, sent with QoS::Consumed, are confirmed after they are echoed. The
+/// service is stopped when dropped
+pub struct EchoService {
+    echoed: Arc<atomic::AtomicU64>,
+    fut: JoinHandle<()>,
+}
+
+impl EchoService {
+    /// Spawns the service, the client event channel is taken. To echo publications, the client
+    /// must be subscribed to topics
+    pub fn spawn(mut client: impl AsyncClient + 'static, qos: QoS) -> Result<Self, Error> {
+        let rx = take_event_channel(&mut client)?;
+        let echoed = Arc::new(atomic::AtomicU64::new(0));
+        let counter = echoed.clone();
+        let fut = tokio::spawn(async move {
+            while let Ok(frame) = rx.recv().await {
+                // own broadcasts are never echoed to avoid loops
+                if frame.sender() == client.get_name() {
+                    continue;
+                }
+                let result = if let Some(header) = frame.header() {
+                    client
+                        .zc_send(frame.sender(), header.into(), frame.payload().into(), qos)
+                        .await
+                } else {
+                    client
+                        .send(frame.sender(), frame.payload().into(), qos)
+                        .await
+                };
+                if let Err(e) = result {
+                    trace!("unable to echo the frame to {}: {}", frame.sender(), e);
+                } else {
+                    counter.fetch_add(1, atomic::Ordering::SeqCst);
+                }
+                if frame.delivery_id().is_some() {
+                    let _r = client.confirm(&frame).await;
+                }
+            }
+        });
+        Ok(Self { echoed, fut })
+    }
+    /// Frames, sent back
+    #[inline]
+    pub fn echoed(&self) -> u64 {
+        self.echoed.load(atomic::Ordering::SeqCst)
+    }
+    #[inline]
+    pub fn stop(&self) {
+        self.fut.abort();
+    }
+}
+
+impl Drop for EchoService {
+    fn drop(&mut self) {
+        self.fut.abort();
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SinkStats {
+    pub frames: u64,
+    /// payload bytes
+    pub bytes: u64,
+    pub messages: u64,
+    pub broadcasts: u64,
+    pub publications: u64,
+}
+
+/// Receives and counts frames. Frames, sent with QoS::Consumed, are confirmed. The service is
+/// stopped when dropped
+pub struct SinkService {
+    stats: watch::Receiver<SinkStats>,
+    fut: JoinHandle<()>,
+}
+
+impl SinkService {
+    /// Spawns the service, the client event channel is taken. To receive publications, the
+    /// client must be subscribed to topics
+    pub fn spawn(mut client: impl AsyncClient + 'static) -> Result<Self, Error> {
+        let rx = take_event_channel(&mut client)?;
+        let (tx, stats) = watch::channel(SinkStats::default());
+        let fut = tokio::spawn(async move {
+            while let Ok(frame) = rx.recv().await {
+                tx.send_modify(|stats| {
+                    stats.frames += 1;
+                    stats.bytes +=
+                        (frame.header().map_or(0, <[u8]>::len) + frame.payload().len()) as u64;
+                    match frame.kind() {
+                        FrameKind::Message => stats.messages += 1,
+                        FrameKind::Broadcast => stats.broadcasts += 1,
+                        FrameKind::Publish => stats.publications += 1,
+                        _ => {}
+                    }
+                });
+                if frame.delivery_id().is_some() {
+                    let _r = client.confirm(&frame).await;
+                }
+            }
+        });
+        Ok(Self { stats, fut })
+    }
+    #[inline]
+    pub fn stats(&self) -> SinkStats {
+        *self.stats.borrow()
+    }
+    /// Waits until the sink receives at least the number of frames
+    ///
+    /// # Errors
+    ///
+    /// Timeout - if not received in time, Io - if the service is stopped
+    pub async fn wait(&self, frames: u64, timeout: Duration) -> Result<(), Error> {
+        let mut stats = self.stats.clone();
+        let result = tokio::time::timeout(timeout, stats.wait_for(|s| s.frames >= frames))
+            .await?
+            .map(|_| ());
+        result.map_err(|_| Error::io("the sink service is stopped"))
+    }
+    #[inline]
+    pub fn stop(&self) {
+        self.fut.abort();
+    }
+}
+
+impl Drop for SinkService {
+    fn drop(&mut self) {
+        self.fut.abort();
+    }
+}
+
+/// RPC handlers for benchmarks and smoke tests: "benchmark.selftest", "benchmark.test" and "echo"
+/// calls return the payload back (the same methods the broker core client serves), other calls
+/// fail with "method not found"
+#[cfg(feature = "rpc")]
+#[derive(Default, Clone)]
+pub struct BenchResponder {
+    calls: Arc<atomic::AtomicU64>,
+    notifications: Arc<atomic::AtomicU64>,
+}
+
+#[cfg(feature = "rpc")]
+impl BenchResponder {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Creates an RPC client with the responder handlers, the counters are shared
+    #[inline]
+    pub fn spawn(&self, client: impl AsyncClient + 'static) -> RpcClient {
+        RpcClient::new(client, self.clone())
+    }
+    /// Calls, answered successfully
+    #[inline]
+    pub fn calls(&self) -> u64 {
+        self.calls.load(atomic::Ordering::SeqCst)
+    }
+    #[inline]
+    pub fn notifications(&self) -> u64 {
+        self.notifications.load(atomic::Ordering::SeqCst)
+    }
+}
+
+#[cfg(feature = "rpc")]
+#[async_trait]
+impl RpcHandlers for BenchResponder {
+    async fn handle_call(&self, event: RpcEvent) -> RpcResult {
+        match event.parse_method()? {
+            "benchmark.selftest" | "benchmark.test" | "echo" => {
+                self.calls.fetch_add(1, atomic::Ordering::SeqCst);
+                Ok(Some(event.payload().to_vec()))
+            }
+            _ => Err(RpcError::method(None)),
+        }
+    }
+    async fn handle_notification(&self, _event: RpcEvent) {
+        self.notifications.fetch_add(1, atomic::Ordering::SeqCst);
+    }
+    async fn handle_frame(&self, _frame: Frame) {}
+}
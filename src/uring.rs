@@ -0,0 +1,195 @@
+use parking_lot::Mutex;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll, Waker};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::Notify;
+use tokio_uring::net::TcpStream;
+
+type ReadFuture = Pin<Box<dyn Future<Output = (io::Result<usize>, Vec<u8>)>>>;
+
+/// Buffered reader of io_uring streams, the buffer is owned by the kernel while a read is in
+/// progress
+pub(crate) struct UringReader {
+    stream: Rc<TcpStream>,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+    pending: Option<ReadFuture>,
+}
+
+impl UringReader {
+    pub(crate) fn new(stream: Rc<TcpStream>, cap: usize) -> Self {
+        Self {
+            stream,
+            buf: vec![0; cap],
+            pos: 0,
+            len: 0,
+            pending: None,
+        }
+    }
+}
+
+impl AsyncRead for UringReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if this.pos == this.len {
+            let fut = this.pending.get_or_insert_with(|| {
+                let stream = this.stream.clone();
+                let buf = std::mem::take(&mut this.buf);
+                Box::pin(async move { stream.read(buf).await })
+            });
+            let (result, buf) = ready!(fut.as_mut().poll(cx));
+            this.pending = None;
+            this.buf = buf;
+            this.pos = 0;
+            this.len = 0;
+            let n = result?;
+            if n == 0 {
+                // EOF
+                return Poll::Ready(Ok(()));
+            }
+            this.len = n;
+        }
+        let n = (this.len - this.pos).min(out.remaining());
+        out.put_slice(&this.buf[this.pos..this.pos + n]);
+        this.pos += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[derive(Default)]
+struct WriterState {
+    buf: Vec<u8>,
+    // the previous chunk is being written by the io_uring task
+    busy: bool,
+    closed: bool,
+    error: Option<io::ErrorKind>,
+    waker: Option<Waker>,
+}
+
+#[derive(Default)]
+struct WriterShared {
+    state: Mutex<WriterState>,
+    notify: Notify,
+}
+
+/// io_uring streams can not be shared between threads, while broker writers must be Send +
+/// Sync. The data is passed to a local task (see [`write_loop`]), which submits writes to the
+/// ring, flushes wait until the task writes everything buffered
+pub(crate) struct UringWriter {
+    shared: Arc<WriterShared>,
+    cap: usize,
+}
+
+impl UringWriter {
+    /// Returns the writer and the future of the local write task
+    pub(crate) fn new(stream: Rc<TcpStream>, cap: usize) -> (Self, impl Future<Output = ()>) {
+        let shared: Arc<WriterShared> = <_>::default();
+        (
+            Self {
+                shared: shared.clone(),
+                cap,
+            },
+            write_loop(stream, shared),
+        )
+    }
+    fn poll_written(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut state = self.shared.state.lock();
+        if let Some(kind) = state.error {
+            return Poll::Ready(Err(kind.into()));
+        }
+        if state.buf.is_empty() && !state.busy {
+            Poll::Ready(Ok(()))
+        } else {
+            state.waker.replace(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl AsyncWrite for UringWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.shared.state.lock();
+        if let Some(kind) = state.error {
+            return Poll::Ready(Err(kind.into()));
+        }
+        if state.closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if !state.buf.is_empty() && state.buf.len() + data.len() > self.cap {
+            state.waker.replace(cx.waker().clone());
+            return Poll::Pending;
+        }
+        state.buf.extend_from_slice(data);
+        drop(state);
+        self.shared.notify.notify_one();
+        Poll::Ready(Ok(data.len()))
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_written(cx)
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_written(cx))?;
+        self.shared.state.lock().closed = true;
+        self.shared.notify.notify_one();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for UringWriter {
+    fn drop(&mut self) {
+        self.shared.state.lock().closed = true;
+        self.shared.notify.notify_one();
+    }
+}
+
+/// Submits buffered data to the ring until the writer is dropped or the stream is failed
+async fn write_loop(stream: Rc<TcpStream>, shared: Arc<WriterShared>) {
+    let mut buf = Vec::new();
+    loop {
+        let idle = {
+            let mut state = shared.state.lock();
+            if state.buf.is_empty() {
+                if state.closed {
+                    break;
+                }
+                true
+            } else {
+                std::mem::swap(&mut buf, &mut state.buf);
+                state.busy = true;
+                false
+            }
+        };
+        // notify_one stores a permit, so a notification, sent after the check, is not lost
+        if idle {
+            shared.notify.notified().await;
+            continue;
+        }
+        let (result, written) = stream.write_all(buf).await;
+        buf = written;
+        buf.clear();
+        let mut state = shared.state.lock();
+        state.busy = false;
+        if let Err(e) = result {
+            state.error.replace(e.kind());
+        }
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        if state.error.is_some() {
+            break;
+        }
+    }
+}